@@ -35,6 +35,11 @@ async fn main() -> std::io::Result<()> {
   let env: services::secrets::Environment =
     services::secrets::Environment::new().expect("Need good env config");
 
+  let plaid_client =
+    services::finchplaid::ApiClient::from_environment(&env).expect("Need good Plaid config");
+  let fx_service =
+    services::fx::FxService::from_environment(&env).expect("Need good exchange rates");
+
   let db_service = services::db::DatabaseService::new(
     env.database_url,
     env.database_user,
@@ -43,26 +48,25 @@ async fn main() -> std::io::Result<()> {
   )
   .await;
 
-  let plaid_client = services::finchplaid::ApiClient {
-    client_id: env.plaid_client_id,
-    secret: env.plaid_sandbox_secret,
-    client_name: "finch".to_string(),
-  };
-
   let user_service = services::users::UserService::new(&db_service).await;
   let session_service = services::sessions::SessionService::new(&db_service).await;
   let fin_product_service = services::financial_products::FinProductService::new(&db_service).await;
   let leaderboard_service = services::leaderboards::LeaderboardService::new(&db_service).await;
+  let transaction_service = services::transactions::TransactionService::new(&db_service).await;
+  let webhook_service = services::webhooks::WebhookService::new(&db_service).await;
 
   HttpServer::new(move || {
     App::new()
       .wrap(create_cookie())
       .wrap(middleware::Logger::default())
       .data(plaid_client.clone())
+      .data(fx_service.clone())
       .data(user_service.clone())
       .data(session_service.clone())
       .data(fin_product_service.clone())
       .data(leaderboard_service.clone())
+      .data(transaction_service.clone())
+      .data(webhook_service.clone())
       .configure(controllers::configure)
       .service(root_route)
       .wrap(middleware::Logger::default())
@@ -1,5 +1,5 @@
 use chrono::Utc;
-use pfp_server::common::Money;
+use pfp_server::common::{Currency, Money};
 use pfp_server::models::user_model::{Location, Snapshot, User};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
@@ -21,6 +21,7 @@ fn main() {
       last_name: "Hamilton".to_string(),
       income: dec!(30_000.0),
       net_worth: dec!(10_000_000.00),
+      base_currency: Currency::USD,
       location: Location {
         // new york city
         has_location: true,
@@ -30,20 +31,31 @@ fn main() {
       birthday: "1755-01-11".to_string(),
       accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: vec![
         Snapshot {
           net_worth: Money::new(dec!(1_000_000)),
+          assets: Money::new(dec!(1_000_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(0)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           snapshot_time: first_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(1_000_000)),
+          assets: Money::new(dec!(1_000_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(6_000)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(6_000)),
           snapshot_time: second_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ],
       recurrings: vec![],
@@ -51,6 +63,8 @@ fn main() {
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     },
     User {
       id: None,
@@ -60,6 +74,7 @@ fn main() {
       last_name: "Jefferson".to_string(),
       income: dec!(30_000.0),
       net_worth: dec!(12_000_000.00),
+      base_currency: Currency::USD,
       location: Location {
         // monticello
         has_location: true,
@@ -69,20 +84,31 @@ fn main() {
       birthday: "1743-04-13".to_string(),
       accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: vec![
         Snapshot {
           net_worth: Money::new(dec!(1_000_000)),
+          assets: Money::new(dec!(1_000_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(0)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           snapshot_time: first_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(1_001_000)),
+          assets: Money::new(dec!(1_001_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(25_000)),
           running_savings: Money::new(dec!(1_000)),
           running_spending: Money::new(dec!(1_000)),
           snapshot_time: second_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ],
       recurrings: vec![],
@@ -90,6 +116,8 @@ fn main() {
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     },
     User {
       id: None,
@@ -99,6 +127,7 @@ fn main() {
       last_name: "Washington".to_string(),
       income: dec!(30_000.0),
       net_worth: dec!(22_000_000.00),
+      base_currency: Currency::USD,
       location: Location {
         // mt vernon
         has_location: true,
@@ -108,20 +137,31 @@ fn main() {
       birthday: "1732-02-22".to_string(),
       accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: vec![
         Snapshot {
           net_worth: Money::new(dec!(1_000_000)),
+          assets: Money::new(dec!(1_000_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(0)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           snapshot_time: first_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(1_040_000)),
+          assets: Money::new(dec!(1_040_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(40_000)),
           running_savings: Money::new(dec!(1_000)),
           running_spending: Money::new(dec!(40_000)),
           snapshot_time: second_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ],
       recurrings: vec![],
@@ -129,6 +169,8 @@ fn main() {
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     },
   ];
 
@@ -141,6 +183,7 @@ fn main() {
       last_name: "Hamilton".to_string(),
       income: dec!(30_000.0),
       net_worth: dec!(100_000.00),
+      base_currency: Currency::USD,
       location: Location {
         // new york city
         has_location: false,
@@ -150,20 +193,31 @@ fn main() {
       birthday: format!("{}-03-03", 2000 - i),
       accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: vec![
         Snapshot {
           net_worth: Money::new(dec!(100_000)),
+          assets: Money::new(dec!(100_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(0)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           snapshot_time: first_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(100_000)),
+          assets: Money::new(dec!(100_000)),
+          liabilities: Money::default(),
           running_income: Money::new(dec!(1_000) * Decimal::from(i)),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(1_000) * Decimal::from(i)),
           snapshot_time: second_snapshot_time,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ],
       recurrings: vec![],
@@ -171,6 +225,8 @@ fn main() {
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     })
   }
 
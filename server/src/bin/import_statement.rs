@@ -0,0 +1,103 @@
+extern crate pfp_server;
+use actix_web::web::Data;
+use pfp_server::*;
+use services::{
+  db::DatabaseService,
+  finchplaid,
+  fx::FxService,
+  imports::{csv::CsvMapping, ImportFormat, ImportService},
+  secrets::Environment,
+  transactions::TransactionService,
+  users::UserService,
+};
+use std::fs;
+
+const USAGE: &str =
+  "usage: import_statement <email> <manual account id> <csv|ofx|qif> <file> [csv mapping json]";
+
+// Imports a bank export into one of a user's manual accounts, like the
+// /manual/account/{id}/import route does. A CSV needs a JSON file mapping its
+// columns, e.g. {"date": "Date", "description": "Payee", "amount": "Amount"}.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  common::finchlog::init_log(module_path!());
+
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.len() < 4 {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+  }
+  let (email, account_id, format, file) = (&args[0], &args[1], &args[2], &args[3]);
+
+  let format = match format.to_lowercase().as_str() {
+    "ofx" | "qfx" => ImportFormat::Ofx,
+    "qif" => ImportFormat::Qif,
+    "csv" => {
+      let mapping = args.get(4).expect(USAGE);
+      let mapping: CsvMapping = serde_json::from_str(&fs::read_to_string(mapping)?)
+        .expect("Need a good CSV column mapping");
+      ImportFormat::Csv(mapping)
+    }
+    _ => panic!("{}", USAGE),
+  };
+  let content = fs::read_to_string(file)?;
+
+  let env = Environment::new().expect("Need good env config");
+  let plaid_client =
+    Data::new(finchplaid::ApiClient::from_environment(&env).expect("Need good Plaid config"));
+  let fx = FxService::from_environment(&env).expect("Need good exchange rates");
+
+  let db_service = DatabaseService::new(
+    env.database_url,
+    env.database_user,
+    env.database_pw,
+    env.database_name,
+  )
+  .await;
+  let user_service = UserService::new(&db_service).await;
+  let transaction_service = TransactionService::new(&db_service).await;
+
+  let mut user = match user_service.find_by_email(email).await {
+    Ok(user) => user,
+    Err(e) => {
+      log::error!("Could not find user {}: {:?}", email, e);
+      return Ok(());
+    }
+  };
+
+  let res = ImportService::import_statement(
+    &mut user,
+    account_id.clone(),
+    &format,
+    &content,
+    &transaction_service,
+  )
+  .await;
+
+  match res {
+    Ok(summary) => {
+      log::info!(
+        "Added {} transactions, skipped {} already imported, recorded {} balances",
+        summary.transactions_added,
+        summary.transactions_skipped,
+        summary.balances_recorded
+      );
+
+      // new balances change net worth, transactions alone don't
+      let saved = match user_service.save(&mut user).await {
+        Ok(_) if summary.balances_recorded > 0 => {
+          user_service
+            .add_new_snapshot(&mut user, plaid_client, &fx)
+            .await
+        }
+        saved => saved,
+      };
+      if let Err(e) = saved {
+        log::error!("Failed to save the import: {:?}", e);
+      }
+    }
+    Err(e) => log::error!("Failed to import {}: {:?}", file, e),
+  }
+
+  Ok(())
+}
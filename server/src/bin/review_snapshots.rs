@@ -0,0 +1,77 @@
+extern crate pfp_server;
+use pfp_server::*;
+use services::{
+  db::DatabaseService, secrets::Environment, snapshots::SnapshotService, users::UserService,
+};
+
+const USAGE: &str = "usage: review_snapshots <email> [accept|reject <snapshot time>]";
+
+// Lists a user's quarantined snapshots, or accepts or rejects one of them like
+// the /snapshots/{snapshot_time}/accept and /reject routes do.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  common::finchlog::init_log(module_path!());
+
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.len() != 1 && args.len() != 3 {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+  }
+  let email = &args[0];
+
+  let env = Environment::new().expect("Need good env config");
+  let db_service = DatabaseService::new(
+    env.database_url,
+    env.database_user,
+    env.database_pw,
+    env.database_name,
+  )
+  .await;
+  let user_service = UserService::new(&db_service).await;
+
+  let mut user = match user_service.find_by_email(email).await {
+    Ok(user) => user,
+    Err(e) => {
+      log::error!("Could not find user {}: {:?}", email, e);
+      return Ok(());
+    }
+  };
+
+  if args.len() == 1 {
+    for snapshot in SnapshotService::get_quarantined_snapshots(user) {
+      println!(
+        "{}\t{}\t{}",
+        snapshot.snapshot_time,
+        snapshot.net_worth.amount,
+        snapshot.quarantine.unwrap_or_default()
+      );
+    }
+    return Ok(());
+  }
+
+  let accept = match args[1].as_str() {
+    "accept" => true,
+    "reject" => false,
+    _ => panic!("{}", USAGE),
+  };
+  let snapshot_time: i64 = args[2].parse().expect(USAGE);
+
+  let reviewed = match SnapshotService::review_snapshot(&mut user, snapshot_time, accept) {
+    Ok(snapshot) => snapshot,
+    Err(e) => {
+      log::error!("Could not review snapshot {}: {:?}", snapshot_time, e);
+      return Ok(());
+    }
+  };
+  match user_service.save(&mut user).await {
+    Ok(_) => log::info!(
+      "{} snapshot {}, net worth {}",
+      if accept { "Accepted" } else { "Rejected" },
+      snapshot_time,
+      reviewed.net_worth.amount
+    ),
+    Err(e) => log::error!("Failed to save the review: {:?}", e),
+  }
+
+  Ok(())
+}
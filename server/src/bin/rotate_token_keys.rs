@@ -0,0 +1,44 @@
+extern crate pfp_server;
+use common::encryption::Keyring;
+use pfp_server::*;
+use services::{db::DatabaseService, secrets::Environment, users::UserService};
+
+// Puts every stored Plaid access token under the current `token_key_id`. Run it
+// once to encrypt the tokens stored before encryption, and again after adding a
+// new master key and making it current, before removing the old one. Stop the
+// API first, as it writes back the tokens of users it read before the rotation.
+// Exits with an error if any token couldn't be rotated.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  common::finchlog::init_log(module_path!());
+
+  let env = Environment::new().expect("Need good env config");
+  let keyring = Keyring::new(&env.token_keys, &env.token_key_id).expect("Need good token keys");
+
+  let db_service = DatabaseService::new(
+    env.database_url,
+    env.database_user,
+    env.database_pw,
+    env.database_name,
+  )
+  .await;
+  let user_service = UserService::new(&db_service).await;
+
+  match user_service.rotate_access_tokens(&keyring).await {
+    Ok((rotated, 0)) => log::info!("Rotated {} access tokens", rotated),
+    Ok((rotated, unreadable)) => {
+      log::error!(
+        "Rotated {} access tokens, but could not read {} users",
+        rotated,
+        unreadable
+      );
+      std::process::exit(1);
+    }
+    Err(e) => {
+      log::error!("Failed to rotate access tokens: {:?}", e);
+      std::process::exit(1);
+    }
+  }
+
+  Ok(())
+}
@@ -9,6 +9,8 @@ async fn main() -> std::io::Result<()> {
 
   let env = Environment::new().expect("Need good env config");
 
+  let plaid_client = finchplaid::ApiClient::from_environment(&env).expect("Need good Plaid config");
+
   let db_service = DatabaseService::new(
     env.database_url,
     env.database_user,
@@ -17,12 +19,6 @@ async fn main() -> std::io::Result<()> {
   )
   .await;
 
-  let plaid_client = finchplaid::ApiClient {
-    client_id: env.plaid_client_id,
-    secret: env.plaid_sandbox_secret,
-    client_name: "finch".to_string(),
-  };
-
   loop {
     let res = InsightsService::run_insights_service(&db_service, &plaid_client).await;
 
@@ -0,0 +1,51 @@
+extern crate pfp_server;
+use actix_web::{self, web::Data};
+use async_std::task;
+use pfp_server::*;
+use services::{
+  db::DatabaseService, finchplaid, fx::FxService, secrets::Environment, snapshots::SnapshotService,
+};
+use std::time::Duration;
+
+// the wait before restarting the service after it fails, doubled on each failure
+const RETRY_BASE_SECS: u64 = 5;
+const RETRY_MAX_SECS: u64 = 10 * 60;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  common::finchlog::init_log(module_path!());
+
+  let env = Environment::new().expect("Need good env config");
+
+  let plaid_client =
+    Data::new(finchplaid::ApiClient::from_environment(&env).expect("Need good Plaid config"));
+  let fx = FxService::from_environment(&env).expect("Need good exchange rates");
+
+  let db_service = DatabaseService::new(
+    env.database_url,
+    env.database_user,
+    env.database_pw,
+    env.database_name,
+  )
+  .await;
+
+  let mut retry_secs = RETRY_BASE_SECS;
+  loop {
+    let res = SnapshotService::run_snapshot_service(&db_service, plaid_client.clone(), &fx).await;
+
+    match res {
+      Err(e) => log::error!(
+        "SnapshotService::run_snapshot_service returned error: {:?}. Retrying in {} seconds...",
+        e,
+        retry_secs
+      ),
+      _ => (),
+    };
+
+    task::sleep(Duration::from_secs(retry_secs)).await;
+    retry_secs = (retry_secs * 2).min(RETRY_MAX_SECS);
+  }
+
+  #[allow(unreachable_code)]
+  Ok(())
+}
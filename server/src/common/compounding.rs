@@ -0,0 +1,333 @@
+// Decimal-exact compounding.
+//
+// Growth is given as an annual growth factor (1.05 for a 5% APY), the same way
+// `Asset::annualized_performance` is stored. Interest accrues at full `Decimal`
+// precision between compounding boundaries and is credited to the balance at each
+// boundary rounded to the cent with `Decimal::round_dp` (banker's rounding, i.e.
+// round half to even). What rounding leaves over carries to the next credit, so
+// small balances still grow. This is how banks post interest, so projected
+// balances line up with statements.
+use crate::common::Money;
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum CompoundingFrequency {
+  #[serde(rename = "daily")]
+  Daily,
+  #[serde(rename = "monthly")]
+  Monthly,
+  #[serde(rename = "annually")]
+  Annually,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum DayCount {
+  // actual days elapsed over a 365 day year, leap years included
+  #[serde(rename = "act/365")]
+  Act365,
+  // US (bond basis) 30/360: every month has 30 days and every year has 360
+  #[serde(rename = "30/360")]
+  Thirty360,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CompoundingConvention {
+  pub frequency: CompoundingFrequency,
+  pub day_count: DayCount,
+}
+
+impl Default for CompoundingConvention {
+  fn default() -> Self {
+    CompoundingConvention {
+      frequency: CompoundingFrequency::Daily,
+      day_count: DayCount::Act365,
+    }
+  }
+}
+
+impl CompoundingFrequency {
+  pub fn periods_per_year(&self) -> u32 {
+    match self {
+      CompoundingFrequency::Daily => 365,
+      CompoundingFrequency::Monthly => 12,
+      CompoundingFrequency::Annually => 1,
+    }
+  }
+
+  // interest is credited at the end of every day, month or year
+  pub fn is_boundary(&self, date: &NaiveDate) -> bool {
+    match self {
+      CompoundingFrequency::Daily => true,
+      CompoundingFrequency::Monthly => date.day() == 1,
+      CompoundingFrequency::Annually => date.ordinal() == 1,
+    }
+  }
+}
+
+impl DayCount {
+  pub fn days_in_year(&self) -> Decimal {
+    match self {
+      DayCount::Act365 => dec!(365),
+      DayCount::Thirty360 => dec!(360),
+    }
+  }
+
+  pub fn days_between(&self, start: &NaiveDate, end: &NaiveDate) -> Decimal {
+    match self {
+      DayCount::Act365 => Decimal::from((*end - *start).num_days()),
+      DayCount::Thirty360 => {
+        let d1 = start.day().min(30) as i64;
+        let d2 = if d1 == 30 {
+          end.day().min(30) as i64
+        } else {
+          end.day() as i64
+        };
+
+        Decimal::from(
+          360 * (end.year() - start.year()) as i64
+            + 30 * (end.month() as i64 - start.month() as i64)
+            + (d2 - d1),
+        )
+      }
+    }
+  }
+
+  pub fn year_fraction(&self, start: &NaiveDate, end: &NaiveDate) -> Decimal {
+    self.days_between(start, end) / self.days_in_year()
+  }
+}
+
+// rounds to the cent, half to even
+pub fn round_cents(amount: Decimal) -> Decimal {
+  amount.round_dp(2)
+}
+
+fn pow(base: Decimal, exp: u32) -> Decimal {
+  let mut result = dec!(1);
+  let mut base = base;
+  let mut exp = exp;
+
+  while exp > 0 {
+    if exp & 1 == 1 {
+      result *= base;
+    }
+    base *= base;
+    exp >>= 1;
+  }
+
+  result
+}
+
+// n-th root by Newton's method, accurate to about 26 decimal places.
+// Returns zero for non-positive values, which only come up for a total loss.
+pub fn nth_root(value: Decimal, n: u32) -> Decimal {
+  if value <= dec!(0) || n == 0 {
+    return dec!(0);
+  }
+  if n == 1 {
+    return value;
+  }
+
+  let n_dec = Decimal::from(n);
+  let tolerance = Decimal::new(1, 26);
+
+  let mut x = dec!(1) + (value - dec!(1)) / n_dec;
+  for _ in 0..100 {
+    let x_n_minus_1 = pow(x, n - 1);
+    let delta = (x_n_minus_1 * x - value) / (n_dec * x_n_minus_1);
+    x -= delta;
+
+    if delta.abs() < tolerance {
+      break;
+    }
+  }
+
+  x
+}
+
+// the interest rate per compounding period that yields `annual_growth` over a year
+pub fn periodic_rate(annual_growth: Decimal, frequency: CompoundingFrequency) -> Decimal {
+  nth_root(annual_growth, frequency.periods_per_year()) - dec!(1)
+}
+
+// Accrues interest day by day and credits it at compounding boundaries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compounder {
+  pub convention: CompoundingConvention,
+  annual_growth: Decimal,
+  nominal_rate: Decimal,
+  accrued: Decimal,
+}
+
+impl Compounder {
+  pub fn new(annual_growth: Decimal, convention: CompoundingConvention) -> Self {
+    let mut compounder = Compounder {
+      convention,
+      annual_growth: dec!(1),
+      nominal_rate: dec!(0),
+      accrued: dec!(0),
+    };
+    compounder.set_annual_growth(annual_growth);
+    compounder
+  }
+
+  // changes the rate going forward, keeping any interest accrued so far
+  pub fn set_annual_growth(&mut self, annual_growth: Decimal) {
+    if annual_growth == self.annual_growth {
+      return;
+    }
+
+    let frequency = self.convention.frequency;
+    self.annual_growth = annual_growth;
+    self.nominal_rate =
+      periodic_rate(annual_growth, frequency) * Decimal::from(frequency.periods_per_year());
+  }
+
+  // accrues interest on `balance` from `from` to `to`, crediting it if `to` is a boundary
+  pub fn step(&mut self, balance: Money, from: &NaiveDate, to: &NaiveDate) -> Money {
+    let day_count = self.convention.day_count;
+
+    self.accrued += balance.amount * self.nominal_rate * day_count.days_between(from, to)
+      / day_count.days_in_year();
+
+    if !self.convention.frequency.is_boundary(to) {
+      return balance;
+    }
+
+    let credited = round_cents(self.accrued);
+    self.accrued -= credited;
+
+    balance + credited
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd(y, m, d)
+  }
+
+  fn run(compounder: &mut Compounder, balance: Money, from: NaiveDate, to: NaiveDate) -> Money {
+    let mut balance = balance;
+    let mut day = from;
+    while day < to {
+      let next = day.succ();
+      balance = compounder.step(balance, &day, &next);
+      day = next;
+    }
+    balance
+  }
+
+  #[test]
+  fn test_day_counts() {
+    // 2020 is a leap year
+    assert_eq!(
+      dec!(366),
+      DayCount::Act365.days_between(&ymd(2020, 1, 1), &ymd(2021, 1, 1))
+    );
+    assert_eq!(
+      dec!(360),
+      DayCount::Thirty360.days_between(&ymd(2020, 1, 1), &ymd(2021, 1, 1))
+    );
+    assert_eq!(
+      dec!(28),
+      DayCount::Thirty360.days_between(&ymd(2021, 1, 31), &ymd(2021, 2, 28))
+    );
+    assert_eq!(
+      dec!(31),
+      DayCount::Thirty360.days_between(&ymd(2021, 1, 31), &ymd(2021, 3, 1))
+    );
+  }
+
+  #[test]
+  fn test_nth_root() {
+    assert_eq!(dec!(1.1), nth_root(dec!(1.21), 2).round_dp(20));
+    assert_eq!(dec!(1), nth_root(dec!(1), 365));
+    assert_eq!(dec!(0), nth_root(dec!(0), 365));
+    assert_eq!(dec!(1.05), pow(nth_root(dec!(1.05), 365), 365).round_dp(20));
+  }
+
+  #[test]
+  fn test_annual_compounding() {
+    let convention = CompoundingConvention {
+      frequency: CompoundingFrequency::Annually,
+      day_count: DayCount::Act365,
+    };
+
+    let mut compounder = Compounder::new(dec!(1.05), convention);
+    assert_eq!(
+      Money::from(dec!(1050.00)),
+      run(
+        &mut compounder,
+        Money::from(dec!(1000)),
+        ymd(2021, 1, 1),
+        ymd(2022, 1, 1)
+      )
+    );
+
+    // an extra day of interest in a leap year
+    let mut compounder = Compounder::new(dec!(1.05), convention);
+    assert_eq!(
+      Money::from(dec!(1050.14)),
+      run(
+        &mut compounder,
+        Money::from(dec!(1000)),
+        ymd(2020, 1, 1),
+        ymd(2021, 1, 1)
+      )
+    );
+  }
+
+  #[test]
+  fn test_daily_compounding_carries_remainder() {
+    // a day's interest on ten dollars is a fraction of a cent
+    let mut compounder = Compounder::new(dec!(1.05), CompoundingConvention::default());
+    assert_eq!(
+      Money::from(dec!(10.50)),
+      run(
+        &mut compounder,
+        Money::from(dec!(10)),
+        ymd(2021, 1, 1),
+        ymd(2022, 1, 1)
+      )
+    );
+  }
+
+  #[test]
+  fn test_monthly_compounding() {
+    let mut compounder = Compounder::new(
+      dec!(1.12),
+      CompoundingConvention {
+        frequency: CompoundingFrequency::Monthly,
+        day_count: DayCount::Thirty360,
+      },
+    );
+
+    // nothing is credited until the first of the month
+    assert_eq!(
+      Money::from(dec!(1000)),
+      run(
+        &mut compounder,
+        Money::from(dec!(1000)),
+        ymd(2021, 1, 1),
+        ymd(2021, 1, 31)
+      )
+    );
+
+    // 1.12 ^ (1 / 12) = 1.00948879...
+    assert_eq!(
+      Money::from(dec!(1009.49)),
+      run(
+        &mut compounder,
+        Money::from(dec!(1000)),
+        ymd(2021, 1, 31),
+        ymd(2021, 2, 1)
+      )
+    );
+  }
+}
@@ -0,0 +1,279 @@
+use crate::common::errors::ApiError;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const KEY_LEN: usize = 32;
+
+// A secret under envelope encryption. A fresh data key encrypts the secret, and
+// the data key is itself encrypted ("wrapped") under the master key `key_id`, so
+// rotating master keys only rewraps the data key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EncryptedSecret {
+  pub key_id: String,
+  pub wrapped_key: String, // base64 of nonce, ciphertext and tag
+  pub ciphertext: String,  // base64 of nonce, ciphertext and tag
+}
+
+// secrets stored before encryption was added are plain strings until rotated
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StoredSecret {
+  Encrypted(EncryptedSecret),
+  Plaintext(String),
+}
+
+// a master key from the environment, the key being 32 base64 encoded bytes
+#[derive(Deserialize, Clone)]
+pub struct MasterKey {
+  pub id: String,
+  pub key: String,
+}
+
+#[derive(Clone)]
+pub struct Keyring {
+  current: String,
+  keys: HashMap<String, Vec<u8>>,
+}
+
+impl Keyring {
+  // secrets are encrypted under `current`, the other keys only decrypt
+  pub fn new(keys: &Vec<MasterKey>, current: &String) -> Result<Keyring, ApiError> {
+    let keys = keys
+      .iter()
+      .map(|master| match base64::decode(&master.key) {
+        Ok(key) if key.len() == KEY_LEN => Ok((master.id.clone(), key)),
+        _ => Err(ApiError::new(
+          500,
+          format!("Master key {} is not 32 base64 encoded bytes", master.id),
+        )),
+      })
+      .collect::<Result<HashMap<String, Vec<u8>>, ApiError>>()?;
+
+    if !keys.contains_key(current) {
+      return Err(ApiError::new(
+        500,
+        format!("No master key with id {}", current),
+      ));
+    }
+
+    Ok(Keyring {
+      current: current.clone(),
+      keys,
+    })
+  }
+
+  pub fn encrypt(&self, plaintext: &str) -> Result<StoredSecret, ApiError> {
+    let mut data_key = [0u8; KEY_LEN];
+    SystemRandom::new()
+      .fill(&mut data_key)
+      .map_err(|_| encryption_error())?;
+
+    Ok(StoredSecret::Encrypted(EncryptedSecret {
+      key_id: self.current.clone(),
+      wrapped_key: self.wrap(&data_key)?,
+      ciphertext: seal(&data_key, Aad::empty(), plaintext.as_bytes())?,
+    }))
+  }
+
+  pub fn decrypt(&self, secret: &StoredSecret) -> Result<String, ApiError> {
+    match secret {
+      StoredSecret::Plaintext(plaintext) => Ok(plaintext.clone()),
+      StoredSecret::Encrypted(secret) => {
+        let data_key = self.unwrap(secret)?;
+        let plaintext = open(&data_key, Aad::empty(), &secret.ciphertext)?;
+        String::from_utf8(plaintext).map_err(|_| encryption_error())
+      }
+    }
+  }
+
+  // The secret under the current master key, or `None` when it already is.
+  // Plaintext secrets are encrypted.
+  pub fn rotate(&self, secret: &StoredSecret) -> Result<Option<StoredSecret>, ApiError> {
+    match secret {
+      StoredSecret::Plaintext(plaintext) => self.encrypt(plaintext).map(Some),
+      StoredSecret::Encrypted(encrypted) if encrypted.key_id == self.current => Ok(None),
+      StoredSecret::Encrypted(encrypted) => {
+        let data_key = self.unwrap(encrypted)?;
+
+        Ok(Some(StoredSecret::Encrypted(EncryptedSecret {
+          key_id: self.current.clone(),
+          wrapped_key: self.wrap(&data_key)?,
+          ciphertext: encrypted.ciphertext.clone(),
+        })))
+      }
+    }
+  }
+
+  // the key id is bound to the wrapped key, so it can't be moved under another key
+  fn wrap(&self, data_key: &[u8]) -> Result<String, ApiError> {
+    seal(
+      &self.keys[&self.current],
+      Aad::from(self.current.as_bytes()),
+      data_key,
+    )
+  }
+
+  fn unwrap(&self, secret: &EncryptedSecret) -> Result<Vec<u8>, ApiError> {
+    let master = self.keys.get(&secret.key_id).ok_or(ApiError::new(
+      500,
+      format!("No master key with id {}", secret.key_id),
+    ))?;
+
+    open(
+      master,
+      Aad::from(secret.key_id.as_bytes()),
+      &secret.wrapped_key,
+    )
+  }
+}
+
+fn encryption_error() -> ApiError {
+  ApiError::new(500, "Encryption Error".to_string())
+}
+
+fn aes_key(key: &[u8]) -> Result<LessSafeKey, ApiError> {
+  UnboundKey::new(&AES_256_GCM, key)
+    .map(LessSafeKey::new)
+    .map_err(|_| encryption_error())
+}
+
+// every seal gets a random nonce, which is kept in front of the ciphertext
+fn seal<A: AsRef<[u8]>>(key: &[u8], aad: Aad<A>, plaintext: &[u8]) -> Result<String, ApiError> {
+  let mut nonce = [0u8; NONCE_LEN];
+  SystemRandom::new()
+    .fill(&mut nonce)
+    .map_err(|_| encryption_error())?;
+
+  let mut in_out = plaintext.to_vec();
+  aes_key(key)?
+    .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), aad, &mut in_out)
+    .map_err(|_| encryption_error())?;
+
+  let mut sealed = nonce.to_vec();
+  sealed.append(&mut in_out);
+  Ok(base64::encode(sealed))
+}
+
+fn open<A: AsRef<[u8]>>(key: &[u8], aad: Aad<A>, sealed: &String) -> Result<Vec<u8>, ApiError> {
+  let sealed = base64::decode(sealed).map_err(|_| encryption_error())?;
+  if sealed.len() < NONCE_LEN {
+    return Err(encryption_error());
+  }
+
+  let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| encryption_error())?;
+
+  let mut in_out = ciphertext.to_vec();
+  let plaintext = aes_key(key)?
+    .open_in_place(nonce, aad, &mut in_out)
+    .map_err(|_| encryption_error())?;
+
+  Ok(plaintext.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn master_key(id: &str, byte: u8) -> MasterKey {
+    MasterKey {
+      id: id.to_string(),
+      key: base64::encode([byte; KEY_LEN]),
+    }
+  }
+
+  #[test]
+  fn test_encrypt_decrypt() {
+    let keyring = Keyring::new(&vec![master_key("a", 1)], &"a".to_string()).unwrap();
+
+    let secret = keyring.encrypt("access-sandbox-de3ce8ef").unwrap();
+    match &secret {
+      StoredSecret::Encrypted(encrypted) => {
+        assert_eq!("a", encrypted.key_id);
+        assert!(!encrypted.ciphertext.contains("access-sandbox"));
+      }
+      StoredSecret::Plaintext(_) => panic!("secret was not encrypted"),
+    }
+    assert_eq!(
+      "access-sandbox-de3ce8ef".to_string(),
+      keyring.decrypt(&secret).unwrap()
+    );
+
+    let plaintext = StoredSecret::Plaintext("access-sandbox-de3ce8ef".to_string());
+    assert_eq!(
+      "access-sandbox-de3ce8ef".to_string(),
+      keyring.decrypt(&plaintext).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_rotate() {
+    let old = Keyring::new(&vec![master_key("a", 1)], &"a".to_string()).unwrap();
+    let new = Keyring::new(
+      &vec![master_key("a", 1), master_key("b", 2)],
+      &"b".to_string(),
+    )
+    .unwrap();
+    let without_old = Keyring::new(&vec![master_key("b", 2)], &"b".to_string()).unwrap();
+
+    let secret = old.encrypt("access-sandbox-de3ce8ef").unwrap();
+    assert_eq!(None, old.rotate(&secret).unwrap());
+
+    let rotated = new.rotate(&secret).unwrap().unwrap();
+    assert_eq!(
+      "access-sandbox-de3ce8ef".to_string(),
+      without_old.decrypt(&rotated).unwrap()
+    );
+    assert!(without_old.decrypt(&secret).is_err());
+
+    let plaintext = StoredSecret::Plaintext("access-sandbox-de3ce8ef".to_string());
+    let encrypted = new.rotate(&plaintext).unwrap().unwrap();
+    assert_ne!(plaintext, encrypted);
+    assert_eq!(
+      "access-sandbox-de3ce8ef".to_string(),
+      new.decrypt(&encrypted).unwrap()
+    );
+  }
+
+  #[test]
+  fn test_tampered_secret() {
+    let keyring = Keyring::new(
+      &vec![master_key("a", 1), master_key("b", 2)],
+      &"a".to_string(),
+    )
+    .unwrap();
+
+    let secret = match keyring.encrypt("access-sandbox-de3ce8ef").unwrap() {
+      StoredSecret::Encrypted(secret) => secret,
+      StoredSecret::Plaintext(_) => panic!("secret was not encrypted"),
+    };
+
+    // the wrapped key is bound to its master key's id
+    let moved = StoredSecret::Encrypted(EncryptedSecret {
+      key_id: "b".to_string(),
+      ..secret.clone()
+    });
+    assert!(keyring.decrypt(&moved).is_err());
+
+    let mut bytes = base64::decode(&secret.ciphertext).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    let tampered = StoredSecret::Encrypted(EncryptedSecret {
+      ciphertext: base64::encode(bytes),
+      ..secret
+    });
+    assert!(keyring.decrypt(&tampered).is_err());
+  }
+
+  #[test]
+  fn test_bad_master_key() {
+    let short = MasterKey {
+      id: "a".to_string(),
+      key: base64::encode([1u8; 16]),
+    };
+    assert!(Keyring::new(&vec![short], &"a".to_string()).is_err());
+    assert!(Keyring::new(&vec![master_key("a", 1)], &"b".to_string()).is_err());
+  }
+}
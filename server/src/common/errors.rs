@@ -2,6 +2,9 @@
 pub struct ApiError {
   code: u16,
   message: String,
+  // Plaid's ERROR_CODE, for errors Plaid explained
+  #[serde(skip_serializing_if = "Option::is_none")]
+  plaid_code: Option<String>,
 }
 
 impl ApiError {
@@ -9,8 +12,29 @@ impl ApiError {
     ApiError {
       code: code,
       message: message,
+      plaid_code: None,
     }
   }
+
+  pub fn plaid(code: u16, plaid_code: String, message: String) -> ApiError {
+    ApiError {
+      code: code,
+      message: message,
+      plaid_code: Some(plaid_code),
+    }
+  }
+
+  pub fn code(&self) -> u16 {
+    self.code
+  }
+
+  pub fn message(&self) -> &String {
+    &self.message
+  }
+
+  pub fn plaid_code(&self) -> Option<&String> {
+    self.plaid_code.as_ref()
+  }
 }
 
 use actix_web::{http::StatusCode, HttpResponse};
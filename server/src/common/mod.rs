@@ -1,3 +1,5 @@
+pub mod compounding;
+pub mod encryption;
 pub mod errors;
 pub mod finchlog;
 mod money;
@@ -1,15 +1,138 @@
+use crate::common::errors::ApiError;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
 use std::ops::{Add, Div, Mul, Sub};
 
+// an ISO 4217 alphabetic code, e.g. USD or EUR
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+  pub const USD: Currency = Currency(*b"USD");
+
+  pub fn new(code: &str) -> Result<Currency, ApiError> {
+    let code = code.trim().to_ascii_uppercase();
+
+    match code.as_bytes() {
+      &[a, b, c] if code.bytes().all(|byte| byte.is_ascii_uppercase()) => Ok(Currency([a, b, c])),
+      _ => Err(ApiError::new(
+        400,
+        format!("{} is not an ISO 4217 currency code", code),
+      )),
+    }
+  }
+
+  pub fn code(&self) -> &str {
+    std::str::from_utf8(&self.0).unwrap_or("???")
+  }
+}
+
+// everything was in US dollars before currencies were tracked
+impl Default for Currency {
+  fn default() -> Self {
+    Currency::USD
+  }
+}
+
+impl fmt::Display for Currency {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.code())
+  }
+}
+
+impl TryFrom<String> for Currency {
+  type Error = String;
+  fn try_from(code: String) -> Result<Self, Self::Error> {
+    Currency::new(&code).map_err(|e| e.message().clone())
+  }
+}
+
+impl From<Currency> for String {
+  fn from(currency: Currency) -> Self {
+    currency.code().to_string()
+  }
+}
+
+// An amount in a currency. Amounts without one are plain numbers, or predate
+// currencies being tracked, and take on the currency of whatever they are
+// combined with. Amounts in two different currencies can't be combined until one
+// is converted, see `services::fx`.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Money {
   pub amount: Decimal,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub currency: Option<Currency>,
 }
 
 impl Money {
   pub fn new<T: Into<Decimal>>(d: T) -> Self {
-    Self { amount: d.into() }
+    Self {
+      amount: d.into(),
+      currency: None,
+    }
+  }
+
+  pub fn in_currency<T: Into<Decimal>>(d: T, currency: Currency) -> Self {
+    Self {
+      amount: d.into(),
+      currency: Some(currency),
+    }
+  }
+
+  // the currency both amounts are in, if they can be combined
+  fn common_currency(&self, other: &Money) -> Result<Option<Currency>, ApiError> {
+    match (self.currency, other.currency) {
+      (Some(a), Some(b)) if a != b => Err(ApiError::new(
+        400,
+        format!("Cannot combine {} with {} without converting", a, b),
+      )),
+      (a, b) => Ok(a.or(b)),
+    }
+  }
+
+  pub fn checked_add<T: Into<Money>>(self, other: T) -> Result<Money, ApiError> {
+    let other = other.into();
+    Ok(Money {
+      amount: self.amount + other.amount,
+      currency: self.common_currency(&other)?,
+    })
+  }
+
+  pub fn checked_sub<T: Into<Money>>(self, other: T) -> Result<Money, ApiError> {
+    let other = other.into();
+    Ok(Money {
+      amount: self.amount - other.amount,
+      currency: self.common_currency(&other)?,
+    })
+  }
+
+  // sums amounts in one currency, failing on the first in another
+  pub fn checked_sum<I: IntoIterator<Item = Money>>(amounts: I) -> Result<Money, ApiError> {
+    amounts
+      .into_iter()
+      .try_fold(Money::default(), |total, amount| total.checked_add(amount))
+  }
+
+  // Mixing currencies is a bug, like overflow, so the operators panic on it.
+  // They are for amounts that can't be in two currencies, like scaling by a
+  // plain number. Amounts from users or Plaid are added with `checked_add` and
+  // `checked_sub`, so a missed conversion is an error rather than a crash.
+  fn combine(self, other: Money, amount: Decimal) -> Money {
+    match self.common_currency(&other) {
+      Ok(currency) => Money { amount, currency },
+      Err(e) => panic!("{}", e.message()),
+    }
+  }
+}
+
+// most tests deal in US dollars
+#[cfg(test)]
+impl Money {
+  pub fn usd<T: Into<Decimal>>(d: T) -> Self {
+    Self::in_currency(d, Currency::USD)
   }
 }
 
@@ -34,27 +157,93 @@ impl<T: Into<Decimal>> From<T> for Money {
 impl<T: Into<Money>> Add<T> for Money {
   type Output = Money;
   fn add(self, other: T) -> Self::Output {
-    Money::new(self.amount + other.into().amount)
+    let other = other.into();
+    self.combine(other, self.amount + other.amount)
   }
 }
 
 impl<T: Into<Money>> Sub<T> for Money {
   type Output = Money;
   fn sub(self, other: T) -> Self::Output {
-    Money::new(self.amount - other.into().amount)
+    let other = other.into();
+    self.combine(other, self.amount - other.amount)
   }
 }
 
 impl<T: Into<Money>> Mul<T> for Money {
   type Output = Money;
   fn mul(self, other: T) -> Self::Output {
-    Money::new(self.amount * other.into().amount)
+    let other = other.into();
+    self.combine(other, self.amount * other.amount)
   }
 }
 
 impl<T: Into<Money>> Div<T> for Money {
   type Output = Money;
   fn div(self, other: T) -> Self::Output {
-    Money::new(self.amount / other.into().amount)
+    let other = other.into();
+    self.combine(other, self.amount / other.amount)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  #[test]
+  fn test_currency_code() {
+    assert_eq!(Currency::USD, Currency::new("usd").unwrap());
+    assert_eq!("EUR", Currency::new("EUR").unwrap().to_string());
+    assert!(Currency::new("EURO").is_err());
+    assert!(Currency::new("E1R").is_err());
+
+    assert_eq!(
+      "{\"amount\":1.5,\"currency\":\"EUR\"}",
+      serde_json::to_string(&Money::in_currency(
+        dec!(1.5),
+        Currency::new("EUR").unwrap()
+      ))
+      .unwrap()
+    );
+    assert_eq!(
+      Money::new(dec!(1.5)),
+      serde_json::from_str::<Money>("{\"amount\":1.5}").unwrap()
+    );
+  }
+
+  #[test]
+  fn test_currency_arithmetic() {
+    let eur = Currency::new("EUR").unwrap();
+
+    assert_eq!(
+      Money::in_currency(dec!(15), eur),
+      Money::in_currency(dec!(10), eur) + dec!(5)
+    );
+    assert_eq!(
+      Money::in_currency(dec!(-5), eur),
+      Money::default() - Money::in_currency(dec!(5), eur)
+    );
+    assert_eq!(
+      Money::in_currency(dec!(20), eur),
+      Money::in_currency(dec!(10), eur) * dec!(2)
+    );
+
+    let usd = Money::in_currency(dec!(10), Currency::USD);
+    assert!(Money::in_currency(dec!(10), eur).checked_add(usd).is_err());
+    assert!(Money::in_currency(dec!(10), eur).checked_sub(usd).is_err());
+
+    assert_eq!(
+      Ok(Money::in_currency(dec!(15), eur)),
+      Money::checked_sum(vec![Money::in_currency(dec!(10), eur), Money::new(dec!(5))])
+    );
+    assert!(Money::checked_sum(vec![Money::in_currency(dec!(10), eur), usd]).is_err());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_mixed_currencies_panic() {
+    let _ = Money::in_currency(dec!(10), Currency::new("EUR").unwrap())
+      + Money::in_currency(dec!(10), Currency::USD);
   }
 }
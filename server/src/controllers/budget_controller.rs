@@ -0,0 +1,123 @@
+use crate::models::{budget_model::*, user_model::User};
+use crate::services::{
+  budgets::BudgetService, fx::FxService, transactions::TransactionService, users::UserService,
+};
+use actix_web::{
+  delete, get, post, put,
+  web::{Data, Path},
+  HttpResponse,
+};
+use actix_web_validator::{Json, Validate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BudgetNewPayload {
+  #[validate(length(min = 1))]
+  pub category: String,
+  #[validate(custom = "crate::common::decimal_at_least_zero")]
+  pub limit: Decimal,
+  pub period: BudgetPeriod,
+  #[serde(default)]
+  pub rollover: bool,
+  // %Y-%m-%d. Left out, new budgets start today and updated ones keep theirs.
+  pub start: Option<String>,
+}
+
+impl Into<Budget> for BudgetNewPayload {
+  fn into(self) -> Budget {
+    Budget {
+      id: None,
+      category: self.category,
+      limit: self.limit,
+      period: self.period,
+      rollover: self.rollover,
+      start: self.start.unwrap_or_default(),
+    }
+  }
+}
+
+#[post("/budget/new")]
+pub async fn new_budget(
+  user: User,
+  user_service: Data<UserService>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+  payload: Json<BudgetNewPayload>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    BudgetService::new_budget(
+      payload.into_inner(),
+      user,
+      user_service,
+      transaction_service,
+      fx,
+    )
+    .await,
+  )
+}
+
+#[get("/budgets")]
+pub async fn get_budgets(
+  user: User,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    BudgetService::get_all_budgets(user, transaction_service, fx).await,
+  )
+}
+
+#[get("/budget/{id}")]
+pub async fn get_budget(
+  user: User,
+  id: Path<String>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    BudgetService::get_budget(id.into_inner(), user, transaction_service, fx).await,
+  )
+}
+
+#[put("/budget/{id}")]
+pub async fn update_budget(
+  user: User,
+  user_service: Data<UserService>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+  id: Path<String>,
+  payload: Json<BudgetNewPayload>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    BudgetService::update_budget(
+      id.into_inner(),
+      payload.into_inner(),
+      user,
+      user_service,
+      transaction_service,
+      fx,
+    )
+    .await,
+  )
+}
+
+#[delete("/budget/{id}")]
+pub async fn delete_budget(
+  user: User,
+  user_service: Data<UserService>,
+  id: Path<String>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    BudgetService::delete_budget(id.into_inner(), user, user_service).await,
+  )
+}
+
+use actix_web::web::ServiceConfig;
+pub fn init_routes(config: &mut ServiceConfig) {
+  config.service(get_budget);
+  config.service(get_budgets);
+  config.service(new_budget);
+  config.service(delete_budget);
+  config.service(update_budget);
+}
@@ -0,0 +1,80 @@
+use crate::common::errors::ApiError;
+use crate::models::user_model::User;
+use crate::services::{
+  exports::{Export, ExportBody, ExportFormat, ExportService},
+  transactions::TransactionService,
+};
+use actix_web::{
+  get,
+  web::{Data, Query},
+  HttpResponse,
+};
+use serde::Deserialize;
+
+// dates are inclusive and formatted %Y-%m-%d. CSV unless another format is asked for.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ExportQuery {
+  pub from: Option<String>,
+  pub to: Option<String>,
+  #[serde(default)]
+  pub format: ExportFormat,
+}
+
+// sent as a download rather than shown in the browser
+fn into_download(export: Result<Export, ApiError>) -> HttpResponse {
+  match export {
+    Ok(export) => {
+      let mut response = HttpResponse::Ok();
+      response.content_type(export.content_type).header(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", export.filename),
+      );
+
+      match export.body {
+        ExportBody::Whole(body) => response.body(body),
+        ExportBody::Rows(rows) => response.streaming(rows),
+      }
+    }
+    Err(error) => error.into(),
+  }
+}
+
+#[get("/export/snapshots")]
+pub async fn export_snapshots(user: User, query: Query<ExportQuery>) -> HttpResponse {
+  into_download(ExportService::export_snapshots(user, query.into_inner()))
+}
+
+#[get("/export/recurrings")]
+pub async fn export_recurrings(user: User, query: Query<ExportQuery>) -> HttpResponse {
+  into_download(ExportService::export_recurrings(user, query.into_inner()))
+}
+
+#[get("/export/goals")]
+pub async fn export_goals(user: User, query: Query<ExportQuery>) -> HttpResponse {
+  into_download(ExportService::export_goals(user, query.into_inner()))
+}
+
+#[get("/export/plans")]
+pub async fn export_plans(user: User, query: Query<ExportQuery>) -> HttpResponse {
+  into_download(ExportService::export_plans(user, query.into_inner()))
+}
+
+#[get("/export/transactions")]
+pub async fn export_transactions(
+  user: User,
+  query: Query<ExportQuery>,
+  transaction_service: Data<TransactionService>,
+) -> HttpResponse {
+  into_download(
+    ExportService::export_transactions(user, query.into_inner(), transaction_service).await,
+  )
+}
+
+use actix_web::web::ServiceConfig;
+pub fn init_routes(config: &mut ServiceConfig) {
+  config.service(export_snapshots);
+  config.service(export_recurrings);
+  config.service(export_goals);
+  config.service(export_plans);
+  config.service(export_transactions);
+}
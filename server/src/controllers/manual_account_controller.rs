@@ -0,0 +1,255 @@
+use crate::common::{errors::ApiError, Currency};
+use crate::models::{
+  manual_account_model::ManualAccountType, plan_model::AssetClass, user_model::User,
+};
+use crate::services::{
+  finchplaid::ApiClient,
+  fx::FxService,
+  imports::{ImportFormat, ImportService},
+  manual_accounts::ManualAccountService,
+  transactions::TransactionService,
+  users::UserService,
+};
+use actix_web::{
+  delete, get, post, put,
+  web::{self, Bytes, Data, Path, PayloadConfig},
+  HttpResponse,
+};
+use actix_web_validator::{Json, Validate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManualAccountNewPayload {
+  #[validate(length(min = 1))]
+  pub name: String,
+  pub account_type: ManualAccountType,
+  pub class: AssetClass,
+  // the user's base currency when left out
+  pub currency: Option<Currency>,
+  // what it is worth, or for a loan what is owed
+  #[validate(custom = "crate::common::decimal_at_least_zero")]
+  pub value: Decimal,
+  // %Y-%m-%d, today when left out
+  pub date: Option<String>,
+}
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManualAccountUpdatePayload {
+  #[validate(length(min = 1))]
+  pub name: Option<String>,
+  pub account_type: Option<ManualAccountType>,
+  pub class: Option<AssetClass>,
+  pub hidden: Option<bool>,
+}
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValuationPayload {
+  #[validate(custom = "crate::common::decimal_at_least_zero")]
+  pub value: Decimal,
+  // %Y-%m-%d, today when left out
+  pub date: Option<String>,
+}
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportPayload {
+  pub format: ImportFormat,
+  // the exported file's text
+  #[validate(length(min = 1))]
+  pub content: String,
+}
+
+// exports run to a few megabytes, well over the 32 KiB JSON bodies are limited to
+pub const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+
+pub fn import_payload_config() -> PayloadConfig {
+  PayloadConfig::new(MAX_IMPORT_BYTES)
+}
+
+fn parse_import_payload(body: &[u8]) -> Result<ImportPayload, ApiError> {
+  let payload: ImportPayload = serde_json::from_slice(body)
+    .map_err(|e| ApiError::new(400, format!("Could not read the import: {}", e)))?;
+
+  payload
+    .validate()
+    .map_err(|e| ApiError::new(400, format!("{}", e)))?;
+
+  Ok(payload)
+}
+
+#[post("/manual/account/new")]
+pub async fn new_manual_account(
+  user: User,
+  payload: Json<ManualAccountNewPayload>,
+  user_service: Data<UserService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    ManualAccountService::new_account(payload.into_inner(), user, user_service, plaid_client, fx)
+      .await,
+  )
+}
+
+#[get("/manual/accounts")]
+pub async fn get_manual_accounts(user: User) -> HttpResponse {
+  crate::common::into_response(ManualAccountService::get_accounts(user))
+}
+
+#[get("/manual/account/{id}")]
+pub async fn get_manual_account(user: User, id: Path<String>) -> HttpResponse {
+  crate::common::into_response_res(ManualAccountService::get_account(id.into_inner(), user))
+}
+
+#[put("/manual/account/{id}")]
+pub async fn update_manual_account(
+  user: User,
+  id: Path<String>,
+  payload: Json<ManualAccountUpdatePayload>,
+  user_service: Data<UserService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    ManualAccountService::update_account(
+      id.into_inner(),
+      payload.into_inner(),
+      user,
+      user_service,
+      plaid_client,
+      fx,
+    )
+    .await,
+  )
+}
+
+#[post("/manual/account/{id}/valuation")]
+pub async fn add_manual_account_valuation(
+  user: User,
+  id: Path<String>,
+  payload: Json<ValuationPayload>,
+  user_service: Data<UserService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    ManualAccountService::add_valuation(
+      id.into_inner(),
+      payload.into_inner(),
+      user,
+      user_service,
+      plaid_client,
+      fx,
+    )
+    .await,
+  )
+}
+
+// an `ImportPayload` as JSON, read under `import_payload_config` rather than the JSON limit
+pub async fn import_manual_account_statement(
+  user: User,
+  id: Path<String>,
+  body: Bytes,
+  user_service: Data<UserService>,
+  transaction_service: Data<TransactionService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  let payload = match parse_import_payload(&body) {
+    Ok(payload) => payload,
+    Err(e) => return e.into(),
+  };
+
+  crate::common::into_response_res(
+    ImportService::import(
+      id.into_inner(),
+      payload,
+      user,
+      user_service,
+      transaction_service,
+      plaid_client,
+      fx,
+    )
+    .await,
+  )
+}
+
+#[delete("/manual/account/{id}")]
+pub async fn delete_manual_account(
+  user: User,
+  id: Path<String>,
+  user_service: Data<UserService>,
+  transaction_service: Data<TransactionService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    ManualAccountService::delete_account(
+      id.into_inner(),
+      user,
+      user_service,
+      transaction_service,
+      plaid_client,
+      fx,
+    )
+    .await,
+  )
+}
+
+use actix_web::web::ServiceConfig;
+pub fn init_routes(config: &mut ServiceConfig) {
+  config.service(get_manual_accounts);
+  config.service(new_manual_account);
+  config.service(get_manual_account);
+  config.service(update_manual_account);
+  config.service(add_manual_account_valuation);
+  config.service(
+    web::resource("/manual/account/{id}/import")
+      .app_data(import_payload_config())
+      .route(web::post().to(import_manual_account_statement)),
+  );
+  config.service(delete_manual_account);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use actix_web::{test::TestRequest, FromRequest};
+
+  #[actix_rt::test]
+  async fn test_import_over_json_limit() {
+    let body = serde_json::to_vec(&ImportPayload {
+      format: ImportFormat::Ofx,
+      content: "<OFX>".repeat(10 * 1024),
+    })
+    .unwrap();
+    assert!(body.len() > 32 * 1024);
+
+    // too big for the default JSON limit
+    let (req, mut payload) = TestRequest::default()
+      .header("content-type", "application/json")
+      .set_payload(body.clone())
+      .to_http_parts();
+    assert!(web::Json::<ImportPayload>::from_request(&req, &mut payload)
+      .await
+      .is_err());
+
+    // but read whole under the import's
+    let (req, mut payload) = TestRequest::default()
+      .app_data(import_payload_config())
+      .set_payload(body)
+      .to_http_parts();
+    let body = Bytes::from_request(&req, &mut payload).await.unwrap();
+    let read = parse_import_payload(&body).unwrap();
+    assert_eq!(ImportFormat::Ofx, read.format);
+    assert_eq!(50 * 1024, read.content.len());
+
+    // and still validated
+    let empty = serde_json::to_vec(&ImportPayload {
+      format: ImportFormat::Ofx,
+      content: "".to_string(),
+    })
+    .unwrap();
+    assert_eq!(400, parse_import_payload(&empty).unwrap_err().code());
+  }
+}
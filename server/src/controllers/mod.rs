@@ -1,12 +1,17 @@
 pub mod asset_controller;
+pub mod budget_controller;
+pub mod export_controller;
 pub mod goal_controller;
 pub mod insights_controller;
 pub mod leaderboards_controller;
+pub mod manual_account_controller;
 pub mod plaid_controller;
 pub mod plans_controller;
 pub mod recurring_controller;
 pub mod snapshot_controller;
+pub mod spending_controller;
 pub mod timeseries_controller;
+pub mod transactions_controller;
 pub mod user_controller;
 
 use actix_web::web::ServiceConfig;
@@ -21,4 +26,9 @@ pub fn configure(config: &mut ServiceConfig) {
   plans_controller::init_routes(config);
   asset_controller::init_routes(config);
   leaderboards_controller::init_routes(config);
+  transactions_controller::init_routes(config);
+  spending_controller::init_routes(config);
+  budget_controller::init_routes(config);
+  manual_account_controller::init_routes(config);
+  export_controller::init_routes(config);
 }
@@ -1,15 +1,21 @@
-use crate::common::errors::ApiError;
-use crate::models::user_model::User;
+use crate::common::{errors::ApiError, Currency};
+use crate::models::{
+  plan_model::AssetClass,
+  user_model::{ItemStatus, User},
+};
 use crate::services::financial_products::FinProductService;
 use crate::services::finchplaid;
+use crate::services::fx::FxService;
+use crate::services::transactions::TransactionService;
 use crate::services::users::UserService;
+use crate::services::webhooks::WebhookService;
 use actix_web::{
   delete, get, post, put,
-  web::{Data, Path},
-  HttpResponse,
+  web::{Bytes, Data, Path},
+  HttpRequest, HttpResponse,
 };
 use actix_web_validator::{Json, Validate};
-use finchplaid::ApiClient;
+use finchplaid::{ApiClient, LiabilityDetails};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
@@ -30,13 +36,33 @@ pub struct AccountResponse {
   pub account_errors: Vec<AccountError>,
 }
 
+// the unhidden accounts split into what is held and what is owed, with the
+// totals in the user's base currency
+#[derive(Serialize)]
+pub struct BalanceSheetResponse {
+  pub currency: Currency,
+  pub assets: Decimal,
+  pub liabilities: Decimal,
+  pub net_worth: Decimal,
+  pub accounts: Vec<AccountSuccess>,
+  // for the credit cards and loans whose institutions support Plaid's Liabilities product
+  pub liability_details: Vec<LiabilityDetails>,
+  #[serde(rename = "errors")]
+  pub account_errors: Vec<AccountError>,
+}
+
 #[derive(Serialize)]
 pub struct AccountSuccess {
   pub item_id: String,
   pub name: String,
   pub balance: Decimal,
+  // what the balance is in, which may not be the user's base currency
+  pub currency: Currency,
   pub account_type: String,
   pub account_id: String,
+  // only manual accounts say what they hold
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub asset_class: Option<AssetClass>,
 }
 
 #[derive(Serialize)]
@@ -44,6 +70,15 @@ pub struct AccountError {
   pub item_id: String,
   pub code: u16,
   pub message: String,
+  pub status: ItemStatus,
+}
+
+#[derive(Serialize)]
+pub struct ItemStatusResponse {
+  pub item_id: String,
+  pub status: ItemStatus,
+  pub last_successful_sync: Option<i64>,
+  pub consent_expiration_time: Option<String>,
 }
 
 #[derive(Deserialize, Validate)]
@@ -62,6 +97,43 @@ async fn link_token(plaid_client: Data<ApiClient>, user: User) -> HttpResponse {
   )
 }
 
+// for repairing an item whose login is required, through Link in update mode
+#[post("/plaid/link_token/{item_id}")]
+async fn update_link_token(
+  Path(item_id): Path<String>,
+  plaid_client: Data<ApiClient>,
+  user: User,
+) -> HttpResponse {
+  let item = match user.accounts.iter().find(|item| item.item_id == item_id) {
+    Some(item) => item,
+    None => {
+      return ApiError::new(400, format!("No item with id {} found", item_id)).into();
+    }
+  };
+
+  crate::common::into_response_res(
+    plaid_client
+      .create_update_link_token(user.id.clone().unwrap().to_hex(), &item.access_token)
+      .await,
+  )
+}
+
+#[get("/plaid/items")]
+async fn get_items(user: User) -> HttpResponse {
+  crate::common::into_response(
+    user
+      .accounts
+      .iter()
+      .map(|item| ItemStatusResponse {
+        item_id: item.item_id.clone(),
+        status: item.status.clone(),
+        last_successful_sync: item.last_successful_sync,
+        consent_expiration_time: item.consent_expiration_time.clone(),
+      })
+      .collect::<Vec<ItemStatusResponse>>(),
+  )
+}
+
 #[post("/plaid/public_token_exchange")]
 async fn access_token(
   plaid_client: Data<ApiClient>,
@@ -69,6 +141,7 @@ async fn access_token(
   user: User,
   user_service: Data<UserService>,
   fin_product_service: Data<FinProductService>,
+  fx: Data<FxService>,
 ) -> HttpResponse {
   let res: Result<ItemIdResponse, ApiError> = finchplaid::exchange_public_token_for_access_token(
     payload.into_inner().public_token,
@@ -76,6 +149,7 @@ async fn access_token(
     user,
     user_service,
     fin_product_service,
+    fx,
   )
   .await;
 
@@ -111,16 +185,38 @@ pub async fn get_accounts(
   )
 }
 
+#[get("/plaid/balance_sheet")]
+pub async fn get_balance_sheet(
+  user: User,
+  user_service: Data<UserService>,
+  plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    user_service
+      .get_balance_sheet(&user, plaid_client, &fx)
+      .await,
+  )
+}
+
 #[delete("/plaid/accounts/{id}")]
 pub async fn delete_account(
   Path(accounts_id): Path<String>,
   user: User,
   user_service: Data<UserService>,
   plaid_client: Data<ApiClient>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
 ) -> HttpResponse {
   crate::common::into_response_res(
     user_service
-      .delete_item_and_save(accounts_id.clone(), user, plaid_client)
+      .delete_item_and_save(
+        accounts_id.clone(),
+        user,
+        plaid_client,
+        transaction_service,
+        &fx,
+      )
       .await
       .map(|item_id| ItemIdResponse { item_id }),
   )
@@ -132,6 +228,7 @@ pub async fn put_hide_unhide_account(
   user_service: Data<UserService>,
   payload: Json<SetAccountAsHiddenPayload>,
   plaid_client: Data<ApiClient>,
+  fx: Data<FxService>,
 ) -> HttpResponse {
   let SetAccountAsHiddenPayload {
     item_id,
@@ -140,16 +237,58 @@ pub async fn put_hide_unhide_account(
   } = payload.into_inner();
 
   let hiding_res = user_service
-    .hide_unhide_account(&mut user, item_id, account_id, hide_or_not, plaid_client)
+    .hide_unhide_account(
+      &mut user,
+      item_id,
+      account_id,
+      hide_or_not,
+      plaid_client,
+      &fx,
+    )
     .await;
 
   crate::common::into_response_res(hiding_res)
 }
 
+// Called by Plaid rather than a user, so it is authenticated by the signed
+// `Plaid-Verification` header instead of a session.
+#[post("/plaid/webhook")]
+pub async fn webhook(
+  req: HttpRequest,
+  body: Bytes,
+  plaid_client: Data<ApiClient>,
+  webhook_service: Data<WebhookService>,
+  transaction_service: Data<TransactionService>,
+) -> HttpResponse {
+  let verification = match req
+    .headers()
+    .get("Plaid-Verification")
+    .and_then(|header| header.to_str().ok())
+  {
+    Some(verification) => verification,
+    None => {
+      return ApiError::new(401, "Missing Plaid-Verification header".to_string()).into();
+    }
+  };
+
+  crate::common::into_response_res(
+    webhook_service
+      .receive(verification, &body, plaid_client, transaction_service)
+      .await
+      .map(|webhook| ItemIdResponse {
+        item_id: webhook.item_id,
+      }),
+  )
+}
+
 pub fn init_routes(config: &mut actix_web::web::ServiceConfig) {
   config.service(link_token);
+  config.service(update_link_token);
+  config.service(get_items);
   config.service(access_token);
   config.service(get_accounts);
+  config.service(get_balance_sheet);
   config.service(delete_account);
   config.service(put_hide_unhide_account);
+  config.service(webhook);
 }
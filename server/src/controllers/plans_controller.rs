@@ -1,8 +1,9 @@
+use crate::common::compounding::CompoundingConvention;
 use crate::models::plan_model::*;
 use crate::models::recurring_model::Recurring;
 use crate::models::user_model::User;
 use crate::services::finchplaid::ApiClient;
-use crate::services::{plans::PlansService, users::UserService};
+use crate::services::{fx::FxService, plans::PlansService, users::UserService};
 use actix_web::{
     delete, get, post, put,
     web::{Data, Path, ServiceConfig},
@@ -20,6 +21,10 @@ pub struct PlanNewPayload {
     pub allocations: Vec<Allocation>,
     #[validate]
     pub events: Vec<Event>,
+    #[validate]
+    pub advisory_fee: Option<AdvisoryFee>,
+    #[serde(default)]
+    pub compounding: CompoundingConvention,
 }
 
 #[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -31,6 +36,12 @@ pub struct PlanUpdatePayload {
     pub allocations: Option<Vec<Allocation>>,
     #[validate]
     pub events: Option<Vec<Event>>,
+    #[validate]
+    pub advisory_fee: Option<AdvisoryFee>,
+    // removes the plan's advisory fee, can't be sent with a new one
+    #[serde(default)]
+    pub clear_advisory_fee: bool,
+    pub compounding: Option<CompoundingConvention>,
 }
 
 impl Into<Plan> for PlanNewPayload {
@@ -41,6 +52,8 @@ impl Into<Plan> for PlanNewPayload {
             recurrings: self.recurrings,
             allocations: self.allocations,
             events: self.events,
+            advisory_fee: self.advisory_fee,
+            compounding: self.compounding,
         };
 
         p.ensure_ids()
@@ -62,9 +75,10 @@ pub async fn get_allocations_from_plaid(
     user: User,
     user_service: Data<UserService>,
     plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::update_plaid_allocation(user, 365, user_service, plaid_client).await,
+        PlansService::update_plaid_allocation(user, 365, user_service, plaid_client, fx).await,
     )
 }
 
@@ -74,33 +88,22 @@ pub async fn get_allocations_from_plaid_with_days(
     user: User,
     user_service: Data<UserService>,
     plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::update_plaid_allocation(user, plan_days, user_service, plaid_client).await,
+        PlansService::update_plaid_allocation(user, plan_days, user_service, plaid_client, fx)
+            .await,
     )
 }
 
 #[get("/plan")]
-pub async fn get_plan(
-    user: User,
-    user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
-) -> HttpResponse {
-    crate::common::into_response_res(
-        PlansService::get_plan(user, 365, user_service, plaid_client).await,
-    )
+pub async fn get_plan(user: User) -> HttpResponse {
+    crate::common::into_response_res(PlansService::get_plan(user, 365).await)
 }
 
 #[get("/plan/{days}")]
-pub async fn get_plan_with_days(
-    user: User,
-    Path(plan_days): Path<i64>,
-    user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
-) -> HttpResponse {
-    crate::common::into_response_res(
-        PlansService::get_plan(user, plan_days, user_service, plaid_client).await,
-    )
+pub async fn get_plan_with_days(user: User, Path(plan_days): Path<i64>) -> HttpResponse {
+    crate::common::into_response_res(PlansService::get_plan(user, plan_days).await)
 }
 
 #[delete("/plan")]
@@ -113,10 +116,9 @@ pub async fn create_new_plan(
     user: User,
     payload: Json<PlanNewPayload>,
     user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::new_plan(payload.into_inner(), user, 365, user_service, plaid_client).await,
+        PlansService::new_plan(payload.into_inner(), user, 365, user_service).await,
     )
 }
 
@@ -126,17 +128,9 @@ pub async fn create_new_plan_with_days(
     user: User,
     payload: Json<PlanNewPayload>,
     user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::new_plan(
-            payload.into_inner(),
-            user,
-            plan_days,
-            user_service,
-            plaid_client,
-        )
-        .await,
+        PlansService::new_plan(payload.into_inner(), user, plan_days, user_service).await,
     )
 }
 
@@ -145,11 +139,9 @@ pub async fn update_plan(
     user: User,
     payload: Json<PlanUpdatePayload>,
     user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::update_plan(payload.into_inner(), user, 365, user_service, plaid_client)
-            .await,
+        PlansService::update_plan(payload.into_inner(), user, 365, user_service).await,
     )
 }
 
@@ -159,17 +151,9 @@ pub async fn update_plan_with_days(
     user: User,
     payload: Json<PlanUpdatePayload>,
     user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
 ) -> HttpResponse {
     crate::common::into_response_res(
-        PlansService::update_plan(
-            payload.into_inner(),
-            user,
-            plan_days,
-            user_service,
-            plaid_client,
-        )
-        .await,
+        PlansService::update_plan(payload.into_inner(), user, plan_days, user_service).await,
     )
 }
 
@@ -197,8 +181,22 @@ mod tests {
             recurrings: None,
             allocations: Some(vec![]),
             events: None,
+            advisory_fee: None,
+            clear_advisory_fee: false,
+            compounding: None,
         };
 
         assert!(p.validate().is_err());
     }
+
+    #[test]
+    fn test_clear_advisory_fee_defaults_to_keeping_it() {
+        let keep: PlanUpdatePayload = serde_json::from_str(r#"{"name": "retire"}"#).unwrap();
+        assert!(!keep.clear_advisory_fee);
+
+        let clear: PlanUpdatePayload =
+            serde_json::from_str(r#"{"clear_advisory_fee": true}"#).unwrap();
+        assert!(clear.clear_advisory_fee);
+        assert_eq!(None, clear.advisory_fee);
+    }
 }
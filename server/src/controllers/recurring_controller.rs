@@ -1,5 +1,7 @@
 use crate::models::{recurring_model::*, user_model::User};
-use crate::services::{recurrings::RecurringService, users::UserService};
+use crate::services::{
+  fx::FxService, recurrings::RecurringService, transactions::TransactionService, users::UserService,
+};
 use actix_web::{
   delete, get, post, put,
   web::{Data, Path},
@@ -109,6 +111,32 @@ pub async fn delete_recurring(
   )
 }
 
+// "suggestions" can never be an ID either
+#[get("/recurring/suggestions")]
+pub async fn get_recurring_suggestions(
+  user: User,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    RecurringService::get_suggestions(&user, transaction_service, fx).await,
+  )
+}
+
+#[post("/recurring/suggestions/{id}/accept")]
+pub async fn accept_recurring_suggestion(
+  Path(suggestion_id): Path<String>,
+  user: User,
+  user_service: Data<UserService>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    RecurringService::accept_suggestion(suggestion_id, user, user_service, transaction_service, fx)
+      .await,
+  )
+}
+
 // "examples" can never be an ID, and we will put the service ahead of the others
 // currently, we take a User to make this an authorised route... Should we?
 #[get("/recurring/examples")]
@@ -144,6 +172,8 @@ pub async fn get_recurring_examples(_: User) -> HttpResponse {
 use actix_web::web::ServiceConfig;
 pub fn init_routes(config: &mut ServiceConfig) {
   config.service(get_recurring_examples);
+  config.service(get_recurring_suggestions);
+  config.service(accept_recurring_suggestion);
   config.service(new_recurring);
   config.service(delete_recurring);
   config.service(update_recurring);
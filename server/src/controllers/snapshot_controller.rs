@@ -1,18 +1,134 @@
+use crate::common::Money;
 use crate::models::user_model::User;
-use crate::services::{finchplaid::ApiClient, users::UserService};
-use actix_web::{get, web::Data, HttpResponse};
+use crate::services::{
+  snapshots::{
+    periods::{SnapshotBucket, SnapshotMetric},
+    SnapshotService,
+  },
+  users::UserService,
+};
+use actix_web::{
+  get, post,
+  web::{Data, Path, Query},
+  HttpResponse,
+};
+use serde::{Deserialize, Serialize};
 
+// dates are inclusive and formatted %Y-%m-%d. Only /snapshots sums into
+// buckets, and a metric narrows those sums to one.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SnapshotQuery {
+  pub from: Option<String>,
+  pub to: Option<String>,
+  pub bucket: Option<SnapshotBucket>,
+  pub metric: Option<SnapshotMetric>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SnapshotAccount {
+  pub item_id: String,
+  pub account_id: String,
+  pub name: Option<String>,
+  pub first_snapshot_time: i64,
+  pub last_snapshot_time: i64,
+  pub balance: Money,
+  pub hidden: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccountHistoryEntry {
+  pub snapshot_time: i64,
+  pub balance: Money,
+  pub money_in: Money,
+  pub money_out: Money,
+  pub running_income: Money,
+  pub running_spending: Money,
+  pub hidden: bool,
+}
+
+// snapshots are taken by the snapshot service, so this is read-only
 #[get("/snapshots")]
-pub async fn get_snapshots(
-  mut user: User,
+pub async fn get_snapshots(user: User, query: Query<SnapshotQuery>) -> HttpResponse {
+  let query = query.into_inner();
+  match query.bucket {
+    Some(_) => {
+      crate::common::into_response_res(SnapshotService::get_snapshot_periods(&user, query))
+    }
+    None => crate::common::into_response_res(SnapshotService::get_snapshots(user, query)),
+  }
+}
+
+#[get("/snapshots/attribution")]
+pub async fn get_net_worth_attribution(user: User, query: Query<SnapshotQuery>) -> HttpResponse {
+  crate::common::into_response_res(SnapshotService::get_net_worth_attribution(
+    &user,
+    query.into_inner(),
+  ))
+}
+
+#[get("/snapshots/performance")]
+pub async fn get_investment_performance(user: User, query: Query<SnapshotQuery>) -> HttpResponse {
+  crate::common::into_response_res(SnapshotService::get_investment_performance(
+    &user,
+    query.into_inner(),
+  ))
+}
+
+#[get("/snapshots/accounts")]
+pub async fn get_snapshot_accounts(user: User) -> HttpResponse {
+  crate::common::into_response(SnapshotService::get_snapshot_accounts(&user))
+}
+
+#[get("/snapshots/accounts/{account_id}")]
+pub async fn get_account_history(
+  Path(account_id): Path<String>,
+  user: User,
+  query: Query<SnapshotQuery>,
+) -> HttpResponse {
+  crate::common::into_response_res(SnapshotService::get_account_history(
+    &user,
+    account_id,
+    query.into_inner(),
+  ))
+}
+
+// snapshots held back because their balances look wrong
+#[get("/snapshots/quarantined")]
+pub async fn get_quarantined_snapshots(user: User) -> HttpResponse {
+  crate::common::into_response(SnapshotService::get_quarantined_snapshots(user))
+}
+
+#[post("/snapshots/{snapshot_time}/accept")]
+pub async fn accept_snapshot(
+  Path(snapshot_time): Path<i64>,
+  user: User,
+  user_service: Data<UserService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    SnapshotService::accept_snapshot(snapshot_time, user, user_service).await,
+  )
+}
+
+#[post("/snapshots/{snapshot_time}/reject")]
+pub async fn reject_snapshot(
+  Path(snapshot_time): Path<i64>,
+  user: User,
   user_service: Data<UserService>,
-  plaid_client: Data<ApiClient>,
 ) -> HttpResponse {
-  crate::common::into_response_res(user_service.get_snapshots(&mut user, plaid_client).await)
+  crate::common::into_response_res(
+    SnapshotService::reject_snapshot(snapshot_time, user, user_service).await,
+  )
 }
 
 // you add the services here.
 use actix_web::web::ServiceConfig;
 pub fn init_routes(config: &mut ServiceConfig) {
   config.service(get_snapshots);
+  config.service(get_net_worth_attribution);
+  config.service(get_investment_performance);
+  config.service(get_snapshot_accounts);
+  config.service(get_account_history);
+  config.service(get_quarantined_snapshots);
+  config.service(accept_snapshot);
+  config.service(reject_snapshot);
 }
@@ -0,0 +1,84 @@
+use crate::models::{spending_model::CategoryRule, user_model::User};
+use crate::services::{
+  fx::FxService, spending::SpendingService, transactions::TransactionService, users::UserService,
+};
+use actix_web::{
+  delete, get, post,
+  web::{Data, Path, Query},
+  HttpResponse,
+};
+use actix_web_validator::{Json, Validate};
+use serde::{Deserialize, Serialize};
+
+// dates are inclusive and formatted %Y-%m-%d
+#[derive(Deserialize, Clone, Debug)]
+pub struct SpendingQuery {
+  pub from: Option<String>,
+  pub to: Option<String>,
+}
+
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryRuleNewPayload {
+  #[validate(length(min = 1))]
+  pub merchant_contains: String,
+  #[validate(length(min = 1))]
+  pub category: String,
+}
+
+impl Into<CategoryRule> for CategoryRuleNewPayload {
+  fn into(self) -> CategoryRule {
+    CategoryRule {
+      id: None,
+      merchant_contains: self.merchant_contains,
+      category: self.category,
+    }
+  }
+}
+
+#[get("/spending/categories")]
+pub async fn get_category_breakdown(
+  user: User,
+  query: Query<SpendingQuery>,
+  transaction_service: Data<TransactionService>,
+  fx: Data<FxService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    SpendingService::get_category_breakdown(query.into_inner(), user, transaction_service, fx)
+      .await,
+  )
+}
+
+#[get("/spending/rules")]
+pub async fn get_rules(user: User) -> HttpResponse {
+  crate::common::into_response(user.category_rules)
+}
+
+#[post("/spending/rule/new")]
+pub async fn new_rule(
+  user: User,
+  user_service: Data<UserService>,
+  payload: Json<CategoryRuleNewPayload>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    SpendingService::new_rule(payload.into_inner(), user, user_service).await,
+  )
+}
+
+#[delete("/spending/rule/{id}")]
+pub async fn delete_rule(
+  user: User,
+  user_service: Data<UserService>,
+  id: Path<String>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    SpendingService::delete_rule(id.into_inner(), user, user_service).await,
+  )
+}
+
+use actix_web::web::ServiceConfig;
+pub fn init_routes(config: &mut ServiceConfig) {
+  config.service(get_category_breakdown);
+  config.service(get_rules);
+  config.service(new_rule);
+  config.service(delete_rule);
+}
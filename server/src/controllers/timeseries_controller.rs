@@ -1,8 +1,7 @@
 use crate::common::Money;
 use crate::models::user_model::User;
-use crate::services::finchplaid::ApiClient;
-use crate::services::{timeseries::TimeseriesService, users::UserService};
-use actix_web::web::{Data, Path};
+use crate::services::timeseries::TimeseriesService;
+use actix_web::web::Path;
 use actix_web::{get, HttpResponse};
 use serde::{Deserialize, Serialize};
 
@@ -16,6 +15,7 @@ pub struct TimeseriesEntry {
 pub struct TimeseriesResponse {
     pub start: i64,
     pub series: Vec<TimeseriesEntry>,
+    pub total_fees: Money,
 }
 
 #[get("/timeseries/example")]
@@ -24,26 +24,13 @@ pub async fn get_example(_: User) -> HttpResponse {
 }
 
 #[get("/timeseries/{days}")]
-pub async fn get_timeseries(
-    Path(timeseries_days): Path<i64>,
-    user: User,
-    user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
-) -> HttpResponse {
-    crate::common::into_response_res(
-        TimeseriesService::get_timeseries(user, timeseries_days, user_service, plaid_client).await,
-    )
+pub async fn get_timeseries(Path(timeseries_days): Path<i64>, user: User) -> HttpResponse {
+    crate::common::into_response_res(TimeseriesService::get_timeseries(user, timeseries_days).await)
 }
 
 #[get("/timeseries")]
-pub async fn get_timeseries_year(
-    user: User,
-    user_service: Data<UserService>,
-    plaid_client: Data<ApiClient>,
-) -> HttpResponse {
-    crate::common::into_response_res(
-        TimeseriesService::get_timeseries(user, 365, user_service, plaid_client).await,
-    )
+pub async fn get_timeseries_year(user: User) -> HttpResponse {
+    crate::common::into_response_res(TimeseriesService::get_timeseries(user, 365).await)
 }
 
 // you add the services here.
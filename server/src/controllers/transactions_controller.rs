@@ -0,0 +1,65 @@
+use crate::models::user_model::User;
+use crate::services::transactions::TransactionService;
+use actix_web::{
+  get, put,
+  web::{Data, Path, Query},
+  HttpResponse,
+};
+use actix_web_validator::{Json, Validate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// dates are inclusive and formatted %Y-%m-%d. Amounts follow Plaid's sign
+// convention, where positive amounts are money leaving the account.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TransactionQuery {
+  pub from: Option<String>,
+  pub to: Option<String>,
+  pub account_id: Option<String>,
+  pub min_amount: Option<Decimal>,
+  pub max_amount: Option<Decimal>,
+}
+
+// a null category clears the override
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryOverridePayload {
+  #[validate(length(min = 1))]
+  pub category: Option<String>,
+}
+
+#[get("/transactions")]
+pub async fn get_transactions(
+  user: User,
+  query: Query<TransactionQuery>,
+  transaction_service: Data<TransactionService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    transaction_service
+      .get_transactions(&user.id.unwrap(), query.into_inner())
+      .await,
+  )
+}
+
+#[put("/transactions/{id}/category")]
+pub async fn put_category_override(
+  Path(transaction_id): Path<String>,
+  user: User,
+  payload: Json<CategoryOverridePayload>,
+  transaction_service: Data<TransactionService>,
+) -> HttpResponse {
+  crate::common::into_response_res(
+    transaction_service
+      .set_category_override(
+        &user.id.unwrap(),
+        transaction_id,
+        payload.into_inner().category,
+      )
+      .await,
+  )
+}
+
+use actix_web::web::ServiceConfig;
+pub fn init_routes(config: &mut ServiceConfig) {
+  config.service(get_transactions);
+  config.service(put_category_override);
+}
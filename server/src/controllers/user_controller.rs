@@ -1,5 +1,6 @@
+use crate::common::Currency;
 use crate::models::user_model::{Location, User};
-use crate::services::{sessions::SessionService, users::UserService};
+use crate::services::{fx::FxService, sessions::SessionService, users::UserService};
 use actix_session::Session;
 use actix_web::{get, post, put, web::Data, HttpResponse};
 use actix_web_validator::{Json, Validate};
@@ -20,6 +21,9 @@ pub struct SignupPayload {
   #[validate(custom = "crate::common::decimal_at_least_zero")]
   pub income: Decimal,
   pub net_worth: Decimal,
+  // US dollars unless given
+  #[serde(default)]
+  pub base_currency: Option<Currency>,
   pub location: Location,
   #[validate(custom = "crate::common::min_age_13yo")]
   pub birthday: String,
@@ -32,6 +36,7 @@ struct SignupResponse {
   pub last_name: String,
   pub income: Decimal,
   pub net_worth: Decimal,
+  pub base_currency: Currency,
   pub location: Location,
 }
 
@@ -43,6 +48,7 @@ impl SignupResponse {
       last_name: u.last_name,
       income: u.income,
       net_worth: u.net_worth,
+      base_currency: u.base_currency,
       location: u.location,
     }
   }
@@ -71,6 +77,8 @@ pub struct UpdatePayload {
   #[validate(custom = "crate::common::decimal_at_least_zero")]
   pub income: Option<Decimal>,
   pub net_worth: Option<Decimal>,
+  // changing it converts the user's history and amounts at today's rates
+  pub base_currency: Option<Currency>,
   pub location: Option<Location>,
   #[validate(custom = "crate::common::min_age_13yo")]
   pub birthday: Option<String>,
@@ -119,9 +127,10 @@ pub async fn update_user(
   user: User,
   update_payload: Json<UpdatePayload>,
   user_service: Data<UserService>,
+  fx: Data<FxService>,
 ) -> HttpResponse {
   let res = user_service
-    .update(user, update_payload.into_inner())
+    .update(user, update_payload.into_inner(), &fx)
     .await
     .and_then(|updated| Ok(UpdateResponse::new(updated)));
 
@@ -270,6 +279,7 @@ mod test {
         last_name: "last name".to_string(),
         income: 1000.into(),
         net_worth: 10000.into(),
+        base_currency: None,
         location: Location::default(),
         birthday: birthday.clone(),
       }
@@ -284,6 +294,7 @@ mod test {
       last_name: "last name".to_string(),
       income: Decimal::new(-1, 0),
       net_worth: 0.into(),
+      base_currency: None,
       location: Location::default(),
       birthday: birthday.clone(),
     }
@@ -298,6 +309,7 @@ mod test {
       last_name: "last name".to_string(),
       income: 1000.into(),
       net_worth: 1000.into(),
+      base_currency: None,
       location: Location::default(),
       birthday: birthday.clone(),
     }
@@ -312,6 +324,7 @@ mod test {
       last_name: "last name".to_string(),
       income: 1000.into(),
       net_worth: 10000.into(),
+      base_currency: None,
       location: Location::default(),
       birthday: birthday.clone(),
     }
@@ -326,6 +339,7 @@ mod test {
       last_name: "".to_string(),
       income: 1000.into(),
       net_worth: 100000.into(),
+      base_currency: None,
       location: Location::default(),
       birthday: birthday.clone(),
     }
@@ -340,6 +354,7 @@ mod test {
       last_name: "b".to_string(),
       income: 1000.into(),
       net_worth: 10000.into(),
+      base_currency: None,
       location: Location::default(),
       birthday: chrono::Utc::now().format("%Y-%m-%d").to_string(),
     }
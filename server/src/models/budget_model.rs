@@ -0,0 +1,57 @@
+use crate::common::Money;
+use chrono::{Datelike, Duration, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use wither::{mongodb::bson::oid::ObjectId, Model};
+
+#[derive(Model, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Budget {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+  pub category: String,
+  pub limit: Decimal,
+  pub period: BudgetPeriod,
+  // carry what is left of each period's limit into the next one
+  pub rollover: bool,
+  // rollover starts from the period containing this day, %Y-%m-%d
+  pub start: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BudgetPeriod {
+  Weekly,
+  Monthly,
+}
+
+impl BudgetPeriod {
+  // first and last day of the period containing `date`. Weeks start on Monday.
+  pub fn containing(&self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    match self {
+      BudgetPeriod::Weekly => {
+        let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+        (start, start + Duration::days(6))
+      }
+      BudgetPeriod::Monthly => {
+        let start = date.with_day(1).unwrap();
+        let next_month = if start.month() == 12 {
+          NaiveDate::from_ymd(start.year() + 1, 1, 1)
+        } else {
+          NaiveDate::from_ymd(start.year(), start.month() + 1, 1)
+        };
+        (start, next_month.pred())
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BudgetStatus {
+  pub budget: Budget,
+  pub period_start: String,
+  pub period_end: String,
+  pub spent: Money,
+  // left over from earlier periods, zero without rollover
+  pub rolled_over: Money,
+  // may be negative when the budget is overspent
+  pub remaining: Money,
+}
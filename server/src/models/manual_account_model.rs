@@ -0,0 +1,135 @@
+use crate::common::Currency;
+use crate::models::plan_model::AssetClass;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use wither::{mongodb::bson::oid::ObjectId, Model};
+
+// what manual accounts are listed under in place of a Plaid item id
+pub const MANUAL_ITEM_ID: &str = "manual";
+
+// An account the user keeps up to date themselves, for what Plaid can't reach
+#[derive(Model, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManualAccount {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+  pub name: String,
+  pub account_type: ManualAccountType,
+  pub class: AssetClass,
+  pub currency: Currency,
+  // oldest first
+  pub valuations: Vec<Valuation>,
+  #[serde(default)]
+  pub hidden: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ManualAccountType {
+  Property,
+  Vehicle,
+  Cash,
+  Crypto,
+  PrivateEquity,
+  Loan,
+  Other,
+}
+
+impl ManualAccountType {
+  pub fn name(&self) -> &'static str {
+    match self {
+      ManualAccountType::Property => "property",
+      ManualAccountType::Vehicle => "vehicle",
+      ManualAccountType::Cash => "cash",
+      ManualAccountType::Crypto => "crypto",
+      ManualAccountType::PrivateEquity => "private equity",
+      ManualAccountType::Loan => "loan",
+      ManualAccountType::Other => "other",
+    }
+  }
+}
+
+// what the account was worth on a day. Loans are valued at what is owed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Valuation {
+  pub date: String, // %Y-%m-%d
+  pub value: Decimal,
+}
+
+impl ManualAccount {
+  pub fn is_liability(&self) -> bool {
+    self.account_type == ManualAccountType::Loan
+  }
+
+  // a later valuation on the same day replaces the earlier one
+  pub fn add_valuation(&mut self, valuation: Valuation) {
+    self.valuations.retain(|v| v.date != valuation.date);
+    self.valuations.push(valuation);
+    self.valuations.sort_by(|a, b| a.date.cmp(&b.date));
+  }
+
+  // the latest valuation on or before `date`, negative for debts like Plaid balances
+  pub fn balance_on(&self, date: &str) -> Option<Decimal> {
+    self
+      .valuations
+      .iter()
+      .filter(|valuation| valuation.date.as_str() <= date)
+      .last()
+      .map(|valuation| {
+        if self.is_liability() {
+          -valuation.value
+        } else {
+          valuation.value
+        }
+      })
+  }
+
+  pub fn account_id(&self) -> String {
+    self.id.as_ref().map(|id| id.to_hex()).unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn account(account_type: ManualAccountType) -> ManualAccount {
+    ManualAccount {
+      id: None,
+      name: "house".to_string(),
+      account_type,
+      class: AssetClass::Other,
+      currency: Currency::USD,
+      valuations: vec![],
+      hidden: false,
+    }
+  }
+
+  #[test]
+  fn test_balance_on() {
+    let mut house = account(ManualAccountType::Property);
+    house.add_valuation(Valuation {
+      date: "2021-03-01".to_string(),
+      value: dec!(300000),
+    });
+    house.add_valuation(Valuation {
+      date: "2020-01-01".to_string(),
+      value: dec!(250000),
+    });
+    house.add_valuation(Valuation {
+      date: "2021-03-01".to_string(),
+      value: dec!(310000),
+    });
+
+    assert_eq!(2, house.valuations.len());
+    assert_eq!(None, house.balance_on("2019-12-31"));
+    assert_eq!(Some(dec!(250000)), house.balance_on("2021-02-28"));
+    assert_eq!(Some(dec!(310000)), house.balance_on("2021-03-01"));
+
+    let mut mortgage = account(ManualAccountType::Loan);
+    mortgage.add_valuation(Valuation {
+      date: "2020-01-01".to_string(),
+      value: dec!(200000),
+    });
+    assert_eq!(Some(dec!(-200000)), mortgage.balance_on("2021-01-01"));
+  }
+}
@@ -1,8 +1,12 @@
+pub mod budget_model;
 pub mod financial_product_model;
 pub mod goal_model;
 pub mod insight_model;
 pub mod leaderboard_model;
+pub mod manual_account_model;
 pub mod plan_model;
 pub mod recurring_model;
 pub mod session_model;
+pub mod spending_model;
+pub mod transaction_model;
 pub mod user_model;
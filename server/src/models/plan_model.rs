@@ -1,5 +1,6 @@
-use crate::common::ensure_id;
+use crate::common::{compounding::CompoundingConvention, ensure_id};
 use crate::models::recurring_model::Recurring;
+use chrono::{DateTime, Datelike, Utc};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
@@ -15,6 +16,10 @@ pub struct Plan {
     pub recurrings: Vec<Recurring>,
     pub allocations: Vec<Allocation>,
     pub events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advisory_fee: Option<AdvisoryFee>,
+    #[serde(default)]
+    pub compounding: CompoundingConvention,
 }
 
 impl Plan {
@@ -48,12 +53,46 @@ impl Default for Allocation {
                     name: "Cash".to_string(),
                     class: AssetClass::Cash,
                     annualized_performance: dec!(0.0),
+                    expense_ratio: None,
                 },
                 proportion: dec!(100.0),
             }],
         }
     }
 }
+
+// a fee charged as a percentage of assets under management, e.g. by an advisor
+#[derive(Validate, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdvisoryFee {
+    #[validate(custom = "crate::common::decimal_between_zero_or_hundred")]
+    pub annual_percentage: Decimal,
+    pub billing: FeeBilling,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum FeeBilling {
+    #[serde(rename = "monthly")]
+    Monthly,
+    #[serde(rename = "quarterly")]
+    Quarterly,
+}
+
+impl FeeBilling {
+    pub fn periods_per_year(&self) -> Decimal {
+        match self {
+            FeeBilling::Monthly => dec!(12),
+            FeeBilling::Quarterly => dec!(4),
+        }
+    }
+
+    // fees are billed on the first day of each month or quarter
+    pub fn is_billing_date(&self, date: &DateTime<Utc>) -> bool {
+        match self {
+            FeeBilling::Monthly => date.day() == 1,
+            FeeBilling::Quarterly => date.day() == 1 && date.month0() % 3 == 0,
+        }
+    }
+}
 #[derive(Validate, Model, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Event {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -70,6 +109,9 @@ pub struct Asset {
     pub name: String,
     pub class: AssetClass,
     pub annualized_performance: Decimal,
+    // annual expense ratio as a percentage, e.g. 0.03 for a 0.03% index fund
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expense_ratio: Option<Decimal>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Eq, Hash)]
@@ -170,6 +212,7 @@ mod tests {
                     name: "dollars".to_string(),
                     class: AssetClass::Cash,
                     annualized_performance: dec!(0.01),
+                    expense_ratio: None,
                 },
                 proportion: dec!(100.0),
             }],
@@ -184,6 +227,7 @@ mod tests {
                     name: "dollars".to_string(),
                     class: AssetClass::Cash,
                     annualized_performance: dec!(0.01),
+                    expense_ratio: None,
                 },
                 proportion: dec!(50.0),
             }],
@@ -199,6 +243,7 @@ mod tests {
                         name: "dollars".to_string(),
                         class: AssetClass::Cash,
                         annualized_performance: dec!(0.01),
+                        expense_ratio: None,
                     },
                     proportion: dec!(60.0),
                 },
@@ -207,6 +252,7 @@ mod tests {
                         name: "dollars".to_string(),
                         class: AssetClass::Equity,
                         annualized_performance: dec!(0.01),
+                        expense_ratio: None,
                     },
                     proportion: dec!(60.0),
                 },
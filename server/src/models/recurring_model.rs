@@ -1,4 +1,5 @@
-use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use crate::common::{compounding::Compounder, Money};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
@@ -29,6 +30,15 @@ pub struct Recurring {
   pub frequency: TimeInterval,
 }
 
+// a recurring found in the user's transactions, which they can accept
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecurringSuggestion {
+  pub id: String,
+  pub recurring: Recurring,
+  pub occurrences: usize,
+  pub last_date: String, // %Y-%m-%d
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TimeInterval {
   #[serde(rename = "typ")]
@@ -56,21 +66,34 @@ pub enum Typ {
 }
 
 impl Recurring {
-  // compounds the principal and returns the change (i.e., the wealth increase)
-  pub fn compound(&mut self) -> Decimal {
+  // Accrues interest on the principal from `from` to `to` and credits it as the
+  // compounder's convention says, `interest` being the annual percentage yield.
+  // Returns the change (i.e., the wealth increase), which is zero until a credit.
+  pub fn compound(
+    &mut self,
+    compounder: &mut Compounder,
+    from: &NaiveDate,
+    to: &NaiveDate,
+  ) -> Decimal {
     // divide by 100 to convert interest percentage to pure interest rate
-    let change = self.principal * self.interest / dec!(100);
+    compounder.set_annual_growth(dec!(1) + self.interest / dec!(100));
 
-    self.principal += change;
+    let before = self.principal;
+    self.principal = compounder
+      .step(Money::from(self.principal), from, to)
+      .amount;
 
-    change
+    self.principal - before
   }
 
-  pub fn is_active(&self, date: &DateTime<Utc>) -> bool {
+  // between its start and end, whether or not it pays on `date`
+  pub fn is_running(&self, date: &DateTime<Utc>) -> bool {
     let ts = date.timestamp();
-    let active_1 = self.start <= ts && self.end > ts;
+    self.start <= ts && self.end > ts
+  }
 
-    if !active_1 {
+  pub fn is_active(&self, date: &DateTime<Utc>) -> bool {
+    if !self.is_running(date) {
       return false;
     }
 
@@ -159,4 +182,39 @@ mod tests {
     assert!(bad_rec2.validate().is_err());
     assert!(bad_rec3.validate().is_err());
   }
+
+  #[test]
+  fn test_compound_with_convention() {
+    use crate::common::compounding::{CompoundingConvention, CompoundingFrequency, DayCount};
+
+    let mut rec = Recurring {
+      id: None,
+      name: "savings".to_string(),
+      start: 0,
+      end: 10,
+      principal: dec!(100),
+      amount: dec!(0),
+      interest: dec!(12),
+      frequency: TimeInterval::new(Typ::Daily, 1),
+    };
+    let mut compounder = Compounder::new(
+      dec!(1),
+      CompoundingConvention {
+        frequency: CompoundingFrequency::Annually,
+        day_count: DayCount::Act365,
+      },
+    );
+
+    // nothing is credited until the new year, then the whole year's interest
+    let mut changes = Vec::new();
+    let mut day = NaiveDate::from_ymd(2021, 1, 1);
+    while day < NaiveDate::from_ymd(2022, 1, 1) {
+      changes.push(rec.compound(&mut compounder, &day, &day.succ()));
+      day = day.succ();
+    }
+
+    assert!(changes[..364].iter().all(|change| *change == dec!(0)));
+    assert_eq!(dec!(12.00), changes[364]);
+    assert_eq!(dec!(112.00), rec.principal);
+  }
 }
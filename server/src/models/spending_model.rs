@@ -0,0 +1,39 @@
+use crate::common::Money;
+use serde::{Deserialize, Serialize};
+use wither::{mongodb::bson::oid::ObjectId, Model};
+
+// Puts transactions whose merchant (or name, when Plaid has no merchant)
+// contains `merchant_contains`, ignoring case, into `category`.
+#[derive(Model, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryRule {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+  pub merchant_contains: String,
+  pub category: String,
+}
+
+impl CategoryRule {
+  pub fn matches(&self, merchant: &str) -> bool {
+    merchant
+      .to_lowercase()
+      .contains(&self.merchant_contains.to_lowercase())
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryTotal {
+  pub category: String,
+  pub total: Money,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MonthlyCategoryTotals {
+  pub month: String, // %Y-%m
+  pub categories: Vec<CategoryTotal>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryBreakdown {
+  pub categories: Vec<CategoryTotal>,
+  pub months: Vec<MonthlyCategoryTotals>,
+}
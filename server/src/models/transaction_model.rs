@@ -0,0 +1,119 @@
+use crate::common::{errors::ApiError, Money};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use wither::{mongodb::bson::oid::ObjectId, Model};
+
+// One posted or pending transaction from Plaid. Amounts keep Plaid's sign
+// convention: positive amounts are money leaving the account.
+#[derive(Model, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[model(index(
+  keys = r#"doc!{"transaction_id": 1}"#,
+  options = r#"doc!{"unique": true}"#
+))]
+#[model(index(keys = r#"doc!{"user_id": 1, "date": -1}"#))]
+pub struct Transaction {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+  pub user_id: ObjectId,
+  pub item_id: String,
+  pub account_id: String,
+  pub transaction_id: String,
+  // the pending transaction this one replaced once it posted
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub pending_transaction_id: Option<String>,
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub merchant_name: Option<String>,
+  pub category: Vec<String>,
+  // set by the user, takes precedence over rules and Plaid's category
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub category_override: Option<String>,
+  // the transaction on another of the user's accounts that this one moved money
+  // to or from. Transfers aren't income or spending.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub transfer_id: Option<String>,
+  pub amount: Money,
+  pub date: String, // %Y-%m-%d
+  pub pending: bool,
+  pub updated_time: i64,
+}
+
+impl Transaction {
+  pub fn from_plaid(
+    user_id: ObjectId,
+    item_id: String,
+    transaction: &plaid::models::Transaction,
+  ) -> Result<Self, ApiError> {
+    let amount = Decimal::try_from(transaction.amount).map_err(|_| {
+      ApiError::new(
+        500,
+        format!(
+          "Could not convert amount {} of transaction {}",
+          transaction.amount, transaction.transaction_id
+        ),
+      )
+    })?;
+    let currency =
+      crate::services::finchplaid::transaction_currency(transaction).ok_or(ApiError::new(
+        500,
+        format!(
+          "Transaction {} isn't in an ISO currency",
+          transaction.transaction_id
+        ),
+      ))?;
+
+    Ok(Transaction {
+      id: None,
+      user_id,
+      item_id,
+      account_id: transaction.account_id.clone(),
+      transaction_id: transaction.transaction_id.clone(),
+      pending_transaction_id: transaction.pending_transaction_id.clone(),
+      name: transaction.name.clone(),
+      merchant_name: transaction.merchant_name.clone(),
+      category: transaction.category.clone().unwrap_or_default(),
+      category_override: None,
+      transfer_id: None,
+      amount: Money::in_currency(amount, currency),
+      date: transaction.date.clone(),
+      pending: transaction.pending,
+      updated_time: chrono::Utc::now().timestamp(),
+    })
+  }
+}
+
+// a posted transaction on "account", for tests to adjust
+#[cfg(test)]
+impl Transaction {
+  pub fn fixture(transaction_id: &str, name: &str, amount: Money, date: &str) -> Self {
+    Transaction {
+      id: None,
+      user_id: ObjectId::with_string("5fe4b3e2f2b8a9a1a0c0ffee").unwrap(),
+      item_id: "item".to_string(),
+      account_id: "account".to_string(),
+      transaction_id: transaction_id.to_string(),
+      pending_transaction_id: None,
+      name: name.to_string(),
+      merchant_name: None,
+      category: vec![],
+      category_override: None,
+      transfer_id: None,
+      amount,
+      date: date.to_string(),
+      pending: false,
+      updated_time: 0,
+    }
+  }
+}
+
+#[allow(unused_imports)]
+use chrono::TimeZone;
+use wither::mongodb::bson::doc;
+use wither::prelude::Migrating;
+
+impl Migrating for Transaction {
+  fn migrations() -> Vec<Box<dyn wither::Migration>> {
+    vec![]
+  }
+}
@@ -1,10 +1,13 @@
-use crate::common::{errors::ApiError, Money};
+use crate::common::{encryption::StoredSecret, errors::ApiError, Currency, Money};
 use crate::models::{
+  budget_model::Budget,
   goal_model::Goal,
   insight_model::{Insight, InsightTypes},
-  leaderboard_model::{Ranking},
+  leaderboard_model::Ranking,
+  manual_account_model::ManualAccount,
   plan_model::Plan,
   recurring_model::Recurring,
+  spending_model::CategoryRule,
 };
 use crate::services::{sessions::SessionService, users::UserService};
 use actix_session::Session;
@@ -12,7 +15,6 @@ use actix_web::{
   dev::Payload, error::ErrorServiceUnavailable, error::ErrorUnauthorized, web::Data, Error,
   FromRequest, HttpRequest,
 };
-use validator::Validate;
 use argon2::{self, Config};
 use chrono::Utc;
 use futures::future::Future;
@@ -21,6 +23,7 @@ use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::pin::Pin;
+use validator::Validate;
 use wither::Model;
 
 #[derive(Model, Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -33,33 +36,258 @@ pub struct User {
   pub last_name: String,
   pub income: Decimal,
   pub net_worth: Decimal,
+  // what snapshots, plans, goals and budgets are in, and what the user's
+  // self-reported amounts are taken to be in
+  #[serde(default)]
+  pub base_currency: Currency,
   pub location: Location,
   pub birthday: String, // %Y-%m-%d
   pub accounts: Vec<PlaidItem>,
   pub account_records: Vec<AccountRecord>,
+  #[serde(default)]
+  pub manual_accounts: Vec<ManualAccount>,
   pub snapshots: Vec<Snapshot>,
   pub recurrings: Vec<Recurring>,
   pub goals: Vec<Goal>,
   pub insights: Vec<Insight>,
   pub plans: Vec<Plan>,
   pub rankings: Vec<Ranking>,
+  pub category_rules: Vec<CategoryRule>,
+  pub budgets: Vec<Budget>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct PlaidItem {
   pub item_id: String,
-  pub access_token: String,
+  pub access_token: StoredSecret,
+  #[serde(default)]
+  pub status: ItemStatus,
+  // when Plaid last answered for the item without error
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub last_successful_sync: Option<i64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub last_failure: Option<ItemFailure>,
+  // when the item was linked, zero for items linked before this was recorded
+  #[serde(default)]
+  pub linked_time: i64,
+  // history before the link still has to be reconstructed by the snapshot service
+  #[serde(default)]
+  pub pending_backfill: bool,
+  // date (%Y-%m-%d) through which the item's transactions are in the ledger
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub transactions_cursor: Option<String>,
+  // Plaid has new transactions for the item, set by webhooks
+  #[serde(default)]
+  pub pending_refresh: bool,
+  // when the user's consent for the item runs out, as reported by Plaid
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub consent_expiration_time: Option<String>,
+}
+
+// the most recent error from Plaid while taking a snapshot of an item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ItemFailure {
+  pub code: u16,
+  pub message: String,
+  pub failure_time: i64,
+}
+
+impl ItemFailure {
+  pub fn new(error: &ApiError) -> Self {
+    ItemFailure {
+      code: error.code(),
+      message: error.message().clone(),
+      failure_time: Utc::now().timestamp(),
+    }
+  }
+}
+
+impl PlaidItem {
+  pub fn record_success(&mut self) {
+    self.status = ItemStatus::Healthy;
+    self.last_successful_sync = Some(Utc::now().timestamp());
+  }
+
+  pub fn record_failure(&mut self, error: &ApiError) {
+    self.status = ItemStatus::from_error(error);
+    self.last_failure = Some(ItemFailure::new(error));
+  }
+}
+
+// a healthy item linked at the epoch, for tests to adjust
+#[cfg(test)]
+impl PlaidItem {
+  pub fn fixture(item_id: &str, access_token: &str) -> Self {
+    PlaidItem {
+      item_id: item_id.to_string(),
+      access_token: StoredSecret::Plaintext(access_token.to_string()),
+      status: ItemStatus::Healthy,
+      last_successful_sync: None,
+      last_failure: None,
+      linked_time: 0,
+      pending_backfill: false,
+      transactions_cursor: None,
+      pending_refresh: false,
+      consent_expiration_time: None,
+    }
+  }
+}
+
+// whether Plaid can currently be reached for an item
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ItemStatus {
+  Healthy,
+  // the user has to go through Link in update mode to repair the item
+  LoginRequired,
+  Error { code: String, message: String },
+}
+
+impl Default for ItemStatus {
+  fn default() -> Self {
+    ItemStatus::Healthy
+  }
+}
+
+impl ItemStatus {
+  pub fn from_plaid_error(code: String, message: String) -> Self {
+    match code.as_str() {
+      "ITEM_LOGIN_REQUIRED" => ItemStatus::LoginRequired,
+      _ => ItemStatus::Error { code, message },
+    }
+  }
+
+  // errors Plaid explained carry its error code, anything else is kept under
+  // its HTTP code
+  pub fn from_error(error: &ApiError) -> Self {
+    match error.plaid_code() {
+      Some(code) => Self::from_plaid_error(code.clone(), error.message().clone()),
+      None => ItemStatus::Error {
+        code: error.code().to_string(),
+        message: error.message().clone(),
+      },
+    }
+  }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Snapshot {
   pub net_worth: Money,
+  // what the net worth is made of, both positive. Zero for snapshots taken
+  // before these were recorded.
+  #[serde(default)]
+  pub assets: Money,
+  #[serde(default)]
+  pub liabilities: Money,
 
   pub running_savings: Money,
   pub running_spending: Money,
   pub running_income: Money,
 
   pub snapshot_time: i64,
+
+  // what each account, hidden ones included, had and moved. Empty for snapshots
+  // taken before these were recorded.
+  #[serde(default)]
+  pub accounts: Vec<AccountSnapshot>,
+
+  // why the snapshot looks wrong, while it waits for the user to accept or
+  // reject it. Goals and peer metrics leave it out until then.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub quarantine: Option<String>,
+
+  // whether the snapshots before it say nothing of what is normal for the ones
+  // after, as the user accepted it or changed the accounts it counts
+  #[serde(default)]
+  pub baseline: bool,
+}
+
+// One account's part of a snapshot, in the snapshot's currency. Hidden accounts
+// are left out of the snapshot's totals.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountSnapshot {
+  pub item_id: String,
+  pub account_id: String,
+  // Plaid's type, like depository or credit, or a manual account's type name
+  #[serde(default)]
+  pub account_type: String,
+  // negative for what is owed
+  pub balance: Money,
+  // since the account's previous snapshot, with the same signs as the running sums
+  pub money_in: Money,
+  pub money_out: Money,
+  // how much its transactions, transfers included, changed the balance since
+  // its previous snapshot. The rest of the change is interest or the market.
+  #[serde(default)]
+  pub transacted: Money,
+  pub hidden: bool,
+}
+
+// an account of "item" in US dollars that moved nothing, for tests to adjust
+#[cfg(test)]
+impl AccountSnapshot {
+  pub fn fixture(account_id: &str, account_type: &str, balance: Decimal) -> Self {
+    AccountSnapshot {
+      item_id: "item".to_string(),
+      account_id: account_id.to_string(),
+      account_type: account_type.to_string(),
+      balance: Money::usd(balance),
+      money_in: Money::usd(0),
+      money_out: Money::usd(0),
+      transacted: Money::usd(0),
+      hidden: false,
+    }
+  }
+
+  // the transactions moved the balance by what came in and went out
+  pub fn with_flows(self, money_in: Decimal, money_out: Decimal) -> Self {
+    AccountSnapshot {
+      money_in: Money::usd(money_in),
+      money_out: Money::usd(money_out),
+      transacted: Money::usd(money_in + money_out),
+      ..self
+    }
+  }
+
+  pub fn with_transacted(self, transacted: Decimal) -> Self {
+    AccountSnapshot {
+      transacted: Money::usd(transacted),
+      ..self
+    }
+  }
+}
+
+// A snapshot of `accounts` in US dollars, its balance sheet totalled from the
+// shown ones, what is owed being negative. For tests to adjust.
+#[cfg(test)]
+impl Snapshot {
+  pub fn fixture(snapshot_time: i64, accounts: Vec<AccountSnapshot>) -> Self {
+    let (assets, liabilities) = accounts.iter().filter(|account| !account.hidden).fold(
+      (Money::usd(0), Money::usd(0)),
+      |(assets, liabilities), account| {
+        if account.balance.amount >= Decimal::new(0, 0) {
+          (assets + account.balance, liabilities)
+        } else {
+          (assets, liabilities - account.balance)
+        }
+      },
+    );
+
+    Snapshot::new_with_time(0, 0, 0, 0, snapshot_time)
+      .with_balance_sheet(assets, liabilities)
+      .unwrap()
+      .with_accounts(accounts)
+  }
+
+  // the running sums, savings being what came in less what went out
+  pub fn with_running_sums(self, income: Decimal, spending: Decimal) -> Self {
+    Snapshot {
+      running_savings: Money::usd(income - spending),
+      running_spending: Money::usd(spending),
+      running_income: Money::usd(income),
+      ..self
+    }
+  }
 }
 
 impl Snapshot {
@@ -85,14 +313,66 @@ impl Snapshot {
     running_income: T,
     snapshot_time: i64,
   ) -> Self {
+    let net_worth = net_worth.into();
+
+    // without a breakdown, a net worth is taken to be all assets or all debt
+    let (assets, liabilities) = if net_worth.amount >= Decimal::new(0, 0) {
+      (net_worth, Money::default())
+    } else {
+      (Money::default(), Money::new(-net_worth.amount))
+    };
+
     Snapshot {
-      net_worth: net_worth.into(),
+      net_worth,
+      assets,
+      liabilities,
       running_savings: running_savings.into(),
       running_spending: running_spending.into(),
       running_income: running_income.into(),
       snapshot_time,
+      accounts: vec![],
+      quarantine: None,
+      baseline: false,
     }
   }
+
+  // sets the breakdown, and the net worth to match
+  pub fn with_balance_sheet<T: Into<Money>>(
+    mut self,
+    assets: T,
+    liabilities: T,
+  ) -> Result<Self, ApiError> {
+    self.assets = assets.into();
+    self.liabilities = liabilities.into();
+    self.net_worth = self.assets.checked_sub(self.liabilities)?;
+    Ok(self)
+  }
+
+  pub fn with_accounts(mut self, accounts: Vec<AccountSnapshot>) -> Self {
+    self.accounts = accounts;
+    self
+  }
+
+  pub fn with_quarantine(mut self, quarantine: Option<String>) -> Self {
+    self.quarantine = quarantine;
+    self
+  }
+
+  pub fn with_baseline(mut self, baseline: bool) -> Self {
+    self.baseline = baseline;
+    self
+  }
+
+  pub fn is_quarantined(&self) -> bool {
+    self.quarantine.is_some()
+  }
+
+  pub fn account(&self, account_id: &str) -> Option<&AccountSnapshot> {
+    self
+      .accounts
+      .iter()
+      .find(|account| account.account_id == account_id)
+  }
 }
 
 #[derive(Validate, Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -164,14 +444,49 @@ impl User {
   }
 }
 
+// a user in US dollars with nothing linked or recorded, for tests to adjust
+#[cfg(test)]
+impl User {
+  pub fn fixture(email: &str) -> Self {
+    User {
+      id: None,
+      email: email.to_string(),
+      password: email.to_string(),
+      first_name: "fn".to_string(),
+      last_name: "ln".to_string(),
+      income: Decimal::new(0, 0),
+      net_worth: Decimal::new(0, 0),
+      base_currency: Currency::USD,
+      location: Location::default(),
+      birthday: "1970-01-01".to_string(),
+      accounts: vec![],
+      account_records: vec![],
+      manual_accounts: vec![],
+      snapshots: vec![],
+      recurrings: vec![],
+      goals: vec![],
+      insights: vec![],
+      plans: vec![],
+      rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
+    }
+  }
+}
+
 impl Default for Snapshot {
   fn default() -> Snapshot {
     Snapshot {
       net_worth: Money::new(Decimal::new(0, 0)),
+      assets: Money::new(Decimal::new(0, 0)),
+      liabilities: Money::new(Decimal::new(0, 0)),
       running_savings: Money::new(Decimal::new(0, 0)),
       running_spending: Money::new(Decimal::new(0, 0)),
       running_income: Money::new(Decimal::new(0, 0)),
       snapshot_time: 0,
+      accounts: vec![],
+      quarantine: None,
+      baseline: false,
     }
   }
 }
@@ -273,7 +588,27 @@ impl Migrating for User {
         // NOTE: use a logical time here. A day after your deployment date, or the like.
         threshold: chrono::Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
         filter: doc! {"rankings": doc!{"$exists": false}},
-        set: Some(doc! {"rankings": wither::mongodb::bson::to_bson(&Vec::<Ranking>::new()).unwrap()}),
+        set: Some(
+          doc! {"rankings": wither::mongodb::bson::to_bson(&Vec::<Ranking>::new()).unwrap()},
+        ),
+        unset: None,
+      }),
+      Box::new(wither::IntervalMigration {
+        name: "add category_rules field".to_string(),
+        // NOTE: use a logical time here. A day after your deployment date, or the like.
+        threshold: chrono::Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+        filter: doc! {"category_rules": doc!{"$exists": false}},
+        set: Some(
+          doc! {"category_rules": wither::mongodb::bson::to_bson(&Vec::<CategoryRule>::new()).unwrap()},
+        ),
+        unset: None,
+      }),
+      Box::new(wither::IntervalMigration {
+        name: "add budgets field".to_string(),
+        // NOTE: use a logical time here. A day after your deployment date, or the like.
+        threshold: chrono::Utc.ymd(2021, 5, 1).and_hms(0, 0, 0),
+        filter: doc! {"budgets": doc!{"$exists": false}},
+        set: Some(doc! {"budgets": wither::mongodb::bson::to_bson(&Vec::<Budget>::new()).unwrap()}),
         unset: None,
       }),
     ]
@@ -333,19 +668,56 @@ mod test {
       last_name: "last_name".to_string(),
       income: 0.into(),
       net_worth: 0.into(),
+      base_currency: Currency::USD,
       location: Location::default(),
       birthday: "1970-01-01".to_string(),
       accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: vec![],
       recurrings: vec![],
       goals: vec![],
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     };
 
     assert_eq!(Ok(true), user.compare_password("password".to_string()));
     assert_eq!(Ok(false), user.compare_password("bad password".to_string()));
   }
+
+  #[test]
+  fn test_item_status_from_error() {
+    assert_eq!(
+      ItemStatus::LoginRequired,
+      ItemStatus::from_error(&ApiError::plaid(
+        400,
+        "ITEM_LOGIN_REQUIRED".to_string(),
+        "the login details of this item have changed".to_string()
+      ))
+    );
+    assert_eq!(
+      ItemStatus::Error {
+        code: "INSTITUTION_DOWN".to_string(),
+        message: "this institution is not currently responding".to_string()
+      },
+      ItemStatus::from_error(&ApiError::plaid(
+        400,
+        "INSTITUTION_DOWN".to_string(),
+        "this institution is not currently responding".to_string()
+      ))
+    );
+    assert_eq!(
+      ItemStatus::Error {
+        code: "500".to_string(),
+        message: "Error while getting accounts".to_string()
+      },
+      ItemStatus::from_error(&ApiError::new(
+        500,
+        "Error while getting accounts".to_string()
+      ))
+    );
+  }
 }
@@ -0,0 +1,384 @@
+#[allow(non_snake_case)]
+pub mod BudgetService {
+  use crate::common::{errors::ApiError, Money};
+  use crate::controllers::budget_controller::BudgetNewPayload;
+  use crate::controllers::transactions_controller::TransactionQuery;
+  use crate::models::{
+    budget_model::*, spending_model::CategoryRule, transaction_model::Transaction, user_model::User,
+  };
+  use crate::services::{
+    fx::FxService, spending::SpendingService, transactions::TransactionService, users::UserService,
+  };
+  use actix_web::web::Data;
+  use chrono::{NaiveDate, Utc};
+  use rust_decimal_macros::dec;
+  use std::collections::HashSet;
+  use wither::{mongodb::bson::oid::ObjectId, Model};
+
+  fn format_date(date: &NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+  }
+
+  fn parse_date(date: &String) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+      ApiError::new(
+        400,
+        format!("Could not parse date {}, expected %Y-%m-%d", date),
+      )
+    })
+  }
+
+  // the day spending is first needed from to work out the budget's current status
+  fn earliest_needed(budget: &Budget, today: NaiveDate) -> NaiveDate {
+    let (current_start, _) = budget.period.containing(today);
+
+    match (budget.rollover, parse_date(&budget.start)) {
+      (true, Ok(start)) => budget.period.containing(start).0.min(current_start),
+      _ => current_start,
+    }
+  }
+
+  fn spent_between(
+    budget: &Budget,
+    transactions: &Vec<Transaction>,
+    rules: &Vec<CategoryRule>,
+    excluded_accounts: &HashSet<String>,
+    from: &NaiveDate,
+    to: &NaiveDate,
+  ) -> Result<Money, ApiError> {
+    let (from, to) = (format_date(from), format_date(to));
+
+    transactions
+      .iter()
+      .filter(|transaction| {
+        transaction.date >= from
+          && transaction.date <= to
+          && SpendingService::is_spending(transaction, excluded_accounts)
+          && SpendingService::categorize(transaction, rules) == budget.category
+      })
+      .try_fold(Money::default(), |spent, transaction| {
+        spent.checked_add(transaction.amount)
+      })
+  }
+
+  pub fn calculate_budget_status(
+    budget: Budget,
+    transactions: &Vec<Transaction>,
+    rules: &Vec<CategoryRule>,
+    excluded_accounts: &HashSet<String>,
+    today: NaiveDate,
+  ) -> Result<BudgetStatus, ApiError> {
+    let (current_start, current_end) = budget.period.containing(today);
+
+    // what is left of each earlier period carries over, overspending does not
+    let mut rolled_over = Money::default();
+    if budget.rollover {
+      let start = parse_date(&budget.start).unwrap_or(today);
+      let (mut period_start, mut period_end) = budget.period.containing(start);
+
+      while period_start < current_start {
+        let spent = spent_between(
+          &budget,
+          transactions,
+          rules,
+          excluded_accounts,
+          &period_start,
+          &period_end,
+        )?;
+        let left = rolled_over.checked_add(budget.limit)?.checked_sub(spent)?;
+        rolled_over = Money {
+          amount: left.amount.max(dec!(0)),
+          ..left
+        };
+
+        let next = budget.period.containing(period_end.succ());
+        period_start = next.0;
+        period_end = next.1;
+      }
+    }
+
+    let spent = spent_between(
+      &budget,
+      transactions,
+      rules,
+      excluded_accounts,
+      &current_start,
+      &current_end,
+    )?;
+
+    Ok(BudgetStatus {
+      remaining: rolled_over.checked_add(budget.limit)?.checked_sub(spent)?,
+      period_start: format_date(&current_start),
+      period_end: format_date(&current_end),
+      spent,
+      rolled_over,
+      budget,
+    })
+  }
+
+  async fn calculate_budget_statuses(
+    budgets: Vec<Budget>,
+    user: &User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<Vec<BudgetStatus>, ApiError> {
+    let today = Utc::now().naive_utc().date();
+
+    let from = match budgets
+      .iter()
+      .map(|budget| earliest_needed(budget, today))
+      .min()
+    {
+      Some(from) => from,
+      None => return Ok(vec![]),
+    };
+
+    let transactions = transaction_service
+      .get_transactions(
+        &user.id.clone().unwrap(),
+        TransactionQuery {
+          from: Some(format_date(&from)),
+          to: None,
+          account_id: None,
+          min_amount: None,
+          max_amount: None,
+        },
+      )
+      .await?;
+    let transactions = SpendingService::in_base_currency(transactions, user, &fx)?;
+
+    let excluded_accounts = user.get_excluded_accounts();
+
+    budgets
+      .into_iter()
+      .map(|budget| {
+        calculate_budget_status(
+          budget,
+          &transactions,
+          &user.category_rules,
+          &excluded_accounts,
+          today,
+        )
+      })
+      .collect()
+  }
+
+  async fn calculate_single_budget_status(
+    budget: Budget,
+    user: &User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<BudgetStatus, ApiError> {
+    calculate_budget_statuses(vec![budget], user, transaction_service, fx)
+      .await?
+      .pop()
+      .ok_or(ApiError::new(500, "Could not calculate budget".to_string()))
+  }
+
+  pub async fn get_all_budgets(
+    user: User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<Vec<BudgetStatus>, ApiError> {
+    calculate_budget_statuses(user.budgets.clone(), &user, transaction_service, fx).await
+  }
+
+  pub async fn get_budget(
+    budget_id: String,
+    user: User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<BudgetStatus, ApiError> {
+    let budget = retrieve_budget(budget_id, &user.budgets)?;
+    calculate_single_budget_status(budget, &user, transaction_service, fx).await
+  }
+
+  pub fn retrieve_budget(budget_id: String, budgets: &Vec<Budget>) -> Result<Budget, ApiError> {
+    let budget_id_opt = Some(
+      ObjectId::with_string(budget_id.as_str())
+        .or(Err(ApiError::new(400, "Malformed Object Id".to_string())))?,
+    );
+
+    budgets
+      .iter()
+      .find(|rec| rec.id == budget_id_opt)
+      .cloned()
+      .ok_or(ApiError::new(
+        400,
+        format!("No budget with id {} found in current user", budget_id),
+      ))
+  }
+
+  fn into_budget(payload: BudgetNewPayload) -> Result<Budget, ApiError> {
+    let start = match payload.start.as_ref() {
+      Some(start) => parse_date(start)?,
+      None => Utc::now().naive_utc().date(),
+    };
+
+    let mut budget: Budget = payload.into();
+    budget.start = format_date(&start);
+    Ok(budget)
+  }
+
+  pub async fn new_budget(
+    payload: BudgetNewPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<BudgetStatus, ApiError> {
+    let mut budget = into_budget(payload)?;
+    budget.set_id(ObjectId::new());
+
+    user.budgets.push(budget.clone());
+
+    user_service.save(&mut user).await?;
+
+    calculate_single_budget_status(budget, &user, transaction_service, fx).await
+  }
+
+  pub async fn update_budget(
+    budget_id: String,
+    payload: BudgetNewPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<BudgetStatus, ApiError> {
+    let existing = retrieve_budget(budget_id, &user.budgets)?;
+
+    // left out, the start stays put so the rollover history isn't lost
+    let mut budget = into_budget(BudgetNewPayload {
+      start: payload.start.or(Some(existing.start)),
+      ..payload
+    })?;
+    budget.id = existing.id;
+
+    for rec in user.budgets.iter_mut().filter(|rec| rec.id == budget.id) {
+      *rec = budget.clone();
+    }
+
+    user_service.save(&mut user).await?;
+
+    calculate_single_budget_status(budget, &user, transaction_service, fx).await
+  }
+
+  pub async fn delete_budget(
+    budget_id: String,
+    mut user: User,
+    user_service: Data<UserService>,
+  ) -> Result<Budget, ApiError> {
+    let budget = retrieve_budget(budget_id, &user.budgets)?;
+
+    user.budgets.retain(|rec| rec.id != budget.id);
+
+    user_service.save(&mut user).await?;
+
+    Ok(budget)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Money;
+  use crate::models::{budget_model::*, transaction_model::Transaction};
+  use chrono::NaiveDate;
+  use rust_decimal::Decimal;
+  use rust_decimal_macros::dec;
+  use std::collections::HashSet;
+
+  fn grocery(amount: Decimal, date: &str) -> Transaction {
+    Transaction {
+      category: vec![
+        "Shops".to_string(),
+        "Supermarkets and Groceries".to_string(),
+      ],
+      ..Transaction::fixture(date, "Trader Joe's", Money::new(amount), date)
+    }
+  }
+
+  fn budget(rollover: bool) -> Budget {
+    Budget {
+      id: None,
+      category: "Shops".to_string(),
+      limit: dec!(300),
+      period: BudgetPeriod::Monthly,
+      rollover,
+      start: "2021-01-15".to_string(),
+    }
+  }
+
+  #[test]
+  fn test_periods() {
+    assert_eq!(
+      (
+        NaiveDate::from_ymd(2020, 12, 1),
+        NaiveDate::from_ymd(2020, 12, 31)
+      ),
+      BudgetPeriod::Monthly.containing(NaiveDate::from_ymd(2020, 12, 25))
+    );
+    // 2021-03-03 is a Wednesday
+    assert_eq!(
+      (
+        NaiveDate::from_ymd(2021, 3, 1),
+        NaiveDate::from_ymd(2021, 3, 7)
+      ),
+      BudgetPeriod::Weekly.containing(NaiveDate::from_ymd(2021, 3, 3))
+    );
+  }
+
+  #[test]
+  fn test_calculate_budget_status() {
+    let transactions = vec![
+      // january leaves 100 unspent, february is overspent by 50
+      grocery(dec!(200), "2021-01-20"),
+      grocery(dec!(450), "2021-02-10"),
+      grocery(dec!(120), "2021-03-02"),
+      grocery(dec!(30), "2021-03-05"),
+    ];
+    let today = NaiveDate::from_ymd(2021, 3, 10);
+
+    let status = BudgetService::calculate_budget_status(
+      budget(false),
+      &transactions,
+      &vec![],
+      &HashSet::new(),
+      today,
+    )
+    .unwrap();
+    assert_eq!("2021-03-01", status.period_start);
+    assert_eq!("2021-03-31", status.period_end);
+    assert_eq!(Money::new(dec!(150)), status.spent);
+    assert_eq!(Money::new(dec!(0)), status.rolled_over);
+    assert_eq!(Money::new(dec!(150)), status.remaining);
+
+    // 100 rolls into february, which overspends 400 by 50, so nothing rolls into march
+    let status = BudgetService::calculate_budget_status(
+      budget(true),
+      &transactions,
+      &vec![],
+      &HashSet::new(),
+      today,
+    )
+    .unwrap();
+    assert_eq!(Money::new(dec!(0)), status.rolled_over);
+    assert_eq!(Money::new(dec!(150)), status.remaining);
+
+    // without the february splurge, 100 + 300 - 50 = 350 rolls over
+    let status = BudgetService::calculate_budget_status(
+      budget(true),
+      &vec![
+        grocery(dec!(200), "2021-01-20"),
+        grocery(dec!(50), "2021-02-10"),
+        grocery(dec!(120), "2021-03-02"),
+      ],
+      &vec![],
+      &HashSet::new(),
+      today,
+    )
+    .unwrap();
+    assert_eq!(Money::new(dec!(350)), status.rolled_over);
+    assert_eq!(Money::new(dec!(530)), status.remaining);
+  }
+}
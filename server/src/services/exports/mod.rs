@@ -0,0 +1,724 @@
+pub mod ofx;
+
+use crate::common::errors::ApiError;
+use crate::models::{
+  goal_model::Goal,
+  plan_model::{AssetClass, Plan},
+  recurring_model::{Recurring, Typ},
+  transaction_model::Transaction,
+  user_model::{Snapshot, User},
+};
+use actix_web::web::Bytes;
+use chrono::{NaiveDate, NaiveDateTime};
+use futures::stream::{self, LocalBoxStream, StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExportFormat {
+  #[serde(rename = "csv")]
+  Csv,
+  #[serde(rename = "json")]
+  Json,
+  // transactions only
+  #[serde(rename = "ofx")]
+  Ofx,
+}
+
+impl Default for ExportFormat {
+  fn default() -> Self {
+    ExportFormat::Csv
+  }
+}
+
+// a file for the user to download
+pub struct Export {
+  pub content_type: &'static str,
+  pub filename: String,
+  pub body: ExportBody,
+}
+
+// transactions can run to years of history, so they're written out as they're
+// read instead of held in memory
+pub enum ExportBody {
+  Whole(String),
+  Rows(LocalBoxStream<'static, Result<Bytes, actix_web::Error>>),
+}
+
+// inclusive, either end may be open
+#[derive(Clone, Debug, PartialEq)]
+pub struct DateRange {
+  pub from: Option<NaiveDate>,
+  pub to: Option<NaiveDate>,
+}
+
+fn parse_date(date: &String) -> Result<NaiveDate, ApiError> {
+  NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+    ApiError::new(
+      400,
+      format!("Could not parse date {}, expected %Y-%m-%d", date),
+    )
+  })
+}
+
+pub fn date_of(timestamp: i64) -> NaiveDate {
+  NaiveDateTime::from_timestamp(timestamp, 0).date()
+}
+
+fn format_time(timestamp: i64) -> String {
+  date_of(timestamp).format("%Y-%m-%d").to_string()
+}
+
+impl DateRange {
+  pub fn new(from: &Option<String>, to: &Option<String>) -> Result<DateRange, ApiError> {
+    let range = DateRange {
+      from: from.as_ref().map(parse_date).transpose()?,
+      to: to.as_ref().map(parse_date).transpose()?,
+    };
+
+    match (range.from, range.to) {
+      (Some(from), Some(to)) if from > to => Err(ApiError::new(
+        400,
+        "The from date is after the to date".to_string(),
+      )),
+      _ => Ok(range),
+    }
+  }
+
+  pub fn contains(&self, timestamp: i64) -> bool {
+    self.overlaps(timestamp, timestamp)
+  }
+
+  // whether anything between start and end falls in the range
+  pub fn overlaps(&self, start: i64, end: i64) -> bool {
+    self.from.map_or(true, |from| date_of(end) >= from)
+      && self.to.map_or(true, |to| date_of(start) <= to)
+  }
+}
+
+// Quotes a field when a spreadsheet would otherwise split or misread it. Text
+// that starts like a formula gets a leading ' so a spreadsheet shows it rather
+// than runs it, numbers are left alone so negative amounts stay numbers.
+pub fn csv_field(value: &str) -> String {
+  let value = if value.starts_with(|c: char| matches!(c, '=' | '+' | '-' | '@' | '\t' | '\r'))
+    && value.parse::<Decimal>().is_err()
+  {
+    format!("'{}", value)
+  } else {
+    value.to_string()
+  };
+
+  if value.contains(|c: char| matches!(c, ',' | '"' | '\n' | '\r')) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value
+  }
+}
+
+pub fn csv_line<S: AsRef<str>>(fields: &[S]) -> String {
+  let fields: Vec<String> = fields
+    .iter()
+    .map(|field| csv_field(field.as_ref()))
+    .collect();
+  format!("{}\r\n", fields.join(","))
+}
+
+pub fn to_csv(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+  std::iter::once(csv_line(headers))
+    .chain(rows.iter().map(|row| csv_line(row)))
+    .collect()
+}
+
+fn frequency_name(typ: Typ) -> &'static str {
+  match typ {
+    Typ::Daily => "daily",
+    Typ::Weekly => "weekly",
+    Typ::Monthly => "monthly",
+    Typ::Annually => "annually",
+  }
+}
+
+fn class_name(class: &AssetClass) -> String {
+  match class {
+    AssetClass::Custom(name) => name.clone(),
+    class => format!("{:?}", class),
+  }
+}
+
+fn id_string(id: &Option<wither::mongodb::bson::oid::ObjectId>) -> String {
+  id.as_ref().map(|id| id.to_hex()).unwrap_or_default()
+}
+
+pub const SNAPSHOT_HEADERS: [&str; 8] = [
+  "date",
+  "currency",
+  "net_worth",
+  "assets",
+  "liabilities",
+  "running_savings",
+  "running_spending",
+  "running_income",
+];
+
+pub fn snapshot_rows(snapshots: &Vec<Snapshot>, user: &User) -> Vec<Vec<String>> {
+  snapshots
+    .iter()
+    .map(|snapshot| {
+      vec![
+        format_time(snapshot.snapshot_time),
+        snapshot
+          .net_worth
+          .currency
+          .unwrap_or(user.base_currency)
+          .to_string(),
+        snapshot.net_worth.amount.to_string(),
+        snapshot.assets.amount.to_string(),
+        snapshot.liabilities.amount.to_string(),
+        snapshot.running_savings.amount.to_string(),
+        snapshot.running_spending.amount.to_string(),
+        snapshot.running_income.amount.to_string(),
+      ]
+    })
+    .collect()
+}
+
+pub const RECURRING_HEADERS: [&str; 10] = [
+  "id",
+  "name",
+  "start",
+  "end",
+  "currency",
+  "amount",
+  "principal",
+  "interest",
+  "frequency",
+  "every",
+];
+
+fn recurring_row(recurring: &Recurring, user: &User) -> Vec<String> {
+  vec![
+    id_string(&recurring.id),
+    recurring.name.clone(),
+    format_time(recurring.start),
+    format_time(recurring.end),
+    user.base_currency.to_string(),
+    recurring.amount.to_string(),
+    recurring.principal.to_string(),
+    recurring.interest.to_string(),
+    frequency_name(recurring.frequency.typ).to_string(),
+    recurring.frequency.content.to_string(),
+  ]
+}
+
+pub fn recurring_rows(recurrings: &Vec<Recurring>, user: &User) -> Vec<Vec<String>> {
+  recurrings
+    .iter()
+    .map(|recurring| recurring_row(recurring, user))
+    .collect()
+}
+
+pub const GOAL_HEADERS: [&str; 7] = [
+  "id",
+  "name",
+  "metric",
+  "start",
+  "end",
+  "currency",
+  "threshold",
+];
+
+pub fn goal_rows(goals: &Vec<Goal>, user: &User) -> Vec<Vec<String>> {
+  goals
+    .iter()
+    .map(|goal| {
+      vec![
+        id_string(&goal.id),
+        goal.name.clone(),
+        format!("{:?}", goal.metric),
+        format_time(goal.start),
+        format_time(goal.end),
+        user.base_currency.to_string(),
+        goal.threshold.to_string(),
+      ]
+    })
+    .collect()
+}
+
+// a plan is a row per recurring, allocation and event, the columns a kind
+// doesn't have left empty
+pub const PLAN_HEADERS: [&str; 14] = [
+  "plan_id",
+  "plan_name",
+  "kind",
+  "id",
+  "name",
+  "start",
+  "end",
+  "currency",
+  "amount",
+  "principal",
+  "interest",
+  "frequency",
+  "every",
+  "details",
+];
+
+pub fn plan_rows(plans: &Vec<Plan>, range: &DateRange, user: &User) -> Vec<Vec<String>> {
+  let mut rows = Vec::new();
+
+  for plan in plans.iter() {
+    let row = |kind: &str, rest: Vec<String>| {
+      let mut row = vec![id_string(&plan.id), plan.name.clone(), kind.to_string()];
+      row.extend(rest);
+      row
+    };
+
+    for recurring in plan
+      .recurrings
+      .iter()
+      .filter(|recurring| range.overlaps(recurring.start, recurring.end))
+    {
+      let mut rest = recurring_row(recurring, user);
+      rest.push(String::new());
+      rows.push(row("recurring", rest));
+    }
+
+    for allocation in plan
+      .allocations
+      .iter()
+      .filter(|allocation| range.contains(allocation.date))
+    {
+      let details: Vec<String> = allocation
+        .schema
+        .iter()
+        .map(|proportion| {
+          format!(
+            "{} ({}) {}%",
+            proportion.asset.name,
+            class_name(&proportion.asset.class),
+            proportion.proportion
+          )
+        })
+        .collect();
+      let mut rest = vec![
+        id_string(&allocation.id),
+        allocation.description.clone(),
+        format_time(allocation.date),
+      ];
+      rest.extend(vec![String::new(); 7]);
+      rest.push(details.join("; "));
+      rows.push(row("allocation", rest));
+    }
+
+    for event in plan
+      .events
+      .iter()
+      .filter(|event| range.contains(event.start))
+    {
+      let details: Vec<String> = event
+        .transforms
+        .iter()
+        .map(|transform| format!("{} {}%", class_name(&transform.class), transform.change))
+        .collect();
+      let mut rest = vec![
+        id_string(&event.id),
+        event.name.clone(),
+        format_time(event.start),
+      ];
+      rest.extend(vec![String::new(); 7]);
+      rest.push(details.join("; "));
+      rows.push(row("event", rest));
+    }
+  }
+
+  rows
+}
+
+// what the user calls each of their accounts, by account id
+pub fn account_names(user: &User) -> HashMap<String, String> {
+  user
+    .account_records
+    .iter()
+    .map(|record| (record.account_id.clone(), record.account_name.clone()))
+    .chain(
+      user
+        .manual_accounts
+        .iter()
+        .map(|account| (account.account_id(), account.name.clone())),
+    )
+    .collect()
+}
+
+// amounts keep the ledger's sign, money out is positive
+pub const TRANSACTION_HEADERS: [&str; 11] = [
+  "date",
+  "transaction_id",
+  "account_id",
+  "account_name",
+  "name",
+  "merchant_name",
+  "category",
+  "currency",
+  "amount",
+  "pending",
+  "transfer_id",
+];
+
+pub fn transaction_row(
+  transaction: &Transaction,
+  names: &HashMap<String, String>,
+  user: &User,
+) -> Vec<String> {
+  vec![
+    transaction.date.clone(),
+    transaction.transaction_id.clone(),
+    transaction.account_id.clone(),
+    names
+      .get(&transaction.account_id)
+      .cloned()
+      .unwrap_or_default(),
+    transaction.name.clone(),
+    transaction.merchant_name.clone().unwrap_or_default(),
+    crate::services::spending::SpendingService::categorize(transaction, &user.category_rules),
+    transaction
+      .amount
+      .currency
+      .unwrap_or(user.base_currency)
+      .to_string(),
+    transaction.amount.amount.to_string(),
+    transaction.pending.to_string(),
+    transaction.transfer_id.clone().unwrap_or_default(),
+  ]
+}
+
+fn export_error(_: ApiError) -> actix_web::Error {
+  actix_web::error::ErrorInternalServerError("Could not write the export")
+}
+
+// The header, then a line per transaction as it comes. A transaction that can't
+// be read ends the stream with an error, so the export isn't silently cut short.
+pub fn transaction_csv(
+  transactions: LocalBoxStream<'static, Result<Transaction, ApiError>>,
+  user: User,
+) -> LocalBoxStream<'static, Result<Bytes, actix_web::Error>> {
+  let names = account_names(&user);
+
+  stream::once(async { Ok(Bytes::from(csv_line(&TRANSACTION_HEADERS))) })
+    .chain(transactions.map(move |transaction| {
+      transaction
+        .map(|transaction| Bytes::from(csv_line(&transaction_row(&transaction, &names, &user))))
+        .map_err(export_error)
+    }))
+    .boxed_local()
+}
+
+// a JSON array with a transaction per line, ended early by an error like the CSV
+pub fn transaction_json(
+  transactions: LocalBoxStream<'static, Result<Transaction, ApiError>>,
+) -> LocalBoxStream<'static, Result<Bytes, actix_web::Error>> {
+  let items = transactions.enumerate().map(|(i, transaction)| {
+    let transaction = transaction.map_err(export_error)?;
+    serde_json::to_string(&transaction)
+      .map(|json| Bytes::from(format!("{}{}", if i == 0 { "\n" } else { ",\n" }, json)))
+      .map_err(|_| actix_web::error::ErrorInternalServerError("Could not write the export"))
+  });
+
+  stream::once(async { Ok(Bytes::from_static(b"[")) })
+    .chain(items)
+    .chain(stream::once(async { Ok(Bytes::from_static(b"\n]\n")) }))
+    .boxed_local()
+}
+
+#[allow(non_snake_case)]
+pub mod ExportService {
+  use super::*;
+  use crate::controllers::{
+    export_controller::ExportQuery, transactions_controller::TransactionQuery,
+  };
+  use crate::services::transactions::TransactionService;
+  use actix_web::web::Data;
+
+  fn export<T: Serialize>(
+    name: &str,
+    format: ExportFormat,
+    items: &Vec<T>,
+    headers: &[&str],
+    rows: Vec<Vec<String>>,
+  ) -> Result<Export, ApiError> {
+    match format {
+      ExportFormat::Csv => Ok(Export {
+        content_type: "text/csv; charset=utf-8",
+        filename: format!("{}.csv", name),
+        body: ExportBody::Whole(to_csv(headers, rows)),
+      }),
+      ExportFormat::Json => Ok(Export {
+        content_type: "application/json",
+        filename: format!("{}.json", name),
+        body: ExportBody::Whole(
+          serde_json::to_string_pretty(items)
+            .map_err(|_| ApiError::new(500, "Could not write the export".to_string()))?,
+        ),
+      }),
+      ExportFormat::Ofx => Err(ApiError::new(
+        400,
+        format!("{} can't be exported as OFX, only transactions", name),
+      )),
+    }
+  }
+
+  pub fn export_snapshots(user: User, query: ExportQuery) -> Result<Export, ApiError> {
+    let range = DateRange::new(&query.from, &query.to)?;
+    let snapshots: Vec<Snapshot> = user
+      .snapshots
+      .iter()
+      .filter(|snapshot| range.contains(snapshot.snapshot_time))
+      .cloned()
+      .collect();
+
+    export(
+      "snapshots",
+      query.format,
+      &snapshots,
+      &SNAPSHOT_HEADERS,
+      snapshot_rows(&snapshots, &user),
+    )
+  }
+
+  // recurrings active at some point in the range
+  pub fn export_recurrings(user: User, query: ExportQuery) -> Result<Export, ApiError> {
+    let range = DateRange::new(&query.from, &query.to)?;
+    let recurrings: Vec<Recurring> = user
+      .recurrings
+      .iter()
+      .filter(|recurring| range.overlaps(recurring.start, recurring.end))
+      .cloned()
+      .collect();
+
+    export(
+      "recurrings",
+      query.format,
+      &recurrings,
+      &RECURRING_HEADERS,
+      recurring_rows(&recurrings, &user),
+    )
+  }
+
+  // goals running at some point in the range
+  pub fn export_goals(user: User, query: ExportQuery) -> Result<Export, ApiError> {
+    let range = DateRange::new(&query.from, &query.to)?;
+    let goals: Vec<Goal> = user
+      .goals
+      .iter()
+      .filter(|goal| range.overlaps(goal.start, goal.end))
+      .cloned()
+      .collect();
+
+    export(
+      "goals",
+      query.format,
+      &goals,
+      &GOAL_HEADERS,
+      goal_rows(&goals, &user),
+    )
+  }
+
+  // the range picks which recurrings, allocations and events of each plan are kept
+  pub fn export_plans(user: User, query: ExportQuery) -> Result<Export, ApiError> {
+    let range = DateRange::new(&query.from, &query.to)?;
+    let plans: Vec<Plan> = user
+      .plans
+      .iter()
+      .map(|plan| {
+        let mut plan = plan.clone();
+        plan
+          .recurrings
+          .retain(|recurring| range.overlaps(recurring.start, recurring.end));
+        plan
+          .allocations
+          .retain(|allocation| range.contains(allocation.date));
+        plan.events.retain(|event| range.contains(event.start));
+        plan
+      })
+      .collect();
+
+    export(
+      "plans",
+      query.format,
+      &plans,
+      &PLAN_HEADERS,
+      plan_rows(&user.plans, &range, &user),
+    )
+  }
+
+  // Oldest first. CSV and JSON are streamed, OFX groups the transactions into a
+  // statement per account so it's written once they're all read.
+  pub async fn export_transactions(
+    user: User,
+    query: ExportQuery,
+    transaction_service: Data<TransactionService>,
+  ) -> Result<Export, ApiError> {
+    let range = DateRange::new(&query.from, &query.to)?;
+    let transactions = transaction_service
+      .stream_transactions(
+        &user.id.clone().unwrap(),
+        TransactionQuery {
+          from: query.from.clone(),
+          to: query.to.clone(),
+          account_id: None,
+          min_amount: None,
+          max_amount: None,
+        },
+      )
+      .await?;
+
+    match query.format {
+      ExportFormat::Csv => Ok(Export {
+        content_type: "text/csv; charset=utf-8",
+        filename: "transactions.csv".to_string(),
+        body: ExportBody::Rows(transaction_csv(transactions, user)),
+      }),
+      ExportFormat::Json => Ok(Export {
+        content_type: "application/json",
+        filename: "transactions.json".to_string(),
+        body: ExportBody::Rows(transaction_json(transactions)),
+      }),
+      ExportFormat::Ofx => {
+        let transactions: Vec<Transaction> = transactions.try_collect().await?;
+        Ok(Export {
+          content_type: "application/x-ofx",
+          filename: "transactions.ofx".to_string(),
+          body: ExportBody::Whole(ofx::write(&transactions, &range, user.base_currency)),
+        })
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn timestamp(date: &str) -> i64 {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+      .unwrap()
+      .and_hms(12, 0, 0)
+      .timestamp()
+  }
+
+  #[test]
+  fn test_date_range() {
+    let range = DateRange::new(
+      &Some("2021-03-01".to_string()),
+      &Some("2021-03-31".to_string()),
+    )
+    .unwrap();
+
+    assert!(range.contains(timestamp("2021-03-01")));
+    assert!(range.contains(timestamp("2021-03-31")));
+    assert!(!range.contains(timestamp("2021-04-01")));
+
+    // a goal from February to April runs through March
+    assert!(range.overlaps(timestamp("2021-02-01"), timestamp("2021-04-30")));
+    assert!(!range.overlaps(timestamp("2021-01-01"), timestamp("2021-02-28")));
+
+    let open = DateRange::new(&None, &Some("2021-03-31".to_string())).unwrap();
+    assert!(open.contains(timestamp("1999-01-01")));
+
+    assert!(DateRange::new(
+      &Some("2021-04-01".to_string()),
+      &Some("2021-03-01".to_string())
+    )
+    .is_err());
+    assert!(DateRange::new(&Some("03/01/2021".to_string()), &None).is_err());
+  }
+
+  #[test]
+  fn test_to_csv() {
+    let csv = to_csv(
+      &["name", "amount"],
+      vec![
+        vec!["COFFEE, INC".to_string(), "4.50".to_string()],
+        vec!["say \"hi\"".to_string(), "-1".to_string()],
+      ],
+    );
+
+    assert_eq!(
+      "name,amount\r\n\"COFFEE, INC\",4.50\r\n\"say \"\"hi\"\"\",-1\r\n",
+      csv
+    );
+
+    // what we write, our importer reads back
+    assert_eq!(
+      vec![
+        vec!["name", "amount"],
+        vec!["COFFEE, INC", "4.50"],
+        vec!["say \"hi\"", "-1"]
+      ],
+      crate::services::imports::csv::parse_rows(&csv)
+    );
+  }
+
+  #[test]
+  fn test_csv_field_neutralises_formulas() {
+    assert_eq!("'=1+1", csv_field("=1+1"));
+    assert_eq!("'@SUM(A1)", csv_field("@SUM(A1)"));
+    assert_eq!("'+cmd", csv_field("+cmd"));
+    assert_eq!("\"'=HYPERLINK(\"\"x\"\")\"", csv_field("=HYPERLINK(\"x\")"));
+
+    // amounts keep their sign
+    assert_eq!("-12.50", csv_field("-12.50"));
+    assert_eq!("COFFEE", csv_field("COFFEE"));
+  }
+
+  #[actix_rt::test]
+  async fn test_transaction_json_streams_an_array() {
+    use crate::common::Money;
+    use rust_decimal_macros::dec;
+
+    let read = |transactions: Vec<Transaction>| async move {
+      let chunks: Vec<Bytes> = transaction_json(stream::iter(transactions).map(Ok).boxed_local())
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+      let json: Vec<u8> = chunks.concat();
+      serde_json::from_slice::<Vec<Transaction>>(&json).unwrap()
+    };
+
+    assert!(read(vec![]).await.is_empty());
+
+    let transactions = vec![
+      Transaction::fixture("a", "COFFEE", Money::new(dec!(4.50)), "2021-03-01"),
+      Transaction::fixture("b", "RENT", Money::new(dec!(1000)), "2021-03-02"),
+    ];
+    let read_back = read(transactions.clone()).await;
+    assert_eq!(2, read_back.len());
+    assert_eq!("a", read_back[0].transaction_id);
+    assert_eq!("b", read_back[1].transaction_id);
+  }
+
+  #[actix_rt::test]
+  async fn test_transaction_json_ends_with_read_errors() {
+    use crate::common::Money;
+    use rust_decimal_macros::dec;
+
+    let transactions = vec![
+      Ok(Transaction::fixture(
+        "a",
+        "COFFEE",
+        Money::new(dec!(4.50)),
+        "2021-03-01",
+      )),
+      Err(ApiError::new(500, "Database Error".to_string())),
+    ];
+    let chunks: Vec<Result<Bytes, actix_web::Error>> =
+      transaction_json(stream::iter(transactions).boxed_local())
+        .collect()
+        .await;
+
+    // the opening bracket and the first transaction, then the error that aborts the response
+    assert_eq!(4, chunks.len());
+    assert!(chunks[1].is_ok());
+    assert!(chunks[2].is_err());
+  }
+}
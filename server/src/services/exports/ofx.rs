@@ -0,0 +1,170 @@
+use super::DateRange;
+use crate::common::Currency;
+use crate::models::transaction_model::Transaction;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+fn escape(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+// OFX wants YYYYMMDD
+fn ofx_date(date: &str) -> String {
+  date.replace('-', "")
+}
+
+fn range_date(date: Option<NaiveDate>, fallback: Option<&String>) -> String {
+  date
+    .map(|date| date.format("%Y%m%d").to_string())
+    .or(fallback.map(|date| ofx_date(date)))
+    .unwrap_or_default()
+}
+
+// An OFX 1.0.2 file with a bank statement per account, which desktop finance
+// apps and our own importer read. Pending transactions are left out, since
+// they may still change or disappear.
+pub fn write(
+  transactions: &Vec<Transaction>,
+  range: &DateRange,
+  base_currency: Currency,
+) -> String {
+  let mut accounts: BTreeMap<&String, Vec<&Transaction>> = BTreeMap::new();
+  for transaction in transactions
+    .iter()
+    .filter(|transaction| !transaction.pending)
+  {
+    accounts
+      .entry(&transaction.account_id)
+      .or_insert_with(Vec::new)
+      .push(transaction);
+  }
+
+  let mut ofx = String::from(
+    "OFXHEADER:100\r\nDATA:OFXSGML\r\nVERSION:102\r\nSECURITY:NONE\r\nENCODING:USASCII\r\n\
+     CHARSET:1252\r\nCOMPRESSION:NONE\r\nOLDFILEUID:NONE\r\nNEWFILEUID:NONE\r\n\r\n",
+  );
+  ofx.push_str("<OFX>\r\n<SIGNONMSGSRSV1><SONRS>\r\n");
+  ofx.push_str("<STATUS><CODE>0<SEVERITY>INFO</STATUS>\r\n");
+  ofx.push_str(&format!(
+    "<DTSERVER>{}\r\n<LANGUAGE>ENG\r\n",
+    chrono::Utc::now().format("%Y%m%d%H%M%S")
+  ));
+  ofx.push_str("</SONRS></SIGNONMSGSRSV1>\r\n<BANKMSGSRSV1>\r\n");
+
+  for (account_id, transactions) in accounts.iter() {
+    let currency = transactions[0].amount.currency.unwrap_or(base_currency);
+
+    ofx.push_str("<STMTTRNRS>\r\n<TRNUID>0\r\n");
+    ofx.push_str("<STATUS><CODE>0<SEVERITY>INFO</STATUS>\r\n<STMTRS>\r\n");
+    ofx.push_str(&format!("<CURDEF>{}\r\n", currency));
+    ofx.push_str(&format!(
+      "<BANKACCTFROM><BANKID>{}<ACCTID>{}<ACCTTYPE>CHECKING</BANKACCTFROM>\r\n",
+      escape(&transactions[0].item_id),
+      escape(account_id)
+    ));
+    ofx.push_str(&format!(
+      "<BANKTRANLIST>\r\n<DTSTART>{}\r\n<DTEND>{}\r\n",
+      range_date(range.from, transactions.first().map(|t| &t.date)),
+      range_date(range.to, transactions.last().map(|t| &t.date))
+    ));
+
+    for transaction in transactions.iter() {
+      // OFX amounts are negative for money out, the ledger's are positive
+      let amount = -transaction.amount.amount;
+      ofx.push_str("<STMTTRN>\r\n");
+      ofx.push_str(&format!(
+        "<TRNTYPE>{}\r\n",
+        if amount < rust_decimal::Decimal::new(0, 0) {
+          "DEBIT"
+        } else {
+          "CREDIT"
+        }
+      ));
+      ofx.push_str(&format!("<DTPOSTED>{}\r\n", ofx_date(&transaction.date)));
+      ofx.push_str(&format!("<TRNAMT>{}\r\n", amount));
+      ofx.push_str(&format!(
+        "<FITID>{}\r\n",
+        escape(&transaction.transaction_id)
+      ));
+      // OFX 1.0.2 allows names of up to 32 characters
+      ofx.push_str(&format!(
+        "<NAME>{}\r\n",
+        escape(&transaction.name.chars().take(32).collect::<String>())
+      ));
+      if let Some(merchant_name) = transaction.merchant_name.as_ref() {
+        ofx.push_str(&format!("<MEMO>{}\r\n", escape(merchant_name)));
+      }
+      ofx.push_str("</STMTTRN>\r\n");
+    }
+
+    ofx.push_str("</BANKTRANLIST>\r\n</STMTRS>\r\n</STMTTRNRS>\r\n");
+  }
+
+  ofx.push_str("</BANKMSGSRSV1>\r\n</OFX>\r\n");
+  ofx
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Money;
+  use crate::services::imports::{self, ImportFormat, StatementLine};
+  use rust_decimal_macros::dec;
+
+  fn transaction(id: &str, name: &str, amount: Money, pending: bool) -> Transaction {
+    Transaction {
+      pending,
+      ..Transaction::fixture(id, name, amount, "2021-03-15")
+    }
+  }
+
+  #[test]
+  fn test_write() {
+    let transactions = vec![
+      transaction(
+        "1",
+        "GROCER & SONS",
+        Money::in_currency(dec!(50.25), Currency::USD),
+        false,
+      ),
+      transaction(
+        "2",
+        "PAYROLL",
+        Money::in_currency(dec!(-2000), Currency::USD),
+        false,
+      ),
+      transaction("3", "PENDING", Money::new(dec!(1)), true),
+    ];
+    let range = DateRange {
+      from: None,
+      to: None,
+    };
+    let ofx = write(&transactions, &range, Currency::USD);
+
+    assert!(ofx.contains("<CURDEF>USD"));
+    assert!(ofx.contains("<DTSTART>20210315"));
+
+    // what we write, our importer reads back
+    let statement = imports::parse(&ImportFormat::Ofx, &ofx).unwrap();
+    assert_eq!(
+      vec![
+        StatementLine {
+          date: "2021-03-15".to_string(),
+          amount: dec!(50.25),
+          name: "GROCER & SONS".to_string(),
+          id: Some("1".to_string()),
+        },
+        StatementLine {
+          date: "2021-03-15".to_string(),
+          amount: dec!(-2000),
+          name: "PAYROLL".to_string(),
+          id: Some("2".to_string()),
+        },
+      ],
+      statement.transactions
+    );
+  }
+}
@@ -0,0 +1,448 @@
+use super::{InvestmentHoldings, LiabilityDetails, WebhookVerificationKey};
+use crate::common::errors::ApiError;
+use async_trait::async_trait;
+use plaid::apis::configuration::Configuration;
+use plaid::models::*;
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryFrom;
+
+// The Plaid endpoints finch uses, taking access tokens in plaintext. Errors
+// follow Plaid's, with its status, ERROR_CODE and message.
+#[async_trait]
+pub trait PlaidBackend: Send + Sync {
+  // with an access token, the link token opens Link in update mode for that item
+  async fn create_link_token(
+    &self,
+    user_id: String,
+    access_token: Option<String>,
+  ) -> Result<CreateLinkTokenResponse, ApiError>;
+
+  async fn exchange_token(&self, public_token: String) -> Result<ExchangeTokenResponse, ApiError>;
+
+  async fn get_accounts(
+    &self,
+    access_token: String,
+  ) -> Result<RetrieveAnItemsAccountsResponse, ApiError>;
+
+  // dates are inclusive and formatted %Y-%m-%d
+  async fn get_transactions_page(
+    &self,
+    access_token: String,
+    start_date: String,
+    end_date: String,
+    count: i64,
+    offset: i64,
+  ) -> Result<RetrieveTransactionsResponse, ApiError>;
+
+  async fn get_investment_holdings(
+    &self,
+    access_token: String,
+  ) -> Result<InvestmentHoldings, ApiError>;
+
+  async fn get_liabilities(&self, access_token: String) -> Result<Vec<LiabilityDetails>, ApiError>;
+
+  async fn get_webhook_verification_key(
+    &self,
+    key_id: String,
+  ) -> Result<WebhookVerificationKey, ApiError>;
+}
+
+// /transactions/get with paging options, which the generated client doesn't expose
+#[derive(Serialize)]
+struct TransactionsPageRequest {
+  client_id: String,
+  secret: String,
+  access_token: String,
+  start_date: String,
+  end_date: String,
+  options: TransactionsPageOptions,
+}
+
+#[derive(Serialize)]
+struct TransactionsPageOptions {
+  count: i64,
+  offset: i64,
+}
+
+// the body of Plaid endpoints that only need the item
+#[derive(Serialize)]
+struct AccessTokenRequest {
+  client_id: String,
+  secret: String,
+  access_token: String,
+}
+
+// /link/token/create for an existing item, which takes its access token and no products
+#[derive(Serialize)]
+struct UpdateLinkTokenRequest {
+  client_id: String,
+  secret: String,
+  client_name: String,
+  country_codes: Vec<String>,
+  language: String,
+  user: LinkTokenUser,
+  access_token: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  webhook: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LinkTokenUser {
+  client_user_id: String,
+}
+
+// the body of a failed Plaid request
+#[derive(Deserialize)]
+struct PlaidErrorResponse {
+  error_code: String,
+  error_message: String,
+}
+
+// /liabilities/get, with only the fields that are kept
+#[derive(Deserialize)]
+pub(super) struct LiabilitiesResponse {
+  pub(super) liabilities: PlaidLiabilities,
+}
+
+#[derive(Deserialize)]
+pub(super) struct PlaidLiabilities {
+  #[serde(default)]
+  credit: Option<Vec<CreditLiability>>,
+  #[serde(default)]
+  student: Option<Vec<StudentLiability>>,
+  #[serde(default)]
+  mortgage: Option<Vec<MortgageLiability>>,
+}
+
+#[derive(Deserialize)]
+struct CreditLiability {
+  account_id: Option<String>,
+  aprs: Vec<CreditApr>,
+  minimum_payment_amount: Option<f64>,
+  next_payment_due_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreditApr {
+  apr_percentage: f64,
+  apr_type: String,
+}
+
+#[derive(Deserialize)]
+struct StudentLiability {
+  account_id: Option<String>,
+  interest_rate_percentage: f64,
+  minimum_payment_amount: Option<f64>,
+  next_payment_due_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MortgageLiability {
+  account_id: String,
+  interest_rate: MortgageInterestRate,
+  next_monthly_payment: Option<f64>,
+  next_payment_due_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MortgageInterestRate {
+  percentage: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct WebhookVerificationKeyRequest {
+  client_id: String,
+  secret: String,
+  key_id: String,
+}
+
+#[derive(Deserialize)]
+struct WebhookVerificationKeyResponse {
+  key: WebhookVerificationKey,
+}
+
+// Plaid itself
+#[derive(Clone)]
+pub struct PlaidHttpBackend {
+  pub client_id: String,
+  pub secret: String,
+  pub client_name: String,
+  pub webhook_url: Option<String>,
+  pub country_codes: Vec<String>,
+}
+
+impl PlaidHttpBackend {
+  fn get_configuration() -> Configuration {
+    Configuration::default()
+  }
+
+  fn access_token_request(&self, access_token: String) -> AccessTokenRequest {
+    AccessTokenRequest {
+      client_id: self.client_id.clone(),
+      secret: self.secret.clone(),
+      access_token,
+    }
+  }
+
+  // Posts to a Plaid endpoint. Errors Plaid explains keep its status and carry its
+  // ERROR_CODE, anything else is a 500 with `context`.
+  async fn post<Req: Serialize, Resp: DeserializeOwned>(
+    path: &str,
+    request: &Req,
+    context: &str,
+  ) -> Result<Resp, ApiError> {
+    let config = Self::get_configuration();
+
+    let response = reqwest::Client::new()
+      .post(&format!("{}{}", config.base_path, path))
+      .json(request)
+      .send()
+      .await
+      .map_err(|_| ApiError::new(500, context.to_string()))?;
+
+    if !response.status().is_success() {
+      let status = response.status().as_u16();
+      return Err(match response.json::<PlaidErrorResponse>().await {
+        Ok(error) => ApiError::plaid(status, error.error_code, error.error_message),
+        Err(_) => ApiError::new(500, context.to_string()),
+      });
+    }
+
+    response
+      .json::<Resp>()
+      .await
+      .map_err(|_| ApiError::new(500, context.to_string()))
+  }
+}
+
+#[async_trait]
+impl PlaidBackend for PlaidHttpBackend {
+  async fn create_link_token(
+    &self,
+    user_id: String,
+    access_token: Option<String>,
+  ) -> Result<CreateLinkTokenResponse, ApiError> {
+    if let Some(access_token) = access_token {
+      return Self::post(
+        "/link/token/create",
+        &UpdateLinkTokenRequest {
+          client_id: self.client_id.clone(),
+          secret: self.secret.clone(),
+          client_name: self.client_name.clone(),
+          country_codes: self.country_codes.clone(),
+          language: "en".to_string(),
+          user: LinkTokenUser {
+            client_user_id: user_id,
+          },
+          access_token,
+          webhook: self.webhook_url.clone(),
+        },
+        "Error while creating link token",
+      )
+      .await;
+    }
+
+    let config = Self::get_configuration();
+    let mut request = plaid::models::CreateLinkTokenRequest::new(
+      self.client_id.clone(),
+      self.secret.clone(),
+      self.client_name.clone(),
+      self.country_codes.clone(),
+      "en".to_string(),
+      plaid::models::User::new(user_id),
+      vec!["auth".to_string(), "transactions".to_string()],
+    );
+    request.webhook = self.webhook_url.clone();
+
+    plaid::apis::link_tokens_api::create_link_token(&config, request)
+      .await
+      .or(Err(ApiError::new(500, "".to_string())))
+  }
+
+  async fn exchange_token(&self, public_token: String) -> Result<ExchangeTokenResponse, ApiError> {
+    let config = Self::get_configuration();
+
+    plaid::apis::item_creation_api::exchange_token(
+      &config,
+      plaid::models::ExchangeTokenRequest::new(
+        self.client_id.clone(),
+        self.secret.clone(),
+        public_token,
+      ),
+    )
+    .await
+    .map_err(|_| ApiError::new(500, "Plaid Exchange Token Error".to_string()))
+  }
+
+  // goes around the generated client so that Plaid's error code survives,
+  // which is how items needing a new login are noticed
+  async fn get_accounts(
+    &self,
+    access_token: String,
+  ) -> Result<RetrieveAnItemsAccountsResponse, ApiError> {
+    Self::post(
+      "/accounts/get",
+      &self.access_token_request(access_token),
+      "Error while getting accounts",
+    )
+    .await
+  }
+
+  async fn get_transactions_page(
+    &self,
+    access_token: String,
+    start_date: String,
+    end_date: String,
+    count: i64,
+    offset: i64,
+  ) -> Result<RetrieveTransactionsResponse, ApiError> {
+    Self::post(
+      "/transactions/get",
+      &TransactionsPageRequest {
+        client_id: self.client_id.clone(),
+        secret: self.secret.clone(),
+        access_token,
+        start_date,
+        end_date,
+        options: TransactionsPageOptions { count, offset },
+      },
+      "Error while getting transactions",
+    )
+    .await
+  }
+
+  async fn get_investment_holdings(
+    &self,
+    access_token: String,
+  ) -> Result<InvestmentHoldings, ApiError> {
+    Self::post(
+      "/investments/holdings/get",
+      &self.access_token_request(access_token),
+      "Error while getting investment holdings",
+    )
+    .await
+  }
+
+  async fn get_liabilities(&self, access_token: String) -> Result<Vec<LiabilityDetails>, ApiError> {
+    Self::post::<_, LiabilitiesResponse>(
+      "/liabilities/get",
+      &self.access_token_request(access_token),
+      "Error while getting liabilities",
+    )
+    .await
+    .map(|response| liability_details(response.liabilities))
+  }
+
+  async fn get_webhook_verification_key(
+    &self,
+    key_id: String,
+  ) -> Result<WebhookVerificationKey, ApiError> {
+    Self::post::<_, WebhookVerificationKeyResponse>(
+      "/webhook_verification_key/get",
+      &WebhookVerificationKeyRequest {
+        client_id: self.client_id.clone(),
+        secret: self.secret.clone(),
+        key_id,
+      },
+      "Error while getting webhook key",
+    )
+    .await
+    .map(|response| response.key)
+  }
+}
+
+fn to_decimal(value: Option<f64>) -> Option<Decimal> {
+  value.and_then(|value| Decimal::try_from(value).ok())
+}
+
+pub(super) fn liability_details(liabilities: PlaidLiabilities) -> Vec<LiabilityDetails> {
+  let credit = liabilities
+    .credit
+    .unwrap_or_default()
+    .into_iter()
+    .filter_map(|card| {
+      let apr = card
+        .aprs
+        .iter()
+        .find(|apr| apr.apr_type == "purchase_apr")
+        .or(card.aprs.first())
+        .map(|apr| apr.apr_percentage);
+
+      Some(LiabilityDetails {
+        account_id: card.account_id?,
+        apr: to_decimal(apr),
+        minimum_payment: to_decimal(card.minimum_payment_amount),
+        next_payment_due_date: card.next_payment_due_date,
+      })
+    });
+
+  let student = liabilities
+    .student
+    .unwrap_or_default()
+    .into_iter()
+    .filter_map(|loan| {
+      Some(LiabilityDetails {
+        account_id: loan.account_id?,
+        apr: to_decimal(Some(loan.interest_rate_percentage)),
+        minimum_payment: to_decimal(loan.minimum_payment_amount),
+        next_payment_due_date: loan.next_payment_due_date,
+      })
+    });
+
+  let mortgage = liabilities
+    .mortgage
+    .unwrap_or_default()
+    .into_iter()
+    .map(|loan| LiabilityDetails {
+      account_id: loan.account_id,
+      apr: to_decimal(loan.interest_rate.percentage),
+      minimum_payment: to_decimal(loan.next_monthly_payment),
+      next_payment_due_date: loan.next_payment_due_date,
+    });
+
+  credit.chain(student).chain(mortgage).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_liability_details() {
+    let response: LiabilitiesResponse = serde_json::from_str(
+      r#"{
+        "liabilities": {
+          "credit": [{
+            "account_id": "card",
+            "aprs": [
+              {"apr_percentage": 27.95, "apr_type": "cash_apr"},
+              {"apr_percentage": 15.24, "apr_type": "purchase_apr"}
+            ],
+            "minimum_payment_amount": 20,
+            "next_payment_due_date": "2020-05-28"
+          }],
+          "student": [{
+            "account_id": "student",
+            "interest_rate_percentage": 5.25,
+            "minimum_payment_amount": 25,
+            "next_payment_due_date": "2019-05-28"
+          }],
+          "mortgage": null
+        }
+      }"#,
+    )
+    .unwrap();
+
+    let details = liability_details(response.liabilities);
+
+    assert_eq!(2, details.len());
+    assert_eq!(Some(Decimal::new(1524, 2)), details[0].apr);
+    assert_eq!(Some(Decimal::new(20, 0)), details[0].minimum_payment);
+    assert_eq!("student", details[1].account_id);
+    assert_eq!(
+      Some("2019-05-28".to_string()),
+      details[1].next_payment_due_date
+    );
+  }
+}
@@ -0,0 +1,378 @@
+use super::backend::{liability_details, LiabilitiesResponse};
+use super::{InvestmentHoldings, LiabilityDetails, PlaidBackend, WebhookVerificationKey};
+use crate::common::errors::ApiError;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use plaid::models::*;
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// A deterministic Plaid for offline development and tests, answering from a
+// /transactions/get response, which also serves /accounts/get, and the JSON
+// responses in a fixtures directory (see tests/fixtures/plaid):
+//  - link_token.json, a /link/token/create response
+//  - investments_holdings.json and liabilities.json, optional, in Plaid's format
+// Each access token is its own item, holding all the accounts and transactions
+// with the item's id appended to their ids.
+#[derive(Clone)]
+pub struct FakePlaidBackend {
+  link_token: CreateLinkTokenResponse,
+  accounts: RetrieveAnItemsAccountsResponse,
+  transactions: RetrieveTransactionsResponse,
+  holdings: Option<InvestmentHoldings>,
+  liabilities: Option<Vec<LiabilityDetails>>,
+}
+
+impl FakePlaidBackend {
+  // transactions are moved forward so the latest one falls on `today`
+  pub fn from_fixtures(
+    dir: &str,
+    transactions: &str,
+    today: NaiveDate,
+  ) -> Result<FakePlaidBackend, ApiError> {
+    let accounts = read_file(Path::new(transactions))?;
+    let mut transactions: RetrieveTransactionsResponse = read_file(Path::new(transactions))?;
+    shift_transactions(&mut transactions.transactions, today);
+
+    let dir = Path::new(dir);
+    Ok(FakePlaidBackend {
+      link_token: read_fixture(dir, "link_token.json")?,
+      accounts,
+      transactions,
+      holdings: read_optional_fixture(dir, "investments_holdings.json")?,
+      liabilities: read_optional_fixture::<LiabilitiesResponse>(dir, "liabilities.json")?
+        .map(|response| liability_details(response.liabilities)),
+    })
+  }
+}
+
+fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, ApiError> {
+  let file = File::open(path)
+    .map_err(|_| ApiError::new(500, format!("Missing Plaid fixture {}", path.display())))?;
+
+  serde_json::from_reader(BufReader::new(file))
+    .map_err(|e| ApiError::new(500, format!("Bad Plaid fixture {}: {}", path.display(), e)))
+}
+
+fn read_fixture<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<T, ApiError> {
+  read_file(&dir.join(name))
+}
+
+// the item an access token from `exchange_token` stands for
+fn item_id_of(access_token: &str) -> String {
+  format!(
+    "item-fake-{}",
+    access_token.trim_start_matches("access-fake-")
+  )
+}
+
+// an account or transaction id of the fixtures as the item's own
+fn item_scoped(id: &str, item_id: &str) -> String {
+  format!("{}-{}", id, item_id)
+}
+
+fn scope_accounts(accounts: &mut Vec<Account>, item_id: &str) {
+  for account in accounts.iter_mut() {
+    account.account_id = item_scoped(&account.account_id, item_id);
+  }
+}
+
+fn read_optional_fixture<T: DeserializeOwned>(
+  dir: &Path,
+  name: &str,
+) -> Result<Option<T>, ApiError> {
+  if dir.join(name).exists() {
+    read_fixture(dir, name).map(Some)
+  } else {
+    Ok(None)
+  }
+}
+
+fn shift_transactions(transactions: &mut Vec<Transaction>, today: NaiveDate) {
+  let parse = |date: &String| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+
+  let latest = match transactions.iter().filter_map(|t| parse(&t.date)).max() {
+    Some(latest) => latest,
+    None => return,
+  };
+  let shift = today.signed_duration_since(latest);
+
+  for transaction in transactions.iter_mut() {
+    if let Some(date) = parse(&transaction.date) {
+      transaction.date = (date + shift).format("%Y-%m-%d").to_string();
+    }
+  }
+}
+
+fn not_supported(product: &str) -> ApiError {
+  ApiError::plaid(
+    400,
+    "PRODUCTS_NOT_SUPPORTED".to_string(),
+    format!("the Plaid fixtures have no {}", product),
+  )
+}
+
+#[async_trait]
+impl PlaidBackend for FakePlaidBackend {
+  async fn create_link_token(
+    &self,
+    _user_id: String,
+    _access_token: Option<String>,
+  ) -> Result<CreateLinkTokenResponse, ApiError> {
+    Ok(self.link_token.clone())
+  }
+
+  // the item takes its id from the public token, so linking twice makes two items
+  async fn exchange_token(&self, public_token: String) -> Result<ExchangeTokenResponse, ApiError> {
+    serde_json::from_value(serde_json::json!({
+      "access_token": format!("access-fake-{}", public_token),
+      "item_id": format!("item-fake-{}", public_token),
+      "request_id": "fake",
+    }))
+    .map_err(|_| ApiError::new(500, "Plaid Exchange Token Error".to_string()))
+  }
+
+  async fn get_accounts(
+    &self,
+    access_token: String,
+  ) -> Result<RetrieveAnItemsAccountsResponse, ApiError> {
+    let mut response = self.accounts.clone();
+    scope_accounts(&mut response.accounts, &item_id_of(&access_token));
+    Ok(response)
+  }
+
+  // newest first, as Plaid pages them
+  async fn get_transactions_page(
+    &self,
+    access_token: String,
+    start_date: String,
+    end_date: String,
+    count: i64,
+    offset: i64,
+  ) -> Result<RetrieveTransactionsResponse, ApiError> {
+    let mut transactions: Vec<Transaction> = self
+      .transactions
+      .transactions
+      .iter()
+      .filter(|t| start_date <= t.date && t.date <= end_date)
+      .cloned()
+      .collect();
+    transactions.sort_by(|a, b| b.date.cmp(&a.date));
+
+    let item_id = item_id_of(&access_token);
+    let total = transactions.len();
+    let page = transactions
+      .into_iter()
+      .skip(offset.max(0) as usize)
+      .take(count.max(0) as usize)
+      .map(|mut transaction| {
+        transaction.account_id = item_scoped(&transaction.account_id, &item_id);
+        transaction.transaction_id = item_scoped(&transaction.transaction_id, &item_id);
+        transaction.pending_transaction_id = transaction
+          .pending_transaction_id
+          .map(|id| item_scoped(&id, &item_id));
+        transaction
+      })
+      .collect();
+
+    let mut response = self.transactions.clone();
+    scope_accounts(&mut response.accounts, &item_id);
+    response.transactions = page;
+    response.total_transactions = total as _;
+    Ok(response)
+  }
+
+  async fn get_investment_holdings(
+    &self,
+    access_token: String,
+  ) -> Result<InvestmentHoldings, ApiError> {
+    let item_id = item_id_of(&access_token);
+    let mut holdings = self
+      .holdings
+      .clone()
+      .ok_or(not_supported("investment holdings"))?;
+    for holding in holdings.holdings.iter_mut() {
+      holding.account_id = item_scoped(&holding.account_id, &item_id);
+    }
+    Ok(holdings)
+  }
+
+  async fn get_liabilities(&self, access_token: String) -> Result<Vec<LiabilityDetails>, ApiError> {
+    let item_id = item_id_of(&access_token);
+    let mut liabilities = self
+      .liabilities
+      .clone()
+      .ok_or(not_supported("liabilities"))?;
+    for liability in liabilities.iter_mut() {
+      liability.account_id = item_scoped(&liability.account_id, &item_id);
+    }
+    Ok(liabilities)
+  }
+
+  // fake items never send webhooks, so there is nothing to verify
+  async fn get_webhook_verification_key(
+    &self,
+    _key_id: String,
+  ) -> Result<WebhookVerificationKey, ApiError> {
+    Err(ApiError::plaid(
+      400,
+      "INVALID_FIELD".to_string(),
+      "the fake Plaid backend has no webhook keys".to_string(),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::encryption::{Keyring, MasterKey};
+  use crate::models::user_model::{PlaidItem, User};
+  use crate::services::{finchplaid::ApiClient, fx::FxService, snapshots::SnapshotService};
+  use actix_web::web::Data;
+  use rust_decimal_macros::dec;
+  use std::collections::HashSet;
+  use std::sync::Arc;
+
+  fn fake() -> FakePlaidBackend {
+    FakePlaidBackend::from_fixtures(
+      "./tests/fixtures/plaid",
+      "./tests/test_snapshots.json",
+      NaiveDate::from_ymd(2021, 3, 31),
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn test_shift_transactions() {
+    let fake = fake();
+    let dates: Vec<String> = fake
+      .transactions
+      .transactions
+      .iter()
+      .map(|t| t.date.clone())
+      .collect();
+
+    // the fixture runs from 2019-04-20 to 2019-05-06
+    assert_eq!("2021-03-31", dates[0]);
+    assert_eq!("2021-03-15", dates[dates.len() - 1]);
+  }
+
+  #[actix_rt::test]
+  async fn test_transaction_pages() {
+    let fake = fake();
+    let page = |start: &str, end: &str, count, offset| {
+      fake.get_transactions_page(
+        "access".to_string(),
+        start.to_string(),
+        end.to_string(),
+        count,
+        offset,
+      )
+    };
+
+    let first = page("2021-01-01", "2021-03-31", 4, 0).await.unwrap();
+    assert_eq!(4, first.transactions.len());
+    assert_eq!(10, first.total_transactions as i64);
+    assert_eq!("2021-03-31", first.transactions[0].date);
+
+    let last = page("2021-01-01", "2021-03-31", 4, 8).await.unwrap();
+    assert_eq!(2, last.transactions.len());
+    assert_eq!("2021-03-15", last.transactions[1].date);
+
+    let window = page("2021-03-25", "2021-03-25", 500, 0).await.unwrap();
+    assert_eq!(3, window.total_transactions as i64);
+    assert!(window.transactions.iter().all(|t| t.date == "2021-03-25"));
+  }
+
+  #[actix_rt::test]
+  async fn test_optional_products() {
+    let fake = fake();
+
+    let liabilities = fake.get_liabilities("access".to_string()).await.unwrap();
+    assert_eq!(3, liabilities.len());
+
+    let without = FakePlaidBackend {
+      holdings: None,
+      ..fake
+    };
+    let error = without
+      .get_investment_holdings("access".to_string())
+      .await
+      .unwrap_err();
+    assert_eq!(400, error.code());
+    assert_eq!(
+      Some(&"PRODUCTS_NOT_SUPPORTED".to_string()),
+      error.plaid_code()
+    );
+  }
+
+  #[actix_rt::test]
+  async fn test_items_have_their_own_accounts() {
+    let fake = fake();
+    let a = fake
+      .get_accounts("access-fake-a".to_string())
+      .await
+      .unwrap();
+    let b = fake
+      .get_accounts("access-fake-b".to_string())
+      .await
+      .unwrap();
+
+    assert_eq!(a.accounts.len(), b.accounts.len());
+    assert!(a
+      .accounts
+      .iter()
+      .all(|account| account.account_id.ends_with("-item-fake-a")));
+    assert!(a
+      .accounts
+      .iter()
+      .zip(b.accounts.iter())
+      .all(|(a, b)| a.account_id != b.account_id));
+  }
+
+  // two linked fake items count as two sets of accounts
+  #[actix_rt::test]
+  async fn test_snapshot_through_the_client() {
+    let keyring = Keyring::new(
+      &vec![MasterKey {
+        id: "a".to_string(),
+        key: base64::encode([1u8; 32]),
+      }],
+      &"a".to_string(),
+    )
+    .unwrap();
+    let client = Data::new(ApiClient::new(Arc::new(fake()), keyring));
+    let fx = FxService::default();
+
+    let mut one = User::fixture("one@test.com");
+    one.accounts = vec![PlaidItem::fixture("item-fake-a", "access-fake-a")];
+    let mut two = User {
+      accounts: vec![
+        PlaidItem::fixture("item-fake-a", "access-fake-a"),
+        PlaidItem::fixture("item-fake-b", "access-fake-b"),
+      ],
+      ..one.clone()
+    };
+
+    SnapshotService::add_new_snapshot(&mut one, client.clone(), &fx)
+      .await
+      .unwrap();
+    SnapshotService::add_new_snapshot(&mut two, client.clone(), &fx)
+      .await
+      .unwrap();
+
+    let (one, two) = (&one.snapshots[0], &two.snapshots[0]);
+    assert!(!one.accounts.is_empty());
+    assert_eq!(2 * one.accounts.len(), two.accounts.len());
+    assert_eq!(one.net_worth.amount * dec!(2), two.net_worth.amount);
+
+    let account_ids: HashSet<&String> = two
+      .accounts
+      .iter()
+      .map(|account| &account.account_id)
+      .collect();
+    assert_eq!(two.accounts.len(), account_ids.len());
+  }
+}
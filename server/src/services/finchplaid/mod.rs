@@ -1,108 +1,298 @@
-use crate::common::{errors::ApiError, Money};
+mod backend;
+mod fake;
+
+pub use backend::{PlaidBackend, PlaidHttpBackend};
+pub use fake::FakePlaidBackend;
+
+use crate::common::{
+  encryption::{Keyring, StoredSecret},
+  errors::ApiError,
+  Currency, Money,
+};
 use crate::controllers::plaid_controller::AccountSuccess;
 use crate::controllers::plaid_controller::ItemIdResponse;
 use crate::models::user_model::{PlaidItem, User};
-use crate::services::{financial_products::FinProductService, users::UserService};
+use crate::services::{
+  financial_products::FinProductService, fx::FxService, secrets::Environment, users::UserService,
+};
 use actix_web::web::Data;
-use plaid::apis::configuration::Configuration;
+use chrono::Utc;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use plaid::models::*;
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::sync::Arc;
+
+// the most transactions Plaid returns in one page
+const TRANSACTIONS_PAGE_SIZE: i64 = 500;
+
+// the positions held in an item's investment accounts, and the securities they are in
+#[derive(Deserialize, Clone, Debug)]
+pub struct InvestmentHoldings {
+  pub holdings: Vec<Holding>,
+  pub securities: Vec<Security>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Holding {
+  pub account_id: String,
+  pub security_id: String,
+  pub institution_value: f64,
+  pub quantity: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Security {
+  pub security_id: String,
+  pub name: Option<String>,
+  pub ticker_symbol: Option<String>,
+  // e.g. "equity", "etf", "mutual fund", "fixed income", "cash", "derivative"
+  #[serde(rename = "type")]
+  pub security_type: Option<String>,
+}
+
+// the terms of a credit card or loan, from Plaid's Liabilities product
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LiabilityDetails {
+  pub account_id: String,
+  // as a percentage, for cards the APR on purchases
+  pub apr: Option<Decimal>,
+  pub minimum_payment: Option<Decimal>,
+  pub next_payment_due_date: Option<String>, // %Y-%m-%d
+}
+
+// a P-256 public key as a JWK, with base64url coordinates
+#[derive(Deserialize, Clone, Debug)]
+pub struct WebhookVerificationKey {
+  pub alg: String,
+  pub crv: String,
+  pub kid: String,
+  pub x: String,
+  pub y: String,
+  pub expired_at: Option<i64>,
+}
 
 #[derive(Clone)]
 pub struct ApiClient {
-  pub client_id: String,
-  pub secret: String,
-  pub client_name: String,
+  backend: Arc<dyn PlaidBackend>,
+  // access tokens are stored encrypted and only decrypted here, for each request
+  pub keyring: Keyring,
 }
 
 impl ApiClient {
-  fn get_configuration() -> Configuration {
-    Configuration::default()
+  pub fn new(backend: Arc<dyn PlaidBackend>, keyring: Keyring) -> ApiClient {
+    ApiClient { backend, keyring }
+  }
+
+  // Plaid itself, or the fake backend when the environment names fixtures
+  pub fn from_environment(env: &Environment) -> Result<ApiClient, ApiError> {
+    let backend: Arc<dyn PlaidBackend> = match env.plaid_fixtures.as_ref() {
+      Some(fixtures) => Arc::new(FakePlaidBackend::from_fixtures(
+        fixtures,
+        &env.plaid_fixture_transactions,
+        Utc::now().naive_utc().date(),
+      )?),
+      None => Arc::new(PlaidHttpBackend {
+        client_id: env.plaid_client_id.clone(),
+        secret: env.plaid_sandbox_secret.clone(),
+        client_name: "finch".to_string(),
+        webhook_url: env.plaid_webhook_url.clone(),
+        country_codes: env.plaid_country_codes.clone(),
+      }),
+    };
+
+    Ok(ApiClient::new(
+      backend,
+      Keyring::new(&env.token_keys, &env.token_key_id)?,
+    ))
   }
 
   pub async fn create_link_token(
     &self,
     user_id: String,
   ) -> Result<CreateLinkTokenResponse, ApiError> {
-    let config = Self::get_configuration();
-    plaid::apis::link_tokens_api::create_link_token(
-      &config,
-      plaid::models::CreateLinkTokenRequest::new(
-        self.client_id.clone(),
-        self.secret.clone(),
-        self.client_name.clone(),
-        vec!["US".to_string()],
-        "en".to_string(),
-        plaid::models::User::new(user_id),
-        vec!["auth".to_string(), "transactions".to_string()],
-      ),
-    )
-    .await
-    .or(Err(ApiError::new(500, "".to_string())))
+    self.backend.create_link_token(user_id, None).await
+  }
+
+  // a link token that opens Link in update mode for the item, so the user can repair it
+  pub async fn create_update_link_token(
+    &self,
+    user_id: String,
+    item_access_token: &StoredSecret,
+  ) -> Result<CreateLinkTokenResponse, ApiError> {
+    self
+      .backend
+      .create_link_token(user_id, Some(self.keyring.decrypt(item_access_token)?))
+      .await
   }
 
   pub async fn retrieve_an_items_accounts(
     &self,
-    item_access_token: String,
+    item_access_token: &StoredSecret,
   ) -> Result<RetrieveAnItemsAccountsResponse, ApiError> {
-    let config = Self::get_configuration();
-    plaid::apis::item_management_api::retrieve_an_items_accounts(
-      &config,
-      RetrieveAnItemsAccountsRequest::new(
-        self.client_id.clone(),
-        self.secret.clone(),
-        item_access_token,
-      ),
-    )
-    .await
-    .or(Err(ApiError::new(
-      500,
-      "Error while getting accounts".to_string(),
-    )))
+    self
+      .backend
+      .get_accounts(self.keyring.decrypt(item_access_token)?)
+      .await
+  }
+
+  // fails for items without investment accounts, or whose institution doesn't support them
+  pub async fn get_investment_holdings(
+    &self,
+    item_access_token: &StoredSecret,
+  ) -> Result<InvestmentHoldings, ApiError> {
+    self
+      .backend
+      .get_investment_holdings(self.keyring.decrypt(item_access_token)?)
+      .await
+  }
+
+  // fails for items whose institution doesn't support the Liabilities product
+  pub async fn get_liabilities(
+    &self,
+    item_access_token: &StoredSecret,
+  ) -> Result<Vec<LiabilityDetails>, ApiError> {
+    self
+      .backend
+      .get_liabilities(self.keyring.decrypt(item_access_token)?)
+      .await
   }
 
   pub async fn exchange_token(
     &self,
     public_token: String,
   ) -> Result<ExchangeTokenResponse, ApiError> {
-    let config = Self::get_configuration();
-
-    plaid::apis::item_creation_api::exchange_token(
-      &config,
-      plaid::models::ExchangeTokenRequest::new(
-        self.client_id.clone(),
-        self.secret.clone(),
-        public_token,
-      ),
-    )
-    .await
-    .map_err(|_| ApiError::new(500, "Plaid Exchange Token Error".to_string()))
+    self.backend.exchange_token(public_token).await
   }
 
   pub async fn retrieve_transactions(
     &self,
-    item_access_token: String,
+    item_access_token: &StoredSecret,
     date: String,
   ) -> Result<RetrieveTransactionsResponse, ApiError> {
-    let config = Self::get_configuration();
-    plaid::apis::transactions_api::retrieve_transactions(
-      &config,
-      RetrieveTransactionsRequest::new(
-        self.client_id.clone(),
-        self.secret.clone(),
-        item_access_token,
-        date.clone(),
-        date,
-      ),
-    )
-    .await
-    .map_err(|_| ApiError::new(500, "Error while getting transactions".to_string()))
+    self
+      .retrieve_transactions_between(item_access_token, date.clone(), date)
+      .await
+  }
+
+  // dates are inclusive and formatted %Y-%m-%d. Every page is retrieved, and
+  // the accounts are those of the first page.
+  pub async fn retrieve_transactions_between(
+    &self,
+    item_access_token: &StoredSecret,
+    start_date: String,
+    end_date: String,
+  ) -> Result<RetrieveTransactionsResponse, ApiError> {
+    self
+      .transaction_pages(item_access_token, start_date, end_date)
+      .try_fold(
+        None,
+        |all: Option<RetrieveTransactionsResponse>, mut page| async move {
+          Ok(Some(match all {
+            None => page,
+            Some(mut all) => {
+              all.transactions.append(&mut page.transactions);
+              all
+            }
+          }))
+        },
+      )
+      .await?
+      .ok_or(ApiError::new(
+        500,
+        "Error while getting transactions".to_string(),
+      ))
+  }
+
+  // every transaction between the dates, fetching pages as the stream is polled
+  pub fn stream_transactions<'a>(
+    &'a self,
+    item_access_token: &StoredSecret,
+    start_date: String,
+    end_date: String,
+  ) -> impl Stream<Item = Result<Transaction, ApiError>> + 'a {
+    self
+      .transaction_pages(item_access_token, start_date, end_date)
+      .map(|page| {
+        stream::iter(match page {
+          Ok(page) => page.transactions.into_iter().map(Ok).collect(),
+          Err(e) => vec![Err(e)],
+        })
+      })
+      .flatten()
+  }
+
+  // pages through `total_transactions` with count/offset, ending after the first error
+  pub fn transaction_pages<'a>(
+    &'a self,
+    item_access_token: &StoredSecret,
+    start_date: String,
+    end_date: String,
+  ) -> impl Stream<Item = Result<RetrieveTransactionsResponse, ApiError>> + 'a {
+    let item_access_token = self.keyring.decrypt(item_access_token);
+
+    stream::unfold(Some(0), move |offset: Option<i64>| {
+      let backend = self.backend.clone();
+      let item_access_token = item_access_token.clone();
+      let start_date = start_date.clone();
+      let end_date = end_date.clone();
+
+      async move {
+        let offset = offset?;
+
+        // a token that can't be decrypted ends the stream with its error
+        let item_access_token = match item_access_token {
+          Ok(item_access_token) => item_access_token,
+          Err(e) => return Some((Err(e), None)),
+        };
+
+        match backend
+          .get_transactions_page(
+            item_access_token,
+            start_date,
+            end_date,
+            TRANSACTIONS_PAGE_SIZE,
+            offset,
+          )
+          .await
+        {
+          Ok(page) => {
+            let next = next_page_offset(
+              offset,
+              page.transactions.len() as i64,
+              page.total_transactions as i64,
+            );
+
+            Some((Ok(page), next))
+          }
+          Err(e) => Some((Err(e), None)),
+        }
+      }
+    })
+  }
+
+  pub async fn get_webhook_verification_key(
+    &self,
+    key_id: String,
+  ) -> Result<WebhookVerificationKey, ApiError> {
+    self.backend.get_webhook_verification_key(key_id).await
+  }
+}
+
+// where the page after one at `offset` starts, if there is one
+fn next_page_offset(offset: i64, page_len: i64, total: i64) -> Option<i64> {
+  // an empty page means the total shrank while paging, so stop rather than spin
+  if page_len == 0 || offset + page_len >= total {
+    None
+  } else {
+    Some(offset + page_len)
   }
 }
 
-// (23 Feb 2021) -- only use positive-valued accounts
+// liabilities count against net worth. Plaid reports what is owed on them as a
+// positive balance.
 pub fn get_account_balance_coefficients(accounts: &Vec<Account>) -> HashMap<String, i64> {
   accounts
     .iter()
@@ -111,8 +301,8 @@ pub fn get_account_balance_coefficients(accounts: &Vec<Account>) -> HashMap<Stri
         account.account_id.clone(),
         match account._type.as_str() {
           "depository" => 1,
-          "credit" => 0,
-          "loan" => 0,
+          "credit" => -1,
+          "loan" => -1,
           "investment" => 1,
           _ => 0,
         },
@@ -139,12 +329,47 @@ pub fn get_account_transaction_coefficients(accounts: &Vec<Account>) -> HashMap<
     .collect()
 }
 
+// The currency an account is held in. Plaid leaves it out for accounts in
+// unofficial currencies, like crypto, which can't be converted and so are left
+// out of balances and totals.
+pub fn account_currency(account: &Account) -> Option<Currency> {
+  let currency = account
+    .balances
+    .iso_currency_code
+    .as_ref()
+    .and_then(|code| Currency::new(code).ok());
+
+  if currency.is_none() {
+    log::warn!(
+      "Leaving out account {}, which isn't in an ISO currency",
+      account.account_id
+    );
+  }
+  currency
+}
+
+// the currency a transaction is in, like its account's
+pub fn transaction_currency(transaction: &Transaction) -> Option<Currency> {
+  let currency = transaction
+    .iso_currency_code
+    .as_ref()
+    .and_then(|code| Currency::new(code).ok());
+
+  if currency.is_none() {
+    log::warn!(
+      "Leaving out transaction {}, which isn't in an ISO currency",
+      transaction.transaction_id
+    );
+  }
+  currency
+}
+
 pub async fn get_account_data<'a>(
   item: &PlaidItem,
   plaid_client: Data<ApiClient>,
 ) -> Result<Vec<AccountSuccess>, ApiError> {
   let accounts = plaid_client
-    .retrieve_an_items_accounts(item.access_token.clone())
+    .retrieve_an_items_accounts(&item.access_token)
     .await?
     .accounts;
   let account_id_to_coeff = get_account_balance_coefficients(&accounts);
@@ -152,6 +377,11 @@ pub async fn get_account_data<'a>(
   let mut account_successes = Vec::new();
 
   for account in accounts.iter() {
+    let currency = match account_currency(account) {
+      Some(currency) => currency,
+      None => continue,
+    };
+
     account_successes.push(AccountSuccess {
       item_id: item.item_id.clone(),
       balance: Decimal::try_from(account.balances.current)
@@ -163,44 +393,94 @@ pub async fn get_account_data<'a>(
             .unwrap(),
           0,
         ),
+      currency,
       name: account.name.clone(),
       account_type: account._type.to_string(),
       account_id: account.account_id.clone(),
+      asset_class: None,
     });
   }
 
   Ok(account_successes)
 }
 
-pub async fn get_net_worth(
-  item: &PlaidItem,
-  plaid_client: Data<ApiClient>,
+// what each account adds to net worth in the base currency, negative for what is
+// owed on it. Accounts that don't count toward net worth are left out.
+pub fn calculate_account_balances(
+  accounts: &Vec<Account>,
+  fx: &FxService,
+  base_currency: Currency,
+) -> Result<Vec<(String, Money)>, ApiError> {
+  let account_id_to_coeff = get_account_balance_coefficients(&accounts);
+
+  accounts
+    .iter()
+    .filter_map(|account: &Account| {
+      let coeff = account_id_to_coeff
+        .get(&account.account_id)
+        .copied()
+        .filter(|coeff| *coeff != 0)?;
+      let currency = account_currency(account)?;
+
+      Some(
+        fx.convert(
+          Money::in_currency(
+            Decimal::try_from(account.balances.current).unwrap(),
+            currency,
+          ),
+          base_currency,
+        )
+        .map(|balance| (account.account_id.clone(), balance * Decimal::new(coeff, 0))),
+      )
+    })
+    .collect()
+}
+
+pub fn calculate_net_worth(
+  accounts: &Vec<Account>,
   excluded_accounts: &HashSet<String>,
+  fx: &FxService,
+  base_currency: Currency,
 ) -> Result<Money, ApiError> {
-  let accounts = plaid_client
-    .retrieve_an_items_accounts(item.access_token.clone())
-    .await?
-    .accounts;
-
-  Ok(calculate_net_worth(&accounts, excluded_accounts))
+  let (assets, liabilities) =
+    calculate_assets_and_liabilities(accounts, excluded_accounts, fx, base_currency)?;
+  assets.checked_sub(liabilities)
 }
 
-pub fn calculate_net_worth(accounts: &Vec<Account>, excluded_accounts: &HashSet<String>) -> Money {
-  // map each account to a coefficient for each transaction.
+// what the item's accounts hold and what is owed on them, both positive and
+// converted to the base currency
+pub fn calculate_assets_and_liabilities(
+  accounts: &Vec<Account>,
+  excluded_accounts: &HashSet<String>,
+  fx: &FxService,
+  base_currency: Currency,
+) -> Result<(Money, Money), ApiError> {
   let account_id_to_coeff = get_account_balance_coefficients(&accounts);
+  let zero = Money::in_currency(0, base_currency);
 
-  //  calculate "net worth" of the item's accounts.
   accounts
     .iter()
     .filter(|&account: &&Account| !excluded_accounts.contains(&account.account_id))
-    .fold(Money::new(0), |net, account: &Account| {
-      let contribution = Money::new(Decimal::try_from(account.balances.current).unwrap())
-        * *account_id_to_coeff
-          .get(&account.account_id)
-          .or(Some(&0))
-          .unwrap();
-      net + contribution
-    })
+    .filter_map(|account: &Account| Some((account, account_currency(account)?)))
+    .try_fold(
+      (zero, zero),
+      |(assets, liabilities), (account, currency)| {
+        let balance = fx.convert(
+          Money::in_currency(
+            Decimal::try_from(account.balances.current).unwrap(),
+            currency,
+          ),
+          base_currency,
+        )?;
+        Ok(
+          match account_id_to_coeff.get(&account.account_id).copied() {
+            Some(1) => (assets.checked_add(balance)?, liabilities),
+            Some(-1) => (assets, liabilities.checked_add(balance)?),
+            _ => (assets, liabilities),
+          },
+        )
+      },
+    )
 }
 
 pub async fn exchange_public_token_for_access_token(
@@ -209,6 +489,7 @@ pub async fn exchange_public_token_for_access_token(
   user: User,
   user_service: Data<UserService>,
   fin_product_service: Data<FinProductService>,
+  fx: Data<FxService>,
 ) -> Result<ItemIdResponse, ApiError> {
   let exchanged = plaid_client.exchange_token(public_token).await?;
 
@@ -218,6 +499,9 @@ pub async fn exchange_public_token_for_access_token(
     request_id: _,
   } = exchanged;
 
+  // the token is never stored in plaintext
+  let item_access_token = plaid_client.keyring.encrypt(&item_access_token)?;
+
   user_service
     .add_new_account(
       user,
@@ -226,7 +510,21 @@ pub async fn exchange_public_token_for_access_token(
       user_service.clone(),
       plaid_client.clone(),
       fin_product_service,
+      fx,
     )
     .await
     .and_then(|_| Ok(ItemIdResponse { item_id: item_id }))
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_next_page_offset() {
+    assert_eq!(Some(500), next_page_offset(0, 500, 1234));
+    assert_eq!(Some(1000), next_page_offset(500, 500, 1234));
+    assert_eq!(None, next_page_offset(1000, 234, 1234));
+    assert_eq!(None, next_page_offset(0, 0, 1234));
+  }
+}
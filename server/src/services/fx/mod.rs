@@ -0,0 +1,150 @@
+use crate::common::{errors::ApiError, Currency, Money};
+use crate::services::secrets::Environment;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+// where exchange rates come from
+pub trait FxProvider: Send + Sync {
+  // what one unit of `from` is worth in `to`, if known
+  fn rate(&self, from: Currency, to: Currency) -> Option<Decimal>;
+}
+
+// {"base": "USD", "rates": {"EUR": 0.84}}, with each rate the units of that
+// currency one unit of `base` buys
+#[derive(Deserialize)]
+struct RatesFile {
+  base: Currency,
+  rates: HashMap<Currency, Decimal>,
+}
+
+// Rates from a local file, all against one base. Without a file, only amounts
+// already in the wanted currency can be converted.
+#[derive(Default)]
+pub struct FileFxProvider {
+  rates: HashMap<Currency, Decimal>,
+}
+
+impl FileFxProvider {
+  pub fn from_file(path: &str) -> Result<FileFxProvider, ApiError> {
+    let file = File::open(path)
+      .map_err(|_| ApiError::new(500, format!("Missing exchange rates file {}", path)))?;
+    let mut rates_file: RatesFile = serde_json::from_reader(BufReader::new(file))
+      .map_err(|e| ApiError::new(500, format!("Bad exchange rates file {}: {}", path, e)))?;
+
+    rates_file.rates.insert(rates_file.base, Decimal::new(1, 0));
+
+    Ok(FileFxProvider {
+      rates: rates_file.rates,
+    })
+  }
+}
+
+impl FxProvider for FileFxProvider {
+  fn rate(&self, from: Currency, to: Currency) -> Option<Decimal> {
+    if from == to {
+      return Some(Decimal::new(1, 0));
+    }
+
+    match (self.rates.get(&from), self.rates.get(&to)) {
+      (Some(from), Some(to)) if !from.is_zero() => Some(to / from),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct FxService {
+  provider: Arc<dyn FxProvider>,
+}
+
+impl FxService {
+  pub fn new(provider: Arc<dyn FxProvider>) -> FxService {
+    FxService { provider }
+  }
+
+  pub fn from_environment(env: &Environment) -> Result<FxService, ApiError> {
+    Ok(match env.fx_rates.as_ref() {
+      Some(path) => FxService::new(Arc::new(FileFxProvider::from_file(path)?)),
+      None => FxService::default(),
+    })
+  }
+
+  // amounts without a currency are taken to already be in `to`
+  pub fn convert(&self, money: Money, to: Currency) -> Result<Money, ApiError> {
+    let from = money.currency.unwrap_or(to);
+
+    self
+      .provider
+      .rate(from, to)
+      .map(|rate| Money::in_currency(money.amount * rate, to))
+      .ok_or(ApiError::new(
+        500,
+        format!("No exchange rate from {} to {}", from, to),
+      ))
+  }
+}
+
+impl Default for FxService {
+  fn default() -> Self {
+    FxService::new(Arc::new(FileFxProvider::default()))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn currency(code: &str) -> Currency {
+    Currency::new(code).unwrap()
+  }
+
+  #[test]
+  fn test_convert() {
+    let fx = FxService::new(Arc::new(
+      FileFxProvider::from_file("./tests/fixtures/fx_rates.json").unwrap(),
+    ));
+
+    assert_eq!(
+      Money::in_currency(dec!(80), currency("EUR")),
+      fx.convert(
+        Money::in_currency(dec!(100), Currency::USD),
+        currency("EUR")
+      )
+      .unwrap()
+    );
+    assert_eq!(
+      Money::in_currency(dec!(75), currency("GBP")),
+      fx.convert(
+        Money::in_currency(dec!(80), currency("EUR")),
+        currency("GBP")
+      )
+      .unwrap()
+    );
+    assert_eq!(
+      Money::in_currency(dec!(12), currency("EUR")),
+      fx.convert(Money::new(dec!(12)), currency("EUR")).unwrap()
+    );
+    assert!(fx
+      .convert(Money::in_currency(dec!(1), currency("JPY")), Currency::USD)
+      .is_err());
+  }
+
+  #[test]
+  fn test_convert_without_rates() {
+    let fx = FxService::default();
+
+    assert_eq!(
+      Money::in_currency(dec!(5), Currency::USD),
+      fx.convert(Money::in_currency(dec!(5), Currency::USD), Currency::USD)
+        .unwrap()
+    );
+    assert!(fx
+      .convert(Money::in_currency(dec!(5), currency("EUR")), Currency::USD)
+      .is_err());
+  }
+}
@@ -15,21 +15,33 @@ pub mod GoalService {
     goal: Goal,
     snapshots: &Vec<Snapshot>,
   ) -> Result<GoalAndStatus, ApiError> {
-    let last_before_start = snapshots.iter().fold(Snapshot::default(), |acc, cur| {
-      if cur.snapshot_time < goal.start {
-        cur.clone()
-      } else {
-        acc
-      }
-    });
+    // quarantined snapshots may be wrong, so they don't count until accepted
+    let snapshots: Vec<&Snapshot> = snapshots
+      .iter()
+      .filter(|snapshot| !snapshot.is_quarantined())
+      .collect();
 
-    let last_in_range = snapshots.iter().fold(Snapshot::default(), |acc, cur| {
-      if cur.snapshot_time > goal.end {
-        acc
-      } else {
-        cur.clone()
-      }
-    });
+    let last_before_start = snapshots
+      .iter()
+      .copied()
+      .fold(Snapshot::default(), |acc, cur| {
+        if cur.snapshot_time < goal.start {
+          cur.clone()
+        } else {
+          acc
+        }
+      });
+
+    let last_in_range = snapshots
+      .iter()
+      .copied()
+      .fold(Snapshot::default(), |acc, cur| {
+        if cur.snapshot_time > goal.end {
+          acc
+        } else {
+          cur.clone()
+        }
+      });
 
     let change = match goal.metric {
       GoalMetrics::Income => {
@@ -178,52 +190,87 @@ mod tests {
     let snapshots = vec![
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(0)),
         running_savings: Money::new(dec!(0)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 1,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(0)),
         running_savings: Money::new(dec!(0)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 2,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(0)),
         running_savings: Money::new(dec!(0)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 4,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(-10)),
         running_savings: Money::new(dec!(50)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 6,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(-10)),
         running_savings: Money::new(dec!(50)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 7,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(-10)),
         running_savings: Money::new(dec!(50)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 10,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_spending: Money::new(dec!(-210)),
         running_savings: Money::new(dec!(50)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 11,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
     ];
 
@@ -253,9 +300,18 @@ mod tests {
       metric: GoalMetrics::Spending,
     };
 
-    let progress3 = GoalService::calculate_goal_progress(spending_goal2, &snapshots).unwrap();
+    let progress3 =
+      GoalService::calculate_goal_progress(spending_goal2.clone(), &snapshots).unwrap();
 
     assert_eq!(dec!(2.1), progress3.progress);
+
+    // a quarantined snapshot doesn't count until it is accepted
+    let mut quarantined = snapshots.clone();
+    quarantined[6].quarantine = Some("Net worth is far from the recent median".to_string());
+
+    let progress4 = GoalService::calculate_goal_progress(spending_goal2, &quarantined).unwrap();
+
+    assert_eq!(dec!(0.1), progress4.progress);
   }
 
   #[test]
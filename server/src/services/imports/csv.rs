@@ -0,0 +1,269 @@
+use super::{parse_amount, Statement, StatementLine};
+use crate::common::errors::ApiError;
+use crate::models::manual_account_model::Valuation;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// Which columns of a bank's CSV export hold what, by header name. Amounts are
+// either one signed `amount` column or separate `debit` and `credit` columns.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CsvMapping {
+  pub date: String,
+  pub description: String,
+  pub amount: Option<String>,
+  pub debit: Option<String>,
+  pub credit: Option<String>,
+  // the running balance after each row, if the bank gives one
+  pub balance: Option<String>,
+  // the bank's own id for each row, if it gives one
+  pub id: Option<String>,
+  #[serde(default = "default_date_format")]
+  pub date_format: String,
+  // most banks export money out as negative, some the other way around
+  #[serde(default)]
+  pub outflows_positive: bool,
+}
+
+fn default_date_format() -> String {
+  "%m/%d/%Y".to_string()
+}
+
+// Splits CSV text into rows of fields. Quoted fields may hold commas, newlines
+// and doubled quotes.
+pub fn parse_rows(content: &str) -> Vec<Vec<String>> {
+  let mut rows = Vec::new();
+  let mut row = Vec::new();
+  let mut field = String::new();
+  let mut in_quotes = false;
+  let mut chars = content.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match (c, in_quotes) {
+      ('"', true) if chars.peek() == Some(&'"') => {
+        field.push('"');
+        chars.next();
+      }
+      ('"', _) => in_quotes = !in_quotes,
+      (',', false) => row.push(std::mem::take(&mut field)),
+      ('\r', false) => (),
+      ('\n', false) => {
+        row.push(std::mem::take(&mut field));
+        rows.push(std::mem::take(&mut row));
+      }
+      (c, _) => field.push(c),
+    }
+  }
+
+  if !field.is_empty() || !row.is_empty() {
+    row.push(field);
+    rows.push(row);
+  }
+
+  // blank lines, often at the end of an export
+  rows.retain(|row| row.iter().any(|field| !field.trim().is_empty()));
+  rows
+}
+
+pub fn parse(content: &str, mapping: &CsvMapping) -> Result<Statement, ApiError> {
+  let mut rows = parse_rows(content).into_iter();
+  let headers: Vec<String> = rows
+    .next()
+    .ok_or(ApiError::new(400, "The CSV file is empty".to_string()))?
+    .iter()
+    .map(|header| header.trim().to_lowercase())
+    .collect();
+
+  let column = |name: &String| {
+    headers
+      .iter()
+      .position(|header| *header == name.trim().to_lowercase())
+      .ok_or(ApiError::new(
+        400,
+        format!("The CSV file has no {} column", name),
+      ))
+  };
+  let optional_column = |name: &Option<String>| name.as_ref().map(column).transpose();
+
+  let date_column = column(&mapping.date)?;
+  let description_column = column(&mapping.description)?;
+  let amount_column = optional_column(&mapping.amount)?;
+  let debit_column = optional_column(&mapping.debit)?;
+  let credit_column = optional_column(&mapping.credit)?;
+  let balance_column = optional_column(&mapping.balance)?;
+  let id_column = optional_column(&mapping.id)?;
+
+  if amount_column.is_none() && debit_column.is_none() && credit_column.is_none() {
+    return Err(ApiError::new(
+      400,
+      "Map either an amount column or debit and credit columns".to_string(),
+    ));
+  }
+
+  let mut lines = Vec::new();
+  let mut balances: Vec<(String, Decimal)> = Vec::new();
+
+  for (number, row) in rows.enumerate() {
+    // the header is line 1
+    let line = number + 2;
+    let get = |index: usize| row.get(index).map(|field| field.trim()).unwrap_or("");
+    let amount_at = |index: Option<usize>| -> Result<Decimal, ApiError> {
+      match index.map(get).filter(|field| !field.is_empty()) {
+        Some(field) => parse_amount(field).ok_or(ApiError::new(
+          400,
+          format!("Could not read amount {} on line {}", field, line),
+        )),
+        None => Ok(Decimal::new(0, 0)),
+      }
+    };
+
+    let date = NaiveDate::parse_from_str(get(date_column), &mapping.date_format)
+      .map_err(|_| {
+        ApiError::new(
+          400,
+          format!(
+            "Could not read date {} on line {}, expected {}",
+            get(date_column),
+            line,
+            mapping.date_format
+          ),
+        )
+      })?
+      .format("%Y-%m-%d")
+      .to_string();
+
+    // the ledger counts money out as positive
+    let amount = match amount_column {
+      Some(_) if mapping.outflows_positive => amount_at(amount_column)?,
+      Some(_) => -amount_at(amount_column)?,
+      None => amount_at(debit_column)?.abs() - amount_at(credit_column)?.abs(),
+    };
+
+    if balance_column.is_some() {
+      balances.push((date.clone(), amount_at(balance_column)?));
+    }
+
+    lines.push(StatementLine {
+      date,
+      amount,
+      name: get(description_column).to_string(),
+      id: id_column
+        .map(get)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_string()),
+    });
+  }
+
+  Ok(Statement {
+    transactions: lines,
+    balances: end_of_day_balances(balances),
+  })
+}
+
+// The balance each day closed at. Exports list rows oldest or newest first, and
+// within a day the row furthest along in that order is the last one.
+fn end_of_day_balances(mut balances: Vec<(String, Decimal)>) -> Vec<Valuation> {
+  if balances.first().map(|first| &first.0) > balances.last().map(|last| &last.0) {
+    balances.reverse();
+  }
+
+  let mut valuations: Vec<Valuation> = Vec::new();
+  for (date, value) in balances.into_iter() {
+    match valuations.last_mut() {
+      Some(last) if last.date == date => last.value = value,
+      _ => valuations.push(Valuation { date, value }),
+    }
+  }
+
+  valuations
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn mapping() -> CsvMapping {
+    CsvMapping {
+      date: "Date".to_string(),
+      description: "Description".to_string(),
+      amount: Some("Amount".to_string()),
+      debit: None,
+      credit: None,
+      balance: Some("Balance".to_string()),
+      id: None,
+      date_format: default_date_format(),
+      outflows_positive: false,
+    }
+  }
+
+  #[test]
+  fn test_parse_rows() {
+    let rows = parse_rows("a,\"b, with comma\",\"say \"\"hi\"\"\"\r\n\r\n1,\"two\nlines\",3\n");
+
+    assert_eq!(
+      vec![
+        vec!["a", "b, with comma", "say \"hi\""],
+        vec!["1", "two\nlines", "3"]
+      ],
+      rows
+    );
+  }
+
+  #[test]
+  fn test_parse() {
+    let content = "Date,Description,Amount,Balance
+03/16/2021,\"COFFEE, INC\",($4.50),\"3,195.50\"
+03/16/2021,PAYROLL,\"2,000.00\",\"3,200.00\"
+03/15/2021,GROCER,-50.25,1200.00
+";
+    let statement = parse(content, &mapping()).unwrap();
+
+    assert_eq!(3, statement.transactions.len());
+    assert_eq!("2021-03-16", statement.transactions[0].date);
+    assert_eq!("COFFEE, INC", statement.transactions[0].name);
+    assert_eq!(dec!(4.50), statement.transactions[0].amount);
+    assert_eq!(dec!(-2000.00), statement.transactions[1].amount);
+
+    // newest first, so the first row of a day is where it ended
+    assert_eq!(
+      vec![
+        Valuation {
+          date: "2021-03-15".to_string(),
+          value: dec!(1200.00)
+        },
+        Valuation {
+          date: "2021-03-16".to_string(),
+          value: dec!(3195.50)
+        },
+      ],
+      statement.balances
+    );
+  }
+
+  #[test]
+  fn test_parse_debit_credit() {
+    let content = "posted,memo,debit,credit\n2021-03-15,rent,1500,\n2021-03-16,refund,,20\n";
+    let statement = parse(
+      content,
+      &CsvMapping {
+        date: "Posted".to_string(),
+        description: "Memo".to_string(),
+        amount: None,
+        debit: Some("Debit".to_string()),
+        credit: Some("Credit".to_string()),
+        balance: None,
+        id: None,
+        date_format: "%Y-%m-%d".to_string(),
+        outflows_positive: false,
+      },
+    )
+    .unwrap();
+
+    assert_eq!(dec!(1500), statement.transactions[0].amount);
+    assert_eq!(dec!(-20), statement.transactions[1].amount);
+    assert!(statement.balances.is_empty());
+
+    assert!(parse(content, &mapping()).is_err());
+  }
+}
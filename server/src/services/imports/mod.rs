@@ -0,0 +1,293 @@
+pub mod csv;
+pub mod ofx;
+pub mod qif;
+
+use crate::common::errors::ApiError;
+use crate::models::manual_account_model::Valuation;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// what kind of bank export is being imported
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "typ", content = "content")]
+pub enum ImportFormat {
+  Csv(csv::CsvMapping),
+  Ofx,
+  Qif,
+}
+
+// a transaction as read from an export, with the ledger's sign: money out is positive
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatementLine {
+  pub date: String, // %Y-%m-%d
+  pub amount: Decimal,
+  pub name: String,
+  // the bank's id, when the format has one
+  pub id: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+  pub transactions: Vec<StatementLine>,
+  // the account's balance at the end of some days
+  pub balances: Vec<Valuation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportSummary {
+  pub transactions_added: usize,
+  // already in the ledger from an earlier import
+  pub transactions_skipped: usize,
+  pub balances_recorded: usize,
+}
+
+// amounts like -1,234.56, $12.00 or (12.00)
+pub fn parse_amount(value: &str) -> Option<Decimal> {
+  let value = value.trim();
+  let (negative, value) = match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+    Some(inner) => (true, inner),
+    None => (false, value),
+  };
+
+  let cleaned: String = value
+    .chars()
+    .filter(|c| !matches!(c, '$' | ',' | ' '))
+    .collect();
+
+  Decimal::from_str(&cleaned)
+    .ok()
+    .map(|amount| if negative { -amount } else { amount })
+}
+
+pub fn parse(format: &ImportFormat, content: &str) -> Result<Statement, ApiError> {
+  match format {
+    ImportFormat::Csv(mapping) => csv::parse(content, mapping),
+    ImportFormat::Ofx => ofx::parse(content),
+    ImportFormat::Qif => qif::parse(content),
+  }
+}
+
+// Ids for the ledger that are the same each time a line is imported, so a
+// re-import or an overlapping export doesn't add it twice. Lines without a bank
+// id are told apart by what they say, and identical lines by their order.
+pub fn transaction_ids(account_id: &str, lines: &Vec<StatementLine>) -> Vec<String> {
+  let mut seen: HashMap<String, usize> = HashMap::new();
+
+  lines
+    .iter()
+    .map(|line| {
+      let key = match line.id.as_ref() {
+        Some(id) => format!("id|{}", id),
+        None => {
+          let key = format!(
+            "line|{}|{}|{}",
+            line.date,
+            line.amount.normalize(),
+            line.name
+          );
+          let occurrence = seen.entry(key.clone()).or_insert(0);
+          *occurrence += 1;
+          format!("{}|{}", key, occurrence)
+        }
+      };
+
+      let digest = ring::digest::digest(
+        &ring::digest::SHA256,
+        format!("{}|{}", account_id, key).as_bytes(),
+      );
+      format!(
+        "import-{}",
+        base64::encode_config(&digest.as_ref()[..18], base64::URL_SAFE_NO_PAD)
+      )
+    })
+    .collect()
+}
+
+#[allow(non_snake_case)]
+pub mod ImportService {
+  use super::*;
+  use crate::common::Money;
+  use crate::controllers::manual_account_controller::ImportPayload;
+  use crate::models::{
+    manual_account_model::{ManualAccount, MANUAL_ITEM_ID},
+    transaction_model::Transaction,
+    user_model::User,
+  };
+  use crate::services::{
+    finchplaid::ApiClient, fx::FxService, manual_accounts::ManualAccountService,
+    transactions::TransactionService, users::UserService,
+  };
+  use actix_web::web::Data;
+
+  // Adds a bank export to a manual account: its transactions to the ledger and
+  // its balances to the account's valuations. The caller saves the user.
+  pub async fn import_statement(
+    user: &mut User,
+    account_id: String,
+    format: &ImportFormat,
+    content: &str,
+    transaction_service: &TransactionService,
+  ) -> Result<ImportSummary, ApiError> {
+    let account = ManualAccountService::retrieve_account(account_id, &user.manual_accounts)?;
+    let statement = parse(format, content)?;
+
+    let transactions = into_transactions(user, &account, &statement.transactions);
+    let transactions_added = transaction_service.insert_new(&transactions).await?;
+
+    // the export may hold one side of a transfer whose other side is already in
+    if let Some(since) = statement
+      .transactions
+      .iter()
+      .filter_map(|line| chrono::NaiveDate::parse_from_str(&line.date, "%Y-%m-%d").ok())
+      .min()
+    {
+      transaction_service
+        .mark_transfers(&user.id.clone().unwrap(), since)
+        .await?;
+    }
+
+    let balances_recorded = statement.balances.len();
+    for rec in user
+      .manual_accounts
+      .iter_mut()
+      .filter(|rec| rec.id == account.id)
+    {
+      // a loan is valued at what is owed, which statements show as negative
+      let liability = rec.is_liability();
+      for balance in statement.balances.iter() {
+        rec.add_valuation(Valuation {
+          date: balance.date.clone(),
+          value: if liability {
+            balance.value.abs()
+          } else {
+            balance.value
+          },
+        });
+      }
+    }
+
+    Ok(ImportSummary {
+      transactions_added,
+      transactions_skipped: transactions.len() - transactions_added,
+      balances_recorded,
+    })
+  }
+
+  fn into_transactions(
+    user: &User,
+    account: &ManualAccount,
+    lines: &Vec<StatementLine>,
+  ) -> Vec<Transaction> {
+    let account_id = account.account_id();
+    let now = chrono::Utc::now().timestamp();
+
+    lines
+      .iter()
+      .zip(transaction_ids(&account_id, lines).into_iter())
+      .map(|(line, transaction_id)| Transaction {
+        id: None,
+        user_id: user.id.clone().unwrap(),
+        item_id: MANUAL_ITEM_ID.to_string(),
+        account_id: account_id.clone(),
+        transaction_id,
+        pending_transaction_id: None,
+        name: line.name.clone(),
+        merchant_name: None,
+        category: vec![],
+        category_override: None,
+        transfer_id: None,
+        amount: Money::in_currency(line.amount, account.currency),
+        date: line.date.clone(),
+        pending: false,
+        updated_time: now,
+      })
+      .collect()
+  }
+
+  pub async fn import(
+    account_id: String,
+    payload: ImportPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    transaction_service: Data<TransactionService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ImportSummary, ApiError> {
+    let summary = import_statement(
+      &mut user,
+      account_id,
+      &payload.format,
+      &payload.content,
+      &transaction_service,
+    )
+    .await?;
+
+    user_service.save(&mut user).await?;
+    if summary.balances_recorded > 0 {
+      user_service
+        .add_new_snapshot(&mut user, plaid_client, &fx)
+        .await?;
+    }
+
+    Ok(summary)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn line(date: &str, amount: Decimal, name: &str, id: Option<&str>) -> StatementLine {
+    StatementLine {
+      date: date.to_string(),
+      amount,
+      name: name.to_string(),
+      id: id.map(|id| id.to_string()),
+    }
+  }
+
+  #[test]
+  fn test_parse_amount() {
+    assert_eq!(Some(dec!(-1234.56)), parse_amount("-1,234.56"));
+    assert_eq!(Some(dec!(12)), parse_amount(" $12.00 "));
+    assert_eq!(Some(dec!(-12)), parse_amount("(12.00)"));
+    assert_eq!(None, parse_amount("twelve"));
+  }
+
+  #[test]
+  fn test_transaction_ids() {
+    let coffee = line("2021-03-15", dec!(4.5), "COFFEE", None);
+    let first = transaction_ids(
+      "account",
+      &vec![
+        coffee.clone(),
+        coffee.clone(),
+        line("2021-03-15", dec!(4.50), "COFFEE", Some("1")),
+      ],
+    );
+
+    // two coffees on a day are two transactions
+    assert_ne!(first[0], first[1]);
+    assert!(first[0].starts_with("import-"));
+
+    // an export overlapping the first one gives the overlap the same ids
+    let second = transaction_ids(
+      "account",
+      &vec![coffee.clone(), coffee.clone(), coffee.clone()],
+    );
+    assert_eq!(first[0..2], second[0..2]);
+
+    // trailing zeros don't make a different amount, but another account does
+    assert_eq!(
+      first[0],
+      transaction_ids(
+        "account",
+        &vec![line("2021-03-15", dec!(4.50), "COFFEE", None)]
+      )[0]
+    );
+    assert_ne!(first[0], transaction_ids("other", &vec![coffee])[0]);
+  }
+}
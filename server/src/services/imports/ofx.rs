@@ -0,0 +1,196 @@
+use super::{parse_amount, Statement, StatementLine};
+use crate::common::errors::ApiError;
+use crate::models::manual_account_model::Valuation;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+// OFX dates start YYYYMMDD, maybe followed by a time and zone
+fn parse_date(value: &str) -> Option<String> {
+  value
+    .get(0..8)
+    .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+    .map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+fn unescape(value: &str) -> String {
+  value
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&amp;", "&")
+}
+
+// The statement in an OFX or QFX file. Handles both the SGML of OFX 1.x, where
+// leaf elements aren't closed, and the XML of OFX 2.x.
+pub fn parse(content: &str) -> Result<Statement, ApiError> {
+  let mut lines = Vec::new();
+  let mut balances = Vec::new();
+
+  // the fields of the transaction or balance being read
+  let mut transaction: Option<Vec<(String, String)>> = None;
+  let mut balance: Option<Vec<(String, String)>> = None;
+
+  for element in content.split('<').skip(1) {
+    let (tag, value) = match element.find('>') {
+      Some(end) => (&element[..end], element[end + 1..].trim()),
+      None => continue,
+    };
+    let tag = tag.trim().to_uppercase();
+
+    match tag.as_str() {
+      "STMTTRN" => transaction = Some(Vec::new()),
+      "/STMTTRN" => {
+        if let Some(fields) = transaction.take() {
+          lines.push(statement_line(&fields)?);
+        }
+      }
+      "LEDGERBAL" => balance = Some(Vec::new()),
+      "/LEDGERBAL" => {
+        if let Some(fields) = balance.take() {
+          balances.push(valuation(&fields)?);
+        }
+      }
+      _ if !tag.starts_with('/') => {
+        let field = (tag.clone(), unescape(value));
+        match (transaction.as_mut(), balance.as_mut()) {
+          (Some(fields), _) => fields.push(field),
+          (_, Some(fields)) => fields.push(field),
+          _ => (),
+        }
+      }
+      _ => (),
+    }
+  }
+
+  if lines.is_empty() && balances.is_empty() {
+    return Err(ApiError::new(
+      400,
+      "The OFX file has no transactions or balances".to_string(),
+    ));
+  }
+
+  Ok(Statement {
+    transactions: lines,
+    balances,
+  })
+}
+
+fn field<'a>(fields: &'a Vec<(String, String)>, tag: &str) -> Option<&'a str> {
+  fields
+    .iter()
+    .find(|(name, _)| name == tag)
+    .map(|(_, value)| value.as_str())
+    .filter(|value| !value.is_empty())
+}
+
+fn required_date(fields: &Vec<(String, String)>, tag: &str) -> Result<String, ApiError> {
+  field(fields, tag).and_then(parse_date).ok_or(ApiError::new(
+    400,
+    format!("An OFX entry has no good {}", tag),
+  ))
+}
+
+fn required_amount(fields: &Vec<(String, String)>, tag: &str) -> Result<Decimal, ApiError> {
+  field(fields, tag)
+    .and_then(parse_amount)
+    .ok_or(ApiError::new(
+      400,
+      format!("An OFX entry has no good {}", tag),
+    ))
+}
+
+fn statement_line(fields: &Vec<(String, String)>) -> Result<StatementLine, ApiError> {
+  Ok(StatementLine {
+    date: required_date(fields, "DTPOSTED")?,
+    // OFX amounts are negative for money out, the ledger's are positive
+    amount: -required_amount(fields, "TRNAMT")?,
+    name: field(fields, "NAME")
+      .or(field(fields, "PAYEE"))
+      .or(field(fields, "MEMO"))
+      .unwrap_or("")
+      .to_string(),
+    id: field(fields, "FITID").map(|id| id.to_string()),
+  })
+}
+
+fn valuation(fields: &Vec<(String, String)>) -> Result<Valuation, ApiError> {
+  Ok(Valuation {
+    date: required_date(fields, "DTASOF")?,
+    value: required_amount(fields, "BALAMT")?,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  #[test]
+  fn test_parse_sgml() {
+    let content = "OFXHEADER:100
+DATA:OFXSGML
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>USD
+<BANKTRANLIST>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20210315120000[-5:EST]
+<TRNAMT>-50.25
+<FITID>2021031501
+<NAME>GROCER
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20210316
+<TRNAMT>2000.00
+<FITID>2021031601
+<MEMO>PAYROLL
+</STMTTRN>
+</BANKTRANLIST>
+<LEDGERBAL>
+<BALAMT>3200.00
+<DTASOF>20210316
+</LEDGERBAL>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+";
+    let statement = parse(content).unwrap();
+
+    assert_eq!(2, statement.transactions.len());
+    assert_eq!(
+      StatementLine {
+        date: "2021-03-15".to_string(),
+        amount: dec!(50.25),
+        name: "GROCER".to_string(),
+        id: Some("2021031501".to_string()),
+      },
+      statement.transactions[0]
+    );
+    assert_eq!("PAYROLL", statement.transactions[1].name);
+    assert_eq!(dec!(-2000.00), statement.transactions[1].amount);
+    assert_eq!(
+      vec![Valuation {
+        date: "2021-03-16".to_string(),
+        value: dec!(3200.00),
+      }],
+      statement.balances
+    );
+  }
+
+  #[test]
+  fn test_parse_xml() {
+    let content = "<?xml version=\"1.0\"?><OFX><STMTTRN><DTPOSTED>20210315</DTPOSTED>\
+      <TRNAMT>-4.5</TRNAMT><FITID>abc</FITID><NAME>COFFEE &amp; CO</NAME></STMTTRN></OFX>";
+    let statement = parse(content).unwrap();
+
+    assert_eq!(1, statement.transactions.len());
+    assert_eq!(dec!(4.5), statement.transactions[0].amount);
+    assert_eq!(Some("abc".to_string()), statement.transactions[0].id);
+    assert_eq!("COFFEE & CO", statement.transactions[0].name);
+
+    assert!(parse("<OFX></OFX>").is_err());
+  }
+}
@@ -0,0 +1,137 @@
+use super::{parse_amount, Statement, StatementLine};
+use crate::common::errors::ApiError;
+use chrono::NaiveDate;
+
+// QIF dates are month first, with the year as 2021, 21 or '21
+fn parse_date(value: &str) -> Option<String> {
+  let parts: Vec<i32> = value
+    .trim()
+    .split(|c| c == '/' || c == '-' || c == '\'' || c == '.')
+    .map(|part| part.trim().parse::<i32>().ok())
+    .collect::<Option<Vec<i32>>>()?;
+
+  let (month, day, year) = match parts.as_slice() {
+    [month, day, year] => (*month as u32, *day as u32, *year),
+    _ => return None,
+  };
+  let year = match year {
+    0..=69 => 2000 + year,
+    70..=99 => 1900 + year,
+    _ => year,
+  };
+
+  NaiveDate::from_ymd_opt(year, month, day).map(|date| date.format("%Y-%m-%d").to_string())
+}
+
+// The transactions in a QIF file's bank or card register. QIF has no ids or
+// balances, so only transactions come out.
+pub fn parse(content: &str) -> Result<Statement, ApiError> {
+  let mut lines = Vec::new();
+
+  let mut date = None;
+  let mut amount = None;
+  let mut payee = None;
+  let mut memo = None;
+
+  for (number, line) in content.lines().enumerate() {
+    let line = line.trim();
+    let (code, value) = match line.chars().next() {
+      Some(code) => (code, line[code.len_utf8()..].trim()),
+      None => continue,
+    };
+
+    match code {
+      'D' => {
+        date = Some(parse_date(value).ok_or(ApiError::new(
+          400,
+          format!("Could not read date {} on line {}", value, number + 1),
+        ))?)
+      }
+      'T' | 'U' => {
+        amount = Some(parse_amount(value).ok_or(ApiError::new(
+          400,
+          format!("Could not read amount {} on line {}", value, number + 1),
+        ))?)
+      }
+      'P' => payee = Some(value.to_string()),
+      'M' => memo = Some(value.to_string()),
+      '^' => {
+        if let (Some(date), Some(amount)) = (date.take(), amount.take()) {
+          lines.push(StatementLine {
+            date,
+            // QIF amounts are negative for money out, the ledger's are positive
+            amount: -amount,
+            name: payee.take().or(memo.take()).unwrap_or_default(),
+            id: None,
+          });
+        }
+        payee = None;
+        memo = None;
+      }
+      // headers like !Type:Bank, and fields we don't keep
+      _ => (),
+    }
+  }
+
+  if lines.is_empty() {
+    return Err(ApiError::new(
+      400,
+      "The QIF file has no transactions".to_string(),
+    ));
+  }
+
+  Ok(Statement {
+    transactions: lines,
+    balances: vec![],
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  #[test]
+  fn test_parse_date() {
+    assert_eq!(Some("2021-03-15".to_string()), parse_date("03/15/2021"));
+    assert_eq!(Some("2021-03-05".to_string()), parse_date("3/ 5'21"));
+    assert_eq!(Some("1999-12-31".to_string()), parse_date("12/31/99"));
+    assert_eq!(None, parse_date("15/03/2021"));
+  }
+
+  #[test]
+  fn test_parse() {
+    let content = "!Type:Bank
+D03/15/2021
+T-1,500.00
+PLANDLORD
+MMarch rent
+^
+D03/16'21
+T2,000.00
+MPAYROLL
+^
+";
+    let statement = parse(content).unwrap();
+
+    assert_eq!(
+      vec![
+        StatementLine {
+          date: "2021-03-15".to_string(),
+          amount: dec!(1500.00),
+          name: "LANDLORD".to_string(),
+          id: None,
+        },
+        StatementLine {
+          date: "2021-03-16".to_string(),
+          amount: dec!(-2000.00),
+          name: "PAYROLL".to_string(),
+          id: None,
+        },
+      ],
+      statement.transactions
+    );
+
+    assert!(parse("!Type:Bank\n").is_err());
+  }
+}
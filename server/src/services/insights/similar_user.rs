@@ -59,9 +59,9 @@ impl UserMetricRates {
     let elapsed = Decimal::new(end.snapshot_time - start.snapshot_time, 0);
 
     UserMetricRates {
-      spending_rate: (end.running_spending - start.running_spending).amount / elapsed,
-      savings_rate: (end.running_savings - start.running_savings).amount / elapsed,
-      income_rate: (end.running_income - start.running_income).amount / elapsed,
+      spending_rate: (end.running_spending.amount - start.running_spending.amount) / elapsed,
+      savings_rate: (end.running_savings.amount - start.running_savings.amount) / elapsed,
+      income_rate: (end.running_income.amount - start.running_income.amount) / elapsed,
     }
   }
 
@@ -92,7 +92,11 @@ pub fn project_snapshots(since: DateTime<Utc>) -> bson::Document {
         "$filter": {
           "input": "$snapshots",
           "cond": {
-            "$gte": ["$$this.snapshot_time", since.timestamp()]
+            "$and": [
+              { "$gte": ["$$this.snapshot_time", since.timestamp()] },
+              // quarantined snapshots may be wrong
+              { "$not": ["$$this.quarantine"] }
+            ]
           }
         }
       }
@@ -252,7 +256,7 @@ fn calculate_rates(snapshots: &Vec<Snapshot>, since: &DateTime<Utc>) -> UserMetr
   let tstamp = since.timestamp();
   let filtered = snapshots
     .iter()
-    .filter(|s| s.snapshot_time >= tstamp)
+    .filter(|s| s.snapshot_time >= tstamp && !s.is_quarantined())
     .collect::<Vec<&Snapshot>>();
 
   let f = filtered.first();
@@ -278,33 +282,53 @@ mod tests {
       Ok(vec![
         Snapshot {
           net_worth: Money::new(dec!(0)),
+          assets: Money::new(dec!(0)),
+          liabilities: Money::default(),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           running_income: Money::new(dec!(0)),
           snapshot_time: 50,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(0)),
+          assets: Money::new(dec!(0)),
+          liabilities: Money::default(),
           running_savings: Money::new(dec!(5000)),
           running_spending: Money::new(dec!(0)),
           running_income: Money::new(dec!(0)),
           snapshot_time: 100,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ]),
       Ok(vec![
         Snapshot {
           net_worth: Money::new(dec!(0)),
+          assets: Money::new(dec!(0)),
+          liabilities: Money::default(),
           running_savings: Money::new(dec!(0)),
           running_spending: Money::new(dec!(0)),
           running_income: Money::new(dec!(0)),
           snapshot_time: 50,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
         Snapshot {
           net_worth: Money::new(dec!(0)),
+          assets: Money::new(dec!(0)),
+          liabilities: Money::default(),
           running_savings: Money::new(dec!(4500)),
           running_spending: Money::new(dec!(0)),
           running_income: Money::new(dec!(0)),
           snapshot_time: 100,
+          accounts: vec![],
+          quarantine: None,
+          baseline: false,
         },
       ]),
     ];
@@ -312,17 +336,27 @@ mod tests {
     let this_users_snapshots: Vec<Snapshot> = vec![
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_savings: Money::new(dec!(0)),
         running_spending: Money::new(dec!(0)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 50,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
       Snapshot {
         net_worth: Money::new(dec!(0)),
+        assets: Money::new(dec!(0)),
+        liabilities: Money::default(),
         running_savings: Money::new(dec!(4700)),
         running_spending: Money::new(dec!(0)),
         running_income: Money::new(dec!(0)),
         snapshot_time: 100,
+        accounts: vec![],
+        quarantine: None,
+        baseline: false,
       },
     ];
 
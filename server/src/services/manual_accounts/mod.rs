@@ -0,0 +1,355 @@
+#[allow(non_snake_case)]
+pub mod ManualAccountService {
+  use crate::common::{errors::ApiError, Currency, Money};
+  use crate::controllers::manual_account_controller::{
+    ManualAccountNewPayload, ManualAccountUpdatePayload, ValuationPayload,
+  };
+  use crate::controllers::plaid_controller::AccountSuccess;
+  use crate::models::{
+    manual_account_model::*,
+    user_model::{AccountSnapshot, User},
+  };
+  use crate::services::{
+    finchplaid::ApiClient, fx::FxService, snapshots::SnapshotService,
+    transactions::TransactionService, users::UserService,
+  };
+  use actix_web::web::Data;
+  use chrono::{NaiveDate, Utc};
+  use rust_decimal_macros::dec;
+  use wither::{mongodb::bson::oid::ObjectId, Model};
+
+  pub fn today() -> String {
+    Utc::now().naive_utc().date().format("%Y-%m-%d").to_string()
+  }
+
+  fn parse_date(date: &String) -> Result<String, ApiError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+      .map(|date| date.format("%Y-%m-%d").to_string())
+      .map_err(|_| {
+        ApiError::new(
+          400,
+          format!("Could not parse date {}, expected %Y-%m-%d", date),
+        )
+      })
+  }
+
+  // listed like Plaid accounts, worth nothing before their first valuation
+  pub fn account_successes(
+    user: &User,
+    show_all_accounts: bool,
+    date: &str,
+  ) -> Vec<AccountSuccess> {
+    user
+      .manual_accounts
+      .iter()
+      .filter(|account| show_all_accounts || !account.hidden)
+      .map(|account| AccountSuccess {
+        item_id: MANUAL_ITEM_ID.to_string(),
+        name: account.name.clone(),
+        balance: account.balance_on(date).unwrap_or(dec!(0)),
+        currency: account.currency,
+        account_type: account.account_type.name().to_string(),
+        account_id: account.account_id(),
+        asset_class: Some(account.class.clone()),
+      })
+      .collect()
+  }
+
+  // what the visible manual accounts add to a balance sheet on `date`, both positive
+  pub fn calculate_assets_and_liabilities(
+    accounts: &Vec<ManualAccount>,
+    date: &str,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<(Money, Money), ApiError> {
+    accounts
+      .iter()
+      .filter(|account| !account.hidden)
+      .filter_map(|account| {
+        account
+          .balance_on(date)
+          .map(|balance| fx.convert(Money::in_currency(balance, account.currency), base_currency))
+      })
+      .try_fold(
+        (
+          Money::in_currency(0, base_currency),
+          Money::in_currency(0, base_currency),
+        ),
+        |(assets, liabilities), balance| {
+          let balance = balance?;
+          Ok(if balance.amount > dec!(0) {
+            (assets.checked_add(balance)?, liabilities)
+          } else {
+            (assets, liabilities.checked_sub(balance)?)
+          })
+        },
+      )
+  }
+
+  // each manual account's part of a snapshot taken on `date`, hidden ones included
+  pub fn account_snapshots(
+    accounts: &Vec<ManualAccount>,
+    date: &str,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<Vec<AccountSnapshot>, ApiError> {
+    let zero = Money::in_currency(0, base_currency);
+
+    accounts
+      .iter()
+      .filter_map(|account| {
+        account
+          .balance_on(date)
+          .map(|balance| -> Result<AccountSnapshot, ApiError> {
+            Ok(AccountSnapshot {
+              item_id: MANUAL_ITEM_ID.to_string(),
+              account_id: account.account_id(),
+              account_type: account.account_type.name().to_string(),
+              balance: fx.convert(Money::in_currency(balance, account.currency), base_currency)?,
+              money_in: zero,
+              money_out: zero,
+              transacted: zero,
+              hidden: account.hidden,
+            })
+          })
+      })
+      .collect()
+  }
+
+  pub fn retrieve_account(
+    account_id: String,
+    accounts: &Vec<ManualAccount>,
+  ) -> Result<ManualAccount, ApiError> {
+    let account_id_opt = Some(
+      ObjectId::with_string(account_id.as_str())
+        .or(Err(ApiError::new(400, "Malformed Object Id".to_string())))?,
+    );
+
+    accounts
+      .iter()
+      .find(|rec| rec.id == account_id_opt)
+      .cloned()
+      .ok_or(ApiError::new(
+        400,
+        format!(
+          "No manual account with id {} found in current user",
+          account_id
+        ),
+      ))
+  }
+
+  // saves the change and takes a snapshot so net worth reflects it right away
+  async fn save_and_snapshot(
+    account: ManualAccount,
+    mut user: User,
+    user_service: Data<UserService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ManualAccount, ApiError> {
+    user_service.save(&mut user).await?;
+    user_service
+      .add_new_snapshot(&mut user, plaid_client, &fx)
+      .await?;
+
+    Ok(account)
+  }
+
+  pub async fn new_account(
+    payload: ManualAccountNewPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ManualAccount, ApiError> {
+    let date = match payload.date.as_ref() {
+      Some(date) => parse_date(date)?,
+      None => today(),
+    };
+
+    let mut account = ManualAccount {
+      id: None,
+      name: payload.name,
+      account_type: payload.account_type,
+      class: payload.class,
+      currency: payload.currency.unwrap_or(user.base_currency),
+      valuations: vec![],
+      hidden: false,
+    };
+    account.set_id(ObjectId::new());
+    account.add_valuation(Valuation {
+      date,
+      value: payload.value,
+    });
+
+    user.manual_accounts.push(account.clone());
+
+    save_and_snapshot(account, user, user_service, plaid_client, fx).await
+  }
+
+  pub fn get_accounts(user: User) -> Vec<ManualAccount> {
+    user.manual_accounts
+  }
+
+  pub fn get_account(account_id: String, user: User) -> Result<ManualAccount, ApiError> {
+    retrieve_account(account_id, &user.manual_accounts)
+  }
+
+  pub async fn update_account(
+    account_id: String,
+    payload: ManualAccountUpdatePayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ManualAccount, ApiError> {
+    let mut account = retrieve_account(account_id, &user.manual_accounts)?;
+
+    if let Some(name) = payload.name {
+      account.name = name;
+    }
+    if let Some(account_type) = payload.account_type {
+      account.account_type = account_type;
+    }
+    if let Some(class) = payload.class {
+      account.class = class;
+    }
+    if let Some(hidden) = payload.hidden {
+      if account.hidden != hidden {
+        SnapshotService::set_account_hidden(&mut user.snapshots, &account.account_id(), hidden);
+      }
+      account.hidden = hidden;
+    }
+
+    for rec in user
+      .manual_accounts
+      .iter_mut()
+      .filter(|rec| rec.id == account.id)
+    {
+      *rec = account.clone();
+    }
+
+    save_and_snapshot(account, user, user_service, plaid_client, fx).await
+  }
+
+  pub async fn add_valuation(
+    account_id: String,
+    payload: ValuationPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ManualAccount, ApiError> {
+    let mut account = retrieve_account(account_id, &user.manual_accounts)?;
+
+    let date = match payload.date.as_ref() {
+      Some(date) => parse_date(date)?,
+      None => today(),
+    };
+    account.add_valuation(Valuation {
+      date,
+      value: payload.value,
+    });
+
+    for rec in user
+      .manual_accounts
+      .iter_mut()
+      .filter(|rec| rec.id == account.id)
+    {
+      *rec = account.clone();
+    }
+
+    save_and_snapshot(account, user, user_service, plaid_client, fx).await
+  }
+
+  // imported transactions go with the account
+  pub async fn delete_account(
+    account_id: String,
+    mut user: User,
+    user_service: Data<UserService>,
+    transaction_service: Data<TransactionService>,
+    plaid_client: Data<ApiClient>,
+    fx: Data<FxService>,
+  ) -> Result<ManualAccount, ApiError> {
+    let account = retrieve_account(account_id, &user.manual_accounts)?;
+
+    transaction_service
+      .delete_account_transactions(&user.id.clone().unwrap(), &account.account_id())
+      .await?;
+
+    user.manual_accounts.retain(|rec| rec.id != account.id);
+
+    save_and_snapshot(account, user, user_service, plaid_client, fx).await
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::{Currency, Money};
+  use crate::models::{manual_account_model::*, plan_model::AssetClass};
+  use crate::services::fx::{FileFxProvider, FxService};
+  use rust_decimal::Decimal;
+  use rust_decimal_macros::dec;
+  use std::sync::Arc;
+
+  fn manual(
+    account_type: ManualAccountType,
+    currency: Currency,
+    value: Decimal,
+    hidden: bool,
+  ) -> ManualAccount {
+    ManualAccount {
+      id: None,
+      name: "manual".to_string(),
+      account_type,
+      class: AssetClass::Other,
+      currency,
+      valuations: vec![Valuation {
+        date: "2021-01-01".to_string(),
+        value,
+      }],
+      hidden,
+    }
+  }
+
+  #[test]
+  fn test_calculate_assets_and_liabilities() {
+    let fx = FxService::new(Arc::new(
+      FileFxProvider::from_file("./tests/fixtures/fx_rates.json").unwrap(),
+    ));
+    let eur = Currency::new("EUR").unwrap();
+
+    let accounts = vec![
+      manual(
+        ManualAccountType::Property,
+        Currency::USD,
+        dec!(1000),
+        false,
+      ),
+      manual(ManualAccountType::Crypto, eur, dec!(80), false),
+      manual(ManualAccountType::Loan, Currency::USD, dec!(300), false),
+      manual(ManualAccountType::Vehicle, Currency::USD, dec!(5000), true),
+    ];
+
+    let (assets, liabilities) = ManualAccountService::calculate_assets_and_liabilities(
+      &accounts,
+      "2021-03-01",
+      &fx,
+      Currency::USD,
+    )
+    .unwrap();
+    assert_eq!(Money::in_currency(dec!(1100), Currency::USD), assets);
+    assert_eq!(Money::in_currency(dec!(300), Currency::USD), liabilities);
+
+    // nothing was valued yet
+    let (assets, liabilities) = ManualAccountService::calculate_assets_and_liabilities(
+      &accounts,
+      "2020-12-31",
+      &fx,
+      Currency::USD,
+    )
+    .unwrap();
+    assert_eq!(dec!(0), assets.amount);
+    assert_eq!(dec!(0), liabilities.amount);
+  }
+}
@@ -1,13 +1,21 @@
+pub mod budgets;
 pub mod db;
+pub mod exports;
 pub mod financial_products;
 pub mod finchplaid;
+pub mod fx;
 pub mod goals;
+pub mod imports;
 pub mod insights;
 pub mod leaderboards;
+pub mod manual_accounts;
 pub mod plans;
 pub mod recurrings;
 pub mod secrets;
 pub mod sessions;
 pub mod snapshots;
+pub mod spending;
 pub mod timeseries;
+pub mod transactions;
 pub mod users;
+pub mod webhooks;
@@ -1,6 +1,6 @@
 #[allow(non_snake_case)]
 pub mod PlansService {
-    use crate::common::errors::ApiError;
+    use crate::common::{compounding::CompoundingConvention, errors::ApiError, Money};
     use crate::controllers::plaid_controller::AccountSuccess;
     use crate::controllers::plans_controller::{PlanNewPayload, PlanUpdatePayload};
     use crate::controllers::timeseries_controller::TimeseriesResponse;
@@ -8,16 +8,20 @@ pub mod PlansService {
     use crate::models::recurring_model::*;
     use crate::models::user_model::User;
     use crate::services::{
-        finchplaid::ApiClient, snapshots::SnapshotService, timeseries::TimeseriesService,
+        finchplaid::{ApiClient, InvestmentHoldings, Security},
+        fx::FxService,
+        timeseries::TimeseriesService,
         users::UserService,
     };
     use actix_web::web::Data;
     use bson::oid::ObjectId;
     use chrono::offset;
+    use rust_decimal::prelude::ToPrimitive;
     use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
     use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
+    use std::convert::TryFrom;
     use validator::Validate;
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -37,6 +41,7 @@ pub mod PlansService {
                         name: "Dollars".to_string(),
                         class: AssetClass::Cash,
                         annualized_performance: dec!(1.01),
+                        expense_ratio: None,
                     },
                     proportion: dec!(100),
                 }],
@@ -49,6 +54,8 @@ pub mod PlansService {
                 name: "Your default Plan".to_string(),
                 recurrings: vec![],
                 events: vec![],
+                advisory_fee: None,
+                compounding: CompoundingConvention::default(),
                 allocations: vec![alloc],
             }
         } else {
@@ -62,7 +69,6 @@ pub mod PlansService {
         mut user: User,
         days: i64,
         user_service: Data<UserService>,
-        plaid_client: Data<ApiClient>,
     ) -> Result<PlanResponse, ApiError> {
         let plan: Plan = payload.into();
 
@@ -74,8 +80,7 @@ pub mod PlansService {
 
         user_service.save(&mut user).await?;
 
-        let timeseries =
-            TimeseriesService::get_timeseries(user, days, user_service, plaid_client).await?;
+        let timeseries = TimeseriesService::get_timeseries(user, days).await?;
 
         Ok(PlanResponse {
             plan: plan,
@@ -83,16 +88,10 @@ pub mod PlansService {
         })
     }
 
-    pub async fn get_plan(
-        user: User,
-        days: i64,
-        user_service: Data<UserService>,
-        plaid_client: Data<ApiClient>,
-    ) -> Result<PlanResponse, ApiError> {
+    pub async fn get_plan(user: User, days: i64) -> Result<PlanResponse, ApiError> {
         let plan = get_user_plan(&user);
 
-        let timeseries =
-            TimeseriesService::get_timeseries(user, days, user_service, plaid_client).await?;
+        let timeseries = TimeseriesService::get_timeseries(user, days).await?;
 
         Ok(PlanResponse {
             plan: plan,
@@ -116,7 +115,6 @@ pub mod PlansService {
         mut user: User,
         days: i64,
         user_service: Data<UserService>,
-        plaid_client: Data<ApiClient>,
     ) -> Result<PlanResponse, ApiError> {
         let mut plan = get_user_plan(&user);
 
@@ -136,6 +134,22 @@ pub mod PlansService {
             plan.events = events;
         }
 
+        match (payload.advisory_fee, payload.clear_advisory_fee) {
+            (Some(_), true) => {
+                return Err(ApiError::new(
+                    400,
+                    "Can't set and clear the advisory fee at once".to_string(),
+                ))
+            }
+            (Some(advisory_fee), false) => plan.advisory_fee = Some(advisory_fee),
+            (None, true) => plan.advisory_fee = None,
+            (None, false) => {}
+        }
+
+        if let Some(compounding) = payload.compounding {
+            plan.compounding = compounding;
+        }
+
         plan = plan.ensure_ids();
 
         if user.plans.len() < 1 {
@@ -146,8 +160,7 @@ pub mod PlansService {
 
         user_service.save(&mut user).await?;
 
-        let timeseries =
-            TimeseriesService::get_timeseries(user, days, user_service, plaid_client).await?;
+        let timeseries = TimeseriesService::get_timeseries(user, days).await?;
 
         Ok(PlanResponse {
             plan: plan,
@@ -156,29 +169,22 @@ pub mod PlansService {
     }
 
     pub async fn update_plaid_allocation(
-        mut user: User,
+        user: User,
         days: i64,
         user_service: Data<UserService>,
         plaid_client: Data<ApiClient>,
+        fx: Data<FxService>,
     ) -> Result<PlanResponse, ApiError> {
-        let last_snapshot = SnapshotService::get_last_snapshot(
-            &(user_service
-                .get_snapshots(&mut user, plaid_client.clone())
-                .await?),
-        );
-        let net_worth = last_snapshot.net_worth.amount;
-
         let plan = user_service
             .add_plaid_plan(
                 user.clone(),
                 user_service.clone(),
                 plaid_client.clone(),
-                net_worth,
+                &fx,
             )
             .await?;
 
-        let timeseries =
-            TimeseriesService::get_timeseries(user, days, user_service, plaid_client).await?;
+        let timeseries = TimeseriesService::get_timeseries(user, days).await?;
 
         Ok(PlanResponse {
             plan: plan,
@@ -190,15 +196,58 @@ pub mod PlansService {
         user: &User,
         user_service: Data<UserService>,
         plaid_client: Data<ApiClient>,
-        net_worth: Decimal,
+        fx: &FxService,
     ) -> Result<Allocation, ApiError> {
-        let accounts = user_service
-            .get_accounts(user, plaid_client, false)
+        let mut accounts = user_service
+            .get_accounts(user, plaid_client.clone(), false)
             .await?
             .accounts;
 
-        let res = if net_worth > dec!(0.0) {
-            generate_plaid_allocation(accounts, net_worth)
+        // proportions only make sense with every account in one currency
+        let mut rates = HashMap::new();
+        for account in accounts.iter_mut() {
+            let rate = fx
+                .convert(Money::in_currency(1, account.currency), user.base_currency)?
+                .amount;
+            rates.insert(account.account_id.clone(), rate);
+
+            account.balance *= rate;
+            account.currency = user.base_currency;
+        }
+
+        // debts aren't held in anything, so the allocation is of assets alone
+        let assets: Decimal = accounts
+            .iter()
+            .filter(|account| account.balance > dec!(0))
+            .map(|account| account.balance)
+            .sum();
+
+        // items without investment accounts have no holdings to ask for
+        let mut holdings = Vec::new();
+        for item in user.accounts.iter().filter(|item| {
+            accounts.iter().any(|account| {
+                account.item_id == item.item_id && account.account_type == "investment"
+            })
+        }) {
+            match plaid_client
+                .get_investment_holdings(&item.access_token)
+                .await
+            {
+                Ok(mut item_holdings) => {
+                    // holdings are valued in their account's currency
+                    for holding in item_holdings.holdings.iter_mut() {
+                        if let Some(rate) = rates.get(&holding.account_id) {
+                            holding.institution_value *= rate.to_f64().unwrap_or(1.0);
+                        }
+                    }
+                    holdings.push(item_holdings)
+                }
+                Err(e) => log::debug!("No holdings for item {}: {:?}", item.item_id, e),
+            }
+        }
+
+        let res = if assets > dec!(0.0) {
+            generate_plaid_allocation(accounts, &holdings, assets)
         } else {
             Allocation {
                 id: None,
@@ -209,6 +258,7 @@ pub mod PlansService {
                         name: "depository".to_string(),
                         class: AssetClass::Cash,
                         annualized_performance: dec!(1.0),
+                        expense_ratio: None,
                     },
                     proportion: dec!(100.0), // for now make everything positive
                 }],
@@ -218,46 +268,79 @@ pub mod PlansService {
         Ok(res)
     }
 
+    // Depository accounts are cash. Investment accounts are broken down by the
+    // securities Plaid reports them holding, or else taken as broad equities.
     pub fn generate_plaid_allocation(
         accounts: Vec<AccountSuccess>,
-        net_worth: Decimal,
+        holdings: &Vec<InvestmentHoldings>,
+        assets: Decimal,
     ) -> Allocation {
         let default_percentages = get_asset_classes_and_default_apys()
             .into_iter()
             .map(|class_and_apy| (class_and_apy.class, class_and_apy.apy))
             .collect::<HashMap<_, _>>();
 
-        let asset_percentages = accounts
-            .into_iter()
+        let proportion = |name: String, asset_class: AssetClass, value: Decimal| {
+            let performance = default_percentages
+                .get(&asset_class)
+                .cloned()
+                .or(Some(dec!(1.0)))
+                .unwrap();
+
+            AllocationProportion {
+                asset: Asset {
+                    name,
+                    class: asset_class,
+                    annualized_performance: performance,
+                    expense_ratio: None,
+                },
+                proportion: value / assets * dec!(100.0),
+            }
+        };
+
+        let investment_accounts: HashSet<String> = accounts
+            .iter()
+            .filter(|account| account.account_type == "investment")
+            .map(|account| account.account_id.clone())
+            .collect();
+
+        let positions = holding_positions(holdings, &investment_accounts);
+        let held_accounts: HashSet<&String> = holdings
+            .iter()
+            .flat_map(|item| item.holdings.iter())
+            .map(|holding| &holding.account_id)
+            .collect();
+
+        let mut asset_percentages: Vec<AllocationProportion> = accounts
+            .iter()
             .filter_map(|account| {
-                let t = account.account_type.as_str();
+                // manual accounts say what they are, and debts hold nothing
+                if let Some(class) = account.asset_class.clone() {
+                    return if account.balance > dec!(0) {
+                        Some(proportion(account.name.clone(), class, account.balance))
+                    } else {
+                        None
+                    };
+                }
 
-                let asset_class = match t {
+                let asset_class = match account.account_type.as_str() {
                     "depository" => Some(AssetClass::Cash),
-                    "investment" => Some(AssetClass::Equity), // for now classify all investments as broad equities
+                    // these are made up of their positions instead
+                    "investment" if held_accounts.contains(&account.account_id) => None,
+                    "investment" => Some(AssetClass::Equity),
                     _ => None,
                 };
 
-                asset_class.map(|c| (c, account.balance, account.name))
-            })
-            .map(|(asset_class, balance, account_name)| {
-                let performance = default_percentages
-                    .get(&asset_class)
-                    .cloned()
-                    .or(Some(dec!(1.0)))
-                    .unwrap();
-
-                AllocationProportion {
-                    asset: Asset {
-                        name: account_name,
-                        class: asset_class,
-                        annualized_performance: performance,
-                    },
-                    proportion: balance / net_worth * dec!(100.0),
-                }
+                asset_class.map(|c| proportion(account.name.clone(), c, account.balance))
             })
             .collect();
 
+        asset_percentages.extend(
+            positions
+                .into_iter()
+                .map(|(name, asset_class, value)| proportion(name, asset_class, value)),
+        );
+
         Allocation {
             id: None,
             description: "Current Holdings".to_string(),
@@ -266,6 +349,72 @@ pub mod PlansService {
         }
     }
 
+    // derivatives, crypto and anything else Plaid can't name go under Other
+    pub fn security_asset_class(security_type: Option<&str>) -> AssetClass {
+        match security_type {
+            Some("equity") => AssetClass::Equity,
+            Some("etf") => AssetClass::Etf,
+            Some("mutual fund") => AssetClass::MutualFund,
+            Some("fixed income") => AssetClass::Fixed,
+            Some("cash") => AssetClass::Cash,
+            _ => AssetClass::Other,
+        }
+    }
+
+    // The value held in each security across the given accounts, in the order the
+    // securities first appear. Short positions can't be a share of an allocation,
+    // so they are left out.
+    fn holding_positions(
+        holdings: &Vec<InvestmentHoldings>,
+        accounts: &HashSet<String>,
+    ) -> Vec<(String, AssetClass, Decimal)> {
+        let mut positions: Vec<(String, String, AssetClass, Decimal)> = Vec::new();
+
+        for item in holdings.iter() {
+            let securities: HashMap<&String, &Security> = item
+                .securities
+                .iter()
+                .map(|security| (&security.security_id, security))
+                .collect();
+
+            for holding in item
+                .holdings
+                .iter()
+                .filter(|holding| accounts.contains(&holding.account_id))
+            {
+                let value = Decimal::try_from(holding.institution_value).unwrap_or(dec!(0));
+                if value <= dec!(0) {
+                    continue;
+                }
+
+                match positions
+                    .iter_mut()
+                    .find(|(security_id, _, _, _)| *security_id == holding.security_id)
+                {
+                    Some(position) => position.3 += value,
+                    None => {
+                        let security = securities.get(&holding.security_id);
+                        let name = security
+                            .and_then(|security| {
+                                security.ticker_symbol.clone().or(security.name.clone())
+                            })
+                            .unwrap_or("Unknown security".to_string());
+                        let asset_class = security_asset_class(
+                            security.and_then(|security| security.security_type.as_deref()),
+                        );
+
+                        positions.push((holding.security_id.clone(), name, asset_class, value));
+                    }
+                }
+            }
+        }
+
+        positions
+            .into_iter()
+            .map(|(_, name, asset_class, value)| (name, asset_class, value))
+            .collect()
+    }
+
     // TODO (not this)
     pub fn generate_sample_plan() -> Plan {
         let recurrings = vec![Recurring {
@@ -286,6 +435,7 @@ pub mod PlansService {
             name: String::from("Finch Savings Account"),
             class: AssetClass::Cash,
             annualized_performance: dec!(1.05),
+            expense_ratio: None,
         };
 
         let test_change = AllocationProportion {
@@ -317,6 +467,8 @@ pub mod PlansService {
             recurrings: recurrings,
             allocations: allocations,
             events: events,
+            advisory_fee: None,
+            compounding: CompoundingConvention::default(),
         }
         .ensure_ids()
     }
@@ -419,8 +571,10 @@ pub mod PlansService {
 
 #[cfg(test)]
 mod test {
+    use crate::common::Currency;
     use crate::controllers::plaid_controller::AccountSuccess;
     use crate::models::plan_model::*;
+    use crate::services::finchplaid::{Holding, InvestmentHoldings, Security};
     use crate::services::plans::PlansService;
     use rust_decimal_macros::dec;
 
@@ -430,15 +584,19 @@ mod test {
                 item_id: "blah".to_string(),
                 name: "blah".to_string(),
                 balance: dec!(500),
+                currency: Currency::USD,
                 account_type: "depository".to_string(),
                 account_id: "blah".to_string(),
+                asset_class: None,
             },
             AccountSuccess {
                 item_id: "blah2".to_string(),
                 name: "blah2".to_string(),
                 balance: dec!(500),
+                currency: Currency::USD,
                 account_type: "investment".to_string(),
                 account_id: "blah".to_string(),
+                asset_class: None,
             },
         ]
     }
@@ -454,6 +612,7 @@ mod test {
                     name: "blah".to_string(),
                     class: AssetClass::Cash,
                     annualized_performance: dec!(1.0),
+                    expense_ratio: None,
                 },
                 proportion: dec!(50.0),
             },
@@ -462,12 +621,105 @@ mod test {
                     name: "blah2".to_string(),
                     class: AssetClass::Equity,
                     annualized_performance: dec!(1.05),
+                    expense_ratio: None,
                 },
                 proportion: dec!(50.0),
             },
         ];
-        let res = PlansService::generate_plaid_allocation(accounts, net_worth);
+        let res = PlansService::generate_plaid_allocation(accounts, &vec![], net_worth);
 
         assert_eq!(target, res.schema);
     }
+
+    #[test]
+    fn test_plaid_allocation_with_manual_accounts() {
+        let manual = |name: &str, balance, class: AssetClass| AccountSuccess {
+            item_id: "manual".to_string(),
+            name: name.to_string(),
+            balance,
+            currency: Currency::USD,
+            account_type: "other".to_string(),
+            account_id: name.to_string(),
+            asset_class: Some(class),
+        };
+
+        let mut accounts = generate_test_accounts();
+        accounts.push(manual(
+            "house",
+            dec!(1000),
+            AssetClass::Custom("Real Estate".to_string()),
+        ));
+        accounts.push(manual("mortgage", dec!(-800), AssetClass::Other));
+
+        let res = PlansService::generate_plaid_allocation(accounts, &vec![], dec!(2000.0));
+
+        let proportions: Vec<(String, rust_decimal::Decimal)> = res
+            .schema
+            .iter()
+            .map(|p| (p.asset.name.clone(), p.proportion))
+            .collect();
+        assert_eq!(
+            vec![
+                ("blah".to_string(), dec!(25)),
+                ("blah2".to_string(), dec!(25)),
+                ("house".to_string(), dec!(50)),
+            ],
+            proportions
+        );
+        assert_eq!(
+            AssetClass::Custom("Real Estate".to_string()),
+            res.schema[2].asset.class
+        );
+    }
+
+    #[test]
+    fn test_plaid_allocation_from_holdings() {
+        let net_worth = dec!(1000.0);
+        let accounts = generate_test_accounts();
+
+        let security = |security_id: &str, ticker: &str, security_type: &str| Security {
+            security_id: security_id.to_string(),
+            name: None,
+            ticker_symbol: Some(ticker.to_string()),
+            security_type: Some(security_type.to_string()),
+        };
+        let holding = |security_id: &str, value: f64| Holding {
+            account_id: "blah".to_string(),
+            security_id: security_id.to_string(),
+            institution_value: value,
+            quantity: 1.0,
+        };
+
+        // a bond-heavy retirement account
+        let holdings = vec![InvestmentHoldings {
+            holdings: vec![
+                holding("bnd", 300.0),
+                holding("vti", 100.0),
+                holding("bnd", 50.0),
+                holding("cash", 50.0),
+                holding("put", -20.0),
+            ],
+            securities: vec![
+                security("bnd", "BND", "fixed income"),
+                security("vti", "VTI", "etf"),
+                security("cash", "USD", "cash"),
+                security("put", "SPY220121P00400000", "derivative"),
+            ],
+        }];
+
+        let res = PlansService::generate_plaid_allocation(accounts, &holdings, net_worth);
+
+        assert_eq!(
+            vec![
+                ("blah".to_string(), AssetClass::Cash, dec!(50.0)),
+                ("BND".to_string(), AssetClass::Fixed, dec!(35.0)),
+                ("VTI".to_string(), AssetClass::Etf, dec!(10.0)),
+                ("USD".to_string(), AssetClass::Cash, dec!(5.0)),
+            ],
+            res.schema
+                .into_iter()
+                .map(|p| (p.asset.name, p.asset.class, p.proportion))
+                .collect::<Vec<_>>()
+        );
+    }
 }
@@ -0,0 +1,255 @@
+// Finds recurring payments in the transaction ledger: the same merchant charging
+// (or paying) a similar amount at a steady weekly, bi-weekly, monthly or annual
+// cadence.
+use crate::models::{
+  recurring_model::{Recurring, RecurringSuggestion, TimeInterval, Typ},
+  transaction_model::Transaction,
+};
+use chrono::{Duration, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+// suggested recurrings run this long from their last occurrence
+const SUGGESTION_YEARS: i64 = 5;
+
+struct Cadence {
+  name: &'static str,
+  frequency: TimeInterval,
+  days: i64,
+  tolerance: i64,
+  min_occurrences: usize,
+}
+
+fn cadences() -> Vec<Cadence> {
+  vec![
+    Cadence {
+      name: "weekly",
+      frequency: TimeInterval::new(Typ::Weekly, 1),
+      days: 7,
+      tolerance: 1,
+      min_occurrences: 4,
+    },
+    Cadence {
+      name: "biweekly",
+      frequency: TimeInterval::new(Typ::Weekly, 2),
+      days: 14,
+      tolerance: 2,
+      min_occurrences: 3,
+    },
+    Cadence {
+      name: "monthly",
+      frequency: TimeInterval::new(Typ::Monthly, 1),
+      days: 30,
+      tolerance: 3,
+      min_occurrences: 3,
+    },
+    Cadence {
+      name: "annually",
+      frequency: TimeInterval::new(Typ::Annually, 1),
+      days: 365,
+      tolerance: 10,
+      min_occurrences: 2,
+    },
+  ]
+}
+
+// "Uber 063015 SF**POOL**" and "UBER 072515 SF**POOL**" are the same merchant
+fn merchant_key(transaction: &Transaction) -> String {
+  transaction
+    .merchant_name
+    .as_ref()
+    .unwrap_or(&transaction.name)
+    .to_lowercase()
+    .split(|c: char| !c.is_alphabetic())
+    .filter(|word| !word.is_empty())
+    .collect::<Vec<&str>>()
+    .join("-")
+}
+
+fn median(values: &mut Vec<Decimal>) -> Decimal {
+  values.sort();
+  values[values.len() / 2]
+}
+
+struct Occurrence {
+  date: NaiveDate,
+  amount: Decimal,
+}
+
+fn detect_group(
+  key: &String,
+  name: &String,
+  occurrences: &Vec<Occurrence>,
+  today: NaiveDate,
+) -> Option<RecurringSuggestion> {
+  let typical_amount = median(&mut occurrences.iter().map(|o| o.amount).collect());
+  // amounts may drift up to 20% from the typical amount
+  let similar_amounts = occurrences
+    .iter()
+    .all(|o| (o.amount - typical_amount).abs() <= typical_amount.abs() * dec!(0.2));
+  if !similar_amounts {
+    return None;
+  }
+
+  let intervals: Vec<i64> = occurrences
+    .windows(2)
+    .map(|pair| (pair[1].date - pair[0].date).num_days())
+    .collect();
+  let last = occurrences.last()?.date;
+
+  let cadence = cadences().into_iter().find(|cadence| {
+    occurrences.len() >= cadence.min_occurrences
+      && intervals
+        .iter()
+        .all(|interval| (interval - cadence.days).abs() <= cadence.tolerance)
+      // a payment that missed two of its dates has probably stopped
+      && (today - last).num_days() <= 2 * cadence.days + cadence.tolerance
+  })?;
+
+  let start = last.and_hms(0, 0, 0).timestamp();
+
+  Some(RecurringSuggestion {
+    id: format!("{}-{}", key, cadence.name),
+    occurrences: occurrences.len(),
+    last_date: last.format("%Y-%m-%d").to_string(),
+    recurring: Recurring {
+      id: None,
+      name: name.clone(),
+      start,
+      end: (last + Duration::days(365 * SUGGESTION_YEARS))
+        .and_hms(0, 0, 0)
+        .timestamp(),
+      principal: dec!(0),
+      // recurrings count money coming in as positive, Plaid the other way around
+      amount: -typical_amount,
+      interest: dec!(0),
+      frequency: cadence.frequency,
+    },
+  })
+}
+
+// `transactions` should be posted and from visible accounts
+pub fn detect_recurrings(
+  transactions: &Vec<Transaction>,
+  today: NaiveDate,
+) -> Vec<RecurringSuggestion> {
+  // money in and money out from the same merchant are different recurrings
+  let mut groups: BTreeMap<(String, bool), (String, Vec<Occurrence>)> = BTreeMap::new();
+
+  for transaction in transactions.iter() {
+    let date = match NaiveDate::parse_from_str(&transaction.date, "%Y-%m-%d") {
+      Ok(date) => date,
+      Err(_) => continue,
+    };
+
+    let key = merchant_key(transaction);
+    if key.is_empty() {
+      continue;
+    }
+
+    let group = groups
+      .entry((key, transaction.amount.amount > dec!(0)))
+      .or_insert_with(|| (String::new(), vec![]));
+
+    group.0 = transaction
+      .merchant_name
+      .clone()
+      .unwrap_or(transaction.name.clone());
+    group.1.push(Occurrence {
+      date,
+      amount: transaction.amount.amount,
+    });
+  }
+
+  groups
+    .into_iter()
+    .filter_map(|((key, _), (name, mut occurrences))| {
+      occurrences.sort_by_key(|o| o.date);
+      detect_group(&key, &name, &occurrences, today)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Money;
+
+  fn transaction(name: &str, amount: Decimal, date: NaiveDate) -> Transaction {
+    let date = date.format("%Y-%m-%d").to_string();
+    Transaction::fixture(
+      &format!("{}-{}", name, date),
+      name,
+      Money::new(amount),
+      &date,
+    )
+  }
+
+  #[test]
+  fn test_merchant_key() {
+    let date = NaiveDate::from_ymd(2021, 3, 1);
+    assert_eq!(
+      "uber-sf-pool",
+      merchant_key(&transaction("Uber 063015 SF**POOL**", dec!(5.4), date))
+    );
+  }
+
+  #[test]
+  fn test_detect_recurrings() {
+    let today = NaiveDate::from_ymd(2021, 3, 20);
+    let mut transactions = vec![];
+
+    // rent on (about) the first of the month
+    for (month, day) in [(11, 1), (12, 1), (1, 2), (2, 1), (3, 1)].iter() {
+      let year = if *month > 10 { 2020 } else { 2021 };
+      transactions.push(transaction(
+        "Rent Co",
+        dec!(1500),
+        NaiveDate::from_ymd(year, *month, *day),
+      ));
+    }
+
+    // salary every other friday, with the odd bonus cent
+    let mut payday = NaiveDate::from_ymd(2021, 1, 8);
+    while payday < today {
+      transactions.push(transaction("ACME PAYROLL 4411", dec!(-2000.01), payday));
+      payday = payday + Duration::days(14);
+    }
+
+    // coffee at no particular cadence
+    for day in [1, 2, 9, 23].iter() {
+      transactions.push(transaction(
+        "Starbucks",
+        dec!(4.5),
+        NaiveDate::from_ymd(2021, 2, *day),
+      ));
+    }
+
+    // a gym that stopped charging months ago
+    for month in [6, 7, 8, 9].iter() {
+      transactions.push(transaction(
+        "Gym",
+        dec!(40),
+        NaiveDate::from_ymd(2020, *month, 15),
+      ));
+    }
+
+    let suggestions = detect_recurrings(&transactions, today);
+    assert_eq!(2, suggestions.len());
+
+    let salary = &suggestions[0];
+    assert_eq!("acme-payroll-biweekly", salary.id);
+    assert_eq!(dec!(2000.01), salary.recurring.amount);
+    assert_eq!(
+      TimeInterval::new(Typ::Weekly, 2),
+      salary.recurring.frequency
+    );
+    assert_eq!("2021-03-19", salary.last_date);
+
+    let rent = &suggestions[1];
+    assert_eq!("rent-co-monthly", rent.id);
+    assert_eq!(dec!(-1500), rent.recurring.amount);
+    assert_eq!(5, rent.occurrences);
+  }
+}
@@ -1,12 +1,93 @@
+pub mod detection;
+
 #[allow(non_snake_case)]
 pub mod RecurringService {
+  use super::*;
   use crate::common::errors::ApiError;
   use crate::controllers::recurring_controller::RecurringNewPayload;
+  use crate::controllers::transactions_controller::TransactionQuery;
   use crate::models::{recurring_model::*, user_model::User};
-  use crate::services::users::UserService;
+  use crate::services::{
+    fx::FxService, spending::SpendingService, transactions::TransactionService, users::UserService,
+  };
   use actix_web::web::Data;
+  use chrono::{Duration, Utc};
   use wither::{mongodb::bson::oid::ObjectId, Model};
 
+  // long enough to see an annual payment twice
+  const SUGGESTION_HISTORY_DAYS: i64 = 400;
+
+  pub async fn get_suggestions(
+    user: &User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<Vec<RecurringSuggestion>, ApiError> {
+    let today = Utc::now().naive_utc().date();
+    let excluded_accounts = user.get_excluded_accounts();
+
+    let transactions = transaction_service
+      .get_transactions(
+        &user.id.clone().unwrap(),
+        TransactionQuery {
+          from: Some(
+            (today - Duration::days(SUGGESTION_HISTORY_DAYS))
+              .format("%Y-%m-%d")
+              .to_string(),
+          ),
+          to: None,
+          account_id: None,
+          min_amount: None,
+          max_amount: None,
+        },
+      )
+      .await?
+      .into_iter()
+      .filter(|transaction| {
+        !transaction.pending && !excluded_accounts.contains(&transaction.account_id)
+      })
+      .collect();
+    // recurrings are in the user's base currency
+    let transactions = SpendingService::in_base_currency(transactions, user, &fx)?;
+
+    // leave out what the user already has as a recurring
+    Ok(
+      detection::detect_recurrings(&transactions, today)
+        .into_iter()
+        .filter(|suggestion| {
+          !user
+            .recurrings
+            .iter()
+            .any(|rec| rec.name.to_lowercase() == suggestion.recurring.name.to_lowercase())
+        })
+        .collect(),
+    )
+  }
+
+  pub async fn accept_suggestion(
+    suggestion_id: String,
+    mut user: User,
+    user_service: Data<UserService>,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<Recurring, ApiError> {
+    let mut recurring = get_suggestions(&user, transaction_service, fx)
+      .await?
+      .into_iter()
+      .find(|suggestion| suggestion.id == suggestion_id)
+      .ok_or(ApiError::new(
+        400,
+        format!("No recurring suggestion with id {} found", suggestion_id),
+      ))?
+      .recurring;
+    recurring.set_id(ObjectId::new());
+
+    user.recurrings.push(recurring.clone());
+
+    user_service.save(&mut user).await?;
+
+    Ok(recurring)
+  }
+
   pub async fn get_recurring(recurring_id: String, user: User) -> Result<Recurring, ApiError> {
     let recurring_id_opt = Some(
       ObjectId::with_string(recurring_id.as_str())
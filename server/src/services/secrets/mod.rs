@@ -1,3 +1,4 @@
+use crate::common::encryption::MasterKey;
 use serde::Deserialize;
 use std::error::Error;
 use std::fs::File;
@@ -9,8 +10,39 @@ pub struct Environment {
   pub database_user: String,
   pub database_pw: String,
   pub database_name: String,
+  // unused when `plaid_fixtures` is set
+  #[serde(default)]
   pub plaid_client_id: String,
+  #[serde(default)]
   pub plaid_sandbox_secret: String,
+  // where Plaid sends webhooks for items linked from now on
+  #[serde(default)]
+  pub plaid_webhook_url: Option<String>,
+  // a directory of Plaid responses (like tests/fixtures/plaid) to answer from
+  // instead of Plaid, for offline development
+  #[serde(default)]
+  pub plaid_fixtures: Option<String>,
+  // the /transactions/get response the fixtures answer with, which also serves /accounts/get
+  #[serde(default = "default_plaid_fixture_transactions")]
+  pub plaid_fixture_transactions: String,
+  // the countries whose institutions Link offers, as ISO 3166-1 alpha-2 codes
+  #[serde(default = "default_plaid_country_codes")]
+  pub plaid_country_codes: Vec<String>,
+  // a rates file for converting between currencies, see `services::fx`
+  #[serde(default)]
+  pub fx_rates: Option<String>,
+  // master keys for the Plaid access tokens stored in the database. Tokens are
+  // encrypted under `token_key_id`, other keys are kept until rotated out.
+  pub token_keys: Vec<MasterKey>,
+  pub token_key_id: String,
+}
+
+fn default_plaid_fixture_transactions() -> String {
+  "./tests/test_snapshots.json".to_string()
+}
+
+fn default_plaid_country_codes() -> Vec<String> {
+  vec!["US".to_string()]
 }
 
 impl Environment {
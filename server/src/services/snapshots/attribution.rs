@@ -0,0 +1,237 @@
+use crate::common::{errors::ApiError, Money};
+use crate::models::user_model::{AccountSnapshot, Snapshot};
+use crate::services::exports::{date_of, DateRange};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+// Where a change in net worth came from. Contributions, investment gains and
+// other add up to the change. Debt paydown overlaps them: it is how much less
+// is owed at the end, whatever paid for it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NetWorthAttribution {
+  pub start_time: i64,
+  pub end_time: i64,
+  pub opening_net_worth: Money,
+  pub closing_net_worth: Money,
+  pub change: Money,
+  // income minus spending, as in the snapshots' running sums
+  pub contributions: Money,
+  // what investment accounts and valued assets gained beyond their transactions
+  pub investment_gains: Money,
+  pub debt_paydown: Money,
+  // interest, fees and accounts linked or removed in between
+  pub other: Money,
+}
+
+// held for their market value rather than spent from
+pub fn is_investment(account: &AccountSnapshot) -> bool {
+  matches!(
+    account.account_type.as_str(),
+    "investment" | "crypto" | "private equity" | "property"
+  )
+}
+
+fn is_debt(account: &AccountSnapshot) -> bool {
+  matches!(account.account_type.as_str(), "credit" | "loan")
+}
+
+// The accepted snapshots from the last on or before `from` to the last on or
+// before `to`, oldest first. These default to the first and latest snapshots.
+pub fn span<'a>(snapshots: &'a Vec<Snapshot>, range: &DateRange) -> Vec<&'a Snapshot> {
+  let mut sorted: Vec<&Snapshot> = snapshots
+    .iter()
+    .filter(|snapshot| !snapshot.is_quarantined())
+    .collect();
+  sorted.sort_by_key(|snapshot| snapshot.snapshot_time);
+
+  let on_or_before = |date| {
+    sorted
+      .iter()
+      .rposition(|snapshot| date_of(snapshot.snapshot_time) <= date)
+  };
+  let first = range.from.and_then(on_or_before).unwrap_or(0);
+  let last = match range.to {
+    Some(to) => on_or_before(to),
+    None => sorted.len().checked_sub(1),
+  };
+
+  match last {
+    Some(last) if first <= last => sorted[first..=last].to_vec(),
+    _ => vec![],
+  }
+}
+
+// The change over the span of the range. Accounts are counted from the first
+// snapshot they appear in, so linking one in between isn't mistaken for a gain,
+// and as hidden or shown as they are at the end.
+pub fn attribute(
+  snapshots: &Vec<Snapshot>,
+  range: &DateRange,
+) -> Result<NetWorthAttribution, ApiError> {
+  let span = span(snapshots, range);
+  let (start, between) = match span.split_first() {
+    Some((start, between)) if !between.is_empty() => (*start, between),
+    _ => {
+      return Err(ApiError::new(
+        400,
+        "Need two snapshots in the range to attribute a change".to_string(),
+      ))
+    }
+  };
+  let end = between[between.len() - 1];
+
+  let zero = Money {
+    amount: Decimal::new(0, 0),
+    currency: end.net_worth.currency,
+  };
+  let mut investment_gains = zero;
+  let mut debt_paydown = zero;
+
+  let account_ids: BTreeSet<&String> = span
+    .iter()
+    .flat_map(|snapshot| snapshot.accounts.iter())
+    .map(|account| &account.account_id)
+    .collect();
+  for account_id in account_ids {
+    if end
+      .account(account_id)
+      .map_or(false, |account| account.hidden)
+    {
+      continue;
+    }
+
+    // its balance where it is first seen, then what happened after
+    let mut seen = span
+      .iter()
+      .filter_map(|snapshot| snapshot.account(account_id));
+    let opening = match seen.next() {
+      Some(opening) => opening,
+      None => continue,
+    };
+    let mut closing = opening;
+    let mut transacted = zero;
+    for account in seen {
+      closing = account;
+      transacted = transacted.checked_add(account.transacted)?;
+    }
+
+    let change = closing.balance.checked_sub(opening.balance)?;
+    if is_investment(closing) {
+      investment_gains = investment_gains
+        .checked_add(change)?
+        .checked_sub(transacted)?;
+    } else if is_debt(closing) {
+      debt_paydown = debt_paydown.checked_add(change)?;
+    }
+  }
+
+  let change = end.net_worth.checked_sub(start.net_worth)?;
+  let contributions = end
+    .running_income
+    .checked_sub(start.running_income)?
+    .checked_add(end.running_spending)?
+    .checked_sub(start.running_spending)?;
+
+  Ok(NetWorthAttribution {
+    start_time: start.snapshot_time,
+    end_time: end.snapshot_time,
+    opening_net_worth: start.net_worth,
+    closing_net_worth: end.net_worth,
+    change,
+    contributions,
+    investment_gains,
+    debt_paydown,
+    other: change
+      .checked_sub(contributions)?
+      .checked_sub(investment_gains)?,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use chrono::NaiveDate;
+  use rust_decimal_macros::dec;
+
+  fn june(day: u32) -> i64 {
+    NaiveDate::from_ymd(2021, 6, day)
+      .and_hms(23, 59, 59)
+      .timestamp()
+  }
+
+  #[test]
+  fn test_attribute() {
+    let snapshots = vec![
+      Snapshot::fixture(
+        june(1),
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(2000)),
+          AccountSnapshot::fixture("brokerage", "investment", dec!(10000)),
+          AccountSnapshot::fixture("card", "credit", dec!(-500)),
+        ],
+      )
+      .with_running_sums(dec!(0), dec!(0)),
+      // paid 3000, spent 800 on the card and paid 1100 of it, and moved 1000
+      // to the brokerage, which also went up 400
+      Snapshot::fixture(
+        june(15),
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(2900)).with_transacted(dec!(900)),
+          AccountSnapshot::fixture("brokerage", "investment", dec!(11400))
+            .with_transacted(dec!(1000)),
+          AccountSnapshot::fixture("card", "credit", dec!(-200)).with_transacted(dec!(300)),
+        ],
+      )
+      .with_running_sums(dec!(3000), dec!(-800)),
+      // a brokerage balance waiting for review doesn't count
+      Snapshot::fixture(
+        june(18),
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(2900)),
+          AccountSnapshot::fixture("brokerage", "investment", dec!(1140000)),
+          AccountSnapshot::fixture("card", "credit", dec!(-200)),
+        ],
+      )
+      .with_running_sums(dec!(3000), dec!(-800))
+      .with_quarantine(Some("Net worth is far from the recent median".to_string())),
+      // a house, linked later, isn't a gain
+      Snapshot::fixture(
+        june(30),
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(2900)),
+          AccountSnapshot::fixture("brokerage", "investment", dec!(11200)),
+          AccountSnapshot::fixture("card", "credit", dec!(-200)),
+          AccountSnapshot::fixture("manual-house", "property", dec!(300000)),
+        ],
+      )
+      .with_running_sums(dec!(3000), dec!(-800)),
+    ];
+    let everything = DateRange {
+      from: None,
+      to: None,
+    };
+
+    let attribution = attribute(&snapshots, &everything).unwrap();
+    assert_eq!(Money::usd(dec!(302400)), attribution.change);
+    assert_eq!(Money::usd(dec!(2200)), attribution.contributions);
+    assert_eq!(Money::usd(dec!(200)), attribution.investment_gains);
+    assert_eq!(Money::usd(dec!(300)), attribution.debt_paydown);
+    assert_eq!(Money::usd(dec!(300000)), attribution.other);
+
+    let first_half = DateRange {
+      from: None,
+      to: Some(NaiveDate::from_ymd(2021, 6, 20)),
+    };
+    let attribution = attribute(&snapshots, &first_half).unwrap();
+    assert_eq!(Money::usd(dec!(400)), attribution.investment_gains);
+    assert_eq!(Money::usd(dec!(0)), attribution.other);
+
+    // one snapshot is no change
+    let last_day = DateRange {
+      from: Some(NaiveDate::from_ymd(2021, 6, 30)),
+      to: None,
+    };
+    assert!(attribute(&snapshots, &last_day).is_err());
+  }
+}
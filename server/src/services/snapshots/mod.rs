@@ -1,54 +1,434 @@
+pub mod attribution;
+pub mod performance;
+pub mod periods;
+pub mod quality;
+
 #[allow(non_snake_case)]
 pub mod SnapshotService {
-  use crate::common::{errors::ApiError, Money};
-  use crate::models::user_model::{PlaidItem, Snapshot, User};
-  use crate::services::finchplaid::ApiClient;
+  use crate::common::{
+    errors::{ApiError, AppError},
+    into_bson_document, Currency, Money,
+  };
+  use crate::controllers::snapshot_controller::{
+    AccountHistoryEntry, SnapshotAccount, SnapshotQuery,
+  };
+  use crate::models::user_model::{AccountSnapshot, PlaidItem, Snapshot, User};
+  use crate::services::snapshots::{
+    attribution::{self, NetWorthAttribution},
+    performance::{self, InvestmentPerformance},
+    periods::{self, SnapshotPeriod},
+    quality,
+  };
+  use crate::services::{
+    db::DatabaseService,
+    exports,
+    finchplaid::{self, ApiClient},
+    fx::FxService,
+    manual_accounts::ManualAccountService,
+    transactions::{transfers, TransactionService},
+    users::UserService,
+  };
   use actix_web::web::Data;
-  use chrono::{Duration, Utc};
-  use log::debug;
-  use plaid::models::{RetrieveTransactionsResponse, Transaction};
+  use async_std::task;
+  use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+  use futures::stream::StreamExt;
+  use log::{debug, info};
+  use plaid::models::{Account, RetrieveTransactionsResponse, Transaction};
   use rust_decimal::Decimal;
-  use std::collections::HashSet;
+  use std::collections::{BTreeMap, HashMap, HashSet};
   use std::convert::TryFrom;
+  use std::time;
+  use wither::{
+    mongodb::bson::{bson, doc, to_bson, Bson},
+    Model,
+  };
+
+  // upper bound on users snapshotted at once, so we stay within Plaid's rate limits
+  const MAX_CONCURRENT_SNAPSHOTS: usize = 8;
+
+  // time between passes over the users needing a snapshot
+  const SNAPSHOT_PASS_INTERVAL_SECS: u64 = 60 * 60;
+
+  // how far back the history of a newly linked item is reconstructed, about 24 months
+  const BACKFILL_DAYS: i64 = 730;
+
+  // a snapshot counts the transactions from this many days before it was taken
+  const TRANSACTION_LAG_DAYS: i64 = 2;
+
+  pub async fn run_snapshot_service(
+    db_service: &DatabaseService,
+    plaid_client: Data<ApiClient>,
+    fx: &FxService,
+  ) -> Result<(), AppError> {
+    let transaction_service = TransactionService::new(db_service).await;
+
+    loop {
+      let one_day_ago = (Utc::now() - Duration::days(1)).timestamp();
+
+      // users with no snapshots, whose latest snapshot is over a day old,
+      // with newly linked items whose history is yet to be backfilled,
+      // or with items Plaid told us have new transactions
+      let users = User::find(
+        &db_service.db,
+        doc! {
+          "$or": vec![
+            doc!{"snapshots": doc!{"$size": 0}},
+            doc!{"accounts.pending_backfill": true},
+            doc!{"accounts.pending_refresh": true},
+            doc!{"$expr": doc!{"$lt": bson!([
+              doc!{"$arrayElemAt": bson!(["$snapshots.snapshot_time", -1])},
+              one_day_ago
+            ])}}
+          ]
+        },
+        None,
+      )
+      .await
+      .map_err(|_| AppError::new("Error while finding users needing snapshot"))?;
+
+      users
+        .for_each_concurrent(MAX_CONCURRENT_SNAPSHOTS, |user| {
+          let plaid_client = plaid_client.clone();
+          let transaction_service = &transaction_service;
+          async move {
+            match user {
+              Ok(mut user) => {
+                take_scheduled_snapshot(
+                  &mut user,
+                  db_service,
+                  transaction_service,
+                  plaid_client,
+                  fx,
+                )
+                .await
+              }
+              Err(e) => log::error!("Could not read user needing snapshot: {:?}", e),
+            }
+          }
+        })
+        .await;
+
+      info!(
+        "Snapshot pass complete. Next pass in {} seconds...",
+        SNAPSHOT_PASS_INTERVAL_SECS
+      );
+
+      task::sleep(time::Duration::from_secs(SNAPSHOT_PASS_INTERVAL_SECS)).await;
+    }
+  }
+
+  async fn take_scheduled_snapshot(
+    user: &mut User,
+    db_service: &DatabaseService,
+    transaction_service: &TransactionService,
+    plaid_client: Data<ApiClient>,
+    fx: &FxService,
+  ) {
+    let pending_backfills: Vec<String> = user
+      .accounts
+      .iter()
+      .filter(|item| item.pending_backfill)
+      .map(|item| item.item_id.clone())
+      .collect();
+    let needs_snapshot = need_new_snapshot(&user.snapshots);
+    let needs_refresh = user.accounts.iter().any(|item| item.pending_refresh);
+
+    // the user may have been handled since the pass started, e.g. by linking an account
+    if pending_backfills.is_empty() && !needs_snapshot && !needs_refresh {
+      return;
+    }
+
+    // a failed backfill stays pending and is retried on the next pass. Plaid
+    // needs a little while after linking before the item's history is ready.
+    let mut history_merged = false;
+    for item_id in pending_backfills.iter() {
+      info!("Backfilling history of item {} for {}", item_id, user.email);
+
+      match backfill_item_history(user, item_id, plaid_client.clone(), fx).await {
+        Ok(()) => history_merged = true,
+        Err(e) => log::error!(
+          "Failed to backfill item {} for {}: {:?}",
+          item_id,
+          user.email,
+          e
+        ),
+      }
+    }
+
+    let mut snapshot_added = false;
+    if needs_snapshot {
+      info!("Taking snapshot for {}", user.email);
+
+      match add_new_snapshot(user, plaid_client.clone(), fx).await {
+        Ok(()) => snapshot_added = true,
+        Err(e) => log::error!("Failed to take snapshot for {}: {:?}", user.email, e),
+      }
+    }
+
+    // the ledger is brought up to date alongside the daily snapshot,
+    // and whenever Plaid has new transactions for an item
+    let user_id = user.id.clone().unwrap();
+    let mut synced_since: Option<NaiveDate> = None;
+    for item in user
+      .accounts
+      .iter_mut()
+      .filter(|item| needs_snapshot || item.pending_refresh)
+    {
+      match transaction_service
+        .sync_item(&user_id, item, plaid_client.clone())
+        .await
+      {
+        Ok(start) => {
+          synced_since = Some(synced_since.map_or(start, |since| since.min(start)));
+          item.pending_refresh = false;
+          item.record_success();
+        }
+        Err(e) => {
+          log::error!(
+            "Failed to sync transactions of item {}: {:?}",
+            item.item_id,
+            e
+          );
+          item.record_failure(&e);
+        }
+      }
+    }
+
+    // transfers can be between items, so they're paired once all are synced
+    if let Some(since) = synced_since {
+      if let Err(e) = transaction_service.mark_transfers(&user_id, since).await {
+        log::error!("Failed to mark transfers for {}: {:?}", user.email, e);
+      }
+    }
+
+    // save even on failure so that the failing item is recorded
+    if let Err(e) = save_scheduled_snapshot(db_service, user, history_merged, snapshot_added).await
+    {
+      log::error!("Failed to save {} after snapshot: {:?}", user.email, e);
+    }
+  }
+
+  // Writes back only what the service changed, the snapshots and the items' sync
+  // state, as the user may have changed the rest through the API meanwhile.
+  async fn save_scheduled_snapshot(
+    db_service: &DatabaseService,
+    user: &User,
+    history_merged: bool,
+    snapshot_added: bool,
+  ) -> Result<(), ApiError> {
+    fn to_bson_value<T: serde::Serialize>(value: &T) -> Result<Bson, ApiError> {
+      to_bson(value).map_err(|_| ApiError::new(500, "Database Error".to_string()))
+    }
 
+    let collection = User::collection(&db_service.db);
+
+    // a merged history changes past snapshots too, so they are all set
+    let snapshots_update = if history_merged {
+      Some(doc! {"$set": doc!{"snapshots": to_bson_value(&user.snapshots)?}})
+    } else if snapshot_added {
+      user
+        .snapshots
+        .last()
+        .map(|snapshot| doc! {"$push": doc!{"snapshots": into_bson_document(snapshot)}})
+    } else {
+      None
+    };
+
+    if let Some(update) = snapshots_update {
+      collection
+        .update_one(doc! {"_id": user.id.clone()}, update, None)
+        .await
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+    }
+
+    // an item removed meanwhile isn't matched, so it stays removed
+    for item in user.accounts.iter() {
+      collection
+        .update_one(
+          doc! {"_id": user.id.clone(), "accounts.item_id": item.item_id.clone()},
+          doc! {"$set": doc!{
+            "accounts.$.status": into_bson_document(&item.status),
+            "accounts.$.last_successful_sync": to_bson_value(&item.last_successful_sync)?,
+            "accounts.$.last_failure": to_bson_value(&item.last_failure)?,
+            "accounts.$.pending_backfill": item.pending_backfill,
+            "accounts.$.transactions_cursor": to_bson_value(&item.transactions_cursor)?,
+            "accounts.$.pending_refresh": item.pending_refresh,
+          }},
+          None,
+        )
+        .await
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+    }
+
+    Ok(())
+  }
+
+  // the snapshot is in the user's base currency, as are all their snapshots
   pub async fn add_new_snapshot(
     user: &mut User,
     plaid_client: Data<ApiClient>,
+    fx: &FxService,
   ) -> Result<(), ApiError> {
     let excluded_accounts = user.get_excluded_accounts();
+    let base_currency = user.base_currency;
+
+    // every item's transactions are fetched first, so that transfers between
+    // items can be paired. An item Plaid can't reach is recorded and carried at
+    // its last known balances, so one broken login doesn't hold up the rest.
+    let day = (Utc::now() - Duration::days(TRANSACTION_LAG_DAYS))
+      .date()
+      .naive_utc();
+    let mut per_item_transactions = HashMap::new();
+    let mut failed_items = Vec::new();
+    let mut last_error = None;
+    for item in user.accounts.iter_mut() {
+      match get_item_transactions_for_new_snapshot(item, day, plaid_client.clone()).await {
+        Ok(transactions) => {
+          per_item_transactions.insert(item.item_id.clone(), transactions);
+        }
+        Err(e) => {
+          log::warn!("Carrying item {} into snapshot: {:?}", item.item_id, e);
+          item.record_failure(&e);
+          failed_items.push(item.item_id.clone());
+          last_error = Some(e);
+        }
+      }
+    }
+
+    // the other side of a transfer may post a few days either side of the
+    // snapshot's day, so transfers are paired across the window as the ledger
+    // pairs them, then only the day itself is counted
+    let transfers = transfers::plaid_transfer_ids(
+      per_item_transactions
+        .values()
+        .flat_map(|response| response.transactions.iter()),
+      &excluded_accounts,
+    );
+    let day_string = day.format("%Y-%m-%d").to_string();
+    for response in per_item_transactions.values_mut() {
+      response
+        .transactions
+        .retain(|transaction| transaction.date == day_string);
+    }
 
     // handle each item connected to user
     let mut per_item_stats = Vec::new();
-    for item in user.accounts.iter() {
-      per_item_stats.push(handle_item(item, plaid_client.clone(), &excluded_accounts).await?);
+    for item in user.accounts.iter_mut() {
+      let transactions = match per_item_transactions.get(&item.item_id) {
+        Some(transactions) => transactions,
+        None => continue,
+      };
+
+      match handle_item(
+        item,
+        transactions,
+        plaid_client.clone(),
+        &excluded_accounts,
+        &transfers,
+        fx,
+        base_currency,
+      )
+      .await
+      {
+        Ok(stats) => {
+          item.record_success();
+          per_item_stats.push(stats);
+        }
+        Err(e) => {
+          log::warn!("Carrying item {} into snapshot: {:?}", item.item_id, e);
+          item.record_failure(&e);
+          failed_items.push(item.item_id.clone());
+          last_error = Some(e);
+        }
+      }
     }
 
+    // with every item failing the snapshot would hold nothing new
+    if per_item_stats.is_empty() {
+      if let Some(e) = last_error {
+        return Err(e);
+      }
+    }
+
+    for item_id in failed_items.iter() {
+      per_item_stats.push(carried_item_stats(
+        item_id,
+        &user.snapshots,
+        &excluded_accounts,
+        base_currency,
+      )?);
+    }
+
+    // manual accounts only add to the balance sheet
+    let (manual_assets, manual_liabilities) =
+      ManualAccountService::calculate_assets_and_liabilities(
+        &user.manual_accounts,
+        &ManualAccountService::today(),
+        fx,
+        base_currency,
+      )?;
+    let manual_account_snapshots = ManualAccountService::account_snapshots(
+      &user.manual_accounts,
+      &ManualAccountService::today(),
+      fx,
+      base_currency,
+    )?;
+
     // accumulate each item to a total
-    let (total_money_in, total_money_out, total_net): (Money, Money, Money) =
-      per_item_stats.iter().fold(
-        (Money::default(), Money::default(), Money::default()),
-        |(a, b, c), (e, f, g)| (a + *e, b + *f, c + *g),
-      );
+    let (total_money_in, total_money_out, total_assets, total_liabilities): (
+      Money,
+      Money,
+      Money,
+      Money,
+    ) = per_item_stats.iter().try_fold(
+      (
+        Money::default(),
+        Money::default(),
+        manual_assets,
+        manual_liabilities,
+      ),
+      |(a, b, c, d), (e, f, g, h, _)| -> Result<(Money, Money, Money, Money), ApiError> {
+        Ok((
+          a.checked_add(*e)?,
+          b.checked_add(*f)?,
+          c.checked_add(*g)?,
+          d.checked_add(*h)?,
+        ))
+      },
+    )?;
+    let mut account_snapshots: Vec<AccountSnapshot> = per_item_stats
+      .into_iter()
+      .flat_map(|(_, _, _, _, account_snapshots)| account_snapshots)
+      .chain(manual_account_snapshots.into_iter())
+      .collect();
 
-    // for rolling sums
-    let last_snapshot = get_last_snapshot(&user.snapshots);
+    // for rolling sums, which quarantined snapshots add to as well
+    let last_snapshot = user.snapshots.last().cloned().unwrap_or_default();
 
     // if user has no accounts,
     // calculated net worth will be zero
-    // so we add in the user's self-reported net worth.
-    let net_worth_adjustment = if user.accounts.is_empty() {
-      user.net_worth
+    // so we use the user's self-reported net worth instead.
+    let has_accounts = !user.accounts.is_empty() || !user.manual_accounts.is_empty();
+    let net_worth = if has_accounts {
+      total_assets.checked_sub(total_liabilities)?
     } else {
-      0.into()
+      Money::in_currency(user.net_worth, base_currency)
+    };
+    let with_balance_sheet = |snapshot: Snapshot| {
+      if has_accounts {
+        snapshot.with_balance_sheet(total_assets, total_liabilities)
+      } else {
+        Ok(snapshot)
+      }
     };
 
     // create the new snapshot
-    let mut new_snapshot = Snapshot::new(
-      total_net + net_worth_adjustment,
-      total_money_in - total_money_out,
+    let mut new_snapshot = with_balance_sheet(Snapshot::new(
+      net_worth,
+      total_money_in.checked_sub(total_money_out)?,
       total_money_out,
       total_money_in,
-    );
+    ))?;
 
     // patch so that we ignore recent stuff if the last snapshot was generated too recently.
     // https://github.com/ucfinancegroup/pfp/issues/212
@@ -59,12 +439,12 @@ pub mod SnapshotService {
       > Utc::now()
     {
       log::debug!("Last snapshot too recent. Omitting last day of transactions");
-      new_snapshot = Snapshot::new(
-        total_net + net_worth_adjustment,
-        0.into(),
-        0.into(),
-        0.into(),
-      );
+      new_snapshot = with_balance_sheet(Snapshot::new(net_worth, 0.into(), 0.into(), 0.into()))?;
+      for account in account_snapshots.iter_mut() {
+        account.money_in.amount = Decimal::new(0, 0);
+        account.money_out.amount = Decimal::new(0, 0);
+        account.transacted.amount = Decimal::new(0, 0);
+      }
     }
 
     // make it a cumulative sum
@@ -72,105 +452,849 @@ pub mod SnapshotService {
     new_snapshot.running_spending.amount += last_snapshot.running_spending.amount;
     new_snapshot.running_income.amount += last_snapshot.running_income.amount;
 
-    user.snapshots.push(new_snapshot);
+    // held back from goals and peer metrics if it looks wrong, until the user
+    // has had a look
+    let new_snapshot = new_snapshot.with_accounts(account_snapshots);
+    let quarantine = quality::check(&new_snapshot, &user.snapshots, &user.accounts);
+    if let Some(reason) = quarantine.as_ref() {
+      log::warn!("Quarantining snapshot for {}: {}", user.email, reason);
+    }
+    let baseline = quality::changes_baseline(&new_snapshot, &user.snapshots);
+    user.snapshots.push(
+      new_snapshot
+        .with_quarantine(quarantine)
+        .with_baseline(baseline),
+    );
 
     Ok(())
   }
 
-  pub async fn handle_item(
-    item: &PlaidItem,
+  pub fn get_quarantined_snapshots(user: User) -> Vec<Snapshot> {
+    user
+      .snapshots
+      .into_iter()
+      .filter(|snapshot| snapshot.is_quarantined())
+      .collect()
+  }
+
+  // Accepting a quarantined snapshot lets it count as it is, and the snapshots
+  // after it are checked against it rather than what came before. Rejecting it
+  // keeps its flows but replaces its balances with the last accepted ones.
+  pub fn review_snapshot(
+    user: &mut User,
+    snapshot_time: i64,
+    accept: bool,
+  ) -> Result<Snapshot, ApiError> {
+    let position = user
+      .snapshots
+      .iter()
+      .position(|snapshot| snapshot.snapshot_time == snapshot_time && snapshot.is_quarantined())
+      .ok_or(ApiError::new(
+        400,
+        format!(
+          "No quarantined snapshot at {} found in current user",
+          snapshot_time
+        ),
+      ))?;
+
+    if accept {
+      user.snapshots[position].quarantine = None;
+      user.snapshots[position].baseline = true;
+    } else {
+      match user.snapshots[..position]
+        .iter()
+        .rev()
+        .find(|snapshot| !snapshot.is_quarantined())
+        .cloned()
+      {
+        Some(previous) => quality::carry_balances(&mut user.snapshots[position], &previous),
+        // nothing to carry, so there is no telling what it should have read
+        None => return Ok(user.snapshots.remove(position)),
+      }
+    }
+
+    Ok(user.snapshots[position].clone())
+  }
+
+  pub async fn accept_snapshot(
+    snapshot_time: i64,
+    mut user: User,
+    user_service: Data<UserService>,
+  ) -> Result<Snapshot, ApiError> {
+    let accepted = review_snapshot(&mut user, snapshot_time, true)?;
+    user_service.save(&mut user).await?;
+    Ok(accepted)
+  }
+
+  pub async fn reject_snapshot(
+    snapshot_time: i64,
+    mut user: User,
+    user_service: Data<UserService>,
+  ) -> Result<Snapshot, ApiError> {
+    let rejected = review_snapshot(&mut user, snapshot_time, false)?;
+    user_service.save(&mut user).await?;
+    Ok(rejected)
+  }
+
+  // Reconstructs the daily history of a newly linked item from its transactions
+  // and merges it into the user's snapshots.
+  pub async fn backfill_item_history(
+    user: &mut User,
+    item_id: &String,
     plaid_client: Data<ApiClient>,
+    fx: &FxService,
+  ) -> Result<(), ApiError> {
+    let item = user
+      .accounts
+      .iter()
+      .find(|item| item.item_id == *item_id)
+      .cloned()
+      .ok_or(ApiError::new(
+        400,
+        format!("No account with id {} found in current user", item_id),
+      ))?;
+
+    let end = Utc::now().naive_utc().date();
+    let start = end - Duration::days(BACKFILL_DAYS);
+
+    let transactions_response = plaid_client
+      .retrieve_transactions_between(
+        &item.access_token,
+        start.format("%Y-%m-%d").to_string(),
+        end.format("%Y-%m-%d").to_string(),
+      )
+      .await?;
+
+    let history = reconstruct_item_history(
+      &item.item_id,
+      &transactions_response,
+      &user.get_excluded_accounts(),
+      start,
+      end,
+      fx,
+      user.base_currency,
+    )?;
+
+    // until the first item is linked, snapshots only hold the self-reported net worth
+    let is_first_item = !user
+      .accounts
+      .iter()
+      .any(|other| other.item_id != item.item_id && other.linked_time <= item.linked_time);
+
+    merge_item_history(
+      &mut user.snapshots,
+      &history,
+      item.linked_time,
+      is_first_item,
+    )?;
+
+    for item in user.accounts.iter_mut() {
+      if item.item_id == *item_id {
+        item.pending_backfill = false;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn end_of_day(date: &NaiveDate) -> i64 {
+    date.and_hms(23, 59, 59).timestamp()
+  }
+
+  fn day_of(snapshot_time: i64) -> NaiveDate {
+    NaiveDateTime::from_timestamp(snapshot_time, 0).date()
+  }
+
+  // in the base currency, keeping Plaid's sign
+  fn transaction_amount(
+    transaction: &Transaction,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<Money, ApiError> {
+    let amount = Decimal::try_from(transaction.amount)
+      .map_err(|e| {
+        log::error!(
+          "Could not convert {} to decimal: {}",
+          transaction.amount.clone(),
+          e
+        );
+      })
+      .unwrap_or(Decimal::new(0, 0));
+
+    // an unofficial currency, like crypto, can't be converted so it's left out
+    match finchplaid::transaction_currency(transaction) {
+      Some(currency) => fx.convert(Money::in_currency(amount, currency), base_currency),
+      None => Ok(Money::in_currency(0, base_currency)),
+    }
+  }
+
+  // Works backward from the item's current balances, undoing each day's posted
+  // transactions, to give one snapshot per day from `start` to `end`. The
+  // snapshots only cover this item and their running sums start at `start`.
+  pub fn reconstruct_item_history(
+    item_id: &String,
+    transactions_response: &RetrieveTransactionsResponse,
     excluded_accounts: &HashSet<String>,
-  ) -> Result<(Money, Money, Money), ApiError> {
-    // accumulate money_in and money_out for items' transactions
-    let (money_in, money_out) =
-      get_money_in_out(item, plaid_client.clone(), excluded_accounts).await?;
-
-    // get net worth of items accounts
-    match crate::services::finchplaid::get_net_worth(item, plaid_client, excluded_accounts).await {
-      Ok(num) => return Ok((money_in, money_out, num)),
-      Err(e) => return Err(e),
-    };
+    start: NaiveDate,
+    end: NaiveDate,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<Vec<Snapshot>, ApiError> {
+    let account_id_to_coeff =
+      finchplaid::get_account_balance_coefficients(&transactions_response.accounts);
+
+    // only transfers within the item can be told apart here, as the other
+    // items' transactions aren't fetched
+    let transfers =
+      transfers::plaid_transfer_ids(transactions_response.transactions.iter(), excluded_accounts);
+
+    // pending transactions are not in the current balances yet. Hidden accounts
+    // are kept for their own history, but left out of the totals.
+    let mut transactions_by_day: HashMap<NaiveDate, Vec<Transaction>> = HashMap::new();
+    for transaction in transactions_response.transactions.iter() {
+      if transaction.pending {
+        continue;
+      }
+      if let Ok(date) = NaiveDate::parse_from_str(&transaction.date, "%Y-%m-%d") {
+        transactions_by_day
+          .entry(date)
+          .or_insert_with(Vec::new)
+          .push(transaction.clone());
+      }
+    }
+
+    // assets and liabilities at the end of each day, latest first
+    let (mut assets, mut liabilities) = finchplaid::calculate_assets_and_liabilities(
+      &transactions_response.accounts,
+      excluded_accounts,
+      fx,
+      base_currency,
+    )?;
+    let mut balances: HashMap<String, Money> =
+      finchplaid::calculate_account_balances(&transactions_response.accounts, fx, base_currency)?
+        .into_iter()
+        .collect();
+    let mut days = Vec::new();
+    let mut day = end;
+    while day >= start {
+      let transactions = transactions_by_day.remove(&day).unwrap_or_default();
+      let mut previous_balances = balances.clone();
+
+      // positive amounts are money leaving the account, so they were taken
+      // out of an asset or added to what is owed
+      let (previous_assets, previous_liabilities) = transactions.iter().try_fold(
+        (assets, liabilities),
+        |(assets, liabilities), transaction| -> Result<(Money, Money), ApiError> {
+          let amount = transaction_amount(transaction, fx, base_currency)?;
+          // either way, the account had `amount` more toward net worth
+          if let Some(balance) = previous_balances.get_mut(&transaction.account_id) {
+            *balance = balance.checked_add(amount)?;
+          }
+
+          Ok(
+            match account_id_to_coeff.get(&transaction.account_id).copied() {
+              _ if excluded_accounts.contains(&transaction.account_id) => (assets, liabilities),
+              Some(1) => (assets.checked_add(amount)?, liabilities),
+              Some(-1) => (assets, liabilities.checked_sub(amount)?),
+              _ => (assets, liabilities),
+            },
+          )
+        },
+      )?;
+
+      days.push((day, assets, liabilities, balances, transactions));
+      assets = previous_assets;
+      liabilities = previous_liabilities;
+      balances = previous_balances;
+      day = day.pred();
+    }
+
+    let mut previous = Snapshot::default();
+    days
+      .into_iter()
+      .rev()
+      .map(
+        |(day, assets, liabilities, balances, transactions)| -> Result<Snapshot, ApiError> {
+          let flows = calculate_account_money_in_out(
+            &transactions_response.accounts,
+            &transactions,
+            &transfers,
+            fx,
+            base_currency,
+          )?;
+          let (money_in, money_out) = total_money_in_out(&flows, excluded_accounts, base_currency)?;
+          let transacted = calculate_account_transacted(&transactions, fx, base_currency)?;
+
+          previous = Snapshot::new_with_time(
+            assets.checked_sub(liabilities)?,
+            previous
+              .running_savings
+              .checked_add(money_in)?
+              .checked_sub(money_out)?,
+            previous.running_spending.checked_add(money_out)?,
+            previous.running_income.checked_add(money_in)?,
+            end_of_day(&day),
+          )
+          .with_balance_sheet(assets, liabilities)?
+          .with_accounts(account_snapshots(
+            item_id,
+            &transactions_response.accounts,
+            balances.into_iter().collect(),
+            &flows,
+            &transacted,
+            excluded_accounts,
+            base_currency,
+          ));
+          Ok(previous.clone())
+        },
+      )
+      .collect()
+  }
+
+  // Adds an item's reconstructed history to the user's snapshots. Snapshots from
+  // before the item was linked gain the item's balance and flows of that day,
+  // missing days are filled in, and later snapshots gain the item's flows from
+  // before it was linked. When `replace_before_link` is set, snapshots from
+  // before the link are dropped in favour of the history.
+  pub fn merge_item_history(
+    snapshots: &mut Vec<Snapshot>,
+    history: &Vec<Snapshot>,
+    linked_time: i64,
+    replace_before_link: bool,
+  ) -> Result<(), ApiError> {
+    if history.is_empty() {
+      return Ok(());
+    }
+
+    let linked_day = day_of(linked_time);
+    let history_by_day: HashMap<NaiveDate, &Snapshot> = history
+      .iter()
+      .map(|snapshot| (day_of(snapshot.snapshot_time), snapshot))
+      .collect();
+
+    // the snapshot taken on linking already counted the flows of its lagged day
+    let counted_from = linked_day - Duration::days(TRANSACTION_LAG_DAYS);
+    let uncounted_flows = history
+      .iter()
+      .rev()
+      .find(|snapshot| day_of(snapshot.snapshot_time) < counted_from)
+      .cloned()
+      .unwrap_or_default();
+
+    if replace_before_link {
+      snapshots.retain(|snapshot| snapshot.snapshot_time >= linked_time);
+    }
+
+    let before_link: Vec<Snapshot> = snapshots
+      .iter()
+      .filter(|snapshot| snapshot.snapshot_time < linked_time)
+      .cloned()
+      .collect();
+
+    for snapshot in snapshots.iter_mut() {
+      if snapshot.snapshot_time >= linked_time {
+        add_running_sums(snapshot, &uncounted_flows)?;
+      } else if let Some(day) = history_by_day.get(&day_of(snapshot.snapshot_time)) {
+        add_balances(snapshot, day)?;
+        add_running_sums(snapshot, day)?;
+      }
+    }
+
+    let covered_days: HashSet<NaiveDate> = before_link
+      .iter()
+      .map(|snapshot| day_of(snapshot.snapshot_time))
+      .collect();
+
+    for day in history.iter() {
+      let date = day_of(day.snapshot_time);
+      if date >= linked_day || covered_days.contains(&date) {
+        continue;
+      }
+
+      // carry the other items forward from the latest snapshot before this day
+      let mut filled = before_link
+        .iter()
+        .rev()
+        .find(|snapshot| snapshot.snapshot_time < day.snapshot_time)
+        .cloned()
+        .unwrap_or_default();
+      // the other items' accounts are carried with their balances, but they
+      // didn't move anything that day
+      for account in filled.accounts.iter_mut() {
+        account.money_in.amount = Decimal::new(0, 0);
+        account.money_out.amount = Decimal::new(0, 0);
+        account.transacted.amount = Decimal::new(0, 0);
+      }
+      add_balances(&mut filled, day)?;
+      add_running_sums(&mut filled, day)?;
+      filled.snapshot_time = day.snapshot_time;
+
+      snapshots.push(filled);
+    }
+
+    snapshots.sort_by_key(|snapshot| snapshot.snapshot_time);
+    Ok(())
+  }
+
+  fn add_balances(snapshot: &mut Snapshot, other: &Snapshot) -> Result<(), ApiError> {
+    snapshot.net_worth = snapshot.net_worth.checked_add(other.net_worth)?;
+    snapshot.assets = snapshot.assets.checked_add(other.assets)?;
+    snapshot.liabilities = snapshot.liabilities.checked_add(other.liabilities)?;
+    snapshot.accounts.extend(other.accounts.iter().cloned());
+    Ok(())
   }
 
-  async fn get_money_in_out(
+  fn add_running_sums(snapshot: &mut Snapshot, other: &Snapshot) -> Result<(), ApiError> {
+    snapshot.running_savings = snapshot
+      .running_savings
+      .checked_add(other.running_savings)?;
+    snapshot.running_spending = snapshot
+      .running_spending
+      .checked_add(other.running_spending)?;
+    snapshot.running_income = snapshot.running_income.checked_add(other.running_income)?;
+    Ok(())
+  }
+
+  // The stats of an item Plaid couldn't be reached for: its accounts at their
+  // balances in the last accepted snapshot to hold them, having moved nothing since.
+  pub fn carried_item_stats(
+    item_id: &String,
+    snapshots: &Vec<Snapshot>,
+    excluded_accounts: &HashSet<String>,
+    base_currency: Currency,
+  ) -> Result<(Money, Money, Money, Money, Vec<AccountSnapshot>), ApiError> {
+    let zero = Money::in_currency(0, base_currency);
+
+    let accounts: Vec<AccountSnapshot> = snapshots
+      .iter()
+      .rev()
+      .filter(|snapshot| !snapshot.is_quarantined())
+      .map(|snapshot| {
+        snapshot
+          .accounts
+          .iter()
+          .filter(|account| account.item_id == *item_id)
+          .cloned()
+          .collect::<Vec<AccountSnapshot>>()
+      })
+      .find(|accounts| !accounts.is_empty())
+      .unwrap_or_default()
+      .into_iter()
+      .map(|account| AccountSnapshot {
+        hidden: excluded_accounts.contains(&account.account_id),
+        money_in: zero,
+        money_out: zero,
+        transacted: zero,
+        ..account
+      })
+      .collect();
+
+    // what is owed is carried as a negative balance
+    let (assets, liabilities) = accounts.iter().filter(|account| !account.hidden).try_fold(
+      (zero, zero),
+      |(assets, liabilities), account| -> Result<(Money, Money), ApiError> {
+        if account.balance.amount >= Decimal::new(0, 0) {
+          Ok((assets.checked_add(account.balance)?, liabilities))
+        } else {
+          Ok((assets, liabilities.checked_sub(account.balance)?))
+        }
+      },
+    )?;
+
+    Ok((zero, zero, assets, liabilities, accounts))
+  }
+
+  // the item's flows, assets and liabilities, and each of its accounts' part
+  pub async fn handle_item(
     item: &PlaidItem,
+    transactions: &RetrieveTransactionsResponse,
     plaid_client: Data<ApiClient>,
     excluded_accounts: &HashSet<String>,
-  ) -> Result<(Money, Money), ApiError> {
-    let transactions = get_item_transactions_for_new_snapshot(item, plaid_client).await?;
+    transfers: &HashSet<String>,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<(Money, Money, Money, Money, Vec<AccountSnapshot>), ApiError> {
+    // accumulate money_in and money_out for each of the item's accounts
+    let flows = calculate_account_money_in_out(
+      &transactions.accounts,
+      &transactions.transactions,
+      transfers,
+      fx,
+      base_currency,
+    )?;
+    let (money_in, money_out) = total_money_in_out(&flows, excluded_accounts, base_currency)?;
+
+    // get assets and liabilities of items accounts
+    let accounts = plaid_client
+      .retrieve_an_items_accounts(&item.access_token)
+      .await?
+      .accounts;
+    let (assets, liabilities) = finchplaid::calculate_assets_and_liabilities(
+      &accounts,
+      excluded_accounts,
+      fx,
+      base_currency,
+    )?;
 
-    Ok(calculate_money_in_out(&transactions, excluded_accounts))
+    let breakdown = account_snapshots(
+      &item.item_id,
+      &accounts,
+      finchplaid::calculate_account_balances(&accounts, fx, base_currency)?,
+      &flows,
+      &calculate_account_transacted(&transactions.transactions, fx, base_currency)?,
+      excluded_accounts,
+      base_currency,
+    );
+
+    Ok((money_in, money_out, assets, liabilities, breakdown))
   }
 
+  fn account_snapshots(
+    item_id: &String,
+    accounts: &Vec<Account>,
+    balances: Vec<(String, Money)>,
+    flows: &HashMap<String, (Money, Money)>,
+    transacted: &HashMap<String, Money>,
+    excluded_accounts: &HashSet<String>,
+    base_currency: Currency,
+  ) -> Vec<AccountSnapshot> {
+    let zero = Money::in_currency(0, base_currency);
+    let account_types: HashMap<&String, &String> = accounts
+      .iter()
+      .map(|account| (&account.account_id, &account._type))
+      .collect();
+
+    let mut account_snapshots: Vec<AccountSnapshot> = balances
+      .into_iter()
+      .map(|(account_id, balance)| {
+        let (money_in, money_out) = flows.get(&account_id).copied().unwrap_or((zero, zero));
+        AccountSnapshot {
+          item_id: item_id.clone(),
+          account_type: account_types
+            .get(&account_id)
+            .map(|account_type| account_type.to_string())
+            .unwrap_or_default(),
+          hidden: excluded_accounts.contains(&account_id),
+          transacted: transacted.get(&account_id).copied().unwrap_or(zero),
+          account_id,
+          balance,
+          money_in,
+          money_out,
+        }
+      })
+      .collect();
+
+    account_snapshots.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    account_snapshots
+  }
+
+  // `transfers` are the ids of transactions moving money between the user's own
+  // accounts, which are neither money in nor out
   pub fn calculate_money_in_out(
     transactions_response: &RetrieveTransactionsResponse,
     excluded_accounts: &HashSet<String>,
-  ) -> (Money, Money) {
-    // map each account to a coefficient for each transaction.
-    let account_id_to_coeff = crate::services::finchplaid::get_account_transaction_coefficients(
+    transfers: &HashSet<String>,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<(Money, Money), ApiError> {
+    calculate_transactions_money_in_out(
       &transactions_response.accounts,
-    );
+      &transactions_response.transactions,
+      excluded_accounts,
+      transfers,
+      fx,
+      base_currency,
+    )
+  }
+
+  pub fn calculate_transactions_money_in_out(
+    accounts: &Vec<Account>,
+    transactions: &Vec<Transaction>,
+    excluded_accounts: &HashSet<String>,
+    transfers: &HashSet<String>,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<(Money, Money), ApiError> {
+    let flows =
+      calculate_account_money_in_out(accounts, transactions, transfers, fx, base_currency)?;
+
+    total_money_in_out(&flows, excluded_accounts, base_currency)
+  }
+
+  // money_in and money_out of each account with transactions, hidden ones included
+  pub fn calculate_account_money_in_out(
+    accounts: &Vec<Account>,
+    transactions: &Vec<Transaction>,
+    transfers: &HashSet<String>,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<HashMap<String, (Money, Money)>, ApiError> {
+    // map each account to a coefficient for each transaction.
+    let account_id_to_coeff = finchplaid::get_account_transaction_coefficients(accounts);
+    let zero = Money::in_currency(0, base_currency);
 
     // accumulate money_in and money_out for transactions
-    transactions_response
-      .transactions
+    let mut flows: HashMap<String, (Money, Money)> = HashMap::new();
+    for transaction in transactions
       .iter()
-      .filter(|&transaction: &&Transaction| !excluded_accounts.contains(&transaction.account_id))
-      .fold(
-        (Money::default(), Money::default()).into(),
-        |(money_in, money_out), transaction: &Transaction| {
-          let s: Decimal = Decimal::try_from(transaction.amount)
-            .and_then(|amount| {
-              Ok(
-                amount
-                  * Decimal::new(
-                    *account_id_to_coeff
-                      .get(&transaction.account_id)
-                      .or(Some(&0))
-                      .unwrap(),
-                    0,
-                  ),
-              )
-            })
-            .map_err(|e| {
-              log::error!(
-                "Could not convert {} to decimal: {}",
-                transaction.amount.clone(),
-                e
-              );
-            })
-            .ok()
-            .or(Some(Decimal::new(0, 0)))
-            .unwrap();
-
-          (money_in + s.max(0.into()), money_out + s.min(0.into()))
+      .filter(|transaction| !transfers.contains(&transaction.transaction_id))
+    {
+      let s: Decimal = transaction_amount(transaction, fx, base_currency)?.amount
+        * Decimal::new(
+          *account_id_to_coeff
+            .get(&transaction.account_id)
+            .or(Some(&0))
+            .unwrap(),
+          0,
+        );
+
+      let (money_in, money_out) = flows
+        .entry(transaction.account_id.clone())
+        .or_insert((zero, zero));
+      *money_in = money_in.checked_add(s.max(0.into()))?;
+      *money_out = money_out.checked_add(s.min(0.into()))?;
+    }
+
+    Ok(flows)
+  }
+
+  // What each account's posted transactions, transfers included, changed its
+  // balance by. Positive amounts left the account, which lowers an asset's
+  // balance and adds to what is owed alike.
+  pub fn calculate_account_transacted(
+    transactions: &Vec<Transaction>,
+    fx: &FxService,
+    base_currency: Currency,
+  ) -> Result<HashMap<String, Money>, ApiError> {
+    let zero = Money::in_currency(0, base_currency);
+
+    let mut transacted: HashMap<String, Money> = HashMap::new();
+    for transaction in transactions
+      .iter()
+      .filter(|transaction| !transaction.pending)
+    {
+      let amount = transaction_amount(transaction, fx, base_currency)?;
+      let total = transacted
+        .entry(transaction.account_id.clone())
+        .or_insert(zero);
+      *total = total.checked_sub(amount)?;
+    }
+
+    Ok(transacted)
+  }
+
+  fn total_money_in_out(
+    flows: &HashMap<String, (Money, Money)>,
+    excluded_accounts: &HashSet<String>,
+    base_currency: Currency,
+  ) -> Result<(Money, Money), ApiError> {
+    let zero = Money::in_currency(0, base_currency);
+
+    flows
+      .iter()
+      .filter(|(account_id, _)| !excluded_accounts.contains(*account_id))
+      .try_fold(
+        (zero, zero),
+        |(money_in, money_out),
+         (_, (account_in, account_out))|
+         -> Result<(Money, Money), ApiError> {
+          Ok((
+            money_in.checked_add(*account_in)?,
+            money_out.checked_add(*account_out)?,
+          ))
         },
       )
   }
 
+  // Hides or shows an account in every snapshot with a breakdown, as if it had
+  // always been that way, so the series doesn't jump where it was toggled.
+  // Snapshots from before breakdowns were recorded are left as they are.
+  pub fn set_account_hidden(snapshots: &mut Vec<Snapshot>, account_id: &str, hidden: bool) {
+    let sign = Decimal::new(if hidden { -1 } else { 1 }, 0);
+
+    // the account's flows up to each snapshot, which its running sums gain or lose
+    let mut money_in = Decimal::new(0, 0);
+    let mut money_out = Decimal::new(0, 0);
+
+    for snapshot in snapshots.iter_mut() {
+      if let Some(account) = snapshot
+        .accounts
+        .iter_mut()
+        .find(|account| account.account_id == account_id && account.hidden != hidden)
+      {
+        account.hidden = hidden;
+        money_in += sign * account.money_in.amount;
+        money_out += sign * account.money_out.amount;
+
+        let balance = sign * account.balance.amount;
+        if account.balance.amount >= Decimal::new(0, 0) {
+          snapshot.assets.amount += balance;
+        } else {
+          snapshot.liabilities.amount -= balance;
+        }
+        snapshot.net_worth.amount += balance;
+      }
+
+      snapshot.running_income.amount += money_in;
+      snapshot.running_spending.amount += money_out;
+      snapshot.running_savings.amount += money_in - money_out;
+    }
+  }
+
+  // The transactions of the day a new snapshot counts, offset by 2 days to ensure
+  // we get a full day and avoid any timezone problems, and of a transfer window
+  // either side of it for pairing transfers.
   async fn get_item_transactions_for_new_snapshot(
     item: &PlaidItem,
+    day: NaiveDate,
     plaid_client: Data<ApiClient>,
   ) -> Result<RetrieveTransactionsResponse, ApiError> {
-    // offset by 2 days to ensure we get a full day and avoid any timezone problems
-    let date = (Utc::now() - chrono::Duration::days(2))
-      .format("%Y-%m-%d")
-      .to_string();
-
-    // TODO: ensure we actually get all the transactions in a day. As of 2021 Jan 03,
-    // the Plaid API returns up to 100 transactions by default. This should be enough
-    // to cover one day of transactions for a normal person on one day.
-    // ... but uhh maybe not thanks Robinhood.
+    let today = Utc::now().date().naive_utc();
+    let window = Duration::days(transfers::TRANSFER_WINDOW_DAYS);
 
     plaid_client
-      .retrieve_transactions(item.access_token.clone(), date)
+      .retrieve_transactions_between(
+        &item.access_token,
+        (day - window).format("%Y-%m-%d").to_string(),
+        (day + window).min(today).format("%Y-%m-%d").to_string(),
+      )
       .await
   }
 
+  // the snapshots in the range, as they were taken
+  pub fn get_snapshots(user: User, query: SnapshotQuery) -> Result<Vec<Snapshot>, ApiError> {
+    if query.metric.is_some() {
+      return Err(ApiError::new(
+        400,
+        "A metric can only be asked for with a bucket".to_string(),
+      ));
+    }
+    let range = exports::DateRange::new(&query.from, &query.to)?;
+
+    Ok(
+      user
+        .snapshots
+        .into_iter()
+        .filter(|snapshot| range.contains(snapshot.snapshot_time))
+        .collect(),
+    )
+  }
+
+  // the snapshots in the range summed into the query's buckets
+  pub fn get_snapshot_periods(
+    user: &User,
+    query: SnapshotQuery,
+  ) -> Result<Vec<SnapshotPeriod>, ApiError> {
+    let range = exports::DateRange::new(&query.from, &query.to)?;
+    let bucket = query
+      .bucket
+      .ok_or_else(|| ApiError::new(400, "No bucket to sum snapshots into".to_string()))?;
+
+    periods::aggregate(&user.snapshots, &range, bucket, query.metric)
+  }
+
+  // how much of the change in net worth over the range was saved, and how much
+  // came from the market
+  pub fn get_net_worth_attribution(
+    user: &User,
+    query: SnapshotQuery,
+  ) -> Result<NetWorthAttribution, ApiError> {
+    let range = exports::DateRange::new(&query.from, &query.to)?;
+    attribution::attribute(&user.snapshots, &range)
+  }
+
+  // how the user's investments did over the range, and what their plans assume
+  pub fn get_investment_performance(
+    user: &User,
+    query: SnapshotQuery,
+  ) -> Result<InvestmentPerformance, ApiError> {
+    let range = exports::DateRange::new(&query.from, &query.to)?;
+    performance::measure_performance(&user.snapshots, &range, &user.plans)
+  }
+
+  // the accounts in the user's snapshots, as of the latest snapshot of each
+  pub fn get_snapshot_accounts(user: &User) -> Vec<SnapshotAccount> {
+    let names = exports::account_names(user);
+    let mut accounts: BTreeMap<&String, SnapshotAccount> = BTreeMap::new();
+
+    for snapshot in user.snapshots.iter() {
+      for account in snapshot.accounts.iter() {
+        let entry = accounts
+          .entry(&account.account_id)
+          .or_insert_with(|| SnapshotAccount {
+            item_id: account.item_id.clone(),
+            account_id: account.account_id.clone(),
+            name: names.get(&account.account_id).cloned(),
+            first_snapshot_time: snapshot.snapshot_time,
+            last_snapshot_time: snapshot.snapshot_time,
+            balance: account.balance,
+            hidden: account.hidden,
+          });
+        entry.last_snapshot_time = snapshot.snapshot_time;
+        entry.balance = account.balance;
+        entry.hidden = account.hidden;
+      }
+    }
+
+    accounts.into_iter().map(|(_, account)| account).collect()
+  }
+
+  // The account's part of each snapshot in the range. Its running sums start
+  // from its first snapshot, whether or not that is in the range.
+  pub fn get_account_history(
+    user: &User,
+    account_id: String,
+    query: SnapshotQuery,
+  ) -> Result<Vec<AccountHistoryEntry>, ApiError> {
+    let range = exports::DateRange::new(&query.from, &query.to)?;
+
+    let mut history = Vec::new();
+    let mut running: Option<(Money, Money)> = None;
+    for snapshot in user.snapshots.iter() {
+      let account = match snapshot.account(&account_id) {
+        Some(account) => account,
+        None => continue,
+      };
+
+      let (running_income, running_spending) = match running {
+        Some((income, spending)) => (
+          income.checked_add(account.money_in)?,
+          spending.checked_add(account.money_out)?,
+        ),
+        None => (account.money_in, account.money_out),
+      };
+      running = Some((running_income, running_spending));
+
+      if range.contains(snapshot.snapshot_time) {
+        history.push(AccountHistoryEntry {
+          snapshot_time: snapshot.snapshot_time,
+          balance: account.balance,
+          money_in: account.money_in,
+          money_out: account.money_out,
+          running_income,
+          running_spending,
+          hidden: account.hidden,
+        });
+      }
+    }
+
+    match running {
+      Some(_) => Ok(history),
+      None => Err(ApiError::new(
+        400,
+        format!(
+          "No snapshots of account {} found in current user",
+          account_id
+        ),
+      )),
+    }
+  }
+
   pub fn need_new_snapshot(snapshots: &Vec<Snapshot>) -> bool {
     let now = Utc::now().timestamp();
-    let last_time = get_last_snapshot(snapshots).snapshot_time;
+    let last_time = snapshots
+      .last()
+      .map_or(0, |snapshot| snapshot.snapshot_time);
 
     debug!("Last snapshot at {}. Currently it is {}", last_time, now);
 
@@ -178,18 +1302,24 @@ pub mod SnapshotService {
     Duration::seconds(now - last_time) > Duration::days(1)
   }
 
+  // the last snapshot that isn't waiting for the user to review it
   pub fn get_last_snapshot(snapshots: &Vec<Snapshot>) -> Snapshot {
-    if snapshots.len() <= 0 {
-      return Snapshot::default();
-    }
-    snapshots[snapshots.len() - 1].clone()
+    snapshots
+      .iter()
+      .rev()
+      .find(|snapshot| !snapshot.is_quarantined())
+      .cloned()
+      .unwrap_or_default()
   }
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::common::Money;
+  use crate::common::{Currency, Money};
+  use crate::models::user_model::{AccountSnapshot, Snapshot, User};
+  use crate::services::{fx::FxService, transactions::transfers};
+  use chrono::NaiveDate;
   use rust_decimal_macros::dec;
   use std::collections::HashSet;
   use std::error::Error;
@@ -205,12 +1335,78 @@ mod test {
     Ok(transactions)
   }
 
+  fn reconstruct(transactions: &RetrieveTransactionsResponse) -> Vec<Snapshot> {
+    SnapshotService::reconstruct_item_history(
+      &"item".to_string(),
+      transactions,
+      &HashSet::new(),
+      NaiveDate::from_ymd(2019, 4, 19),
+      NaiveDate::from_ymd(2019, 5, 6),
+      &FxService::default(),
+      Currency::USD,
+    )
+    .unwrap()
+  }
+
   #[test]
   fn test_calculate_money_in_money_out() {
     let transactions = load_test_data().unwrap();
     assert_eq!(
-      (Money::new(dec!(0)), Money::new(dec!(-10965.23))),
-      SnapshotService::calculate_money_in_out(&transactions, &HashSet::new())
+      (Money::usd(dec!(0)), Money::usd(dec!(-10965.23))),
+      SnapshotService::calculate_money_in_out(
+        &transactions,
+        &HashSet::new(),
+        &HashSet::new(),
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_calculate_money_in_out_without_transfers() {
+    let mut transactions = load_test_data().unwrap();
+
+    // the savings account paid $25 off the card, which shows on the card a day later
+    let mut payment_received = transactions
+      .transactions
+      .iter()
+      .find(|transaction| transaction.name.starts_with("CREDIT CARD 3333 PAYMENT"))
+      .unwrap()
+      .clone();
+    payment_received.transaction_id = "payment received".to_string();
+    payment_received.account_id = "P4EvDWZ6P8SqWmz6mPAACVbV5bQXBdt75PkZJ".to_string();
+    payment_received.amount = -25.0;
+    payment_received.date = "2019-04-22".to_string();
+    transactions.transactions.push(payment_received);
+
+    // counted as both spending and income
+    assert_eq!(
+      (Money::usd(dec!(25)), Money::usd(dec!(-10965.23))),
+      SnapshotService::calculate_money_in_out(
+        &transactions,
+        &HashSet::new(),
+        &HashSet::new(),
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
+    );
+
+    let transfers =
+      transfers::plaid_transfer_ids(transactions.transactions.iter(), &HashSet::new());
+    assert_eq!(2, transfers.len());
+    assert_eq!(
+      (Money::usd(dec!(0)), Money::usd(dec!(-10940.23))),
+      SnapshotService::calculate_money_in_out(
+        &transactions,
+        &HashSet::new(),
+        &transfers,
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
     );
   }
 
@@ -218,20 +1414,363 @@ mod test {
   fn test_calculate_net_worth() {
     let transactions = load_test_data().unwrap();
     assert_eq!(
-      Money::new(dec!(68472.74)),
-      crate::services::finchplaid::calculate_net_worth(&transactions.accounts, &HashSet::new())
+      (Money::usd(dec!(68472.74)), Money::usd(dec!(121974.06))),
+      crate::services::finchplaid::calculate_assets_and_liabilities(
+        &transactions.accounts,
+        &HashSet::new(),
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
+    );
+    assert_eq!(
+      Money::usd(dec!(68472.74) - dec!(121974.06)),
+      crate::services::finchplaid::calculate_net_worth(
+        &transactions.accounts,
+        &HashSet::new(),
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
     );
 
     // exclude plaid money market account and expect that the net worth should be lower, accordingly
     assert_eq!(
-      Money::new(dec!(68472.74) - dec!(43200)),
+      Money::usd(dec!(68472.74) - dec!(121974.06) - dec!(43200)),
       crate::services::finchplaid::calculate_net_worth(
         &transactions.accounts,
         &["jdgBn5mNDjSKwnLQng66C3n3mnRjMEi1mVMqx".to_string()]
           .iter()
           .cloned()
-          .collect()
+          .collect(),
+        &FxService::default(),
+        Currency::USD
+      )
+      .unwrap()
+    );
+
+    // an account in an unofficial currency can't be converted, so it's left out too
+    let mut unofficial = transactions.accounts.clone();
+    unofficial[4].balances.iso_currency_code = None;
+    assert_eq!(
+      Money::usd(dec!(68472.74) - dec!(121974.06) - dec!(43200)),
+      crate::services::finchplaid::calculate_net_worth(
+        &unofficial,
+        &HashSet::new(),
+        &FxService::default(),
+        Currency::USD
       )
+      .unwrap()
+    );
+  }
+
+  #[test]
+  fn test_calculate_net_worth_in_base_currency() {
+    let transactions = load_test_data().unwrap();
+    let fx = FxService::new(std::sync::Arc::new(
+      crate::services::fx::FileFxProvider::from_file("./tests/fixtures/fx_rates.json").unwrap(),
+    ));
+    let eur = Currency::new("EUR").unwrap();
+
+    assert_eq!(
+      (
+        Money::in_currency(dec!(68472.74) * dec!(0.8), eur),
+        Money::in_currency(dec!(121974.06) * dec!(0.8), eur)
+      ),
+      crate::services::finchplaid::calculate_assets_and_liabilities(
+        &transactions.accounts,
+        &HashSet::new(),
+        &fx,
+        eur
+      )
+      .unwrap()
+    );
+
+    // without a rate the accounts can't be counted
+    assert!(crate::services::finchplaid::calculate_net_worth(
+      &transactions.accounts,
+      &HashSet::new(),
+      &FxService::default(),
+      eur
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_reconstruct_item_history() {
+    let transactions = load_test_data().unwrap();
+    let current_net_worth = crate::services::finchplaid::calculate_net_worth(
+      &transactions.accounts,
+      &HashSet::new(),
+      &FxService::default(),
+      Currency::USD,
+    )
+    .unwrap();
+
+    let history = reconstruct(&transactions);
+
+    assert_eq!(18, history.len());
+    assert_eq!(current_net_worth, history.last().unwrap().net_worth);
+
+    // before the first transaction, the money spent since was still in the bank
+    // and not yet owed on the card
+    assert_eq!(
+      current_net_worth + dec!(6886.73) + dec!(4078.5),
+      history.first().unwrap().net_worth
+    );
+    assert_eq!(
+      history.last().unwrap().liabilities - dec!(4078.5),
+      history.first().unwrap().liabilities
+    );
+
+    // the running sums add up to the whole period
+    let (money_in, money_out) = SnapshotService::calculate_money_in_out(
+      &transactions,
+      &HashSet::new(),
+      &HashSet::new(),
+      &FxService::default(),
+      Currency::USD,
+    )
+    .unwrap();
+    assert_eq!(money_in, history.last().unwrap().running_income);
+    assert_eq!(money_out, history.last().unwrap().running_spending);
+
+    // and each day's accounts add up to its net worth
+    for snapshot in history.iter() {
+      assert_eq!(
+        snapshot.net_worth,
+        snapshot
+          .accounts
+          .iter()
+          .fold(Money::usd(dec!(0)), |total, account| total
+            + account.balance)
+      );
+    }
+  }
+
+  #[test]
+  fn test_carried_item_stats() {
+    let quarantined = Snapshot::fixture(
+      3,
+      vec![AccountSnapshot::fixture("checking", "depository", dec!(0))],
+    )
+    .with_quarantine(Some("net worth fell by 100%".to_string()));
+    let snapshots = vec![
+      Snapshot::fixture(
+        1,
+        vec![AccountSnapshot::fixture(
+          "checking",
+          "depository",
+          dec!(700),
+        )],
+      ),
+      Snapshot::fixture(
+        2,
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(900))
+            .with_flows(dec!(50), dec!(-100)),
+          AccountSnapshot::fixture("card", "credit", dec!(-300)).with_flows(dec!(0), dec!(-20)),
+          AccountSnapshot::fixture("savings", "depository", dec!(500)),
+        ],
+      ),
+      quarantined,
+    ];
+    let excluded: HashSet<String> = vec!["savings".to_string()].into_iter().collect();
+
+    // the last accepted balances, with nothing moved and hidden accounts left out
+    let (money_in, money_out, assets, liabilities, accounts) = SnapshotService::carried_item_stats(
+      &"item".to_string(),
+      &snapshots,
+      &excluded,
+      Currency::USD,
+    )
+    .unwrap();
+    assert_eq!(Money::usd(dec!(0)), money_in);
+    assert_eq!(Money::usd(dec!(0)), money_out);
+    assert_eq!(Money::usd(dec!(900)), assets);
+    assert_eq!(Money::usd(dec!(300)), liabilities);
+    assert_eq!(3, accounts.len());
+    assert_eq!(Money::usd(dec!(900)), accounts[0].balance);
+    assert_eq!(Money::usd(dec!(0)), accounts[0].money_in);
+    assert_eq!(Money::usd(dec!(0)), accounts[0].transacted);
+    assert!(accounts[2].hidden);
+
+    // an item no snapshot has held yet carries nothing
+    let (_, _, assets, _, accounts) = SnapshotService::carried_item_stats(
+      &"other".to_string(),
+      &snapshots,
+      &excluded,
+      Currency::USD,
+    )
+    .unwrap();
+    assert_eq!(Money::usd(dec!(0)), assets);
+    assert!(accounts.is_empty());
+  }
+
+  #[test]
+  fn test_review_snapshot() {
+    let mut user = User {
+      snapshots: vec![
+        Snapshot::fixture(
+          1,
+          vec![AccountSnapshot::fixture(
+            "checking",
+            "depository",
+            dec!(1000),
+          )],
+        ),
+        Snapshot::fixture(
+          2,
+          vec![AccountSnapshot::fixture(
+            "checking",
+            "depository",
+            dec!(1000000),
+          )],
+        )
+        .with_quarantine(Some("Net worth is far from the recent median".to_string())),
+      ],
+      ..User::fixture("test@example.com")
+    };
+
+    // projections start from the last accepted snapshot
+    assert_eq!(
+      1,
+      SnapshotService::get_last_snapshot(&user.snapshots).snapshot_time
+    );
+
+    // once accepted it counts, and is what later snapshots are checked against
+    let accepted = SnapshotService::review_snapshot(&mut user, 2, true).unwrap();
+    assert!(!accepted.is_quarantined());
+    assert!(accepted.baseline);
+    assert_eq!(
+      2,
+      SnapshotService::get_last_snapshot(&user.snapshots).snapshot_time
+    );
+    assert!(SnapshotService::review_snapshot(&mut user, 2, false).is_err());
+  }
+
+  #[test]
+  fn test_set_account_hidden() {
+    let snapshots = vec![
+      Snapshot::fixture(
+        1,
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(1000))
+            .with_flows(dec!(0), dec!(-100)),
+          AccountSnapshot::fixture("savings", "depository", dec!(500))
+            .with_flows(dec!(50), dec!(0)),
+        ],
+      )
+      .with_running_sums(dec!(50), dec!(-100)),
+      Snapshot::fixture(
+        2,
+        vec![
+          AccountSnapshot::fixture("checking", "depository", dec!(900))
+            .with_flows(dec!(0), dec!(-100)),
+          AccountSnapshot::fixture("savings", "depository", dec!(600))
+            .with_flows(dec!(100), dec!(0)),
+          AccountSnapshot::fixture("card", "credit", dec!(-300)),
+        ],
+      )
+      .with_running_sums(dec!(150), dec!(-200)),
+    ];
+
+    let mut hidden = snapshots.clone();
+    SnapshotService::set_account_hidden(&mut hidden, "savings", true);
+
+    // as if savings had never been counted
+    assert_eq!(Money::usd(dec!(1000)), hidden[0].net_worth);
+    assert_eq!(Money::usd(dec!(0)), hidden[0].running_income);
+    assert_eq!(Money::usd(dec!(100)), hidden[0].running_savings);
+    assert_eq!(Money::usd(dec!(900)), hidden[1].net_worth);
+    assert_eq!(Money::usd(dec!(1200)), hidden[1].assets);
+    assert_eq!(Money::usd(dec!(300)), hidden[1].liabilities);
+    assert_eq!(Money::usd(dec!(0)), hidden[1].running_income);
+    assert_eq!(Money::usd(dec!(-200)), hidden[1].running_spending);
+    assert_eq!(Money::usd(dec!(200)), hidden[1].running_savings);
+    assert!(hidden[1].account("savings").unwrap().hidden);
+
+    // hiding again changes nothing, and showing it undoes the hiding
+    let mut twice = hidden.clone();
+    SnapshotService::set_account_hidden(&mut twice, "savings", true);
+    assert_eq!(hidden, twice);
+
+    SnapshotService::set_account_hidden(&mut hidden, "savings", false);
+    assert_eq!(snapshots, hidden);
+  }
+
+  #[test]
+  fn test_merge_item_history_replaces_self_reported() {
+    let transactions = load_test_data().unwrap();
+    let history = reconstruct(&transactions);
+
+    let linked_time = NaiveDate::from_ymd(2019, 5, 7)
+      .and_hms(12, 0, 0)
+      .timestamp();
+    let mut snapshots = vec![
+      // self-reported at signup
+      Snapshot::new_with_time(dec!(5000), dec!(0), dec!(0), dec!(0), linked_time - 60),
+      // taken on linking
+      Snapshot::new_with_time(
+        history.last().unwrap().net_worth.amount,
+        dec!(0),
+        dec!(0),
+        dec!(0),
+        linked_time,
+      ),
+    ];
+
+    SnapshotService::merge_item_history(&mut snapshots, &history, linked_time, true).unwrap();
+
+    assert_eq!(19, snapshots.len());
+    assert_eq!(history[0], snapshots[0]);
+    assert!(snapshots
+      .windows(2)
+      .all(|pair| pair[0].snapshot_time < pair[1].snapshot_time));
+
+    // flows up to two days before linking were not counted by the snapshot taken on linking
+    assert_eq!(history[15].running_spending, snapshots[18].running_spending);
+  }
+
+  #[test]
+  fn test_merge_item_history_adds_to_existing() {
+    let transactions = load_test_data().unwrap();
+    let history = reconstruct(&transactions);
+
+    // another item was already being snapshotted on 2019-05-01
+    let existing_time = NaiveDate::from_ymd(2019, 5, 1).and_hms(6, 0, 0).timestamp();
+    let linked_time = NaiveDate::from_ymd(2019, 5, 7)
+      .and_hms(12, 0, 0)
+      .timestamp();
+    let mut snapshots = vec![Snapshot::new_with_time(
+      dec!(100),
+      dec!(0),
+      dec!(0),
+      dec!(0),
+      existing_time,
+    )];
+
+    SnapshotService::merge_item_history(&mut snapshots, &history, linked_time, false).unwrap();
+
+    // every day before the link is covered exactly once
+    assert_eq!(18, snapshots.len());
+    assert_eq!(
+      history[12].net_worth + dec!(100),
+      snapshots
+        .iter()
+        .find(|snapshot| snapshot.snapshot_time == existing_time)
+        .unwrap()
+        .net_worth
+    );
+
+    // days after the existing snapshot carry the other item forward
+    assert_eq!(
+      history[13].net_worth + dec!(100),
+      snapshots
+        .iter()
+        .find(|snapshot| snapshot.snapshot_time == history[13].snapshot_time)
+        .unwrap()
+        .net_worth
     );
   }
 }
@@ -0,0 +1,430 @@
+use super::attribution::{is_investment, span};
+use crate::common::{errors::ApiError, Currency, Money};
+use crate::models::plan_model::Plan;
+use crate::models::user_model::Snapshot;
+use crate::services::{exports::DateRange, timeseries::TimeseriesService};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+// Returns are fractions of what was invested, like 0.05 for 5%.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct InvestmentPerformance {
+  pub portfolio: AccountPerformance,
+  pub accounts: Vec<AccountPerformance>,
+  pub plans: Vec<PlanComparison>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AccountPerformance {
+  // none for the whole portfolio
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_id: Option<String>,
+  pub start_time: i64,
+  pub end_time: i64,
+  pub opening_value: Money,
+  pub closing_value: Money,
+  // put in less taken out
+  pub net_contributions: Money,
+  pub time_weighted_return: Option<Decimal>,
+  pub annualized_time_weighted_return: Option<Decimal>,
+  // the yearly rate (XIRR) that makes the flows and values add up
+  pub money_weighted_return: Option<Decimal>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PlanComparison {
+  pub plan_id: String,
+  pub name: String,
+  // what the plan's allocation at the end of the period expects in a year
+  pub assumed_annual_return: Decimal,
+  // the portfolio's annualized time-weighted return less the assumed one
+  pub difference: Option<Decimal>,
+}
+
+// a value at a time, and what was put in since the previous one, negative for
+// what was taken out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+  pub time: i64,
+  pub value: Decimal,
+  pub flow: Decimal,
+}
+
+// Chains each interval's growth, taking its flows to arrive at its end, so how
+// much was put in and when doesn't change the return. None when there's no
+// interval to chain or the growth overflows.
+pub fn time_weighted_return(points: &[Point]) -> Option<Decimal> {
+  let mut growth = Decimal::new(1, 0);
+  let mut intervals = 0;
+  for pair in points.windows(2) {
+    // nothing was invested to grow
+    if pair[0].value <= Decimal::new(0, 0) {
+      continue;
+    }
+    let interval = pair[1]
+      .value
+      .checked_sub(pair[1].flow)?
+      .checked_div(pair[0].value)?;
+    growth = growth.checked_mul(interval)?;
+    intervals += 1;
+  }
+
+  if intervals == 0 {
+    None
+  } else {
+    Some((growth - Decimal::new(1, 0)).round_dp(6))
+  }
+}
+
+// the yearly rate compounding to `total_return` over `seconds`
+pub fn annualize(total_return: Decimal, seconds: i64) -> Option<Decimal> {
+  let years = seconds as f64 / SECONDS_PER_YEAR;
+  let growth = (total_return + Decimal::new(1, 0)).to_f64()?;
+  if years <= 0.0 || growth <= 0.0 {
+    return None;
+  }
+  Decimal::from_f64(growth.powf(1.0 / years) - 1.0).map(|rate| rate.round_dp(6))
+}
+
+// The yearly rate at which the opening value and every flow would have grown
+// to the closing value, found by bisection. None when no rate does.
+pub fn money_weighted_return(points: &[Point]) -> Option<Decimal> {
+  let (first, last) = (points.first()?, points.last()?);
+  if last.time <= first.time {
+    return None;
+  }
+  let years = |point: &Point| (point.time - first.time) as f64 / SECONDS_PER_YEAR;
+
+  // from the investor's side, money put in is negative
+  let mut flows = vec![(0.0, -first.value.to_f64()?)];
+  for point in points[1..].iter() {
+    flows.push((years(point), -point.flow.to_f64()?));
+  }
+  flows.push((years(last), last.value.to_f64()?));
+
+  let value_at = |rate: f64| -> f64 {
+    flows
+      .iter()
+      .map(|(years, amount)| amount / (1.0 + rate).powf(*years))
+      .sum()
+  };
+
+  let (mut low, mut high) = (-0.9999, 100.0);
+  if value_at(low).signum() == value_at(high).signum() {
+    return None;
+  }
+  for _ in 0..200 {
+    let middle = (low + high) / 2.0;
+    if value_at(middle).signum() == value_at(low).signum() {
+      low = middle;
+    } else {
+      high = middle;
+    }
+  }
+  Decimal::from_f64((low + high) / 2.0).map(|rate| rate.round_dp(6))
+}
+
+// the account's value in each snapshot it is in, with what its transactions
+// moved in or out
+fn account_points(span: &[&Snapshot], account_id: &str) -> Vec<Point> {
+  span
+    .iter()
+    .filter_map(|snapshot| {
+      snapshot.account(account_id).map(|account| Point {
+        time: snapshot.snapshot_time,
+        value: account.balance.amount,
+        flow: account.transacted.amount,
+      })
+    })
+    .collect()
+}
+
+// The accounts' combined value in each snapshot with any of them. An account
+// first seen counts as all of it put in, and one no longer seen as taken out.
+fn portfolio_points(span: &[&Snapshot], account_ids: &BTreeSet<&String>) -> Vec<Point> {
+  let mut points = Vec::new();
+  let mut previous: HashMap<&String, Decimal> = HashMap::new();
+
+  for snapshot in span.iter() {
+    let mut current: HashMap<&String, Decimal> = HashMap::new();
+    let mut flow = Decimal::new(0, 0);
+    for account in snapshot
+      .accounts
+      .iter()
+      .filter(|account| account_ids.contains(&account.account_id))
+    {
+      current.insert(&account.account_id, account.balance.amount);
+      flow += if previous.contains_key(&account.account_id) {
+        account.transacted.amount
+      } else {
+        account.balance.amount
+      };
+    }
+    for (account_id, value) in previous.iter() {
+      if !current.contains_key(account_id) {
+        flow -= *value;
+      }
+    }
+
+    if current.is_empty() && previous.is_empty() {
+      continue;
+    }
+    points.push(Point {
+      time: snapshot.snapshot_time,
+      value: current.values().copied().sum(),
+      flow,
+    });
+    previous = current;
+  }
+
+  points
+}
+
+fn measure(
+  account_id: Option<String>,
+  points: &[Point],
+  currency: Option<Currency>,
+) -> Option<AccountPerformance> {
+  let (first, last) = (points.first()?, points.last()?);
+  let money = |amount| Money { amount, currency };
+  let time_weighted_return = time_weighted_return(points);
+
+  Some(AccountPerformance {
+    account_id,
+    start_time: first.time,
+    end_time: last.time,
+    opening_value: money(first.value),
+    closing_value: money(last.value),
+    net_contributions: money(points[1..].iter().map(|point| point.flow).sum()),
+    annualized_time_weighted_return: time_weighted_return
+      .and_then(|total_return| annualize(total_return, last.time - first.time)),
+    time_weighted_return,
+    money_weighted_return: money_weighted_return(points),
+  })
+}
+
+// The yearly return each plan assumes with the allocation it has at `time`. Plans
+// without an allocation by then assume nothing, so they're left out.
+fn compare_plans(plans: &Vec<Plan>, time: i64, actual: Option<Decimal>) -> Vec<PlanComparison> {
+  plans
+    .iter()
+    .filter_map(|plan| {
+      let allocation = plan
+        .allocations
+        .iter()
+        .filter(|allocation| allocation.date <= time)
+        .max_by_key(|allocation| allocation.date)
+        .cloned()?;
+      let assumed_annual_return =
+        TimeseriesService::calculate_apy_from_allocation(allocation) - Decimal::new(1, 0);
+
+      Some(PlanComparison {
+        plan_id: plan.id.as_ref().map(|id| id.to_hex()).unwrap_or_default(),
+        name: plan.name.clone(),
+        assumed_annual_return,
+        difference: actual.map(|actual| actual - assumed_annual_return),
+      })
+    })
+    .collect()
+}
+
+// Returns of the investment accounts and valued assets over the span of the
+// range, counting those hidden at the end out.
+pub fn measure_performance(
+  snapshots: &Vec<Snapshot>,
+  range: &DateRange,
+  plans: &Vec<Plan>,
+) -> Result<InvestmentPerformance, ApiError> {
+  let span = span(snapshots, range);
+  let end = match span.last() {
+    Some(end) if span.len() > 1 => *end,
+    _ => {
+      return Err(ApiError::new(
+        400,
+        "Need two snapshots in the range to measure performance".to_string(),
+      ))
+    }
+  };
+  let currency = end.net_worth.currency;
+
+  let account_ids: BTreeSet<&String> = span
+    .iter()
+    .flat_map(|snapshot| snapshot.accounts.iter())
+    .filter(|account| is_investment(account))
+    .map(|account| &account.account_id)
+    .filter(|account_id| {
+      !end
+        .account(account_id)
+        .map_or(false, |account| account.hidden)
+    })
+    .collect();
+
+  let portfolio = measure(None, &portfolio_points(&span, &account_ids), currency)
+    .ok_or_else(|| ApiError::new(400, "No investment accounts in the range".to_string()))?;
+  let accounts = account_ids
+    .iter()
+    .filter_map(|account_id| {
+      measure(
+        Some(account_id.to_string()),
+        &account_points(&span, account_id),
+        currency,
+      )
+    })
+    .collect();
+
+  Ok(InvestmentPerformance {
+    plans: compare_plans(
+      plans,
+      portfolio.end_time,
+      portfolio.annualized_time_weighted_return,
+    ),
+    portfolio,
+    accounts,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  const DAY: i64 = 24 * 60 * 60;
+
+  fn point(day: i64, value: Decimal, flow: Decimal) -> Point {
+    Point {
+      time: day * DAY,
+      value,
+      flow,
+    }
+  }
+
+  #[test]
+  fn test_time_weighted_return() {
+    // up 10%, then 1000 put in, then up 10% again
+    let points = vec![
+      point(0, dec!(1000), dec!(0)),
+      point(100, dec!(1100), dec!(0)),
+      point(200, dec!(2210), dec!(1000)),
+    ];
+    assert_eq!(Some(dec!(0.21)), time_weighted_return(&points));
+
+    // a year of 21% is 21% a year, two are 10% each
+    assert_eq!(Some(dec!(0.21)), annualize(dec!(0.21), 365 * DAY));
+    assert_eq!(Some(dec!(0.1)), annualize(dec!(0.21), 2 * 365 * DAY));
+
+    assert_eq!(None, time_weighted_return(&points[..1]));
+
+    // a cent that reads as the most a decimal holds doesn't panic
+    let runaway = vec![
+      point(0, dec!(0.01), dec!(0)),
+      point(1, Decimal::MAX, dec!(0)),
+    ];
+    assert_eq!(None, time_weighted_return(&runaway));
+  }
+
+  #[test]
+  fn test_money_weighted_return() {
+    // 1000 grew 10% in a year
+    let points = vec![
+      point(0, dec!(1000), dec!(0)),
+      point(365, dec!(1100), dec!(0)),
+    ];
+    assert_eq!(Some(dec!(0.1)), money_weighted_return(&points));
+
+    // still 10% a year with 1000 more put in after the first
+    let points = vec![
+      point(0, dec!(1000), dec!(0)),
+      point(365, dec!(2100), dec!(1000)),
+      point(730, dec!(2310), dec!(0)),
+    ];
+    assert_eq!(Some(dec!(0.1)), money_weighted_return(&points));
+
+    // nothing can turn 1000 into 1000 plus what was put in
+    let points = vec![
+      point(0, dec!(1000), dec!(0)),
+      point(365, dec!(1000), dec!(1000)),
+    ];
+    assert_eq!(None, money_weighted_return(&points));
+  }
+
+  #[test]
+  fn test_compare_plans() {
+    use crate::models::plan_model::{Allocation, AllocationProportion, Asset, AssetClass};
+
+    let plan = Plan {
+      id: None,
+      name: "all in".to_string(),
+      recurrings: vec![],
+      allocations: vec![Allocation {
+        id: None,
+        description: "index fund".to_string(),
+        date: 10 * DAY,
+        schema: vec![AllocationProportion {
+          asset: Asset {
+            name: "index".to_string(),
+            class: AssetClass::Etf,
+            annualized_performance: dec!(1.07),
+            expense_ratio: None,
+          },
+          proportion: dec!(100),
+        }],
+      }],
+      events: vec![],
+      advisory_fee: None,
+      compounding: Default::default(),
+    };
+    let plans = vec![plan];
+
+    assert_eq!(
+      vec![PlanComparison {
+        plan_id: String::new(),
+        name: "all in".to_string(),
+        assumed_annual_return: dec!(0.07),
+        difference: Some(dec!(0.03)),
+      }],
+      compare_plans(&plans, 20 * DAY, Some(dec!(0.1)))
+    );
+
+    // no allocation yet, so nothing to compare with
+    assert!(compare_plans(&plans, 5 * DAY, Some(dec!(0.1))).is_empty());
+  }
+
+  #[test]
+  fn test_portfolio_points() {
+    use crate::models::user_model::AccountSnapshot;
+
+    let account = |account_id: &str, balance: Decimal, transacted: Decimal| {
+      AccountSnapshot::fixture(account_id, "investment", balance).with_transacted(transacted)
+    };
+    let snapshot = |day: i64, accounts| Snapshot::fixture(day * DAY, accounts);
+    let snapshots = vec![
+      snapshot(0, vec![]),
+      snapshot(1, vec![account("ira", dec!(1000), dec!(0))]),
+      snapshot(
+        2,
+        vec![
+          account("ira", dec!(1200), dec!(100)),
+          account("brokerage", dec!(500), dec!(0)),
+        ],
+      ),
+      snapshot(3, vec![account("brokerage", dec!(550), dec!(0))]),
+    ];
+    let span: Vec<&Snapshot> = snapshots.iter().collect();
+    let ira = "ira".to_string();
+    let brokerage = "brokerage".to_string();
+    let account_ids: BTreeSet<&String> = vec![&ira, &brokerage].into_iter().collect();
+
+    assert_eq!(
+      vec![
+        point(1, dec!(1000), dec!(1000)),
+        point(2, dec!(1700), dec!(600)),
+        point(3, dec!(550), dec!(-1200)),
+      ],
+      portfolio_points(&span, &account_ids)
+    );
+  }
+}
@@ -0,0 +1,248 @@
+use crate::common::{errors::ApiError, Money};
+use crate::models::user_model::Snapshot;
+use crate::services::exports::{date_of, DateRange};
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotBucket {
+  Day,
+  Week,
+  Month,
+  Quarter,
+  Year,
+}
+
+impl SnapshotBucket {
+  // first day of the bucket containing `date`. Weeks start on Monday.
+  pub fn start_of(&self, date: NaiveDate) -> NaiveDate {
+    match self {
+      SnapshotBucket::Day => date,
+      SnapshotBucket::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+      SnapshotBucket::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+      SnapshotBucket::Quarter => {
+        NaiveDate::from_ymd(date.year(), (date.month() - 1) / 3 * 3 + 1, 1)
+      }
+      SnapshotBucket::Year => NaiveDate::from_ymd(date.year(), 1, 1),
+    }
+  }
+
+  // last day of the bucket starting on `start`
+  pub fn end_of(&self, start: NaiveDate) -> NaiveDate {
+    let months = match self {
+      SnapshotBucket::Day => return start,
+      SnapshotBucket::Week => return start + Duration::days(6),
+      SnapshotBucket::Month => 1,
+      SnapshotBucket::Quarter => 3,
+      SnapshotBucket::Year => 12,
+    };
+    let next = start.month0() + months;
+    NaiveDate::from_ymd(start.year() + (next / 12) as i32, next % 12 + 1, 1).pred()
+  }
+}
+
+// what a client charts. Without one, periods carry every metric.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMetric {
+  NetWorth,
+  Income,
+  Spending,
+  Savings,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SnapshotPeriod {
+  pub period_start: String,
+  pub period_end: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub opening_net_worth: Option<Money>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub closing_net_worth: Option<Money>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub income: Option<Money>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub spending: Option<Money>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub savings: Option<Money>,
+}
+
+// Sums the accepted snapshots in the range into buckets, skipping buckets
+// without any.
+// A period's flows are the change in the running sums since the snapshot before
+// it, which is also where its net worth opened, even when that snapshot is
+// before the range.
+pub fn aggregate(
+  snapshots: &Vec<Snapshot>,
+  range: &DateRange,
+  bucket: SnapshotBucket,
+  metric: Option<SnapshotMetric>,
+) -> Result<Vec<SnapshotPeriod>, ApiError> {
+  let mut sorted: Vec<&Snapshot> = snapshots
+    .iter()
+    .filter(|snapshot| !snapshot.is_quarantined())
+    .collect();
+  sorted.sort_by_key(|snapshot| snapshot.snapshot_time);
+
+  // each bucket's snapshot before it, and its first and last snapshots
+  let mut buckets: BTreeMap<NaiveDate, (Option<&Snapshot>, &Snapshot, &Snapshot)> = BTreeMap::new();
+  for (i, snapshot) in sorted.iter().enumerate() {
+    if !range.contains(snapshot.snapshot_time) {
+      continue;
+    }
+    let before = if i > 0 { Some(sorted[i - 1]) } else { None };
+    buckets
+      .entry(bucket.start_of(date_of(snapshot.snapshot_time)))
+      .and_modify(|(_, _, last)| *last = snapshot)
+      .or_insert((before, snapshot, snapshot));
+  }
+
+  let wants = |wanted: SnapshotMetric| metric.map_or(true, |metric| metric == wanted);
+  let change = |last: Money, before: Option<Money>| match before {
+    Some(before) => last.checked_sub(before),
+    None => Ok(last),
+  };
+
+  buckets
+    .into_iter()
+    .map(
+      |(start, (before, first, last))| -> Result<SnapshotPeriod, ApiError> {
+        Ok(SnapshotPeriod {
+          period_start: start.format("%Y-%m-%d").to_string(),
+          period_end: bucket.end_of(start).format("%Y-%m-%d").to_string(),
+          opening_net_worth: if wants(SnapshotMetric::NetWorth) {
+            Some(before.unwrap_or(first).net_worth)
+          } else {
+            None
+          },
+          closing_net_worth: if wants(SnapshotMetric::NetWorth) {
+            Some(last.net_worth)
+          } else {
+            None
+          },
+          income: if wants(SnapshotMetric::Income) {
+            Some(change(
+              last.running_income,
+              before.map(|s| s.running_income),
+            )?)
+          } else {
+            None
+          },
+          spending: if wants(SnapshotMetric::Spending) {
+            Some(change(
+              last.running_spending,
+              before.map(|s| s.running_spending),
+            )?)
+          } else {
+            None
+          },
+          savings: if wants(SnapshotMetric::Savings) {
+            Some(change(
+              last.running_savings,
+              before.map(|s| s.running_savings),
+            )?)
+          } else {
+            None
+          },
+        })
+      },
+    )
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn snapshot(date: NaiveDate, net_worth: i64, income: i64, spending: i64) -> Snapshot {
+    Snapshot::new_with_time(
+      Money::from(net_worth),
+      Money::from(income + spending),
+      Money::from(spending),
+      Money::from(income),
+      date.and_hms(12, 0, 0).timestamp(),
+    )
+  }
+
+  #[test]
+  fn test_bucket_bounds() {
+    let date = NaiveDate::from_ymd(2021, 11, 17);
+    for (bucket, start, end) in [
+      (SnapshotBucket::Day, (2021, 11, 17), (2021, 11, 17)),
+      (SnapshotBucket::Week, (2021, 11, 15), (2021, 11, 21)),
+      (SnapshotBucket::Month, (2021, 11, 1), (2021, 11, 30)),
+      (SnapshotBucket::Quarter, (2021, 10, 1), (2021, 12, 31)),
+      (SnapshotBucket::Year, (2021, 1, 1), (2021, 12, 31)),
+    ]
+    .iter()
+    {
+      let (sy, sm, sd) = *start;
+      let (ey, em, ed) = *end;
+      assert_eq!(NaiveDate::from_ymd(sy, sm, sd), bucket.start_of(date));
+      assert_eq!(
+        NaiveDate::from_ymd(ey, em, ed),
+        bucket.end_of(bucket.start_of(date))
+      );
+    }
+  }
+
+  #[test]
+  fn test_aggregate() {
+    let snapshots = vec![
+      snapshot(NaiveDate::from_ymd(2021, 1, 20), 1000, 100, -50),
+      snapshot(NaiveDate::from_ymd(2021, 2, 3), 1100, 300, -100),
+      snapshot(NaiveDate::from_ymd(2021, 2, 25), 1200, 500, -200),
+      snapshot(NaiveDate::from_ymd(2021, 3, 15), 90000, 550, -400)
+        .with_quarantine(Some("Net worth is far from the recent median".to_string())),
+      snapshot(NaiveDate::from_ymd(2021, 4, 2), 900, 600, -600),
+    ];
+    let everything = DateRange {
+      from: None,
+      to: None,
+    };
+
+    let periods = aggregate(&snapshots, &everything, SnapshotBucket::Month, None).unwrap();
+
+    // march had no accepted snapshots
+    assert_eq!(3, periods.len());
+    assert_eq!("2021-02-01", periods[1].period_start);
+    assert_eq!("2021-02-28", periods[1].period_end);
+    assert_eq!(Some(Money::new(dec!(1000))), periods[1].opening_net_worth);
+    assert_eq!(Some(Money::new(dec!(1200))), periods[1].closing_net_worth);
+    assert_eq!(Some(Money::new(dec!(400))), periods[1].income);
+    assert_eq!(Some(Money::new(dec!(-150))), periods[1].spending);
+    assert_eq!(Some(Money::new(dec!(250))), periods[1].savings);
+
+    // the first period opens with its own first snapshot
+    assert_eq!(Some(Money::new(dec!(1000))), periods[0].opening_net_worth);
+    assert_eq!(Some(Money::new(dec!(100))), periods[0].income);
+
+    // a range still counts from the snapshot before it
+    let from_april = DateRange {
+      from: Some(NaiveDate::from_ymd(2021, 4, 1)),
+      to: None,
+    };
+    let periods = aggregate(
+      &snapshots,
+      &from_april,
+      SnapshotBucket::Quarter,
+      Some(SnapshotMetric::Spending),
+    )
+    .unwrap();
+    assert_eq!(
+      vec![SnapshotPeriod {
+        period_start: "2021-04-01".to_string(),
+        period_end: "2021-06-30".to_string(),
+        opening_net_worth: None,
+        closing_net_worth: None,
+        income: None,
+        spending: Some(Money::new(dec!(-400))),
+        savings: None,
+      }],
+      periods
+    );
+  }
+}
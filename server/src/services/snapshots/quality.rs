@@ -0,0 +1,307 @@
+use crate::common::Money;
+use crate::models::manual_account_model::MANUAL_ITEM_ID;
+use crate::models::user_model::{AccountSnapshot, ItemStatus, PlaidItem, Snapshot};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeSet;
+
+// how far back the history a new snapshot is held against goes
+const OUTLIER_LOOKBACK_SECS: i64 = 30 * 24 * 60 * 60;
+
+// fewer snapshots than this don't say what is normal
+const OUTLIER_MIN_HISTORY: usize = 5;
+
+// A net worth is an outlier when it is further from the recent median than
+// this many median absolute deviations, and than this share of the median.
+// Either alone flags ordinary moves of a steady or a volatile net worth.
+const OUTLIER_MADS: i64 = 10;
+const OUTLIER_MIN_CHANGE_PERCENT: i64 = 50;
+
+fn median(mut values: Vec<Decimal>) -> Decimal {
+  values.sort();
+  let middle = values.len() / 2;
+  if values.len() % 2 == 0 {
+    (values[middle - 1] + values[middle]) / dec!(2)
+  } else {
+    values[middle]
+  }
+}
+
+fn plaid_accounts(snapshot: &Snapshot) -> impl Iterator<Item = &AccountSnapshot> {
+  snapshot
+    .accounts
+    .iter()
+    .filter(|account| account.item_id != MANUAL_ITEM_ID)
+}
+
+// every Plaid account reads zero though Plaid reported no problem, while they
+// didn't before
+fn zero_balances(snapshot: &Snapshot, previous: Option<&Snapshot>, items: &Vec<PlaidItem>) -> bool {
+  let healthy = !items.is_empty() && items.iter().all(|item| item.status == ItemStatus::Healthy);
+  let all_zero = plaid_accounts(snapshot).count() > 0
+    && plaid_accounts(snapshot).all(|account| account.balance.amount.is_zero());
+  let had_balances = previous.map_or(false, |previous| {
+    plaid_accounts(previous).any(|account| !account.balance.amount.is_zero())
+  });
+
+  healthy && all_zero && had_balances
+}
+
+// the median of the recent net worths, if the net worth is far off it
+fn outlier(snapshot: &Snapshot, recent: &[&Snapshot]) -> Option<Decimal> {
+  if recent.len() < OUTLIER_MIN_HISTORY {
+    return None;
+  }
+
+  let net_worths: Vec<Decimal> = recent
+    .iter()
+    .map(|snapshot| snapshot.net_worth.amount)
+    .collect();
+  let median_net_worth = median(net_worths.clone());
+  let deviation = median(
+    net_worths
+      .into_iter()
+      .map(|net_worth| (net_worth - median_net_worth).abs())
+      .collect(),
+  );
+
+  let threshold = (deviation * Decimal::new(OUTLIER_MADS, 0))
+    .max(median_net_worth.abs() * Decimal::new(OUTLIER_MIN_CHANGE_PERCENT, 2));
+  if threshold > dec!(0) && (snapshot.net_worth.amount - median_net_worth).abs() > threshold {
+    Some(median_net_worth)
+  } else {
+    None
+  }
+}
+
+// the accepted snapshots before `snapshot`, oldest first
+fn accepted_before<'a>(snapshot: &Snapshot, history: &'a Vec<Snapshot>) -> Vec<&'a Snapshot> {
+  history
+    .iter()
+    .filter(|previous| {
+      !previous.is_quarantined() && previous.snapshot_time < snapshot.snapshot_time
+    })
+    .collect()
+}
+
+// an account was linked or removed, or a manual account revalued
+fn accounts_changed(snapshot: &Snapshot, previous: &Snapshot) -> bool {
+  let account_ids = |snapshot: &Snapshot| -> BTreeSet<String> {
+    snapshot
+      .accounts
+      .iter()
+      .map(|account| account.account_id.clone())
+      .collect()
+  };
+  let revalued = snapshot
+    .accounts
+    .iter()
+    .filter(|account| account.item_id == MANUAL_ITEM_ID)
+    .any(|account| {
+      previous
+        .account(&account.account_id)
+        .map_or(true, |before| before.balance != account.balance)
+    });
+
+  revalued || account_ids(snapshot) != account_ids(previous)
+}
+
+// Whether `snapshot` counts other accounts or valuations than the last accepted
+// one, so its net worth can't be held against the ones before it.
+pub fn changes_baseline(snapshot: &Snapshot, history: &Vec<Snapshot>) -> bool {
+  accepted_before(snapshot, history)
+    .last()
+    .map_or(false, |previous| accounts_changed(snapshot, previous))
+}
+
+// Why a new snapshot looks wrong next to the accepted ones since the last
+// baseline, if it does. Plaid now and then answers with zero or wildly off
+// balances.
+pub fn check(
+  snapshot: &Snapshot,
+  history: &Vec<Snapshot>,
+  items: &Vec<PlaidItem>,
+) -> Option<String> {
+  let accepted = accepted_before(snapshot, history);
+
+  if zero_balances(snapshot, accepted.last().copied(), items) {
+    return Some("Every linked account reads zero".to_string());
+  }
+
+  if changes_baseline(snapshot, history) {
+    return None;
+  }
+
+  let since_baseline = accepted
+    .iter()
+    .rposition(|previous| previous.baseline)
+    .unwrap_or(0);
+  let recent: Vec<&Snapshot> = accepted[since_baseline..]
+    .iter()
+    .copied()
+    .filter(|previous| previous.snapshot_time >= snapshot.snapshot_time - OUTLIER_LOOKBACK_SECS)
+    .collect();
+  outlier(snapshot, &recent).map(|median_net_worth| {
+    format!(
+      "Net worth of {} is far from the recent median of {}",
+      snapshot.net_worth.amount, median_net_worth
+    )
+  })
+}
+
+// A rejected snapshot keeps what moved, so the running sums and the flows of
+// each account still add up, but takes the balances of the snapshot before it.
+pub fn carry_balances(snapshot: &mut Snapshot, previous: &Snapshot) {
+  snapshot.net_worth = previous.net_worth;
+  snapshot.assets = previous.assets;
+  snapshot.liabilities = previous.liabilities;
+
+  let zero = Money {
+    amount: dec!(0),
+    currency: snapshot.net_worth.currency,
+  };
+  snapshot.accounts = previous
+    .accounts
+    .iter()
+    .map(|account| {
+      let moved = snapshot.account(&account.account_id);
+      AccountSnapshot {
+        money_in: moved.map_or(zero, |moved| moved.money_in),
+        money_out: moved.map_or(zero, |moved| moved.money_out),
+        transacted: moved.map_or(zero, |moved| moved.transacted),
+        ..account.clone()
+      }
+    })
+    .collect();
+  snapshot.quarantine = None;
+  snapshot.baseline = false;
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn snapshot(day: i64, balances: Vec<Decimal>) -> Snapshot {
+    let accounts = balances
+      .into_iter()
+      .enumerate()
+      .map(|(i, balance)| {
+        AccountSnapshot::fixture(&format!("account {}", i), "depository", balance)
+      })
+      .collect();
+    Snapshot::fixture(day * 24 * 60 * 60, accounts)
+  }
+
+  fn items(status: ItemStatus) -> Vec<PlaidItem> {
+    vec![PlaidItem {
+      status,
+      ..PlaidItem::fixture("item", "access-sandbox")
+    }]
+  }
+
+  #[test]
+  fn test_check() {
+    let history: Vec<Snapshot> = (0..10)
+      .map(|day| snapshot(day, vec![dec!(1000) + Decimal::from(day * 10), dec!(500)]))
+      .collect();
+    let healthy = items(ItemStatus::Healthy);
+
+    // an ordinary day
+    assert_eq!(
+      None,
+      check(
+        &snapshot(10, vec![dec!(1150), dec!(450)]),
+        &history,
+        &healthy
+      )
+    );
+
+    // a balance a thousand times too big
+    let wrong = snapshot(10, vec![dec!(1100000), dec!(500)]);
+    assert!(check(&wrong, &history, &healthy).is_some());
+
+    // nothing anywhere, though Plaid is fine, unless the item is broken
+    let zeros = snapshot(10, vec![dec!(0), dec!(0)]);
+    assert_eq!(
+      Some("Every linked account reads zero".to_string()),
+      check(&zeros, &history, &healthy)
+    );
+    assert!(check(
+      &zeros,
+      &history[..1].to_vec(),
+      &items(ItemStatus::LoginRequired)
+    )
+    .is_none());
+
+    // quarantined snapshots aren't history to compare with
+    let mut quarantined = history.clone();
+    quarantined.push(wrong.clone().with_quarantine(Some("wrong".to_string())));
+    assert!(check(
+      &snapshot(11, vec![dec!(1100000), dec!(500)]),
+      &quarantined,
+      &healthy
+    )
+    .is_some());
+  }
+
+  #[test]
+  fn test_baseline() {
+    let history: Vec<Snapshot> = (0..10)
+      .map(|day| snapshot(day, vec![dec!(1000), dec!(500)]))
+      .collect();
+    let healthy = items(ItemStatus::Healthy);
+
+    // linking an account isn't an outlier, and neither are the days after
+    let linked = snapshot(10, vec![dec!(1000), dec!(500), dec!(100000)]);
+    assert!(changes_baseline(&linked, &history));
+    assert_eq!(None, check(&linked, &history, &healthy));
+
+    let mut since_linked = history.clone();
+    since_linked.push(linked.with_baseline(true));
+    let next = snapshot(11, vec![dec!(1000), dec!(500), dec!(100000)]);
+    assert!(!changes_baseline(&next, &since_linked));
+    assert_eq!(None, check(&next, &since_linked, &healthy));
+
+    // nor is revaluing a manual account, though a Plaid balance as far off is
+    let manual = |mut snapshot: Snapshot| {
+      snapshot.accounts[1].item_id = MANUAL_ITEM_ID.to_string();
+      snapshot
+    };
+    let with_house: Vec<Snapshot> = history.iter().cloned().map(manual).collect();
+    let revalued = manual(snapshot(10, vec![dec!(1000), dec!(90000)]));
+    assert_eq!(None, check(&revalued, &with_house, &healthy));
+    let wrong = manual(snapshot(10, vec![dec!(90000), dec!(500)]));
+    assert!(check(&wrong, &with_house, &healthy).is_some());
+
+    // an accepted snapshot is what the ones after it are held against
+    let mut accepted = history.clone();
+    accepted.push(snapshot(10, vec![dec!(50000), dec!(500)]).with_baseline(true));
+    assert_eq!(
+      None,
+      check(
+        &snapshot(11, vec![dec!(50000), dec!(500)]),
+        &accepted,
+        &healthy
+      )
+    );
+  }
+
+  #[test]
+  fn test_carry_balances() {
+    let previous = snapshot(0, vec![dec!(1000), dec!(500)]);
+    let mut rejected = snapshot(1, vec![dec!(0), dec!(0)])
+      .with_quarantine(Some("zero".to_string()))
+      .with_baseline(true);
+    rejected.running_income = Money::usd(dec!(200));
+    rejected.accounts[0].money_in = Money::usd(dec!(200));
+
+    carry_balances(&mut rejected, &previous);
+
+    assert_eq!(Money::usd(dec!(1500)), rejected.net_worth);
+    assert_eq!(Money::usd(dec!(1000)), rejected.accounts[0].balance);
+    assert_eq!(Money::usd(dec!(200)), rejected.accounts[0].money_in);
+    assert_eq!(Money::usd(dec!(200)), rejected.running_income);
+    assert!(!rejected.is_quarantined());
+    assert!(!rejected.baseline);
+  }
+}
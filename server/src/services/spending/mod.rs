@@ -0,0 +1,286 @@
+#[allow(non_snake_case)]
+pub mod SpendingService {
+  use crate::common::{errors::ApiError, Money};
+  use crate::controllers::spending_controller::{CategoryRuleNewPayload, SpendingQuery};
+  use crate::controllers::transactions_controller::TransactionQuery;
+  use crate::models::{spending_model::*, transaction_model::Transaction, user_model::User};
+  use crate::services::{fx::FxService, transactions::TransactionService, users::UserService};
+  use actix_web::web::Data;
+  use rust_decimal_macros::dec;
+  use std::collections::{BTreeMap, HashSet};
+  use wither::{mongodb::bson::oid::ObjectId, Model};
+
+  pub const UNCATEGORIZED: &str = "Uncategorized";
+
+  // the user's override, then the first matching rule, then Plaid's top level category
+  pub fn categorize(transaction: &Transaction, rules: &Vec<CategoryRule>) -> String {
+    if let Some(category) = transaction.category_override.as_ref() {
+      return category.clone();
+    }
+
+    let merchant = transaction
+      .merchant_name
+      .as_ref()
+      .unwrap_or(&transaction.name);
+
+    rules
+      .iter()
+      .find(|rule| rule.matches(merchant))
+      .map(|rule| rule.category.clone())
+      .or(transaction.category.first().cloned())
+      .unwrap_or(UNCATEGORIZED.to_string())
+  }
+
+  // money that left a visible account, for posted transactions only. Moving
+  // money between the user's own accounts isn't spending.
+  pub fn is_spending(transaction: &Transaction, excluded_accounts: &HashSet<String>) -> bool {
+    !transaction.pending
+      && transaction.transfer_id.is_none()
+      && !excluded_accounts.contains(&transaction.account_id)
+      && transaction.amount.amount > dec!(0)
+  }
+
+  pub fn calculate_category_breakdown(
+    transactions: &Vec<Transaction>,
+    rules: &Vec<CategoryRule>,
+    excluded_accounts: &HashSet<String>,
+  ) -> Result<CategoryBreakdown, ApiError> {
+    let mut totals: BTreeMap<String, Money> = BTreeMap::new();
+    let mut monthly_totals: BTreeMap<String, BTreeMap<String, Money>> = BTreeMap::new();
+
+    for transaction in transactions
+      .iter()
+      .filter(|transaction| is_spending(transaction, excluded_accounts))
+    {
+      let category = categorize(transaction, rules);
+      let month = transaction.date.chars().take(7).collect::<String>();
+
+      let total = totals.entry(category.clone()).or_default();
+      *total = total.checked_add(transaction.amount)?;
+
+      let monthly_total = monthly_totals
+        .entry(month)
+        .or_default()
+        .entry(category)
+        .or_default();
+      *monthly_total = monthly_total.checked_add(transaction.amount)?;
+    }
+
+    Ok(CategoryBreakdown {
+      categories: into_category_totals(totals),
+      months: monthly_totals
+        .into_iter()
+        .map(|(month, totals)| MonthlyCategoryTotals {
+          month,
+          categories: into_category_totals(totals),
+        })
+        .collect(),
+    })
+  }
+
+  // largest total first
+  fn into_category_totals(totals: BTreeMap<String, Money>) -> Vec<CategoryTotal> {
+    let mut category_totals: Vec<CategoryTotal> = totals
+      .into_iter()
+      .map(|(category, total)| CategoryTotal { category, total })
+      .collect();
+
+    category_totals.sort_by(|a, b| b.total.amount.cmp(&a.total.amount));
+    category_totals
+  }
+
+  // the ledger keeps each transaction in its account's currency
+  pub fn in_base_currency(
+    transactions: Vec<Transaction>,
+    user: &User,
+    fx: &FxService,
+  ) -> Result<Vec<Transaction>, ApiError> {
+    transactions
+      .into_iter()
+      .map(|mut transaction| {
+        transaction.amount = fx.convert(transaction.amount, user.base_currency)?;
+        Ok(transaction)
+      })
+      .collect()
+  }
+
+  pub async fn get_category_breakdown(
+    query: SpendingQuery,
+    user: User,
+    transaction_service: Data<TransactionService>,
+    fx: Data<FxService>,
+  ) -> Result<CategoryBreakdown, ApiError> {
+    let transactions = transaction_service
+      .get_transactions(
+        &user.id.clone().unwrap(),
+        TransactionQuery {
+          from: query.from,
+          to: query.to,
+          account_id: None,
+          min_amount: None,
+          max_amount: None,
+        },
+      )
+      .await?;
+    let transactions = in_base_currency(transactions, &user, &fx)?;
+
+    calculate_category_breakdown(
+      &transactions,
+      &user.category_rules,
+      &user.get_excluded_accounts(),
+    )
+  }
+
+  pub async fn new_rule(
+    payload: CategoryRuleNewPayload,
+    mut user: User,
+    user_service: Data<UserService>,
+  ) -> Result<CategoryRule, ApiError> {
+    let mut rule: CategoryRule = payload.into();
+    rule.set_id(ObjectId::new());
+
+    user.category_rules.push(rule.clone());
+
+    user_service.save(&mut user).await?;
+
+    Ok(rule)
+  }
+
+  pub async fn delete_rule(
+    rule_id: String,
+    mut user: User,
+    user_service: Data<UserService>,
+  ) -> Result<CategoryRule, ApiError> {
+    let rule_id_opt = Some(
+      ObjectId::with_string(rule_id.as_str())
+        .or(Err(ApiError::new(400, "Malformed Object Id".to_string())))?,
+    );
+
+    let rule = user
+      .category_rules
+      .iter()
+      .position(|rec| rec.id == rule_id_opt)
+      .ok_or(ApiError::new(
+        400,
+        format!("No category rule with id {} found in current user", rule_id),
+      ))
+      .and_then(|pos| Ok(user.category_rules.remove(pos)))?;
+
+    user_service.save(&mut user).await?;
+
+    Ok(rule)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Money;
+  use crate::models::{
+    spending_model::{CategoryRule, CategoryTotal},
+    transaction_model::Transaction,
+  };
+  use rust_decimal_macros::dec;
+  use std::collections::HashSet;
+
+  fn transaction(merchant: &str, amount: rust_decimal::Decimal, date: &str) -> Transaction {
+    Transaction {
+      merchant_name: Some(merchant.to_string()),
+      category: vec!["Travel".to_string(), "Taxi".to_string()],
+      ..Transaction::fixture(
+        &format!("{}-{}", merchant, date),
+        merchant,
+        Money::new(amount),
+        date,
+      )
+    }
+  }
+
+  fn uber_rule() -> CategoryRule {
+    CategoryRule {
+      id: None,
+      merchant_contains: "UBER".to_string(),
+      category: "Transport".to_string(),
+    }
+  }
+
+  #[test]
+  fn test_categorize() {
+    let rules = vec![uber_rule()];
+
+    // plaid's category by default
+    assert_eq!(
+      "Travel",
+      SpendingService::categorize(
+        &transaction("United Airlines", dec!(500), "2021-03-01"),
+        &rules
+      )
+    );
+
+    // rules match the merchant ignoring case
+    assert_eq!(
+      "Transport",
+      SpendingService::categorize(&transaction("Uber", dec!(5.4), "2021-03-01"), &rules)
+    );
+
+    // overrides beat rules
+    let mut overridden = transaction("Uber", dec!(5.4), "2021-03-01");
+    overridden.category_override = Some("Work".to_string());
+    assert_eq!("Work", SpendingService::categorize(&overridden, &rules));
+
+    let mut uncategorized = transaction("Corner Store", dec!(3), "2021-03-01");
+    uncategorized.category = vec![];
+    assert_eq!(
+      SpendingService::UNCATEGORIZED,
+      SpendingService::categorize(&uncategorized, &rules)
+    );
+  }
+
+  #[test]
+  fn test_calculate_category_breakdown() {
+    let mut pending = transaction("Uber", dec!(100), "2021-04-01");
+    pending.pending = true;
+
+    let transactions = vec![
+      transaction("Uber", dec!(5.4), "2021-03-01"),
+      transaction("Uber", dec!(12.5), "2021-04-02"),
+      transaction("United Airlines", dec!(500), "2021-04-03"),
+      // money coming in is not spending
+      transaction("Payroll", dec!(-2000), "2021-04-15"),
+      pending,
+    ];
+
+    let breakdown = SpendingService::calculate_category_breakdown(
+      &transactions,
+      &vec![uber_rule()],
+      &HashSet::new(),
+    )
+    .unwrap();
+
+    assert_eq!(
+      vec![
+        CategoryTotal {
+          category: "Travel".to_string(),
+          total: Money::new(dec!(500)),
+        },
+        CategoryTotal {
+          category: "Transport".to_string(),
+          total: Money::new(dec!(17.9)),
+        },
+      ],
+      breakdown.categories
+    );
+
+    assert_eq!(2, breakdown.months.len());
+    assert_eq!("2021-03", breakdown.months[0].month);
+    assert_eq!(
+      vec![CategoryTotal {
+        category: "Transport".to_string(),
+        total: Money::new(dec!(5.4)),
+      }],
+      breakdown.months[0].categories
+    );
+    assert_eq!("2021-04", breakdown.months[1].month);
+    assert_eq!(2, breakdown.months[1].categories.len());
+  }
+}
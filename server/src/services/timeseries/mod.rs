@@ -1,45 +1,57 @@
 #[allow(non_snake_case)]
 pub mod TimeseriesService {
-    use crate::common::{errors::ApiError, Money};
+    use crate::common::{
+        compounding::{Compounder, CompoundingConvention},
+        errors::ApiError,
+        Money,
+    };
     use crate::controllers::timeseries_controller::{TimeseriesEntry, TimeseriesResponse};
-    use crate::models::plan_model::{Allocation, AllocationProportion, Event, Plan};
+    use crate::models::plan_model::{AdvisoryFee, Allocation, AllocationProportion, Event, Plan};
     use crate::models::recurring_model::Recurring;
     use crate::models::user_model::{Snapshot, User};
-    use crate::services::finchplaid::ApiClient;
-    use crate::services::{plans::PlansService, users::UserService};
-    use actix_web::web::Data;
+    use crate::services::{plans::PlansService, snapshots::SnapshotService};
     use chrono::{offset, DateTime, Duration, TimeZone, Utc};
-    use rust_decimal::prelude::ToPrimitive;
     use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
 
     struct RecurringState {
         rec: Recurring,
         skips: i32,
+        compounder: Compounder,
     }
 
     impl RecurringState {
+        fn new(rec: Recurring, convention: CompoundingConvention) -> Self {
+            Self {
+                rec,
+                skips: 0,
+                compounder: Compounder::new(dec!(1.0), convention),
+            }
+        }
+
         fn check_skip(&mut self) -> bool {
             self.skips += 1;
             self.skips % self.rec.frequency.content == 0
         }
 
         pub fn take_payment(&mut self, date: &DateTime<Utc>) -> Decimal {
-            if !self.rec.is_active(date) || !self.check_skip() {
-                return dec!(0);
+            // a principal earns interest every day it runs, credited as the plan compounds
+            if self.rec.principal != dec!(0) {
+                if !self.rec.is_running(date) {
+                    return dec!(0);
+                }
+
+                let today = date.date().naive_utc();
+                return self
+                    .rec
+                    .compound(&mut self.compounder, &today.pred(), &today);
             }
 
-            if self.rec.principal == dec!(0) {
-                self.rec.amount
-            } else {
-                self.rec.compound()
+            if !self.rec.is_active(date) || !self.check_skip() {
+                return dec!(0);
             }
-        }
-    }
 
-    impl From<Recurring> for RecurringState {
-        fn from(rec: Recurring) -> Self {
-            Self { rec, skips: 0 }
+            self.rec.amount
         }
     }
 
@@ -81,6 +93,7 @@ pub mod TimeseriesService {
         return TimeseriesResponse {
             start: today.timestamp(),
             series: res,
+            total_fees: Money::default(),
         };
     }
 
@@ -94,7 +107,7 @@ pub mod TimeseriesService {
             .collect()
     }
 
-    fn calculate_apy_from_allocation(allocation: Allocation) -> Decimal {
+    pub fn calculate_apy_from_allocation(allocation: Allocation) -> Decimal {
         allocation
             .schema
             .iter()
@@ -102,13 +115,17 @@ pub mod TimeseriesService {
             .sum()
     }
 
-    fn calculate_account_value_from_apy(previous_value: Money, apy: Decimal) -> Money {
-        let dpy = match apy.to_f64() {
-            Some(p) => p.powf(1.0 / 365.0) * 1e9,
-            None => 1e9, // if we cant convert, assume 1
-        };
+    // grows the value by one day's interest, credited according to the plan's convention
+    fn calculate_account_value_from_apy(
+        previous_value: Money,
+        apy: Decimal,
+        compounder: &mut Compounder,
+        date: &DateTime<Utc>,
+    ) -> Money {
+        compounder.set_annual_growth(apy);
 
-        return previous_value * Money::from(Decimal::new(dpy as i64, 9));
+        let today = date.date().naive_utc();
+        compounder.step(previous_value, &today.pred(), &today)
     }
 
     fn calculate_payments_from_recurrings(
@@ -123,6 +140,32 @@ pub mod TimeseriesService {
         Money::from(payments)
     }
 
+    // the daily share of each asset's annual expense ratio, weighted by the allocation
+    fn calculate_expense_ratio_fee(net_worth: Money, allocation: &Allocation) -> Money {
+        let weighted_expense_ratio: Decimal = allocation
+            .schema
+            .iter()
+            .map(|a| a.proportion * a.asset.expense_ratio.unwrap_or(dec!(0.0)) / dec!(100.0))
+            .sum();
+
+        Money::from(net_worth.amount.max(dec!(0.0)) * weighted_expense_ratio / dec!(36500.0))
+    }
+
+    fn calculate_advisory_fee(
+        net_worth: Money,
+        advisory_fee: &Option<AdvisoryFee>,
+        date: &DateTime<Utc>,
+    ) -> Money {
+        match advisory_fee {
+            Some(fee) if fee.billing.is_billing_date(date) => Money::from(
+                net_worth.amount.max(dec!(0.0)) * fee.annual_percentage
+                    / dec!(100.0)
+                    / fee.billing.periods_per_year(),
+            ),
+            _ => Money::default(),
+        }
+    }
+
     fn calculate_account_value_from_event(event: Event, allocation: Allocation) -> Money {
         Money::from(
             allocation
@@ -150,15 +193,29 @@ pub mod TimeseriesService {
         start_net_worth: Money,
         start_date: i64,
     ) -> Vec<TimeseriesEntry> {
+        generate_projection_from_plan(plan, days, start_net_worth, start_date).0
+    }
+
+    // projects the plan forward, returning the series and the total fees paid over it
+    pub fn generate_projection_from_plan(
+        plan: Plan,
+        days: i64,
+        start_net_worth: Money,
+        start_date: i64,
+    ) -> (Vec<TimeseriesEntry>, Money) {
         let start_date_dt = Utc.timestamp(start_date, 0);
         let mut apy = dec!(0.0);
         let mut net_worth = start_net_worth;
+        let mut total_fees = Money::default();
+        let mut compounder = Compounder::new(dec!(1.0), plan.compounding);
+
+        let advisory_fee = plan.advisory_fee.clone();
 
         let mut recurrings: Vec<RecurringState> = plan
             .recurrings
             .iter()
             .cloned()
-            .map(RecurringState::from)
+            .map(|rec| RecurringState::new(rec, plan.compounding))
             .collect();
 
         let mut events = plan.events.clone();
@@ -168,7 +225,7 @@ pub mod TimeseriesService {
         events.sort_by(|a, b| a.start.cmp(&b.start));
         allocations.sort_by(|a, b| a.date.cmp(&b.date));
 
-        (1..days + 1)
+        let series = (1..days + 1)
             .map(|d| start_date_dt + Duration::days(d))
             .map(|date| {
                 let allocation = allocations
@@ -183,29 +240,38 @@ pub mod TimeseriesService {
 
                 net_worth = match events.iter().position(|a| a.start <= date.timestamp()) {
                     Some(i) => {
-                        net_worth * calculate_account_value_from_event(events.remove(i), allocation)
+                        net_worth
+                            * calculate_account_value_from_event(
+                                events.remove(i),
+                                allocation.clone(),
+                            )
                     }
                     None => net_worth,
                 };
 
                 let payments = calculate_payments_from_recurrings(&mut recurrings, &date);
 
-                net_worth = calculate_account_value_from_apy(net_worth, apy) + payments;
+                net_worth =
+                    calculate_account_value_from_apy(net_worth, apy, &mut compounder, &date)
+                        + payments;
+
+                let fees = calculate_expense_ratio_fee(net_worth, &allocation)
+                    + calculate_advisory_fee(net_worth, &advisory_fee, &date);
+
+                net_worth = net_worth - fees;
+                total_fees = total_fees + fees;
 
                 TimeseriesEntry {
                     date: date.timestamp(),
                     net_worth: net_worth.clone(),
                 }
             })
-            .collect()
+            .collect();
+
+        (series, total_fees)
     }
 
-    pub async fn get_timeseries(
-        mut user: User,
-        days: i64,
-        user_service: Data<UserService>,
-        plaid_client: Data<ApiClient>,
-    ) -> Result<TimeseriesResponse, ApiError> {
+    pub async fn get_timeseries(user: User, days: i64) -> Result<TimeseriesResponse, ApiError> {
         let mut plan = if user.plans.len() > 0 {
             user.plans[0].clone()
         } else {
@@ -213,20 +279,31 @@ pub mod TimeseriesService {
         };
         plan.recurrings.append(&mut user.recurrings.clone());
 
-        let snapshots = user_service.get_snapshots(&mut user, plaid_client).await?;
-        let last_day = snapshots[snapshots.len() - 1].clone();
+        // quarantined snapshots may be wrong, so the series leaves them out
+        let snapshots: Vec<Snapshot> = user
+            .snapshots
+            .iter()
+            .filter(|snapshot| !snapshot.is_quarantined())
+            .cloned()
+            .collect();
+
+        // users who have not been snapshotted yet start from their self-reported net worth
+        let last_day = if snapshots.is_empty() {
+            Snapshot::new(user.net_worth, dec!(0), dec!(0), dec!(0))
+        } else {
+            SnapshotService::get_last_snapshot(&snapshots)
+        };
+
+        let (projection, total_fees) =
+            generate_projection_from_plan(plan, days, last_day.net_worth, last_day.snapshot_time);
 
         Ok(TimeseriesResponse {
             start: last_day.snapshot_time,
             series: generate_timeseries_from_snapshots(snapshots)
                 .into_iter()
-                .chain(generate_timeseries_from_plan(
-                    plan,
-                    days,
-                    last_day.net_worth,
-                    last_day.snapshot_time,
-                ))
+                .chain(projection)
                 .collect(),
+            total_fees,
         })
     }
 
@@ -234,10 +311,13 @@ pub mod TimeseriesService {
     mod test {
         use super::*;
 
-        use chrono::{offset, DateTime, Duration, Utc};
+        use chrono::{offset, DateTime, Duration, TimeZone, Utc};
         use rust_decimal_macros::dec;
 
-        use crate::common::Money;
+        use crate::common::{
+            compounding::{CompoundingConvention, CompoundingFrequency, DayCount},
+            Money,
+        };
         use crate::controllers::timeseries_controller::TimeseriesEntry;
         use crate::models::plan_model::*;
         use crate::models::recurring_model::{Recurring, TimeInterval, Typ};
@@ -248,10 +328,15 @@ pub mod TimeseriesService {
             (0..2)
                 .map(|n| Snapshot {
                     net_worth: Money::new(Decimal::new(n * 100, 0)),
+                    assets: Money::new(Decimal::new(n * 100, 0)),
+                    liabilities: Money::default(),
                     running_savings: Money::new(Decimal::new(n, 0)),
                     running_spending: Money::new(Decimal::new(n, 0)),
                     running_income: Money::new(Decimal::new(n, 0)),
                     snapshot_time: (today - Duration::days(2 - n)).timestamp(),
+                    accounts: vec![],
+                    quarantine: None,
+                    baseline: false,
                 })
                 .collect()
         }
@@ -269,7 +354,7 @@ pub mod TimeseriesService {
             (1..2)
                 .map(|n| TimeseriesEntry {
                     date: (today + Duration::days(n)).timestamp(),
-                    net_worth: Money::new(dec!(150.01305785)),
+                    net_worth: Money::new(dec!(150.01)),
                 })
                 .collect()
         }
@@ -295,6 +380,7 @@ pub mod TimeseriesService {
                 name: String::from("A Test Asset"),
                 class: AssetClass::Equity,
                 annualized_performance: dec!(1.2),
+                expense_ratio: None,
             };
 
             let test_change1 = AllocationProportion {
@@ -306,6 +392,7 @@ pub mod TimeseriesService {
                 name: String::from("A Test Asset"),
                 class: AssetClass::Equity,
                 annualized_performance: dec!(0.7),
+                expense_ratio: None,
             };
 
             let test_change2 = AllocationProportion {
@@ -342,6 +429,7 @@ pub mod TimeseriesService {
                 name: String::from("A Test Asset"),
                 class: AssetClass::Equity,
                 annualized_performance: dec!(1.1),
+                expense_ratio: None,
             };
 
             let test_change = AllocationProportion {
@@ -372,15 +460,24 @@ pub mod TimeseriesService {
         fn test_account_value_calculation() {
             let test_apy = dec!(1.1);
             let initial_value = Money::from(dec!(100.0));
-            let target_value = Money::from(dec!(200.0261157000));
+            let target_value = Money::from(dec!(200.03));
 
-            let mut recurrings = vec![RecurringState::from(generate_test_recurring())];
+            let mut recurrings = vec![RecurringState::new(
+                generate_test_recurring(),
+                CompoundingConvention::default(),
+            )];
 
-            let calculated_value = calculate_account_value_from_apy(initial_value, test_apy)
-                + calculate_payments_from_recurrings(
-                    &mut recurrings,
-                    &(Utc::now() + Duration::days(1)),
-                );
+            let mut compounder = Compounder::new(test_apy, CompoundingConvention::default());
+
+            let calculated_value = calculate_account_value_from_apy(
+                initial_value,
+                test_apy,
+                &mut compounder,
+                &(Utc::now() + Duration::days(1)),
+            ) + calculate_payments_from_recurrings(
+                &mut recurrings,
+                &(Utc::now() + Duration::days(1)),
+            );
 
             assert_eq!(target_value, calculated_value);
         }
@@ -389,7 +486,7 @@ pub mod TimeseriesService {
         fn test_account_value_calculation_negative_recurring() {
             let test_apy = dec!(1.1);
             let initial_value = Money::from(dec!(100.0));
-            let target_value = Money::from(dec!(0.0261157000));
+            let target_value = Money::from(dec!(0.03));
 
             let mut recurrings = vec![RecurringState::from(Recurring {
                 id: None,
@@ -405,11 +502,17 @@ pub mod TimeseriesService {
                 },
             })];
 
-            let calculated_value = calculate_account_value_from_apy(initial_value, test_apy)
-                + calculate_payments_from_recurrings(
-                    &mut recurrings,
-                    &(Utc::now() + Duration::days(1)),
-                );
+            let mut compounder = Compounder::new(test_apy, CompoundingConvention::default());
+
+            let calculated_value = calculate_account_value_from_apy(
+                initial_value,
+                test_apy,
+                &mut compounder,
+                &(Utc::now() + Duration::days(1)),
+            ) + calculate_payments_from_recurrings(
+                &mut recurrings,
+                &(Utc::now() + Duration::days(1)),
+            );
             assert_eq!(target_value, calculated_value);
         }
 
@@ -420,41 +523,59 @@ pub mod TimeseriesService {
             let calculated_apy = calculate_apy_from_allocation(test_allocation);
 
             let initial_value = Money::from(dec!(100.0));
-            let target_value = Money::from(dec!(200.0261157000));
+            let target_value = Money::from(dec!(200.03));
 
-            let mut recurrings = vec![RecurringState::from(generate_test_recurring())];
+            let mut recurrings = vec![RecurringState::new(
+                generate_test_recurring(),
+                CompoundingConvention::default(),
+            )];
 
-            let calculated_value = calculate_account_value_from_apy(initial_value, calculated_apy)
-                + calculate_payments_from_recurrings(
-                    &mut recurrings,
-                    &(Utc::now() + Duration::days(1)),
-                );
+            let mut compounder = Compounder::new(calculated_apy, CompoundingConvention::default());
+
+            let calculated_value = calculate_account_value_from_apy(
+                initial_value,
+                calculated_apy,
+                &mut compounder,
+                &(Utc::now() + Duration::days(1)),
+            ) + calculate_payments_from_recurrings(
+                &mut recurrings,
+                &(Utc::now() + Duration::days(1)),
+            );
             assert_eq!(target_value, calculated_value);
         }
 
         #[test]
         fn test_compounding_recurring() {
-            let mut recurrings = vec![RecurringState::from(Recurring {
-                id: None,
-                name: String::from("Test Compounding Recurring"),
-                start: (offset::Utc::now()).timestamp(),
-                end: (offset::Utc::now() + Duration::days(2)).timestamp(),
-                principal: dec!(100.0),
-                interest: dec!(12),
-                amount: dec!(0),
-                frequency: TimeInterval {
-                    typ: Typ::Daily,
-                    content: 1,
+            let start = Utc.ymd(2021, 12, 30).and_hms(0, 0, 0);
+            let convention = CompoundingConvention {
+                frequency: CompoundingFrequency::Annually,
+                day_count: DayCount::Act365,
+            };
+            let mut recurrings = vec![RecurringState::new(
+                Recurring {
+                    id: None,
+                    name: String::from("Test Compounding Recurring"),
+                    start: start.timestamp(),
+                    end: (start + Duration::days(3)).timestamp(),
+                    principal: dec!(100.0),
+                    interest: dec!(12),
+                    amount: dec!(0),
+                    frequency: TimeInterval {
+                        typ: Typ::Daily,
+                        content: 1,
+                    },
                 },
-            })];
+                convention,
+            )];
 
-            let day_one = calculate_payments_from_recurrings(&mut recurrings, &Utc::now());
-            let day_two = calculate_payments_from_recurrings(
-                &mut recurrings,
-                &(Utc::now() + Duration::days(1)),
-            );
+            // the two days of interest accrued in 2021 are credited on new year's day
+            let last_day =
+                calculate_payments_from_recurrings(&mut recurrings, &(start + Duration::days(1)));
+            let new_year =
+                calculate_payments_from_recurrings(&mut recurrings, &(start + Duration::days(2)));
 
-            assert_eq!(Money::from(dec!(12) + dec!(13.44)), day_one + day_two);
+            assert_eq!(Money::from(dec!(0)), last_day);
+            assert_eq!(Money::from(dec!(0.07)), new_year);
         }
 
         #[test]
@@ -483,6 +604,8 @@ pub mod TimeseriesService {
                 recurrings: test_recurrings,
                 allocations: test_allocations,
                 events: test_events,
+                advisory_fee: None,
+                compounding: CompoundingConvention::default(),
             };
 
             let generated = generate_timeseries_from_plan(
@@ -498,5 +621,44 @@ pub mod TimeseriesService {
                 assert_eq!(generated[i], verification[i]);
             }
         }
+
+        #[test]
+        fn test_generate_projection_with_fees() {
+            let test_plan = Plan {
+                id: None,
+                name: String::from("Test Plan"),
+                recurrings: vec![],
+                allocations: vec![Allocation {
+                    id: None,
+                    description: String::from("Index Fund"),
+                    date: 0,
+                    schema: vec![AllocationProportion {
+                        asset: Asset {
+                            name: String::from("Index Fund"),
+                            class: AssetClass::Etf,
+                            annualized_performance: dec!(1.0),
+                            expense_ratio: Some(dec!(3.65)),
+                        },
+                        proportion: dec!(100.0),
+                    }],
+                }],
+                events: vec![],
+                advisory_fee: Some(AdvisoryFee {
+                    annual_percentage: dec!(12.0),
+                    billing: FeeBilling::Monthly,
+                }),
+                compounding: CompoundingConvention::default(),
+            };
+
+            // the first projected day is the first of the month, so the advisory fee is billed
+            let start_date = Utc.ymd(2021, 1, 31).and_hms(0, 0, 0).timestamp();
+
+            let (series, total_fees) =
+                generate_projection_from_plan(test_plan, 2, Money::from(dec!(1000.0)), start_date);
+
+            // 0.10 expense ratio and 10.00 advisory fee on day one, then 0.09899 expense ratio
+            assert_eq!(Money::from(dec!(989.9)), series[0].net_worth);
+            assert_eq!(Money::from(dec!(10.19899)), total_fees);
+        }
     }
 }
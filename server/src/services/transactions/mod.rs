@@ -0,0 +1,558 @@
+pub mod transfers;
+
+use crate::common::{errors::ApiError, into_bson_document};
+use crate::controllers::transactions_controller::TransactionQuery;
+use crate::models::{transaction_model::Transaction, user_model::PlaidItem};
+use crate::services::{db::DatabaseService, finchplaid::ApiClient};
+use actix_web::web::Data;
+use chrono::{Duration, NaiveDate, Utc};
+use futures::stream::{LocalBoxStream, StreamExt, TryStreamExt};
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{HashMap, HashSet};
+use wither::{
+  mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument, UpdateOptions},
+    Database,
+  },
+  prelude::Migrating,
+  Model,
+};
+
+// how far back the first sync of an item goes, about 24 months
+const INITIAL_SYNC_DAYS: i64 = 730;
+
+// Plaid may still post, amend or drop transactions this many days after their date,
+// so every sync refetches them
+const RESYNC_OVERLAP_DAYS: i64 = 30;
+
+#[derive(Clone)]
+pub struct TransactionService {
+  db: Database,
+}
+
+impl TransactionService {
+  pub async fn new(db: &DatabaseService) -> TransactionService {
+    let _ = Transaction::sync(&db.db).await.unwrap();
+    let _ = Transaction::migrate(&db.db).await.unwrap();
+    TransactionService { db: db.db.clone() }
+  }
+
+  // Brings the ledger up to date with the item's transactions since its cursor,
+  // then moves the cursor to today. Returns the earliest day synced.
+  pub async fn sync_item(
+    &self,
+    user_id: &ObjectId,
+    item: &mut PlaidItem,
+    plaid_client: Data<ApiClient>,
+  ) -> Result<NaiveDate, ApiError> {
+    let end = Utc::now().naive_utc().date();
+    let start = sync_start(&item.transactions_cursor, end);
+    let start_date = start.format("%Y-%m-%d").to_string();
+    let end_date = end.format("%Y-%m-%d").to_string();
+
+    // a transaction that can't be read is left out rather than holding up the rest
+    let mut fetched: Vec<Transaction> = plaid_client
+      .stream_transactions(&item.access_token, start_date.clone(), end_date.clone())
+      .try_collect::<Vec<plaid::models::Transaction>>()
+      .await?
+      .iter()
+      .filter_map(|transaction| {
+        match Transaction::from_plaid(user_id.clone(), item.item_id.clone(), transaction) {
+          Ok(transaction) => Some(transaction),
+          Err(e) => {
+            log::error!("Skipping transaction of item {}: {:?}", item.item_id, e);
+            None
+          }
+        }
+      })
+      .collect();
+
+    // a category the user gave a pending transaction stays once it posts
+    let posted_from: Vec<String> = fetched
+      .iter()
+      .filter_map(|transaction| transaction.pending_transaction_id.clone())
+      .collect();
+    if !posted_from.is_empty() {
+      let overridden = Transaction::find(
+        &self.db,
+        doc! {
+          "item_id": item.item_id.clone(),
+          "transaction_id": doc!{"$in": posted_from},
+          "category_override": doc!{"$exists": true},
+        },
+        None,
+      )
+      .await
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+      .try_collect::<Vec<Transaction>>()
+      .await?;
+
+      carry_category_overrides(&mut fetched, &overridden);
+    }
+
+    for transaction in fetched.iter() {
+      self.upsert(transaction).await?;
+    }
+
+    // a pending transaction that posted comes back under a new id, and one that
+    // was dropped doesn't come back at all, so pending entries Plaid no longer
+    // returns are removed
+    let pending = Transaction::find(
+      &self.db,
+      doc! {
+        "item_id": item.item_id.clone(),
+        "pending": true,
+        "date": doc!{"$gte": start_date, "$lte": end_date},
+      },
+      None,
+    )
+    .await
+    .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+    .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+    .try_collect::<Vec<Transaction>>()
+    .await?;
+
+    let stale = stale_pending_ids(&pending, &fetched);
+    if !stale.is_empty() {
+      Transaction::delete_many(&self.db, doc! {"transaction_id": doc!{"$in": stale}}, None)
+        .await
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+    }
+
+    item.transactions_cursor = Some(end.format("%Y-%m-%d").to_string());
+
+    Ok(start)
+  }
+
+  // Pairs up the transfers between the user's accounts in the ledger from
+  // `since` on, across items and manual accounts, and unpairs transactions whose
+  // counterpart is gone. Transactions up to a transfer window earlier are looked
+  // at and paired too, for transfers that straddle `since`.
+  pub async fn mark_transfers(&self, user_id: &ObjectId, since: NaiveDate) -> Result<(), ApiError> {
+    let transactions = self
+      .get_transactions(
+        user_id,
+        TransactionQuery {
+          from: Some(
+            (since - Duration::days(transfers::TRANSFER_WINDOW_DAYS))
+              .format("%Y-%m-%d")
+              .to_string(),
+          ),
+          to: None,
+          account_id: None,
+          min_amount: None,
+          max_amount: None,
+        },
+      )
+      .await?;
+
+    let movements: Vec<transfers::Movement> = transactions
+      .iter()
+      .filter_map(transfers::Movement::from_ledger)
+      .collect();
+    let matched = transfers::match_transfers(&movements);
+
+    for (transaction_id, transfer_id) in transfer_changes(&transactions, &matched, since) {
+      let update = match transfer_id {
+        Some(transfer_id) => doc! {"$set": doc!{"transfer_id": transfer_id}},
+        None => doc! {"$unset": doc!{"transfer_id": ""}},
+      };
+      Transaction::collection(&self.db)
+        .update_one(doc! {"transaction_id": transaction_id}, update, None)
+        .await
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+    }
+
+    Ok(())
+  }
+
+  async fn upsert(&self, transaction: &Transaction) -> Result<(), ApiError> {
+    Transaction::collection(&self.db)
+      .update_one(
+        doc! {"transaction_id": transaction.transaction_id.clone()},
+        doc! {"$set": into_bson_document(transaction)},
+        Some(UpdateOptions::builder().upsert(true).build()),
+      )
+      .await
+      .map(|_| ())
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+  }
+
+  // Adds the transactions whose ids aren't in the ledger yet, leaving the rest
+  // as they are. Returns how many were added.
+  pub async fn insert_new(&self, transactions: &Vec<Transaction>) -> Result<usize, ApiError> {
+    let ids: Vec<String> = transactions
+      .iter()
+      .map(|transaction| transaction.transaction_id.clone())
+      .collect();
+
+    let mut existing: HashSet<String> =
+      Transaction::find(&self.db, doc! {"transaction_id": doc!{"$in": ids}}, None)
+        .await
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+        .map_ok(|transaction| transaction.transaction_id)
+        .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+        .try_collect()
+        .await?;
+
+    // an export can repeat a line too
+    let mut added = 0;
+    for transaction in transactions.iter() {
+      if existing.insert(transaction.transaction_id.clone()) {
+        self.upsert(transaction).await?;
+        added += 1;
+      }
+    }
+
+    Ok(added)
+  }
+
+  pub async fn remove_transactions(
+    &self,
+    item_id: &String,
+    transaction_ids: &Vec<String>,
+  ) -> Result<(), ApiError> {
+    Transaction::delete_many(
+      &self.db,
+      doc! {"item_id": item_id.clone(), "transaction_id": doc!{"$in": transaction_ids.clone()}},
+      None,
+    )
+    .await
+    .map(|_| ())
+    .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+  }
+
+  pub async fn delete_item_transactions(&self, item_id: &String) -> Result<(), ApiError> {
+    Transaction::delete_many(&self.db, doc! {"item_id": item_id.clone()}, None)
+      .await
+      .map(|_| ())
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+  }
+
+  pub async fn delete_account_transactions(
+    &self,
+    user_id: &ObjectId,
+    account_id: &String,
+  ) -> Result<(), ApiError> {
+    Transaction::delete_many(
+      &self.db,
+      doc! {"user_id": user_id.clone(), "account_id": account_id.clone()},
+      None,
+    )
+    .await
+    .map(|_| ())
+    .map_err(|_| ApiError::new(500, "Database Error".to_string()))
+  }
+
+  // `None` clears the override, going back to rules and Plaid's category
+  pub async fn set_category_override(
+    &self,
+    user_id: &ObjectId,
+    transaction_id: String,
+    category: Option<String>,
+  ) -> Result<Transaction, ApiError> {
+    let update = match category {
+      Some(category) => doc! {"$set": doc!{"category_override": category}},
+      None => doc! {"$unset": doc!{"category_override": ""}},
+    };
+
+    Transaction::find_one_and_update(
+      &self.db,
+      doc! {"user_id": user_id.clone(), "transaction_id": transaction_id.clone()},
+      update,
+      Some(
+        FindOneAndUpdateOptions::builder()
+          .return_document(ReturnDocument::After)
+          .build(),
+      ),
+    )
+    .await
+    .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+    .ok_or(ApiError::new(
+      400,
+      format!("No transaction with id {} found", transaction_id),
+    ))
+  }
+
+  // newest first
+  pub async fn get_transactions(
+    &self,
+    user_id: &ObjectId,
+    query: TransactionQuery,
+  ) -> Result<Vec<Transaction>, ApiError> {
+    self
+      .find_transactions(user_id, &query, -1)
+      .await?
+      .try_collect::<Vec<Transaction>>()
+      .await
+  }
+
+  // Oldest first, read from the database as the stream is polled rather than all
+  // at once. A transaction that can't be read ends the stream with an error.
+  pub async fn stream_transactions(
+    &self,
+    user_id: &ObjectId,
+    query: TransactionQuery,
+  ) -> Result<LocalBoxStream<'static, Result<Transaction, ApiError>>, ApiError> {
+    self.find_transactions(user_id, &query, 1).await
+  }
+
+  async fn find_transactions(
+    &self,
+    user_id: &ObjectId,
+    query: &TransactionQuery,
+    order: i32,
+  ) -> Result<LocalBoxStream<'static, Result<Transaction, ApiError>>, ApiError> {
+    Ok(
+      Transaction::find(
+        &self.db,
+        transaction_filter(user_id, query)?,
+        Some(FindOptions::builder().sort(doc! {"date": order}).build()),
+      )
+      .await
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+      .map_err(|e| {
+        log::error!("Could not read transaction: {:?}", e);
+        ApiError::new(500, "Database Error".to_string())
+      })
+      .boxed_local(),
+    )
+  }
+}
+
+fn sync_start(cursor: &Option<String>, today: NaiveDate) -> NaiveDate {
+  cursor
+    .as_ref()
+    .and_then(|cursor| NaiveDate::parse_from_str(cursor, "%Y-%m-%d").ok())
+    .map(|cursor| cursor - Duration::days(RESYNC_OVERLAP_DAYS))
+    .unwrap_or(today - Duration::days(INITIAL_SYNC_DAYS))
+}
+
+fn stale_pending_ids(pending: &Vec<Transaction>, fetched: &Vec<Transaction>) -> Vec<String> {
+  let fetched_ids: HashSet<&String> = fetched
+    .iter()
+    .map(|transaction| &transaction.transaction_id)
+    .collect();
+
+  pending
+    .iter()
+    .filter(|transaction| !fetched_ids.contains(&transaction.transaction_id))
+    .map(|transaction| transaction.transaction_id.clone())
+    .collect()
+}
+
+// gives each posted transaction the category override of the pending one it replaced
+fn carry_category_overrides(fetched: &mut Vec<Transaction>, pending: &Vec<Transaction>) {
+  let overrides: HashMap<&String, &String> = pending
+    .iter()
+    .filter_map(|transaction| {
+      transaction
+        .category_override
+        .as_ref()
+        .map(|category| (&transaction.transaction_id, category))
+    })
+    .collect();
+
+  for transaction in fetched.iter_mut() {
+    if let Some(category) = transaction
+      .pending_transaction_id
+      .as_ref()
+      .and_then(|id| overrides.get(id))
+    {
+      transaction.category_override = Some((*category).clone());
+    }
+  }
+}
+
+// The transfer ids to write, `None` to unpair. Both sides of every pair found
+// are written, but only from `since` on can a transaction be told it has no
+// counterpart, as an earlier one's may be older than those looked at.
+fn transfer_changes(
+  transactions: &Vec<Transaction>,
+  matched: &HashMap<String, String>,
+  since: NaiveDate,
+) -> Vec<(String, Option<String>)> {
+  let since = since.format("%Y-%m-%d").to_string();
+
+  transactions
+    .iter()
+    .filter_map(|transaction| {
+      let transfer_id = matched.get(&transaction.transaction_id);
+      if transfer_id == transaction.transfer_id.as_ref()
+        || (transfer_id.is_none() && transaction.date < since)
+      {
+        return None;
+      }
+      Some((transaction.transaction_id.clone(), transfer_id.cloned()))
+    })
+    .collect()
+}
+
+fn parse_date(date: &String) -> Result<String, ApiError> {
+  NaiveDate::parse_from_str(date, "%Y-%m-%d")
+    .map(|date| date.format("%Y-%m-%d").to_string())
+    .map_err(|_| {
+      ApiError::new(
+        400,
+        format!("Could not parse date {}, expected %Y-%m-%d", date),
+      )
+    })
+}
+
+fn transaction_filter(user_id: &ObjectId, query: &TransactionQuery) -> Result<Document, ApiError> {
+  let mut filter = doc! {"user_id": user_id.clone()};
+
+  let mut date_range = Document::new();
+  if let Some(from) = query.from.as_ref() {
+    date_range.insert("$gte", parse_date(from)?);
+  }
+  if let Some(to) = query.to.as_ref() {
+    date_range.insert("$lte", parse_date(to)?);
+  }
+  if !date_range.is_empty() {
+    filter.insert("date", date_range);
+  }
+
+  if let Some(account_id) = query.account_id.as_ref() {
+    filter.insert("account_id", account_id.clone());
+  }
+
+  // decimals are stored as floats
+  let mut amount_range = Document::new();
+  if let Some(min) = query.min_amount.and_then(|min| min.to_f64()) {
+    amount_range.insert("$gte", min);
+  }
+  if let Some(max) = query.max_amount.and_then(|max| max.to_f64()) {
+    amount_range.insert("$lte", max);
+  }
+  if !amount_range.is_empty() {
+    filter.insert("amount.amount", amount_range);
+  }
+
+  Ok(filter)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Money;
+  use rust_decimal_macros::dec;
+
+  fn transaction(transaction_id: &str, pending: bool) -> Transaction {
+    Transaction {
+      merchant_name: Some("Uber".to_string()),
+      category: vec!["Travel".to_string(), "Taxi".to_string()],
+      pending,
+      ..Transaction::fixture(
+        transaction_id,
+        "Uber 063015 SF**POOL**",
+        Money::new(dec!(5.40)),
+        "2019-04-21",
+      )
+    }
+  }
+
+  #[test]
+  fn test_sync_start() {
+    let today = NaiveDate::from_ymd(2021, 3, 31);
+    assert_eq!(
+      NaiveDate::from_ymd(2021, 3, 1),
+      sync_start(&Some("2021-03-31".to_string()), today)
+    );
+    assert_eq!(
+      today - Duration::days(INITIAL_SYNC_DAYS),
+      sync_start(&None, today)
+    );
+  }
+
+  #[test]
+  fn test_stale_pending_ids() {
+    let pending = vec![transaction("a", true), transaction("b", true)];
+
+    // "a" posted as "c", "b" is still pending
+    let mut posted = transaction("c", false);
+    posted.pending_transaction_id = Some("a".to_string());
+    let fetched = vec![posted, transaction("b", true)];
+
+    assert_eq!(vec!["a".to_string()], stale_pending_ids(&pending, &fetched));
+  }
+
+  #[test]
+  fn test_carry_category_overrides() {
+    let mut pending = transaction("a", true);
+    pending.category_override = Some("Business".to_string());
+
+    // "a" posted as "c", "d" was never pending
+    let mut posted = transaction("c", false);
+    posted.pending_transaction_id = Some("a".to_string());
+    let mut fetched = vec![posted, transaction("d", false)];
+
+    carry_category_overrides(&mut fetched, &vec![pending]);
+    assert_eq!(Some("Business".to_string()), fetched[0].category_override);
+    assert_eq!(None, fetched[1].category_override);
+  }
+
+  #[test]
+  fn test_transfer_changes() {
+    let since = NaiveDate::from_ymd(2019, 4, 21);
+    let on = |transaction_id: &str, date: &str, transfer_id: Option<&str>| Transaction {
+      date: date.to_string(),
+      transfer_id: transfer_id.map(|id| id.to_string()),
+      ..transaction(transaction_id, false)
+    };
+    let transactions = vec![
+      // "a" and "b" straddle `since`, and "c" was paired with a transaction
+      // older than those looked at
+      on("a", "2019-04-19", None),
+      on("b", "2019-04-21", None),
+      on("c", "2019-04-19", Some("old")),
+      // "d" lost its counterpart
+      on("d", "2019-04-22", Some("gone")),
+    ];
+    let matched: HashMap<String, String> = vec![
+      ("a".to_string(), "b".to_string()),
+      ("b".to_string(), "a".to_string()),
+    ]
+    .into_iter()
+    .collect();
+
+    assert_eq!(
+      vec![
+        ("a".to_string(), Some("b".to_string())),
+        ("b".to_string(), Some("a".to_string())),
+        ("d".to_string(), None),
+      ],
+      transfer_changes(&transactions, &matched, since)
+    );
+  }
+
+  #[test]
+  fn test_transaction_filter() {
+    let user_id = transaction("a", false).user_id;
+    let query = TransactionQuery {
+      from: Some("2021-01-01".to_string()),
+      to: None,
+      account_id: Some("account".to_string()),
+      min_amount: Some(dec!(10.5)),
+      max_amount: None,
+    };
+
+    assert_eq!(
+      doc! {
+        "user_id": user_id.clone(),
+        "date": doc!{"$gte": "2021-01-01"},
+        "account_id": "account",
+        "amount.amount": doc!{"$gte": 10.5},
+      },
+      transaction_filter(&user_id, &query).unwrap()
+    );
+
+    let bad_query = TransactionQuery {
+      from: Some("01/01/2021".to_string()),
+      ..query
+    };
+    assert!(transaction_filter(&user_id, &bad_query).is_err());
+  }
+}
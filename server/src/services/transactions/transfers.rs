@@ -0,0 +1,197 @@
+use crate::common::Money;
+use crate::models::transaction_model::Transaction;
+use crate::services::finchplaid;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+// how many days apart the two sides of a transfer may post, since the receiving
+// bank often takes a day or two longer
+pub const TRANSFER_WINDOW_DAYS: i64 = 3;
+
+// what matching needs to know of a transaction, from the ledger or from Plaid
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movement {
+  pub transaction_id: String,
+  pub account_id: String,
+  pub date: NaiveDate,
+  // money out is positive
+  pub amount: Money,
+}
+
+impl Movement {
+  pub fn from_ledger(transaction: &Transaction) -> Option<Movement> {
+    Some(Movement {
+      transaction_id: transaction.transaction_id.clone(),
+      account_id: transaction.account_id.clone(),
+      date: NaiveDate::parse_from_str(&transaction.date, "%Y-%m-%d").ok()?,
+      amount: transaction.amount,
+    })
+  }
+
+  pub fn from_plaid(transaction: &plaid::models::Transaction) -> Option<Movement> {
+    Some(Movement {
+      transaction_id: transaction.transaction_id.clone(),
+      account_id: transaction.account_id.clone(),
+      date: NaiveDate::parse_from_str(&transaction.date, "%Y-%m-%d").ok()?,
+      amount: Money::in_currency(
+        Decimal::try_from(transaction.amount).ok()?,
+        finchplaid::transaction_currency(transaction)?,
+      ),
+    })
+  }
+}
+
+// Pairs money leaving one account with the same amount arriving in another of
+// the user's accounts within a few days, like a move to savings or a card
+// payment. Each transaction is paired at most once, the closest pairs in time
+// first, so an earlier lookalike doesn't take a transfer's other side.
+// Returns each paired transaction's counterpart, both ways.
+pub fn match_transfers(movements: &Vec<Movement>) -> HashMap<String, String> {
+  let outflows = movements
+    .iter()
+    .filter(|movement| movement.amount.amount > Decimal::new(0, 0));
+  let inflows: Vec<&Movement> = movements
+    .iter()
+    .filter(|movement| movement.amount.amount < Decimal::new(0, 0))
+    .collect();
+
+  let mut candidates: Vec<(i64, &Movement, &Movement)> = Vec::new();
+  for outflow in outflows {
+    for inflow in inflows.iter() {
+      let apart = (inflow.date - outflow.date).num_days().abs();
+      if inflow.account_id != outflow.account_id
+        && inflow.amount.currency == outflow.amount.currency
+        && inflow.amount.amount == -outflow.amount.amount
+        && apart <= TRANSFER_WINDOW_DAYS
+      {
+        candidates.push((apart, outflow, inflow));
+      }
+    }
+  }
+  // ties go to the earlier outflow, then by id so the result doesn't depend on order
+  candidates.sort_by(|(a_apart, a_out, a_in), (b_apart, b_out, b_in)| {
+    (
+      a_apart,
+      a_out.date,
+      &a_out.transaction_id,
+      &a_in.transaction_id,
+    )
+      .cmp(&(
+        b_apart,
+        b_out.date,
+        &b_out.transaction_id,
+        &b_in.transaction_id,
+      ))
+  });
+
+  let mut matched: HashMap<String, String> = HashMap::new();
+  for (_, outflow, inflow) in candidates.into_iter() {
+    if matched.contains_key(&outflow.transaction_id) || matched.contains_key(&inflow.transaction_id)
+    {
+      continue;
+    }
+    matched.insert(
+      outflow.transaction_id.clone(),
+      inflow.transaction_id.clone(),
+    );
+    matched.insert(
+      inflow.transaction_id.clone(),
+      outflow.transaction_id.clone(),
+    );
+  }
+
+  matched
+}
+
+// the ids of Plaid transactions that are one side of a transfer
+pub fn plaid_transfer_ids<'a, I>(
+  transactions: I,
+  excluded_accounts: &HashSet<String>,
+) -> HashSet<String>
+where
+  I: Iterator<Item = &'a plaid::models::Transaction>,
+{
+  let movements: Vec<Movement> = transactions
+    .filter(|transaction| !excluded_accounts.contains(&transaction.account_id))
+    .filter_map(Movement::from_plaid)
+    .collect();
+
+  match_transfers(&movements)
+    .into_iter()
+    .map(|(id, _)| id)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::common::Currency;
+  use rust_decimal_macros::dec;
+
+  fn movement(id: &str, account_id: &str, day: u32, amount: Money) -> Movement {
+    Movement {
+      transaction_id: id.to_string(),
+      account_id: account_id.to_string(),
+      date: NaiveDate::from_ymd(2021, 3, day),
+      amount,
+    }
+  }
+
+  #[test]
+  fn test_match_transfers() {
+    let movements = vec![
+      // checking to savings, arriving the next day
+      movement("to savings", "checking", 1, Money::usd(dec!(500))),
+      movement("from checking", "savings", 2, Money::usd(dec!(-500))),
+      // a card payoff, with a refund of the same amount on the same card
+      movement("card payment", "checking", 10, Money::usd(dec!(250))),
+      movement("refund", "card", 4, Money::usd(dec!(-250))),
+      movement("payment received", "card", 11, Money::usd(dec!(-250))),
+      // too far apart
+      movement("rent", "checking", 15, Money::usd(dec!(1500))),
+      movement("deposit", "savings", 25, Money::usd(dec!(-1500))),
+      // same account
+      movement("purchase", "card", 20, Money::usd(dec!(40))),
+      movement("return", "card", 21, Money::usd(dec!(-40))),
+      // another currency
+      movement("wire", "checking", 22, Money::usd(dec!(100))),
+      movement(
+        "wire in",
+        "euro",
+        22,
+        Money::in_currency(dec!(-100), Currency::new("EUR").unwrap()),
+      ),
+    ];
+
+    let matched = match_transfers(&movements);
+
+    let mut expected = HashMap::new();
+    for (a, b) in [
+      ("to savings", "from checking"),
+      ("card payment", "payment received"),
+    ]
+    .iter()
+    {
+      expected.insert(a.to_string(), b.to_string());
+      expected.insert(b.to_string(), a.to_string());
+    }
+    assert_eq!(expected, matched);
+  }
+
+  #[test]
+  fn test_match_transfers_pairs_once() {
+    // two equal moves out, only one arrived, on the day of the second
+    let movements = vec![
+      movement("first", "checking", 1, Money::usd(dec!(100))),
+      movement("second", "checking", 3, Money::usd(dec!(100))),
+      movement("arrived", "savings", 3, Money::usd(dec!(-100))),
+    ];
+
+    let matched = match_transfers(&movements);
+
+    assert_eq!(2, matched.len());
+    assert_eq!(Some(&"second".to_string()), matched.get("arrived"));
+  }
+}
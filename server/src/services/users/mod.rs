@@ -1,21 +1,30 @@
-use crate::common::errors::ApiError;
-use crate::controllers::plaid_controller::{AccountError, AccountResponse};
+use crate::common::{
+  compounding::CompoundingConvention,
+  encryption::{Keyring, StoredSecret},
+  errors::ApiError,
+  Currency, Money,
+};
+use crate::controllers::plaid_controller::{AccountError, AccountResponse, BalanceSheetResponse};
 use crate::controllers::user_controller::{LoginPayload, SignupPayload, UpdatePayload};
 use crate::models::plan_model::*;
 use crate::models::{
   insight_model::Insight,
   session_model,
-  user_model::{AccountRecord, PlaidItem, Snapshot, User},
+  user_model::{AccountRecord, ItemStatus, PlaidItem, Snapshot, User},
 };
 use crate::services::{
-  db, financial_products::FinProductService, finchplaid::ApiClient, plans::PlansService,
-  snapshots::SnapshotService,
+  db, financial_products::FinProductService, finchplaid::ApiClient, fx::FxService,
+  manual_accounts::ManualAccountService, plans::PlansService, snapshots::SnapshotService,
+  transactions::TransactionService,
 };
 use actix_web::web::Data;
+use chrono::Utc;
+use futures::stream::StreamExt;
 use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use wither::{
   mongodb::{
-    bson::{doc, oid::ObjectId},
+    bson::{doc, oid::ObjectId, to_bson},
     Database,
   },
   prelude::Migrating,
@@ -58,16 +67,21 @@ impl UserService {
       last_name: data.last_name,
       income: data.income,
       net_worth: data.net_worth,
+      base_currency: data.base_currency.unwrap_or_default(),
       location: data.location,
       birthday: data.birthday,
       accounts: vec![],
       account_records: vec![],
-      snapshots: vec![],
+      manual_accounts: vec![],
+      // start the user's history with their self-reported net worth
+      snapshots: vec![Snapshot::new(data.net_worth, dec!(0), dec!(0), dec!(0))],
       recurrings: vec![],
       goals: vec![],
       insights: vec![],
       plans: vec![],
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
     };
 
     user.save(&self.db, None).await.map_or_else(
@@ -76,6 +90,13 @@ impl UserService {
     )
   }
 
+  pub async fn find_by_email(&self, email: &String) -> Result<User, ApiError> {
+    User::find_one(&self.db, Some(doc! {"email": email}), None)
+      .await
+      .map_err(|_| ApiError::new(500, "DB Error".to_string()))
+      .and_then(|user_opt| user_opt.ok_or(ApiError::new(400, "User not found".to_string())))
+  }
+
   pub async fn login(&self, data: LoginPayload) -> Result<User, ApiError> {
     // search db for user
     let search_db_res = User::find_one(&self.db, Some(doc! {"email": data.email.clone()}), None)
@@ -103,7 +124,12 @@ impl UserService {
     })
   }
 
-  pub async fn update(&self, mut user: User, data: UpdatePayload) -> Result<User, ApiError> {
+  pub async fn update(
+    &self,
+    mut user: User,
+    data: UpdatePayload,
+    fx: &FxService,
+  ) -> Result<User, ApiError> {
     if let Some(email) = data.email {
       user.email = email;
     }
@@ -125,6 +151,10 @@ impl UserService {
     if let Some(net_worth) = data.net_worth {
       user.net_worth = net_worth;
     }
+    // after income and net worth, which are given in the old base
+    if let Some(base_currency) = data.base_currency {
+      Self::change_base_currency(&mut user, base_currency, fx)?;
+    }
     if let Some(location) = data.location {
       user.location = location;
     }
@@ -138,6 +168,66 @@ impl UserService {
     )
   }
 
+  // Converts everything the user has recorded in their base currency at today's
+  // rates, so their history stays comparable with new snapshots.
+  pub fn change_base_currency(
+    user: &mut User,
+    to: Currency,
+    fx: &FxService,
+  ) -> Result<(), ApiError> {
+    let from = user.base_currency;
+    if from == to {
+      return Ok(());
+    }
+
+    // amounts without a currency were recorded in the old base
+    let money = |money: Money| {
+      fx.convert(
+        Money::in_currency(money.amount, money.currency.unwrap_or(from)),
+        to,
+      )
+    };
+    let decimal = |amount: Decimal| money(Money::new(amount)).map(|m| m.amount);
+
+    for snapshot in user.snapshots.iter_mut() {
+      snapshot.net_worth = money(snapshot.net_worth)?;
+      snapshot.assets = money(snapshot.assets)?;
+      snapshot.liabilities = money(snapshot.liabilities)?;
+      snapshot.running_savings = money(snapshot.running_savings)?;
+      snapshot.running_spending = money(snapshot.running_spending)?;
+      snapshot.running_income = money(snapshot.running_income)?;
+
+      for account in snapshot.accounts.iter_mut() {
+        account.balance = money(account.balance)?;
+        account.money_in = money(account.money_in)?;
+        account.money_out = money(account.money_out)?;
+        account.transacted = money(account.transacted)?;
+      }
+    }
+
+    for goal in user.goals.iter_mut() {
+      goal.threshold = decimal(goal.threshold)?;
+    }
+    for budget in user.budgets.iter_mut() {
+      budget.limit = decimal(budget.limit)?;
+    }
+
+    let plan_recurrings = user
+      .plans
+      .iter_mut()
+      .flat_map(|plan| plan.recurrings.iter_mut());
+    for recurring in user.recurrings.iter_mut().chain(plan_recurrings) {
+      recurring.principal = decimal(recurring.principal)?;
+      recurring.amount = decimal(recurring.amount)?;
+    }
+
+    user.income = decimal(user.income)?;
+    user.net_worth = decimal(user.net_worth)?;
+    user.base_currency = to;
+
+    Ok(())
+  }
+
   pub async fn new_from_session(&self, session: session_model::Session) -> Result<User, ApiError> {
     User::find_one(&self.db, Some(doc! {"_id": session.user_id.clone()}), None)
       .await
@@ -148,21 +238,30 @@ impl UserService {
   pub async fn add_new_account(
     &self,
     mut user: User,
-    access_token: String,
+    access_token: StoredSecret,
     item_id: String,
     user_service: Data<UserService>,
     plaid_client: Data<ApiClient>,
     fin_product_service: Data<FinProductService>,
+    fx: Data<FxService>,
   ) -> Result<(), ApiError> {
     user.accounts.push(PlaidItem {
       item_id: item_id.clone(),
       access_token,
+      status: ItemStatus::Healthy,
+      last_successful_sync: Some(Utc::now().timestamp()),
+      last_failure: None,
+      linked_time: Utc::now().timestamp(),
+      pending_backfill: true,
+      transactions_cursor: None,
+      pending_refresh: false,
+      consent_expiration_time: None,
     });
 
     self.save(&mut user).await?;
 
     let accounts_info = plaid_client
-      .retrieve_an_items_accounts(user.accounts.last().unwrap().access_token.clone())
+      .retrieve_an_items_accounts(&user.accounts.last().unwrap().access_token)
       .await;
     let accounts = accounts_info?.accounts;
 
@@ -182,7 +281,7 @@ impl UserService {
 
     // update snapshots after account added
     self
-      .add_new_snapshot(&mut user, plaid_client.clone())
+      .add_new_snapshot(&mut user, plaid_client.clone(), &fx)
       .await?;
 
     self.save(&mut user).await?;
@@ -190,14 +289,7 @@ impl UserService {
     // add plan if its user's first account
     if user.accounts.len() == 1 {
       self
-        .add_plaid_plan(
-          user.clone(),
-          user_service,
-          plaid_client.clone(),
-          SnapshotService::get_last_snapshot(&(self.get_snapshots(&mut user, plaid_client).await?))
-            .net_worth
-            .amount,
-        )
+        .add_plaid_plan(user.clone(), user_service, plaid_client.clone(), &fx)
         .await?;
     }
 
@@ -209,10 +301,10 @@ impl UserService {
     mut user: User,
     user_service: Data<UserService>,
     plaid_client: Data<ApiClient>,
-    net_worth: Decimal,
+    fx: &FxService,
   ) -> Result<Plan, ApiError> {
     let allocation =
-      PlansService::get_plaid_allocation(&user, user_service, plaid_client, net_worth).await?;
+      PlansService::get_plaid_allocation(&user, user_service, plaid_client, fx).await?;
 
     if user.plans.len() < 1 {
       user.plans.push(Plan {
@@ -221,6 +313,8 @@ impl UserService {
         recurrings: vec![],
         allocations: vec![allocation],
         events: vec![],
+        advisory_fee: None,
+        compounding: CompoundingConvention::default(),
       });
     } else {
       user.plans[0].allocations.push(allocation);
@@ -245,10 +339,11 @@ impl UserService {
     for item in user.accounts.iter() {
       match crate::services::finchplaid::get_account_data(item, plaid_client.clone()).await {
         Ok(mut res) => account_successes.append(&mut res),
-        Err(_) => account_errors.push(AccountError {
+        Err(e) => account_errors.push(AccountError {
           item_id: item.item_id.clone(),
-          code: 500,
-          message: "Failed to obtain account information with given id".to_string(),
+          code: e.code(),
+          message: e.message().clone(),
+          status: ItemStatus::from_error(&e),
         }),
       };
     }
@@ -263,22 +358,93 @@ impl UserService {
         .collect();
     }
 
+    account_successes.append(&mut ManualAccountService::account_successes(
+      user,
+      show_all_accounts,
+      &ManualAccountService::today(),
+    ));
+
     Ok(AccountResponse {
       accounts: account_successes,
       account_errors: account_errors,
     })
   }
 
+  pub async fn get_balance_sheet(
+    &self,
+    user: &User,
+    plaid_client: Data<ApiClient>,
+    fx: &FxService,
+  ) -> Result<BalanceSheetResponse, ApiError> {
+    let AccountResponse {
+      accounts,
+      account_errors,
+    } = self.get_accounts(user, plaid_client.clone(), false).await?;
+
+    // liabilities have negative balances, and accounts are totalled in the base currency
+    let mut assets = dec!(0);
+    let mut liabilities = dec!(0);
+    for account in accounts.iter() {
+      let balance = fx
+        .convert(
+          Money::in_currency(account.balance, account.currency),
+          user.base_currency,
+        )?
+        .amount;
+
+      if balance > dec!(0) {
+        assets += balance;
+      } else {
+        liabilities -= balance;
+      }
+    }
+
+    // the details are extra, so items without the Liabilities product are skipped
+    let mut liability_details = Vec::new();
+    for item in user.accounts.iter().filter(|item| {
+      accounts.iter().any(|account| {
+        account.item_id == item.item_id
+          && (account.account_type == "credit" || account.account_type == "loan")
+      })
+    }) {
+      match plaid_client.get_liabilities(&item.access_token).await {
+        Ok(mut details) => liability_details.append(&mut details),
+        Err(e) => log::debug!("No liabilities for item {}: {:?}", item.item_id, e),
+      }
+    }
+
+    // hidden accounts stay out of the details too
+    liability_details.retain(|details| {
+      accounts
+        .iter()
+        .any(|account| account.account_id == details.account_id)
+    });
+
+    Ok(BalanceSheetResponse {
+      currency: user.base_currency,
+      assets,
+      liabilities,
+      net_worth: assets - liabilities,
+      accounts,
+      liability_details,
+      account_errors,
+    })
+  }
+
   pub async fn delete_item_and_save(
     &self,
     account_id: String,
     mut user: User,
     plaid_client: Data<ApiClient>,
+    transaction_service: Data<TransactionService>,
+    fx: &FxService,
   ) -> Result<String, ApiError> {
     let item = Self::delete_item(account_id, &mut user)?;
 
+    transaction_service.delete_item_transactions(&item).await?;
+
     // update snappshot after account change
-    self.add_new_snapshot(&mut user, plaid_client).await?;
+    self.add_new_snapshot(&mut user, plaid_client, fx).await?;
 
     self.save(&mut user).await?;
 
@@ -306,23 +472,13 @@ impl UserService {
     Ok(item.item_id)
   }
 
-  pub async fn get_snapshots(
-    &self,
-    user: &mut User,
-    plaid_client: Data<ApiClient>,
-  ) -> Result<Vec<Snapshot>, ApiError> {
-    if SnapshotService::need_new_snapshot(&user.snapshots) {
-      self.add_new_snapshot(user, plaid_client).await?
-    }
-    Ok(user.snapshots.clone())
-  }
-
   pub async fn add_new_snapshot(
     &self,
     user: &mut User,
     plaid_client: Data<ApiClient>,
+    fx: &FxService,
   ) -> Result<(), ApiError> {
-    SnapshotService::add_new_snapshot(user, plaid_client).await?;
+    SnapshotService::add_new_snapshot(user, plaid_client, fx).await?;
     user.save(&self.db, None).await.map_err(|_| {
       ApiError::new(
         500,
@@ -366,6 +522,7 @@ impl UserService {
     account_id: String,
     hide_or_not: bool,
     plaid_client: Data<ApiClient>,
+    fx: &FxService,
   ) -> Result<AccountResponse, ApiError> {
     for account in user
       .account_records
@@ -375,12 +532,66 @@ impl UserService {
       account.hidden = hide_or_not;
     }
 
+    // past snapshots count the account as if it had always been hidden or shown
+    SnapshotService::set_account_hidden(&mut user.snapshots, &account_id, hide_or_not);
+
     self.save(user).await?;
-    self.add_new_snapshot(user, plaid_client.clone()).await?;
+    self
+      .add_new_snapshot(user, plaid_client.clone(), fx)
+      .await?;
 
     self.get_accounts(user, plaid_client, false).await
   }
 
+  // Puts every access token under the keyring's current master key, encrypting
+  // those still in plaintext. Returns how many tokens were changed, and how many
+  // users couldn't be read and so kept theirs.
+  pub async fn rotate_access_tokens(&self, keyring: &Keyring) -> Result<(usize, usize), ApiError> {
+    let mut unreadable = 0;
+    let users = User::find(&self.db, None, None)
+      .await
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))?
+      .collect::<Vec<Result<User, _>>>()
+      .await
+      .into_iter()
+      .filter_map(|res| match res {
+        Ok(user) => Some(user),
+        Err(e) => {
+          log::error!("Could not read user to rotate their access tokens: {:?}", e);
+          unreadable += 1;
+          None
+        }
+      })
+      .collect::<Vec<User>>();
+
+    let mut rotated = 0;
+    for user in users.iter() {
+      for item in user.accounts.iter() {
+        let access_token = match keyring.rotate(&item.access_token)? {
+          Some(access_token) => to_bson(&access_token)
+            .map_err(|_| ApiError::new(500, "Encryption Error".to_string()))?,
+          None => continue,
+        };
+
+        // Only the token is set, but the API saves whole users, so one read
+        // before this and saved after writes the old token back. Rotate with the
+        // API stopped, or rotate again before removing the old key.
+        User::collection(&self.db)
+          .update_one(
+            doc! {"_id": user.id.clone(), "accounts.item_id": item.item_id.clone()},
+            doc! {"$set": doc!{"accounts.$.access_token": access_token}},
+            None,
+          )
+          .await
+          .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+
+        rotated += 1;
+      }
+    }
+
+    Ok((rotated, unreadable))
+  }
+
   pub async fn save(&self, u: &mut User) -> Result<(), ApiError> {
     u.save(&self.db, None)
       .await
@@ -406,18 +617,8 @@ mod test {
     Ok(transactions)
   }
 
-  #[test]
-  fn test_delete_account() {
-    let accounts = load_test_data().unwrap();
-
-    let to_delete = PlaidItem {
-      item_id: accounts.accounts[0].account_id.clone(),
-      access_token: String::from("12345"),
-    };
-    let mut accounts_array: Vec<PlaidItem> = Vec::new();
-    accounts_array.push(to_delete);
-
-    let mut user = User {
+  fn test_user() -> User {
+    User {
       id: None,
       email: String::from("test@test.com"),
       password: String::from("test@test.com"),
@@ -425,18 +626,47 @@ mod test {
       last_name: String::from("ln"),
       income: dec!(0.0),
       net_worth: dec!(0.0),
+      base_currency: Currency::USD,
       location: Location {
         ..Default::default()
       },
       birthday: "1970-01-01".to_string(),
-      accounts: accounts_array,
+      accounts: vec![],
       account_records: vec![],
+      manual_accounts: vec![],
       snapshots: Vec::new(),
       recurrings: Vec::new(),
       goals: Vec::new(),
       insights: Vec::new(),
       plans: Vec::new(),
       rankings: vec![],
+      category_rules: vec![],
+      budgets: vec![],
+    }
+  }
+
+  #[test]
+  fn test_delete_account() {
+    let accounts = load_test_data().unwrap();
+
+    let to_delete = PlaidItem {
+      item_id: accounts.accounts[0].account_id.clone(),
+      access_token: StoredSecret::Plaintext(String::from("12345")),
+      status: ItemStatus::Healthy,
+      last_successful_sync: None,
+      last_failure: None,
+      linked_time: 0,
+      pending_backfill: false,
+      transactions_cursor: None,
+      pending_refresh: false,
+      consent_expiration_time: None,
+    };
+    let mut accounts_array: Vec<PlaidItem> = Vec::new();
+    accounts_array.push(to_delete);
+
+    let mut user = User {
+      accounts: accounts_array,
+      ..test_user()
     };
 
     let mut found = false;
@@ -452,4 +682,134 @@ mod test {
 
     assert_eq!(false, found);
   }
+  #[test]
+  fn test_change_base_currency() {
+    use crate::models::{
+      budget_model::{Budget, BudgetPeriod},
+      goal_model::{Goal, GoalMetrics},
+    };
+    use crate::services::fx::FileFxProvider;
+    use std::sync::Arc;
+
+    let fx = FxService::new(Arc::new(
+      FileFxProvider::from_file("./tests/fixtures/fx_rates.json").unwrap(),
+    ));
+    let eur = Currency::new("EUR").unwrap();
+
+    let mut user = User {
+      income: dec!(1000),
+      net_worth: dec!(500),
+      snapshots: vec![Snapshot::new(dec!(100), dec!(10), dec!(20), dec!(30))],
+      goals: vec![Goal {
+        id: None,
+        name: "save".to_string(),
+        start: 0,
+        end: 1,
+        threshold: dec!(50),
+        metric: GoalMetrics::Savings,
+      }],
+      ..test_user()
+    };
+    user.budgets.push(Budget {
+      id: None,
+      category: "Food".to_string(),
+      limit: dec!(200),
+      period: BudgetPeriod::Monthly,
+      rollover: false,
+      start: "2021-01-01".to_string(),
+    });
+
+    UserService::change_base_currency(&mut user, eur, &fx).unwrap();
+
+    assert_eq!(eur, user.base_currency);
+    assert_eq!(dec!(800), user.income);
+    assert_eq!(dec!(400), user.net_worth);
+    assert_eq!(
+      Money::in_currency(dec!(80), eur),
+      user.snapshots[0].net_worth
+    );
+    assert_eq!(
+      Money::in_currency(dec!(24), eur),
+      user.snapshots[0].running_income
+    );
+    assert_eq!(dec!(40), user.goals[0].threshold);
+    assert_eq!(dec!(160), user.budgets[0].limit);
+
+    // nothing changes when a rate is missing
+    let mut stuck = test_user();
+    assert!(
+      UserService::change_base_currency(&mut stuck, Currency::new("JPY").unwrap(), &fx).is_err()
+    );
+    assert_eq!(Currency::USD, stuck.base_currency);
+  }
+
+  #[test]
+  fn test_change_base_currency_converts_accounts() {
+    use crate::models::user_model::AccountSnapshot;
+    use crate::services::exports::DateRange;
+    use crate::services::fx::FileFxProvider;
+    use crate::services::snapshots::{attribution, performance};
+    use std::sync::Arc;
+
+    let fx = FxService::new(Arc::new(
+      FileFxProvider::from_file("./tests/fixtures/fx_rates.json").unwrap(),
+    ));
+    let eur = Currency::new("EUR").unwrap();
+    let account = |account_id: &str, account_type: &str, balance, transacted| {
+      AccountSnapshot::fixture(account_id, account_type, balance).with_transacted(transacted)
+    };
+    let snapshot = |day: i64, income, accounts| {
+      Snapshot::fixture(day * 86400, accounts).with_running_sums(income, dec!(0))
+    };
+
+    // 100 earned and put in the brokerage, which also went up 100
+    let mut user = User {
+      snapshots: vec![
+        snapshot(
+          0,
+          dec!(0),
+          vec![
+            account("checking", "depository", dec!(500), dec!(0)),
+            account("brokerage", "investment", dec!(1000), dec!(0)),
+          ],
+        ),
+        snapshot(
+          30,
+          dec!(100),
+          vec![
+            account("checking", "depository", dec!(500), dec!(0)),
+            account("brokerage", "investment", dec!(1200), dec!(100)),
+          ],
+        ),
+      ],
+      ..test_user()
+    };
+
+    UserService::change_base_currency(&mut user, eur, &fx).unwrap();
+
+    let accounts = &user.snapshots[1].accounts;
+    assert_eq!(Money::in_currency(dec!(960), eur), accounts[1].balance);
+    assert_eq!(Money::in_currency(dec!(80), eur), accounts[1].transacted);
+    assert_eq!(Money::in_currency(dec!(0), eur), accounts[1].money_in);
+
+    let everything = DateRange {
+      from: None,
+      to: None,
+    };
+
+    let attribution = attribution::attribute(&user.snapshots, &everything).unwrap();
+    assert_eq!(Money::in_currency(dec!(160), eur), attribution.change);
+    assert_eq!(Money::in_currency(dec!(80), eur), attribution.contributions);
+    assert_eq!(
+      Money::in_currency(dec!(80), eur),
+      attribution.investment_gains
+    );
+
+    let performance =
+      performance::measure_performance(&user.snapshots, &everything, &user.plans).unwrap();
+    assert_eq!(
+      Money::in_currency(dec!(960), eur),
+      performance.portfolio.closing_value
+    );
+  }
 }
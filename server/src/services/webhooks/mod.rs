@@ -0,0 +1,379 @@
+use crate::common::{errors::ApiError, into_bson_document};
+use crate::models::user_model::{ItemStatus, User};
+use crate::services::{
+  db::DatabaseService,
+  finchplaid::{ApiClient, WebhookVerificationKey},
+  transactions::TransactionService,
+};
+use actix_web::web::Data;
+use chrono::Utc;
+use ring::{digest, signature};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use wither::{
+  mongodb::{
+    bson::{doc, Document},
+    Database,
+  },
+  Model,
+};
+
+// webhooks older than this are rejected, so captured ones can't be replayed
+const MAX_WEBHOOK_AGE_SECS: i64 = 5 * 60;
+
+// how far ahead of our clock Plaid's may be
+const MAX_WEBHOOK_CLOCK_SKEW_SECS: i64 = 60;
+
+// verification keys are fetched again after this, to notice when they expire
+const VERIFICATION_KEY_CACHE_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PlaidWebhook {
+  pub webhook_type: String,
+  pub webhook_code: String,
+  pub item_id: String,
+  #[serde(default)]
+  pub removed_transactions: Vec<String>,
+  pub error: Option<PlaidWebhookError>,
+  pub consent_expiration_time: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PlaidWebhookError {
+  pub error_code: String,
+  pub error_message: String,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+  alg: String,
+  kid: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+  iat: i64,
+  request_body_sha256: String,
+}
+
+#[derive(Clone)]
+pub struct WebhookService {
+  db: Database,
+  // verification keys by kid, with when they were fetched. Shared by every worker.
+  keys: Arc<RwLock<HashMap<String, (WebhookVerificationKey, i64)>>>,
+}
+
+impl WebhookService {
+  pub async fn new(db: &DatabaseService) -> WebhookService {
+    WebhookService {
+      db: db.db.clone(),
+      keys: Arc::new(RwLock::new(HashMap::new())),
+    }
+  }
+
+  async fn verification_key(
+    &self,
+    kid: String,
+    plaid_client: &Data<ApiClient>,
+    now: i64,
+  ) -> Result<WebhookVerificationKey, ApiError> {
+    let cached = self
+      .keys
+      .read()
+      .map_err(|_| ApiError::new(500, "Verification key cache poisoned".to_string()))?
+      .get(&kid)
+      .filter(|(_, fetched)| now - fetched < VERIFICATION_KEY_CACHE_SECS)
+      .map(|(key, _)| key.clone());
+    if let Some(key) = cached {
+      return Ok(key);
+    }
+
+    let key = plaid_client
+      .get_webhook_verification_key(kid.clone())
+      .await?;
+    self
+      .keys
+      .write()
+      .map_err(|_| ApiError::new(500, "Verification key cache poisoned".to_string()))?
+      .insert(kid, (key.clone(), now));
+
+    Ok(key)
+  }
+
+  // Checks the `Plaid-Verification` JWT against the raw body, then acts on the webhook.
+  pub async fn receive(
+    &self,
+    verification: &str,
+    body: &[u8],
+    plaid_client: Data<ApiClient>,
+    transaction_service: Data<TransactionService>,
+  ) -> Result<PlaidWebhook, ApiError> {
+    let header: JwtHeader = decode_segment(verification.split('.').next().unwrap_or(""))?;
+    if header.alg != "ES256" {
+      return Err(ApiError::new(
+        401,
+        "Unexpected webhook algorithm".to_string(),
+      ));
+    }
+
+    let now = Utc::now().timestamp();
+    let key = self
+      .verification_key(header.kid, &plaid_client, now)
+      .await?;
+    verify_webhook(verification, body, &key, now)?;
+
+    let webhook: PlaidWebhook = serde_json::from_slice(body)
+      .map_err(|_| ApiError::new(400, "Malformed webhook".to_string()))?;
+
+    self.handle(&webhook, transaction_service).await?;
+
+    Ok(webhook)
+  }
+
+  async fn handle(
+    &self,
+    webhook: &PlaidWebhook,
+    transaction_service: Data<TransactionService>,
+  ) -> Result<(), ApiError> {
+    log::info!(
+      "Plaid webhook {} {} for item {}",
+      webhook.webhook_type,
+      webhook.webhook_code,
+      webhook.item_id
+    );
+
+    match (webhook.webhook_type.as_str(), webhook.webhook_code.as_str()) {
+      // the snapshot service syncs the item's ledger on its next pass
+      ("TRANSACTIONS", "INITIAL_UPDATE")
+      | ("TRANSACTIONS", "HISTORICAL_UPDATE")
+      | ("TRANSACTIONS", "DEFAULT_UPDATE") => {
+        self
+          .update_item(&webhook.item_id, doc! {"accounts.$.pending_refresh": true})
+          .await
+      }
+      ("TRANSACTIONS", "TRANSACTIONS_REMOVED") => {
+        transaction_service
+          .remove_transactions(&webhook.item_id, &webhook.removed_transactions)
+          .await
+      }
+      ("ITEM", "ERROR") => {
+        let status = webhook
+          .error
+          .as_ref()
+          .map(|error| {
+            ItemStatus::from_plaid_error(error.error_code.clone(), error.error_message.clone())
+          })
+          .unwrap_or(ItemStatus::Error {
+            code: "UNKNOWN".to_string(),
+            message: "Unknown item error".to_string(),
+          });
+
+        self.set_status(&webhook.item_id, status).await
+      }
+      ("ITEM", "USER_PERMISSION_REVOKED") => {
+        self
+          .set_status(
+            &webhook.item_id,
+            ItemStatus::from_plaid_error(
+              "USER_PERMISSION_REVOKED".to_string(),
+              "the user revoked access to this item".to_string(),
+            ),
+          )
+          .await
+      }
+      // the user went through Link in update mode
+      ("ITEM", "LOGIN_REPAIRED") => self.set_status(&webhook.item_id, ItemStatus::Healthy).await,
+      ("ITEM", "PENDING_EXPIRATION") => {
+        self
+          .update_item(
+            &webhook.item_id,
+            doc! {"accounts.$.consent_expiration_time": webhook.consent_expiration_time.clone()},
+          )
+          .await
+      }
+      _ => {
+        log::debug!("Ignoring webhook");
+        Ok(())
+      }
+    }
+  }
+
+  async fn set_status(&self, item_id: &String, status: ItemStatus) -> Result<(), ApiError> {
+    self
+      .update_item(
+        item_id,
+        doc! {"accounts.$.status": into_bson_document(&status)},
+      )
+      .await
+  }
+
+  // sets fields of the user's item in place, so concurrent saves of other fields survive
+  async fn update_item(&self, item_id: &String, set: Document) -> Result<(), ApiError> {
+    let res = User::collection(&self.db)
+      .update_one(
+        doc! {"accounts.item_id": item_id.clone()},
+        doc! {"$set": set},
+        None,
+      )
+      .await
+      .map_err(|_| ApiError::new(500, "Database Error".to_string()))?;
+
+    if res.matched_count == 0 {
+      return Err(ApiError::new(
+        400,
+        format!("No item with id {} found", item_id),
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+fn decode_segment<T: DeserializeOwned>(segment: &str) -> Result<T, ApiError> {
+  base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+    .ok()
+    .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+    .ok_or(ApiError::new(
+      401,
+      "Malformed webhook verification".to_string(),
+    ))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Verifies the ES256 signed JWT Plaid sends with each webhook, which also
+// carries when it was issued and the SHA-256 of the body.
+pub fn verify_webhook(
+  jwt: &str,
+  body: &[u8],
+  key: &WebhookVerificationKey,
+  now: i64,
+) -> Result<(), ApiError> {
+  let unverified = || ApiError::new(401, "Could not verify webhook".to_string());
+
+  let segments: Vec<&str> = jwt.split('.').collect();
+  if segments.len() != 3 || key.expired_at.is_some() {
+    return Err(unverified());
+  }
+
+  // the public key is the uncompressed point 0x04 || x || y
+  let mut public_key = vec![0x04];
+  for coordinate in [&key.x, &key.y].iter() {
+    public_key.append(
+      &mut base64::decode_config(coordinate, base64::URL_SAFE_NO_PAD).map_err(|_| unverified())?,
+    );
+  }
+
+  let signed = format!("{}.{}", segments[0], segments[1]);
+  let jwt_signature =
+    base64::decode_config(segments[2], base64::URL_SAFE_NO_PAD).map_err(|_| unverified())?;
+
+  signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key)
+    .verify(signed.as_bytes(), &jwt_signature)
+    .map_err(|_| unverified())?;
+
+  let claims: JwtClaims = decode_segment(segments[1])?;
+  if now - claims.iat > MAX_WEBHOOK_AGE_SECS || claims.iat - now > MAX_WEBHOOK_CLOCK_SKEW_SECS {
+    return Err(unverified());
+  }
+
+  if to_hex(digest::digest(&digest::SHA256, body).as_ref()) != claims.request_body_sha256 {
+    return Err(unverified());
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ring::rand::SystemRandom;
+  use ring::signature::KeyPair;
+
+  fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+  }
+
+  // signs a webhook the way Plaid does, returning the JWT and the matching key
+  fn sign(body: &[u8], iat: i64) -> (String, WebhookVerificationKey) {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+      signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+        .unwrap();
+    let key_pair = signature::EcdsaKeyPair::from_pkcs8(
+      &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+      pkcs8.as_ref(),
+    )
+    .unwrap();
+
+    let header = encode(br#"{"alg":"ES256","kid":"key","typ":"JWT"}"#);
+    let claims = encode(
+      format!(
+        r#"{{"iat":{},"request_body_sha256":"{}"}}"#,
+        iat,
+        to_hex(digest::digest(&digest::SHA256, body).as_ref())
+      )
+      .as_bytes(),
+    );
+    let signed = format!("{}.{}", header, claims);
+    let jwt_signature = key_pair.sign(&rng, signed.as_bytes()).unwrap();
+
+    let public_key = key_pair.public_key().as_ref();
+    let key = WebhookVerificationKey {
+      alg: "ES256".to_string(),
+      crv: "P-256".to_string(),
+      kid: "key".to_string(),
+      x: encode(&public_key[1..33]),
+      y: encode(&public_key[33..65]),
+      expired_at: None,
+    };
+
+    (
+      format!("{}.{}", signed, encode(jwt_signature.as_ref())),
+      key,
+    )
+  }
+
+  #[test]
+  fn test_verify_webhook() {
+    let body =
+      br#"{"webhook_type":"TRANSACTIONS","webhook_code":"DEFAULT_UPDATE","item_id":"item"}"#;
+    let (jwt, key) = sign(body, 1_600_000_000);
+
+    assert!(verify_webhook(&jwt, body, &key, 1_600_000_060).is_ok());
+
+    // tampered body
+    assert!(verify_webhook(&jwt, br#"{"webhook_type":"ITEM"}"#, &key, 1_600_000_060).is_err());
+
+    // too old
+    assert!(verify_webhook(&jwt, body, &key, 1_600_001_000).is_err());
+
+    // issued in the future
+    assert!(verify_webhook(&jwt, body, &key, 1_599_999_970).is_ok());
+    assert!(verify_webhook(&jwt, body, &key, 1_599_999_000).is_err());
+
+    // signed by someone else
+    let (_, other_key) = sign(body, 1_600_000_000);
+    assert!(verify_webhook(&jwt, body, &other_key, 1_600_000_060).is_err());
+  }
+
+  #[test]
+  fn test_parse_webhook() {
+    let webhook: PlaidWebhook = serde_json::from_str(
+      r#"{
+        "webhook_type": "TRANSACTIONS",
+        "webhook_code": "TRANSACTIONS_REMOVED",
+        "item_id": "wz666MBjYWTp2PDzzggYhM6oWWmBb",
+        "removed_transactions": ["yBVBEwrPyJs8GvR77N7QTxnGg6wG74H7dEDN6", "kgygNvAVPzSX9KkddNdWHaVGRVex1MHm3k9no"],
+        "error": null
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(2, webhook.removed_transactions.len());
+    assert!(webhook.error.is_none());
+  }
+}